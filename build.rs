@@ -0,0 +1,24 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/build.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::process::Command;
+
+fn main() {
+    // Commit the tools were built from, recorded in the provenance of their outputs
+    let output = Command::new("git").args(&["rev-parse", "HEAD"]).output();
+
+    if let Ok(output) = output {
+        if output.status.success() {
+            let commit = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+            println!("cargo:rustc-env=GIT_COMMIT_HASH={}", commit);
+        }
+    }
+
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
@@ -0,0 +1,64 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/decode/src/acq_mode.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use serde::{Deserialize, Serialize};
+
+use crate::TOT_ADU_TO_NS;
+
+/// Largest integral ToT the ToT field of an event count hit can hold (25 ns units)
+const MAX_ITOT_ADU: u32 = 0x3FFF;
+
+/// Timepix3 operation mode, which sets what the ToA and ToT fields of a pixel packet hold
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AcqMode {
+    /// ToA (14 bits) and ToT (10 bits), with the fast ToA
+    #[default]
+    ToaTot,
+    /// ToA (14 bits) and fast ToA only, the ToT field is not filled
+    Toa,
+    /// Integral ToT (14 bits, in place of the ToA) and event count (10 bits, in place of the
+    /// ToT), with no time measurement beyond the SPIDR timestamp
+    EventCount,
+}
+
+impl AcqMode {
+    /// Names accepted by `parse`, as used on the command line
+    pub const NAMES: [&'static str; 3] = ["toa-tot", "toa", "event-count"];
+
+    pub fn parse(name: &str) -> Result<AcqMode, String> {
+        match name {
+            "toa-tot" => Ok(AcqMode::ToaTot),
+            "toa" => Ok(AcqMode::Toa),
+            "event-count" => Ok(AcqMode::EventCount),
+            _ => Err(format!("Unknown acquisition mode '{}', expected one of {}", name, AcqMode::NAMES.join(", "))),
+        }
+    }
+
+    /// Mode set in the Timepix3 general configuration register (bits 1 and 2)
+    pub fn from_general_config(config: u32) -> Option<AcqMode> {
+        match (config >> 1) & 0x3 {
+            0 => Some(AcqMode::ToaTot),
+            1 => Some(AcqMode::Toa),
+            2 => Some(AcqMode::EventCount),
+            _ => None,
+        }
+    }
+}
+
+/// Packs the integral ToT (ns) and event count of an event count hit into its ToT field: the
+/// integral ToT in 25 ns units in bits 0 to 13 and the count in bits 14 to 23, the same widths
+/// as the packet fields, so it fits in a hit record
+pub fn encode_event_count_field(itot: u32, event_count: u32) -> u32 {
+    (itot / TOT_ADU_TO_NS).min(MAX_ITOT_ADU) | (event_count & 0x3FF) << 14
+}
+
+/// Splits the ToT field of an event count hit into the integral ToT (ns) and event count
+pub fn decode_event_count_field(tot: u32) -> (u32, u32) {
+    ((tot & MAX_ITOT_ADU) * TOT_ADU_TO_NS, (tot >> 14) & 0x3FF)
+}
@@ -0,0 +1,73 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/decode/src/control_packets.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Checks whether a packet is a control or heartbeat packet (e.g. the 0x71 end of command and end
+/// of readout packets), ie. anything other than a pixel hit (0xA/0xB) or a time/trigger packet
+/// (0x4/0x6)
+pub fn is_control_packet(packet: u64) -> bool {
+    !matches!(packet >> 60, 0x4 | 0x6 | 0xA | 0xB)
+}
+
+/// Type of a packet, its first byte (header and subheader)
+pub fn packet_type(packet: u64) -> u8 {
+    (packet >> 56) as u8
+}
+
+/// Counts of the control and heartbeat packets seen, by type. Unexpected types or counts point
+/// to a misconfigured firmware or readout, so they are recorded rather than silently dropped.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(transparent)]
+pub struct ControlPacketCounts {
+    #[serde(with = "hex_keys")]
+    counts: BTreeMap<u8, usize>,
+}
+
+impl ControlPacketCounts {
+    pub fn new() -> ControlPacketCounts {
+        ControlPacketCounts::default()
+    }
+
+    pub fn count(&mut self, packet: u64) {
+        *self.counts.entry(packet_type(packet)).or_insert(0) += 1;
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    /// Packet types and their counts, in type order
+    pub fn iter(&self) -> impl Iterator<Item = (u8, usize)> + '_ {
+        self.counts.iter().map(|(&packet_type, &count)| (packet_type, count))
+    }
+}
+
+/// Packet types as hex strings (e.g. `0x71`), as they are written in the Timepix3 manual
+mod hex_keys {
+    use std::collections::BTreeMap;
+
+    use serde::de::Error as _;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(counts: &BTreeMap<u8, usize>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(counts.iter().map(|(packet_type, count)| (format!("0x{:02X}", packet_type), count)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<u8, usize>, D::Error> {
+        BTreeMap::<String, usize>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(key, count)| match u8::from_str_radix(key.trim_start_matches("0x"), 16) {
+                Ok(packet_type) => Ok((packet_type, count)),
+                Err(_) => Err(D::Error::custom(format!("Invalid packet type '{}'", key))),
+            })
+            .collect()
+    }
+}
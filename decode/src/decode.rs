@@ -0,0 +1,337 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/decode/src/decode.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use serde::{Deserialize, Serialize};
+
+use crate::{encode_event_count_field, AcqMode, Hit, HitFlags, TOT_ADU_TO_NS};
+
+/// Length of a coarse TDC clock tick (ps)
+pub const TDC_COARSE_TICK_PS: u32 = 25_000;
+
+/// Length of a 320 MHz TDC sub-tick (ps), eight of which make up a coarse tick
+const TDC_SUB_TICK_PS: u32 = 3_125;
+
+/// Number of fine phases the 320 MHz sub-tick is divided into
+const TDC_FINE_PHASES: u32 = 12;
+
+/// Backward jumps in the coarse counter larger than this are treated as a 32 bit wrap (25 ns ticks)
+const TDC_WRAP_THRESHOLD: u64 = 1000;
+
+/// Trigger/TDC timestamp split into an extended coarse count (25 ns ticks) and the fine
+/// offset within that tick (ps).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TdcTime {
+    pub coarse: u64,
+    pub fine: u32,
+}
+
+impl TdcTime {
+    /// Full timestamp (ps)
+    pub fn as_ps(&self) -> u64 {
+        self.coarse * u64::from(TDC_COARSE_TICK_PS) + u64::from(self.fine)
+    }
+
+    /// Full timestamp (ns), truncated
+    pub fn as_ns(&self) -> u64 {
+        self.as_ps() / 1000
+    }
+}
+
+/// Irregularity in the coarse counter seen while decoding a TDC packet
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TdcTimeJump {
+    /// The 32 bit coarse counter wrapped and the extension was incremented
+    Wrapped,
+    /// The coarse counter went backwards by less than the wrap threshold
+    Backward,
+}
+
+/// Decodes the fine part of a TDC packet into ps within the 25 ns coarse tick.
+///
+/// Bits 9 to 11 count 3.125 ns ticks of the 320 MHz clock and bits 5 to 8 hold the phase
+/// (1 to 12) within that tick. Out of range phases are clamped rather than allowed to underflow.
+pub fn decode_tdc_fine(packet: u64) -> u32 {
+    let sub_ticks = ((packet >> 9) & 0x7) as u32;
+    let phase = ((packet >> 5) & 0xF) as u32;
+    let phase = phase.saturating_sub(1).min(TDC_FINE_PHASES - 1);
+
+    sub_ticks * TDC_SUB_TICK_PS + phase * TDC_SUB_TICK_PS / TDC_FINE_PHASES
+}
+
+/// Reconstructs full trigger/TDC times from a stream of TDC packets, extending the 32 bit
+/// coarse counter across wraps.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct TdcTimeDecoder {
+    prev_coarse: u64,
+    global_ext: u64,
+}
+
+impl TdcTimeDecoder {
+    pub fn new() -> TdcTimeDecoder {
+        TdcTimeDecoder::default()
+    }
+
+    pub fn decode(&mut self, packet: u64) -> (TdcTime, Option<TdcTimeJump>) {
+        let coarse = (packet & 0x0000_0FFF_FFFF_F000) >> 12;
+
+        let jump = if coarse < self.prev_coarse {
+            if coarse + TDC_WRAP_THRESHOLD < self.prev_coarse {
+                self.global_ext += 0x1_0000_0000;
+                Some(TdcTimeJump::Wrapped)
+            } else {
+                Some(TdcTimeJump::Backward)
+            }
+        } else {
+            None
+        };
+
+        self.prev_coarse = coarse;
+
+        let time = TdcTime {
+            coarse: self.global_ext + coarse,
+            fine: decode_tdc_fine(packet),
+        };
+
+        (time, jump)
+    }
+}
+
+/// Correction for the column to column clock phase shift (1.5625 ns fast ToA ticks).
+///
+/// Double columns are spread evenly over `clock_phases` phases of the 40 MHz clock, with the
+/// first phase treated as a whole clock period late.
+pub fn column_phase_correction(col: u16, clock_phases: u32) -> u64 {
+    let clock_phases = u64::from(clock_phases.clamp(1, 16));
+    let phase = (u64::from(col) / 2) % clock_phases;
+
+    if phase == 0 {
+        16
+    } else {
+        phase * (16 / clock_phases)
+    }
+}
+
+/// Decodes a pixel hit packet (header 0xA or 0xB).
+///
+/// Packet layout (Timepix3 manual, data driven readout with the SPIDR 16 bit timestamp):
+///   - bits 63-60: header
+///   - bits 59-44: pixel address (double column, super pixel, pixel)
+///   - bits 43-30: ToA (14 bits, 25 ns)
+///   - bits 29-20: ToT (10 bits, 25 ns)
+///   - bits 19-16: fast ToA (4 bits, 1.5625 ns, counting down to the next clock edge)
+///   - bits 15-0: SPIDR timestamp (16 bits, 409.6 µs)
+///
+/// ToA and ToT are measured with 40 MHz counters, and the fast ToA with a 640 MHz common stop
+/// TDC whose count is subtracted from the time measurement.
+///
+/// `long_time` is the most recent global timestamp (25 ns) from the 0x4/0x5 time packets, used
+/// to place the 30 bit pixel time onto the global timeline. The returned ToA is in 1.5625 ns
+/// units and the ToT in ns.
+pub fn decode_hit(packet: u64, long_time: u64, clock_phases: u32) -> Hit {
+    decode_hit_with_mode(packet, long_time, clock_phases, AcqMode::ToaTot)
+}
+
+/// Decodes a pixel hit packet recorded in any acquisition mode. In ToA only mode the ToT is 0.
+/// In event count mode the ToT field holds the integral ToT and event count (see
+/// `encode_event_count_field`), and the time is that of the SPIDR timestamp alone.
+pub fn decode_hit_with_mode(packet: u64, long_time: u64, clock_phases: u32, acq_mode: AcqMode) -> Hit {
+    // Calculate col and row
+    let dcol = (packet & 0x0FE0_0000_0000_0000) >> 52; //(16+28+9-1)
+    let spix = (packet & 0x001F_8000_0000_0000) >> 45; //(16+28+3-2)
+    let pix = (packet & 0x0000_7000_0000_0000) >> 44; //(16+28)
+    let col = (dcol + pix / 4) as u16;
+    let row = (spix + (pix & 0x3)) as u16;
+
+    let toa_field = (packet & 0x0000_0FFF_C000_0000) >> 30;
+    let tot_field = ((packet & 0x0000_0000_3FF0_0000) >> 20) as u32;
+
+    // Calculate ToT
+    let tot = match acq_mode {
+        AcqMode::ToaTot => tot_field * TOT_ADU_TO_NS,
+        AcqMode::Toa => 0,
+        AcqMode::EventCount => encode_event_count_field(toa_field as u32 * TOT_ADU_TO_NS, tot_field),
+    };
+
+    // Extract timing information
+    let spidr_time = packet & 0x0000_0000_0000_FFFF;
+    let (temp_toa, temp_toa_fast) = match acq_mode {
+        AcqMode::EventCount => (0, 0),
+        _ => (toa_field, (packet & 0x0000_0000_000F_0000) >> 16),
+    };
+    let temp_toa_coarse = (spidr_time << 14) | temp_toa;
+
+    // Calculate the global time
+    let pixel_bits = ((temp_toa_coarse >> 28) & 0x3) as i32; // units 25 ns
+    let long_time_bits = ((long_time >> 28) & 0x3) as i32; // units 25 ns;
+    let diff = long_time_bits - pixel_bits;
+
+    let global_time = match diff {
+        1 | -3 => (long_time.saturating_sub(0x1000_0000) & 0xFFFF_C000_0000) | (temp_toa_coarse & 0x3FFF_FFFF),
+        3 | -1 => ((long_time + 0x1000_0000) & 0xFFFF_C000_0000) | (temp_toa_coarse & 0x3FFF_FFFF),
+        _ => (long_time & 0xFFFF_C000_0000) | (temp_toa_coarse & 0x3FFF_FFFF),
+    };
+
+    // Subtract fast toa (ftoa count until the first clock edge, so less counts means later arrival of the hit)
+    let toa = (global_time << 4).saturating_sub(temp_toa_fast);
+
+    // Now correct for the column to column phase shift
+    let toa = match acq_mode {
+        AcqMode::EventCount => toa,
+        _ => toa + column_phase_correction(col, clock_phases),
+    };
+
+    Hit {
+        col,
+        row,
+        toa,
+        tot,
+        flags: HitFlags::empty(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// TDC packet (header 0x6) with the coarse count (25 ns), 320 MHz sub-ticks and fine phase
+    fn tdc_packet(coarse: u64, sub_ticks: u64, phase: u64) -> u64 {
+        (0x6 << 60) | (coarse << 12) | (sub_ticks << 9) | (phase << 5)
+    }
+
+    #[test]
+    fn fine_phase_0_does_not_underflow() {
+        assert_eq!(decode_tdc_fine(tdc_packet(0, 0, 0)), 0);
+        assert_eq!(decode_tdc_fine(tdc_packet(0, 5, 0)), 5 * TDC_SUB_TICK_PS);
+    }
+
+    #[test]
+    fn fine_phases_1_to_12_divide_the_sub_tick() {
+        let mut previous = None;
+
+        for phase in 1..=12 {
+            let fine = decode_tdc_fine(tdc_packet(0, 3, phase));
+
+            assert_eq!(fine, 3 * TDC_SUB_TICK_PS + (phase as u32 - 1) * TDC_SUB_TICK_PS / TDC_FINE_PHASES);
+            assert!(fine < 4 * TDC_SUB_TICK_PS);
+            assert!(previous.map_or(true, |previous| fine > previous));
+
+            previous = Some(fine);
+        }
+
+        // Out of range phases are clamped to the last one
+        assert_eq!(decode_tdc_fine(tdc_packet(0, 3, 15)), decode_tdc_fine(tdc_packet(0, 3, 12)));
+    }
+
+    #[test]
+    fn time_has_sub_coarse_tick_precision() {
+        let (time, jump) = TdcTimeDecoder::new().decode(tdc_packet(4, 2, 3));
+
+        assert_eq!(jump, None);
+        assert_eq!(time.coarse, 4);
+        assert_eq!(time.fine, 2 * 3_125 + 2 * 3_125 / 12);
+        assert_eq!(time.as_ps(), 4 * 25_000 + 6_250 + 520);
+        assert_eq!(time.as_ns(), 106);
+
+        // Triggers in the same 25 ns tick are told apart
+        let (later, _) = TdcTimeDecoder::new().decode(tdc_packet(4, 7, 12));
+        assert_eq!(later.coarse, time.coarse);
+        assert_eq!(later.as_ps(), 4 * 25_000 + 7 * 3_125 + 11 * 3_125 / 12);
+        assert!(later.as_ps() > time.as_ps());
+    }
+
+    #[test]
+    fn coarse_wraps_through_the_extension() {
+        let mut decoder = TdcTimeDecoder::new();
+
+        let (before, jump) = decoder.decode(tdc_packet(0xFFFF_FFFF, 7, 12));
+        assert_eq!(jump, None);
+        assert_eq!(before.coarse, 0xFFFF_FFFF);
+
+        let (after, jump) = decoder.decode(tdc_packet(0x1, 0, 1));
+        assert_eq!(jump, Some(TdcTimeJump::Wrapped));
+        assert_eq!(after.coarse, 0x1_0000_0001);
+        assert!(after.as_ps() > before.as_ps());
+        assert_eq!(after.as_ps() - before.as_ps(), 2 * 25_000 - (7 * 3_125 + 11 * 3_125 / 12));
+
+        // The extension is kept for the packets after the wrap
+        let (next, jump) = decoder.decode(tdc_packet(0x2, 0, 1));
+        assert_eq!(jump, None);
+        assert_eq!(next.coarse, 0x1_0000_0002);
+
+        // A small step back is not a wrap
+        let (back, jump) = decoder.decode(tdc_packet(0x1, 0, 1));
+        assert_eq!(jump, Some(TdcTimeJump::Backward));
+        assert_eq!(back.coarse, 0x1_0000_0001);
+    }
+
+    /// Pixel packet (header 0xB) from its fields: double column, super pixel and pixel address,
+    /// ToA, ToT and fast ToA counts and the SPIDR timestamp
+    fn pixel_packet(dcol: u64, spix: u64, pix: u64, toa: u64, tot: u64, ftoa: u64, spidr_time: u64) -> u64 {
+        (0xB << 60) | (dcol << 53) | (spix << 47) | (pix << 44) | (toa << 30) | (tot << 20) | (ftoa << 16) | spidr_time
+    }
+
+    #[test]
+    fn hit_address_gives_col_and_row() {
+        // Pixels 0 to 3 are the left column of the super pixel, 4 to 7 the right
+        let hit = decode_hit(pixel_packet(10, 20, 1, 0, 0, 0, 0), 0, 16);
+        assert_eq!((hit.col, hit.row), (20, 81));
+
+        let hit = decode_hit(pixel_packet(10, 20, 6, 0, 0, 0, 0), 0, 16);
+        assert_eq!((hit.col, hit.row), (21, 82));
+
+        let hit = decode_hit(pixel_packet(127, 63, 7, 0, 0, 0, 0), 0, 16);
+        assert_eq!((hit.col, hit.row), (255, 255));
+    }
+
+    #[test]
+    fn hit_tot_is_in_ns() {
+        assert_eq!(decode_hit(pixel_packet(0, 0, 0, 0, 1, 0, 0), 0, 16).tot, 25);
+        assert_eq!(decode_hit(pixel_packet(0, 0, 0, 0, 0x3FF, 0, 0), 0, 16).tot, 0x3FF * 25);
+
+        // Not measured in ToA only mode
+        assert_eq!(decode_hit_with_mode(pixel_packet(0, 0, 0, 0, 0x3FF, 0, 0), 0, 16, AcqMode::Toa).tot, 0);
+    }
+
+    #[test]
+    fn hit_toa_combines_timestamp_toa_and_fast_toa() {
+        // Column 20 is in phase 10 of 16, 10 fast ToA ticks late
+        let hit = decode_hit(pixel_packet(10, 0, 0, 0x100, 0, 3, 0x10), 0x40000, 16);
+
+        let coarse = (0x10 << 14) | 0x100; // 25 ns
+        assert_eq!(hit.toa, (coarse << 4) - 3 + 10);
+    }
+
+    #[test]
+    fn hit_toa_is_corrected_for_the_column_phase() {
+        let toa = |dcol: u64, clock_phases: u32| decode_hit(pixel_packet(dcol, 0, 0, 1, 0, 0, 0), 0, clock_phases).toa;
+
+        // The first phase is a whole clock period late
+        assert_eq!(toa(0, 16), 16 + 16);
+        assert_eq!(toa(1, 16), 16 + 1);
+        assert_eq!(toa(15, 16), 16 + 15);
+        assert_eq!(toa(16, 16), 16 + 16);
+
+        assert_eq!(toa(3, 4), 16 + 12);
+        assert_eq!(toa(4, 4), 16 + 16);
+
+        // Clock phases out of range are clamped
+        assert_eq!(toa(3, 0), toa(3, 1));
+        assert_eq!(toa(3, 32), toa(3, 16));
+    }
+
+    #[test]
+    fn hit_toa_is_placed_next_to_the_global_time() {
+        // Pixel time from just before the global timestamp crossed into the next 2^28 block
+        let hit = decode_hit(pixel_packet(1, 0, 0, 0x3FF0, 0, 0, 0x3FFF), 0x1000_0005, 16);
+        assert_eq!(hit.toa >> 4, 0x0FFF_FFF0);
+
+        // Pixel time from just after the global timestamp crossed into the next 2^28 block
+        let hit = decode_hit(pixel_packet(1, 0, 0, 0x10, 0, 0, 0x4000), 0x0FFF_FFF0, 16);
+        assert_eq!(hit.toa >> 4, 0x1000_0010);
+    }
+}
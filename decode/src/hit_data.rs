@@ -0,0 +1,65 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/decode/src/hit_data.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::convert::TryInto;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::{Hit, HitFlags};
+
+/// Size of a hit in a `hits.bin` file (bytes): col and row (u16), ToA (u64) and the ToT field
+/// (u32), little endian
+pub const HIT_RECORD_SIZE: usize = 16;
+
+/// Version of the hit record layout. Version 2 keeps the flags of each hit in the top byte of the
+/// ToT field, which is always zero in version 1 files, so those read back as unflagged hits.
+pub const HITS_FORMAT_VERSION: u32 = 2;
+
+/// Largest ToT a hit record can hold (ns), far beyond the 10 bit ToT counter
+pub const MAX_RECORD_TOT: u32 = 0x00FF_FFFF;
+
+/// Packs a ToT (ns) and flags into the ToT field of a hit record
+pub fn encode_tot_field(tot: u32, flags: HitFlags) -> u32 {
+    tot.min(MAX_RECORD_TOT) | u32::from(flags.0) << 24
+}
+
+/// Splits the ToT field of a hit record into the ToT (ns) and flags
+pub fn decode_tot_field(field: u32) -> (u32, HitFlags) {
+    (field & MAX_RECORD_TOT, HitFlags((field >> 24) as u8))
+}
+
+pub fn decode_hit_record(bytes: &[u8; HIT_RECORD_SIZE]) -> Hit {
+    let (tot, flags) = decode_tot_field(LittleEndian::read_u32(&bytes[12..16]));
+
+    Hit {
+        col: LittleEndian::read_u16(&bytes[0..2]),
+        row: LittleEndian::read_u16(&bytes[2..4]),
+        toa: LittleEndian::read_u64(&bytes[4..12]),
+        tot,
+        flags,
+    }
+}
+
+pub fn encode_hit_record(hit: &Hit) -> [u8; HIT_RECORD_SIZE] {
+    let mut bytes = [0; HIT_RECORD_SIZE];
+
+    LittleEndian::write_u16(&mut bytes[0..2], hit.col);
+    LittleEndian::write_u16(&mut bytes[2..4], hit.row);
+    LittleEndian::write_u64(&mut bytes[4..12], hit.toa);
+    LittleEndian::write_u32(&mut bytes[12..16], encode_tot_field(hit.tot, hit.flags));
+
+    bytes
+}
+
+/// Decodes the hits in a block of a hits file. A partial hit at the end is ignored.
+pub fn decode_hit_records(bytes: &[u8]) -> Vec<Hit> {
+    bytes
+        .chunks_exact(HIT_RECORD_SIZE)
+        .map(|record| decode_hit_record(record.try_into().unwrap()))
+        .collect()
+}
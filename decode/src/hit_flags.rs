@@ -0,0 +1,44 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/decode/src/hit_flags.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use serde::{Deserialize, Serialize};
+
+/// Quality flags set on a hit by the stages of the pipeline it went through, so analyses can
+/// apply or relax cuts on them without reprocessing the raw data
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, Eq, PartialEq, Hash)]
+#[serde(transparent)]
+pub struct HitFlags(pub u8);
+
+impl HitFlags {
+    /// Pixel next to a hot or masked pixel
+    pub const HOT_NEIGHBOUR: HitFlags = HitFlags(0x01);
+    /// Pixel on the edge of the matrix
+    pub const EDGE_PIXEL: HitFlags = HitFlags(0x02);
+    /// ToA corrected for the timing offset of the pixel's column or superpixel
+    pub const TIME_CORRECTED: HitFlags = HitFlags(0x04);
+    /// Checked for retransmitted copies, any of which were removed
+    pub const DEDUPLICATED: HitFlags = HitFlags(0x08);
+    /// ToT corrected with the flat field gain map
+    pub const CALIBRATED: HitFlags = HitFlags(0x10);
+
+    pub fn empty() -> HitFlags {
+        HitFlags(0)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(self, flags: HitFlags) -> bool {
+        self.0 & flags.0 == flags.0
+    }
+
+    pub fn insert(&mut self, flags: HitFlags) {
+        self.0 |= flags.0;
+    }
+}
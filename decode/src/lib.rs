@@ -0,0 +1,96 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/decode/src/lib.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+
+mod acq_mode;
+pub use acq_mode::{decode_event_count_field, encode_event_count_field, AcqMode};
+
+mod control_packets;
+pub use control_packets::{is_control_packet, packet_type, ControlPacketCounts};
+
+mod decode;
+pub use decode::{column_phase_correction, decode_hit, decode_hit_with_mode, decode_tdc_fine, TdcTime, TdcTimeDecoder, TdcTimeJump, TDC_COARSE_TICK_PS};
+
+mod hit_data;
+pub use hit_data::{decode_hit_record, decode_hit_records, decode_tot_field, encode_hit_record, encode_tot_field, HITS_FORMAT_VERSION, HIT_RECORD_SIZE, MAX_RECORD_TOT};
+
+mod hit_flags;
+pub use hit_flags::HitFlags;
+
+mod packets;
+pub use packets::{is_plausible_packet, split_packets};
+
+mod parse_raw;
+pub use parse_raw::{parse_raw_packets_from_slice, DecoderState};
+
+mod spidr_header;
+pub use spidr_header::{SpidrHeader, DEFAULT_CLOCK_PHASES, SPIDR_MAX_HEADER_SIZE};
+
+mod timestamp_packets;
+pub use timestamp_packets::{AbsoluteTimestamp, TimestampDecoder, TimestampSource, TIMESTAMP_LATCH_SUBHEADER, TIMESTAMP_SECONDS_SUBHEADER};
+
+mod time_extension;
+pub use time_extension::{GlobalTimeJump, TimeExtensionCounters, ToaExtender, TOA_ROLLOVER_PERIOD};
+
+pub const TOA_CLOCK_TO_NS: f64 = 1.5625;
+pub const TOT_ADU_TO_NS: u32 = 25;
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq)]
+pub struct Hit {
+    pub toa: u64,
+    pub tot: u32,
+    pub col: u16,
+    pub row: u16,
+    #[serde(default)]
+    pub flags: HitFlags,
+}
+
+impl Ord for Hit {
+    fn cmp(&self, other: &Hit) -> Ordering {
+        self.toa.cmp(&other.toa)
+    }
+}
+
+impl PartialOrd for Hit {
+    fn partial_cmp(&self, other: &Hit) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Hit {
+    fn eq(&self, other: &Hit) -> bool {
+        self.toa == other.toa
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq)]
+pub struct Trigger {
+    pub event: u32,
+    pub time: u64,
+}
+
+impl Ord for Trigger {
+    fn cmp(&self, other: &Trigger) -> Ordering {
+        self.time.cmp(&other.time)
+    }
+}
+
+impl PartialOrd for Trigger {
+    fn partial_cmp(&self, other: &Trigger) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Trigger {
+    fn eq(&self, other: &Trigger) -> bool {
+        self.time == other.time
+    }
+}
@@ -0,0 +1,23 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/decode/src/packets.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use byteorder::{ByteOrder, LittleEndian};
+
+/// Checks whether a packet starts with a header the SPIDR readout produces
+pub fn is_plausible_packet(packet: u64) -> bool {
+    [0x4, 0x6, 0x7, 0xA, 0xB].contains(&(packet >> 60))
+}
+
+/// Splits a block of raw data into its 8 byte packets, returning them along with the bytes left
+/// over at the end that do not make up a whole packet
+pub fn split_packets(bytes: &[u8]) -> (impl Iterator<Item = u64> + '_, &[u8]) {
+    let packets = bytes.chunks_exact(8);
+    let remainder = packets.remainder();
+
+    (packets.map(LittleEndian::read_u64), remainder)
+}
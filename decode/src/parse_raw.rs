@@ -0,0 +1,109 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/decode/src/parse_raw.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use serde::{Deserialize, Serialize};
+
+use crate::{split_packets, AcqMode, ControlPacketCounts, Hit, TdcTimeDecoder, ToaExtender, Trigger, DEFAULT_CLOCK_PHASES};
+
+/// Everything the decoder carries from one block of raw data to the next: the global timestamp
+/// and ToA extension, the trigger coarse counter extension and previous coarse value, the trigger
+/// number overflows, the control packet counts and any partial packet left at the end of the
+/// last block. Serialisable, so decoding can be suspended and resumed elsewhere.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DecoderState {
+    pub clock_phases: u32,
+    #[serde(default)]
+    pub acq_mode: AcqMode,
+    pub toa_extender: ToaExtender,
+    pub trigger_time_decoder: TdcTimeDecoder,
+    pub trigger_overflows: u32,
+    #[serde(default)]
+    pub control_packets: ControlPacketCounts,
+    /// Bytes at the end of the last block that did not make up a whole packet
+    pub remainder: Vec<u8>,
+}
+
+impl DecoderState {
+    pub fn new(clock_phases: u32) -> DecoderState {
+        DecoderState {
+            clock_phases,
+            acq_mode: AcqMode::ToaTot,
+            toa_extender: ToaExtender::new(),
+            trigger_time_decoder: TdcTimeDecoder::new(),
+            trigger_overflows: 0,
+            control_packets: ControlPacketCounts::new(),
+            remainder: Vec::new(),
+        }
+    }
+}
+
+impl Default for DecoderState {
+    fn default() -> DecoderState {
+        DecoderState::new(DEFAULT_CLOCK_PHASES)
+    }
+}
+
+/// Decodes a block of raw packet data (ie. from after the SPIDR header) held in memory, such as a
+/// buffer received over the network. Blocks are handed over in order and can split packets
+/// anywhere, with the decoder state carried between them in `state`.
+pub fn parse_raw_packets_from_slice(bytes: &[u8], state: &mut DecoderState) -> (Vec<Hit>, Vec<Trigger>) {
+    let mut hits = Vec::with_capacity(bytes.len() / 8);
+    let mut triggers = Vec::new();
+
+    // Complete the packet split across the end of the last block
+    let mut bytes = bytes;
+    let mut joined = Vec::new();
+
+    if !state.remainder.is_empty() {
+        let needed = (8 - state.remainder.len()).min(bytes.len());
+
+        joined.append(&mut state.remainder);
+        joined.extend_from_slice(&bytes[..needed]);
+        bytes = &bytes[needed..];
+    }
+
+    let (joined_packets, joined_remainder) = split_packets(&joined);
+    let (packets, remainder) = split_packets(bytes);
+
+    for packet in joined_packets.chain(packets) {
+        let header = packet >> 60;
+
+        if header == 0xA || header == 0xB {
+            hits.push(state.toa_extender.decode_hit_with_mode(packet, state.clock_phases, state.acq_mode));
+        } else if header == 0x4 || header == 0x6 {
+            let subheader = (packet >> 56) & 0xF;
+
+            if subheader == 0xF {
+                // Trigger information
+                let raw_count = ((packet & 0x00FF_F000_0000_0000) >> 44) as u32;
+                let (time, _) = state.trigger_time_decoder.decode(packet);
+
+                triggers.push(Trigger {
+                    event: raw_count + 4096 * state.trigger_overflows,
+                    time: time.as_ns(),
+                });
+
+                if raw_count == 4095 {
+                    state.trigger_overflows += 1;
+                }
+            } else if subheader == 0x4 {
+                // 32 lsb of timestamp
+                state.toa_extender.update_time_lsb(packet);
+            } else if subheader == 0x5 {
+                // 16 msb of timestamp
+                state.toa_extender.update_time_msb(packet);
+            }
+        } else {
+            state.control_packets.count(packet);
+        }
+    }
+
+    state.remainder = if joined_remainder.is_empty() { remainder.to_vec() } else { joined_remainder.to_vec() };
+
+    (hits, triggers)
+}
@@ -0,0 +1,161 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/decode/src/spidr_header.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::cmp;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::AcqMode;
+
+/// Maximum size of the header at the start of each SPIDR data file (bytes)
+pub const SPIDR_MAX_HEADER_SIZE: u32 = 66304;
+
+// The SPIDR DAQ library (Nikhef SpidrDaq) starts each file with its 512 byte `SpidrHeader_t`,
+// followed by the `DevHeader_t` of the Timepix3, whose 32 bit words begin `headerId`,
+// `headerSize`, `format`, `deviceId`, `genConfig`, `outblockConfig`, `pllConfig`, ...
+
+/// Offset of the Timepix3 general configuration word (`genConfig`, the 5th word of the device
+/// header)
+const GENERAL_CONFIG_OFFSET: usize = 512 + 4 * 4;
+
+/// Offset of the Timepix3 PLL configuration word (`pllConfig`, the 7th word of the device header)
+const PLL_CONFIG_OFFSET: usize = 512 + 6 * 4;
+
+/// Number of column clock phases used when the header does not say otherwise
+pub const DEFAULT_CLOCK_PHASES: u32 = 16;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SpidrHeader {
+    pub spidr_id: u32,
+    pub header_size: u32,
+    pub general_config: Option<u32>,
+    pub pll_config: Option<u32>,
+}
+
+impl SpidrHeader {
+    /// Parses the header from the start of a SPIDR data file. Returns `None` while `bytes` does
+    /// not yet hold the whole header, so a file arriving in chunks can be buffered until it does.
+    pub fn parse(bytes: &[u8]) -> Option<SpidrHeader> {
+        if bytes.len() < 8 {
+            return None;
+        }
+
+        // First bytes in file are spidr ID and subsequent header size
+        let spidr_id = LittleEndian::read_u32(&bytes[0..4]);
+        let header_size = cmp::min(LittleEndian::read_u32(&bytes[4..8]), SPIDR_MAX_HEADER_SIZE);
+
+        let header = SpidrHeader {
+            spidr_id,
+            header_size,
+            general_config: None,
+            pll_config: None,
+        };
+
+        if bytes.len() < header.data_offset() {
+            return None;
+        }
+
+        let pll_config = if header_size as usize >= PLL_CONFIG_OFFSET + 4 {
+            Some(LittleEndian::read_u32(&bytes[PLL_CONFIG_OFFSET..PLL_CONFIG_OFFSET + 4]))
+        } else {
+            None
+        };
+
+        let general_config = if header_size as usize >= GENERAL_CONFIG_OFFSET + 4 {
+            Some(LittleEndian::read_u32(&bytes[GENERAL_CONFIG_OFFSET..GENERAL_CONFIG_OFFSET + 4]))
+        } else {
+            None
+        };
+
+        Some(SpidrHeader {
+            general_config,
+            pll_config,
+            ..header
+        })
+    }
+
+    /// Offset of the first packet in the file (bytes)
+    pub fn data_offset(&self) -> usize {
+        8 + self.header_size as usize
+    }
+
+    /// Number of column clock phases configured in the PLL (bits 6 to 8 hold log2 of the phase
+    /// count), if the header contains a valid setting.
+    pub fn clock_phases(&self) -> Option<u32> {
+        match self.pll_config.map(|x| (x >> 6) & 0x7) {
+            Some(n) if n <= 4 => Some(1 << n),
+            _ => None,
+        }
+    }
+
+    /// Acquisition mode set in the general configuration, if the header contains it
+    pub fn acq_mode(&self) -> Option<AcqMode> {
+        self.general_config.and_then(AcqMode::from_general_config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Header of `header_size` bytes with the general and PLL configuration words set
+    fn header_bytes(header_size: u32, general_config: u32, pll_config: u32) -> Vec<u8> {
+        let mut bytes = vec![0; 8 + header_size as usize];
+
+        LittleEndian::write_u32(&mut bytes[0..4], 0x1234);
+        LittleEndian::write_u32(&mut bytes[4..8], header_size);
+        LittleEndian::write_u32(&mut bytes[GENERAL_CONFIG_OFFSET..GENERAL_CONFIG_OFFSET + 4], general_config);
+        LittleEndian::write_u32(&mut bytes[PLL_CONFIG_OFFSET..PLL_CONFIG_OFFSET + 4], pll_config);
+
+        bytes
+    }
+
+    #[test]
+    fn parses_device_configuration() {
+        // ToA only mode, 8 clock phases (log2 in bits 6 to 8)
+        let bytes = header_bytes(1024, 0x1 << 1, 0x3 << 6);
+
+        let header = SpidrHeader::parse(&bytes).unwrap();
+
+        assert_eq!(header.spidr_id, 0x1234);
+        assert_eq!(header.header_size, 1024);
+        assert_eq!(header.data_offset(), 8 + 1024);
+        assert_eq!(header.general_config, Some(0x1 << 1));
+        assert_eq!(header.pll_config, Some(0x3 << 6));
+        assert_eq!(header.clock_phases(), Some(8));
+        assert_eq!(header.acq_mode(), Some(AcqMode::Toa));
+    }
+
+    #[test]
+    fn waits_for_the_whole_header() {
+        let bytes = header_bytes(1024, 0, 0);
+
+        assert!(SpidrHeader::parse(&bytes[..4]).is_none());
+        assert!(SpidrHeader::parse(&bytes[..1000]).is_none());
+        assert!(SpidrHeader::parse(&bytes).is_some());
+    }
+
+    #[test]
+    fn short_header_has_no_device_configuration() {
+        let mut bytes = vec![0; 8 + 512];
+        LittleEndian::write_u32(&mut bytes[4..8], 512);
+
+        let header = SpidrHeader::parse(&bytes).unwrap();
+
+        assert_eq!(header.pll_config, None);
+        assert_eq!(header.clock_phases(), None);
+        assert_eq!(header.acq_mode(), None);
+    }
+
+    #[test]
+    fn invalid_clock_phases_are_ignored() {
+        let header = SpidrHeader::parse(&header_bytes(1024, 0, 0x5 << 6)).unwrap();
+
+        assert_eq!(header.clock_phases(), None);
+    }
+}
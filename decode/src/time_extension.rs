@@ -0,0 +1,338 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/decode/src/time_extension.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use serde::{Deserialize, Serialize};
+
+use crate::{decode_hit_with_mode, AcqMode, Hit};
+
+/// Range of the 30 bit pixel coarse time (16 bit SPIDR timestamp + 14 bit ToA), about 26.8 s
+/// (1.5625 ns units)
+pub const TOA_ROLLOVER_PERIOD: u64 = 1 << 34;
+
+/// Jumps in the global timestamp larger than this are treated as a jump or reset, about 6.7 s
+/// (25 ns units)
+const LONG_TIME_JUMP_THRESHOLD: u64 = 0x1000_0000;
+
+/// Backward steps larger than this at the start of a file are treated as the DAQ having reset
+/// the timestamp between files, about 1.6 ms (1.5625 ns units)
+const FILE_RESET_TOLERANCE: u64 = 0x10_0000;
+
+/// Counters of irregularities corrected while extending ToA values
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct TimeExtensionCounters {
+    /// Rollovers of the 30 bit pixel time that were not covered by global timestamp packets
+    pub rollovers: usize,
+    /// Backward jumps of the SPIDR global timestamp (eg. a timer reset)
+    pub resets: usize,
+    /// Large forward jumps of the SPIDR global timestamp that were suppressed
+    pub forward_jumps: usize,
+    /// Files in a run that started earlier in time than the previous file finished
+    pub file_resets: usize,
+}
+
+/// Irregularity in the SPIDR global timestamp seen while handling a time packet
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GlobalTimeJump {
+    Forward,
+    Reset,
+}
+
+/// Tracks the SPIDR global timestamp and extends pixel ToA values onto a single 64 bit timeline
+/// (1.5625 ns units) that does not roll over or reset.
+///
+/// The global timestamp packets (0x4 and 0x5 subheaders) provide the upper bits of the time.
+/// When they are missing or the timer is reset the 30 bit pixel time would otherwise go
+/// backwards every 26.8 s, so any large backward step is absorbed into an offset instead.
+/// Hits are read out slightly out of order, so small steps back are passed through as they are
+/// and the extended ToA values are only in order once sorted.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ToaExtender {
+    long_time: u64,
+    longtime_lsb: u64,
+    offset: u64,
+    last_toa: u64,
+    /// A file has been started and not yet finished
+    in_file: bool,
+    /// The first hit of the current file, where a reset between files is joined, is still to come
+    file_join_pending: bool,
+    pub counters: TimeExtensionCounters,
+    /// Offset applied at the start of each file (1.5625 ns units)
+    pub file_offsets: Vec<u64>,
+}
+
+impl ToaExtender {
+    pub fn new() -> ToaExtender {
+        ToaExtender::default()
+    }
+
+    /// Current SPIDR global timestamp (25 ns units)
+    pub fn long_time(&self) -> u64 {
+        self.long_time
+    }
+
+    /// Latest extended ToA handed out (1.5625 ns units)
+    pub fn last_toa(&self) -> u64 {
+        self.last_toa
+    }
+
+    /// Marks the start of the next file in a run, so a timestamp reset by the DAQ between files
+    /// is joined onto the end of the previous file at the first hit of the file. The global
+    /// timestamp of the previous file is forgotten, so the time packets of the new file are not
+    /// taken as a reset or jump of the timer.
+    pub fn start_file(&mut self) {
+        self.finish_file();
+
+        self.long_time = 0;
+        self.longtime_lsb = 0;
+        self.in_file = true;
+        self.file_join_pending = true;
+    }
+
+    /// Marks the end of the current file, called after the last file in a run. A file without
+    /// any hits is recorded with no offset.
+    pub fn finish_file(&mut self) {
+        if self.in_file && self.file_join_pending {
+            self.file_offsets.push(0);
+        }
+
+        self.in_file = false;
+        self.file_join_pending = false;
+    }
+
+    /// Handles a global timestamp packet holding the 32 lsb of the time (0x4 subheader)
+    pub fn update_time_lsb(&mut self, packet: u64) {
+        self.longtime_lsb = (packet & 0x0000_FFFF_FFFF_0000) >> 16;
+    }
+
+    /// Handles a global timestamp packet holding the 16 msb of the time (0x5 subheader)
+    pub fn update_time_msb(&mut self, packet: u64) -> Option<GlobalTimeJump> {
+        let longtime_msb = (packet & 0x0000_0000_FFFF_0000) << 16;
+        let tmplongtime = longtime_msb | self.longtime_lsb;
+
+        if tmplongtime > self.long_time + LONG_TIME_JUMP_THRESHOLD && self.long_time > 0 {
+            // Large forward jumps are a known SPIDR glitch in the msb
+            self.long_time = longtime_msb.saturating_sub(LONG_TIME_JUMP_THRESHOLD) | self.longtime_lsb;
+            self.counters.forward_jumps += 1;
+
+            Some(GlobalTimeJump::Forward)
+        } else if tmplongtime + LONG_TIME_JUMP_THRESHOLD < self.long_time {
+            // Timer was reset, carry on from where it was
+            self.offset += (self.long_time - tmplongtime) << 4;
+            self.long_time = tmplongtime;
+            self.counters.resets += 1;
+
+            Some(GlobalTimeJump::Reset)
+        } else {
+            self.long_time = tmplongtime;
+
+            None
+        }
+    }
+
+    /// Extended ToA (1.5625 ns units) of a SPIDR global time (25 ns units) from this point in
+    /// the data, without moving the timeline on
+    pub fn timeline_toa(&self, long_time: u64) -> u64 {
+        (long_time << 4) + self.offset
+    }
+
+    /// Moves an absolute ToA (1.5625 ns units) onto the extended timeline. Steps back of less
+    /// than half a rollover period are left as they are, being hits read out of order.
+    pub fn extend(&mut self, toa: u64) -> u64 {
+        let mut toa = toa + self.offset;
+
+        if self.file_join_pending {
+            let mut join = 0;
+
+            if toa + FILE_RESET_TOLERANCE < self.last_toa {
+                join = self.last_toa - toa;
+
+                self.offset += join;
+                self.counters.file_resets += 1;
+
+                toa = self.last_toa;
+            }
+
+            self.file_offsets.push(join);
+            self.file_join_pending = false;
+        }
+
+        // Hits arrive slightly out of order, so only steps back of over half a period count
+        if toa + TOA_ROLLOVER_PERIOD / 2 < self.last_toa {
+            let periods = (self.last_toa - toa + TOA_ROLLOVER_PERIOD / 2) / TOA_ROLLOVER_PERIOD;
+
+            self.offset += periods * TOA_ROLLOVER_PERIOD;
+            self.counters.rollovers += periods as usize;
+
+            toa += periods * TOA_ROLLOVER_PERIOD;
+        }
+
+        self.last_toa = self.last_toa.max(toa);
+
+        toa
+    }
+
+    /// Decodes a pixel hit packet and extends its ToA
+    pub fn decode_hit(&mut self, packet: u64, clock_phases: u32) -> Hit {
+        self.decode_hit_with_mode(packet, clock_phases, AcqMode::ToaTot)
+    }
+
+    /// Decodes a pixel hit packet recorded in any acquisition mode and extends its ToA
+    pub fn decode_hit_with_mode(&mut self, packet: u64, clock_phases: u32, acq_mode: AcqMode) -> Hit {
+        let hit = decode_hit_with_mode(packet, self.long_time, clock_phases, acq_mode);
+
+        Hit {
+            toa: self.extend(hit.toa),
+            ..hit
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Global timestamp packet holding the 32 lsb of the time (0x4 subheader)
+    fn lsb_packet(lsb: u64) -> u64 {
+        (0x44 << 56) | ((lsb & 0xFFFF_FFFF) << 16)
+    }
+
+    /// Global timestamp packet holding the 16 msb of the time (0x5 subheader)
+    fn msb_packet(msb: u64) -> u64 {
+        (0x45 << 56) | ((msb & 0xFFFF) << 16)
+    }
+
+    /// Sets the global timestamp (25 ns units) from a pair of time packets
+    fn update_time(extender: &mut ToaExtender, long_time: u64) -> Option<GlobalTimeJump> {
+        extender.update_time_lsb(lsb_packet(long_time));
+        extender.update_time_msb(msb_packet(long_time >> 32))
+    }
+
+    #[test]
+    fn time_packets_set_the_global_time() {
+        let mut extender = ToaExtender::new();
+
+        extender.update_time_lsb(lsb_packet(0x1234_5678));
+        assert_eq!(extender.long_time(), 0);
+
+        assert_eq!(extender.update_time_msb(msb_packet(0x9)), None);
+        assert_eq!(extender.long_time(), 0x9_1234_5678);
+        assert_eq!(extender.timeline_toa(extender.long_time()), 0x9_1234_5678 << 4);
+
+        // The lsb only takes effect with the next msb
+        extender.update_time_lsb(lsb_packet(0x1234_6000));
+        assert_eq!(extender.long_time(), 0x9_1234_5678);
+
+        assert_eq!(extender.update_time_msb(msb_packet(0x9)), None);
+        assert_eq!(extender.long_time(), 0x9_1234_6000);
+        assert_eq!(extender.counters, TimeExtensionCounters::default());
+    }
+
+    #[test]
+    fn forward_jumps_of_the_msb_are_suppressed() {
+        let mut extender = ToaExtender::new();
+
+        update_time(&mut extender, 0x1_0000_0100);
+        assert_eq!(update_time(&mut extender, 0x2_0000_0200), Some(GlobalTimeJump::Forward));
+
+        assert_eq!(extender.long_time(), (0x2_0000_0000 - LONG_TIME_JUMP_THRESHOLD) | 0x200);
+        assert_eq!(extender.counters.forward_jumps, 1);
+    }
+
+    #[test]
+    fn timer_reset_is_absorbed_into_the_offset() {
+        let mut extender = ToaExtender::new();
+
+        let before = 0x2_0000_0000;
+        update_time(&mut extender, before);
+        let last = extender.extend(before << 4);
+        assert_eq!(last, before << 4);
+
+        assert_eq!(update_time(&mut extender, 0x100), Some(GlobalTimeJump::Reset));
+        assert_eq!(extender.counters.resets, 1);
+        assert_eq!(extender.long_time(), 0x100);
+
+        // The timeline carries on from where it was
+        assert_eq!(extender.timeline_toa(0x100), last);
+        assert_eq!(extender.extend((0x100 << 4) + 5), last + 5);
+    }
+
+    #[test]
+    fn rollover_without_time_packets_is_extended() {
+        let mut extender = ToaExtender::new();
+
+        assert_eq!(extender.extend(TOA_ROLLOVER_PERIOD - 100), TOA_ROLLOVER_PERIOD - 100);
+        assert_eq!(extender.extend(50), TOA_ROLLOVER_PERIOD + 50);
+        assert_eq!(extender.counters.rollovers, 1);
+
+        // Later hits carry on in the next period
+        assert_eq!(extender.extend(60), TOA_ROLLOVER_PERIOD + 60);
+        assert_eq!(extender.counters.rollovers, 1);
+    }
+
+    #[test]
+    fn small_steps_back_are_passed_through() {
+        let mut extender = ToaExtender::new();
+
+        assert_eq!(extender.extend(1_000), 1_000);
+        assert_eq!(extender.extend(900), 900);
+        assert_eq!(extender.last_toa(), 1_000);
+        assert_eq!(extender.counters, TimeExtensionCounters::default());
+    }
+
+    #[test]
+    fn reset_between_files_is_joined_at_the_first_hit() {
+        let mut extender = ToaExtender::new();
+
+        extender.start_file();
+        update_time(&mut extender, 0x2_0000_0000);
+        let last = extender.extend(0x2_0000_0000 << 4);
+
+        // The DAQ reset the timestamp before the next file
+        extender.start_file();
+        assert_eq!(extender.long_time(), 0);
+
+        assert_eq!(update_time(&mut extender, 0x100), None);
+        assert_eq!(extender.counters.resets, 0);
+
+        assert_eq!(extender.extend(0x100 << 4), last);
+        assert_eq!(extender.extend((0x100 << 4) + 5), last + 5);
+        assert_eq!(extender.counters.file_resets, 1);
+
+        // Recorded when applied, the rest of the file does not change it
+        assert_eq!(extender.file_offsets, vec![0, last - (0x100 << 4)]);
+
+        extender.extend((0x100 << 4) + TOA_ROLLOVER_PERIOD - 10);
+        extender.extend(0x100 << 4);
+        assert_eq!(extender.counters.rollovers, 1);
+
+        extender.finish_file();
+        assert_eq!(extender.file_offsets, vec![0, last - (0x100 << 4)]);
+    }
+
+    #[test]
+    fn every_file_has_an_offset() {
+        let mut extender = ToaExtender::new();
+
+        extender.start_file();
+        extender.extend(1_000);
+
+        // Timer carries on across the files
+        extender.start_file();
+        extender.extend(1_100);
+
+        // No hits at all
+        extender.start_file();
+        extender.finish_file();
+
+        // Finishing again does not add another
+        extender.finish_file();
+
+        assert_eq!(extender.file_offsets, vec![0, 0, 0]);
+        assert_eq!(extender.counters.file_resets, 0);
+    }
+}
@@ -0,0 +1,93 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/decode/src/timestamp_packets.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ToaExtender, TOA_CLOCK_TO_NS};
+
+/// Subheader (of a 0x4 packet) of the first packet of an absolute timestamp pair
+pub const TIMESTAMP_LATCH_SUBHEADER: u64 = 0x8;
+
+/// Subheader (of a 0x4 packet) of the second packet of an absolute timestamp pair
+pub const TIMESTAMP_SECONDS_SUBHEADER: u64 = 0x9;
+
+/// Reference an absolute timestamp came from
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampSource {
+    Gps,
+    WhiteRabbit,
+    Unknown,
+}
+
+impl TimestampSource {
+    fn from_bits(bits: u64) -> TimestampSource {
+        match bits {
+            0x0 => TimestampSource::Gps,
+            0x1 => TimestampSource::WhiteRabbit,
+            _ => TimestampSource::Unknown,
+        }
+    }
+}
+
+/// Absolute time of a pulse-per-second edge, placed on the run's ToA timeline
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub struct AbsoluteTimestamp {
+    pub time: f64,   // ToA timeline (ns)
+    pub utc_ns: i64, // ns since the Unix epoch
+    pub source: TimestampSource,
+}
+
+/// Pairs up the absolute timestamp packets the upgraded DAQ sends on each pulse-per-second edge
+/// from a GPS receiver or White Rabbit node.
+///
+/// The first packet (0x4 header, 0x8 subheader) holds the SPIDR global time the edge was latched
+/// at (25 ns units) in bits 0 to 47 and the source in bits 48 to 51 (0 for GPS, 1 for White
+/// Rabbit). The second (0x4 header, 0x9 subheader) holds the UTC second the edge started in bits
+/// 0 to 47, as seconds since the Unix epoch.
+#[derive(Clone, Debug, Default)]
+pub struct TimestampDecoder {
+    latch: Option<(u64, TimestampSource)>,
+    /// Packets that could not be paired, e.g. a pair split by a dropped packet
+    pub unpaired: usize,
+}
+
+impl TimestampDecoder {
+    pub fn new() -> TimestampDecoder {
+        TimestampDecoder::default()
+    }
+
+    /// Handles the first packet of a pair
+    pub fn latch(&mut self, packet: u64) {
+        if self.latch.is_some() {
+            self.unpaired += 1;
+        }
+
+        self.latch = Some((packet & 0x0000_FFFF_FFFF_FFFF, TimestampSource::from_bits((packet >> 48) & 0xF)));
+    }
+
+    /// Handles the second packet of a pair, giving the timestamp if it completes one. The latched
+    /// global time is moved onto the extended ToA timeline with the offset currently in use.
+    pub fn seconds(&mut self, packet: u64, toa_extender: &ToaExtender) -> Option<AbsoluteTimestamp> {
+        let (latch, source) = match self.latch.take() {
+            Some(latch) => latch,
+            None => {
+                self.unpaired += 1;
+                return None;
+            }
+        };
+
+        let seconds = (packet & 0x0000_FFFF_FFFF_FFFF) as i64;
+
+        Some(AbsoluteTimestamp {
+            time: toa_extender.timeline_toa(latch) as f64 * TOA_CLOCK_TO_NS,
+            utc_ns: seconds * 1_000_000_000,
+            source,
+        })
+    }
+}
@@ -0,0 +1,141 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/quicklook-wasm/src/lib.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use timepix_decode::{decode_hit_records, parse_raw_packets_from_slice, DecoderState, Hit, SpidrHeader, DEFAULT_CLOCK_PHASES, TOA_CLOCK_TO_NS};
+use wasm_bindgen::prelude::*;
+
+/// Hits and trigger times decoded from a chunk of a file, as columns that become typed arrays in
+/// JavaScript
+#[wasm_bindgen]
+#[derive(Debug, Default)]
+pub struct DecodedChunk {
+    col: Vec<u16>,
+    row: Vec<u16>,
+    toa: Vec<f64>, // ns
+    tot: Vec<u32>, // ns
+    trigger_times: Vec<f64>, // ns
+}
+
+impl DecodedChunk {
+    fn push_hit(&mut self, hit: Hit) {
+        self.col.push(hit.col);
+        self.row.push(hit.row);
+        self.toa.push(hit.toa as f64 * TOA_CLOCK_TO_NS);
+        self.tot.push(hit.tot);
+    }
+}
+
+#[wasm_bindgen]
+impl DecodedChunk {
+    #[wasm_bindgen(getter)]
+    pub fn col(&self) -> Vec<u16> {
+        self.col.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn row(&self) -> Vec<u16> {
+        self.row.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn toa(&self) -> Vec<f64> {
+        self.toa.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn tot(&self) -> Vec<u32> {
+        self.tot.clone()
+    }
+
+    #[wasm_bindgen(getter = triggerTimes)]
+    pub fn trigger_times(&self) -> Vec<f64> {
+        self.trigger_times.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn hits(&self) -> usize {
+        self.col.len()
+    }
+}
+
+/// Decodes a raw SPIDR `.dat` file handed over in chunks (eg. slices of an uploaded `File`), which
+/// must be given in order from the start of the file. Chunks can split the header or a packet
+/// anywhere.
+#[wasm_bindgen]
+#[derive(Debug, Default)]
+pub struct RawFileDecoder {
+    header_bytes: Vec<u8>, // Start of the file, until it holds the whole header
+    state: Option<DecoderState>,
+}
+
+#[wasm_bindgen]
+impl RawFileDecoder {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> RawFileDecoder {
+        RawFileDecoder::default()
+    }
+
+    #[wasm_bindgen(js_name = decodeChunk)]
+    pub fn decode_chunk(&mut self, chunk: &[u8]) -> DecodedChunk {
+        let mut decoded = DecodedChunk::default();
+
+        let (hits, triggers) = match &mut self.state {
+            Some(state) => parse_raw_packets_from_slice(chunk, state),
+            None => {
+                self.header_bytes.extend_from_slice(chunk);
+
+                let header = match SpidrHeader::parse(&self.header_bytes) {
+                    Some(header) => header,
+                    None => return decoded,
+                };
+
+                let mut state = DecoderState::new(header.clock_phases().unwrap_or(DEFAULT_CLOCK_PHASES));
+                state.acq_mode = header.acq_mode().unwrap_or_default();
+                let decoded_packets = parse_raw_packets_from_slice(&self.header_bytes[header.data_offset()..], &mut state);
+
+                self.header_bytes = Vec::new();
+                self.state = Some(state);
+
+                decoded_packets
+            }
+        };
+
+        for hit in hits {
+            decoded.push_hit(hit);
+        }
+
+        decoded.trigger_times = triggers.iter().map(|trigger| trigger.time as f64).collect();
+
+        decoded
+    }
+
+    /// Types of the control and heartbeat packets decoded so far (e.g. 0x71), in type order
+    #[wasm_bindgen(js_name = controlPacketTypes)]
+    pub fn control_packet_types(&self) -> Vec<u8> {
+        self.state.iter().flat_map(|state| state.control_packets.iter()).map(|(packet_type, _)| packet_type).collect()
+    }
+
+    /// Number of control and heartbeat packets of each type in `controlPacketTypes`
+    #[wasm_bindgen(js_name = controlPacketCounts)]
+    pub fn control_packet_counts(&self) -> Vec<u32> {
+        self.state.iter().flat_map(|state| state.control_packets.iter()).map(|(_, count)| count as u32).collect()
+    }
+}
+
+/// Decodes a chunk of a `hits.bin` file. Chunks should be a multiple of 16 bytes (one hit), as a
+/// partial hit at the end is dropped.
+#[wasm_bindgen(js_name = decodeHitsChunk)]
+pub fn decode_hits_chunk(chunk: &[u8]) -> DecodedChunk {
+    let mut decoded = DecodedChunk::default();
+
+    for hit in decode_hit_records(chunk) {
+        decoded.push_hit(hit);
+    }
+
+    decoded
+}
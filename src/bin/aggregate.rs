@@ -0,0 +1,374 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * ---------------------------
+ * Timepix Cluster Aggregation
+ * ---------------------------
+ *
+ * timepix-spidr-data-parser/src/bin/aggregate.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap;
+use colored::Colorize;
+use glob::glob;
+use separator::Separatable as _;
+use serde::Serialize;
+
+use timepix_spidr_data_parser::*;
+
+#[derive(Clone, Serialize)]
+struct Settings {
+    cluster_filename: String,
+    max_size: usize,
+    max_tot: f64, // ns
+    tot_bins: usize,
+    max_duration: f64, // ns
+    duration_bins: usize,
+    rate_bins: usize,
+}
+
+/// Live time weighted cluster statistics of a single run
+#[derive(Serialize)]
+struct RunRecord {
+    run: String,
+    live_time: f64,                 // s
+    live_time_source: &'static str, // 'live_time' from the live time tool, or 'duration' of the hits
+    clusters: usize,
+    rate: f64, // Hz
+}
+
+/// Row of a cluster spectrum, the rate is per second of live time over all the runs
+#[derive(Serialize)]
+struct SpectrumBin {
+    min: f64,
+    max: Option<f64>, // Empty for the overflow bin
+    clusters: u64,
+    rate: f64,
+    rate_error: f64,
+}
+
+/// Row of the cluster rate histogram, each run counts with its live time
+#[derive(Serialize)]
+struct RateBin {
+    rate_min: f64,
+    rate_max: f64,
+    runs: usize,
+    live_time: f64, // s
+}
+
+/// Fixed width bins from `min`, with an overflow bin for values past the last
+struct Histogram {
+    min: f64,
+    width: f64,
+    counts: Vec<u64>,
+    overflow: u64,
+}
+
+impl Histogram {
+    fn new(min: f64, max: f64, bins: usize) -> Histogram {
+        Histogram {
+            min,
+            width: (max - min) / bins as f64,
+            counts: vec![0; bins],
+            overflow: 0,
+        }
+    }
+
+    fn fill(&mut self, x: f64) {
+        let i = ((x - self.min) / self.width).floor().max(0.0) as usize;
+
+        match self.counts.get_mut(i) {
+            Some(count) => *count += 1,
+            None => self.overflow += 1,
+        }
+    }
+
+    fn write(&self, path: &Path, live_time: f64) -> io::Result<()> {
+        let mut csv_writer = csv::Writer::from_path(path)?;
+
+        let bin = |min: f64, max: Option<f64>, clusters: u64| SpectrumBin {
+            min,
+            max,
+            clusters,
+            rate: clusters as f64 / live_time,
+            rate_error: (clusters as f64).sqrt() / live_time,
+        };
+
+        for (i, count) in self.counts.iter().enumerate() {
+            let min = self.min + i as f64 * self.width;
+            csv_writer.serialize(bin(min, Some(min + self.width), *count))?;
+        }
+
+        csv_writer.serialize(bin(self.min + self.counts.len() as f64 * self.width, None, self.overflow))?;
+
+        csv_writer.flush()
+    }
+}
+
+fn main() -> io::Result<()> {
+    println!(
+        "\n---------------------------\n{}\n---------------------------\n",
+        "Timepix Cluster Aggregation".bold()
+    );
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(
+            clap::Arg::with_name("INPUT")
+                .help("Sets the input directory pattern")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("OUTPUT")
+                .help("Sets the output directory for the histograms")
+                .required(true)
+                .index(2),
+        )
+        .arg(
+            clap::Arg::with_name("cluster-filename")
+                .help("Sets the cluster filename (without extension!) to aggregate (default is 'clusters')")
+                .long("cluster-filename")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("max-size")
+                .help("Largest cluster size (hits) in the size histogram, larger clusters go in the overflow bin (default is 100)")
+                .long("max-size")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("max-tot")
+                .help("Upper edge of the sum ToT histogram (ns) (default is 100000)")
+                .long("max-tot")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("tot-bins")
+                .help("Number of bins in the sum ToT histogram (default is 200)")
+                .long("tot-bins")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("max-duration")
+                .help("Upper edge of the cluster duration histogram (ns) (default is 1000)")
+                .long("max-duration")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("duration-bins")
+                .help("Number of bins in the cluster duration histogram (default is 64)")
+                .long("duration-bins")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("rate-bins")
+                .help("Number of bins in the cluster rate histogram, spanning 0 to the highest run rate (default is 50)")
+                .long("rate-bins")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_glob_str = matches.value_of("INPUT").unwrap();
+    let output_dir = PathBuf::from(matches.value_of("OUTPUT").unwrap());
+
+    let settings = {
+        let cluster_filename = matches.value_of("cluster-filename").unwrap_or("clusters").to_owned();
+
+        let max_size = matches.value_of("max-size").map_or(100, |x| x.parse::<usize>().unwrap());
+        let max_tot = matches.value_of("max-tot").map_or(100_000.0, |x| x.parse::<f64>().unwrap());
+        let tot_bins = matches.value_of("tot-bins").map_or(200, |x| x.parse::<usize>().unwrap());
+        let max_duration = matches.value_of("max-duration").map_or(1000.0, |x| x.parse::<f64>().unwrap());
+        let duration_bins = matches.value_of("duration-bins").map_or(64, |x| x.parse::<usize>().unwrap());
+        let rate_bins = matches.value_of("rate-bins").map_or(50, |x| x.parse::<usize>().unwrap());
+
+        assert!(max_size > 0 && max_tot > 0.0 && tot_bins > 0 && max_duration > 0.0 && duration_bins > 0 && rate_bins > 0);
+
+        Settings {
+            cluster_filename,
+            max_size,
+            max_tot,
+            tot_bins,
+            max_duration,
+            duration_bins,
+            rate_bins,
+        }
+    };
+
+    //
+    // Parse input file list
+    //
+    let input_dirs: Vec<_> = glob(input_glob_str)
+        .expect("Failed to read input file glob pattern")
+        .filter_map(|x| x.ok()) // Check glob worked
+        .filter(|x| x.is_dir()) // Check is directory
+        .filter(|x| x.join("hits.bin").exists())
+        .filter(|x| x.join(format!("{}.csv", settings.cluster_filename)).exists())
+        .collect();
+
+    if input_dirs.is_empty() {
+        println!("No input directories matched!");
+        return Ok(());
+    }
+
+    println!("Matched {} input directories", input_dirs.len());
+
+    //
+    // Check output files do not already exist
+    //
+    if output_dir.join("cluster_size.csv").exists() {
+        println!(
+            "{}",
+            format!("Given output directory '{}' already holds histograms!", output_dir.display()).red()
+        );
+        return Ok(());
+    }
+
+    fs::create_dir_all(&output_dir)?;
+
+    let mut size_histogram = Histogram::new(1.0, settings.max_size as f64 + 1.0, settings.max_size);
+    let mut tot_histogram = Histogram::new(0.0, settings.max_tot, settings.tot_bins);
+    let mut duration_histogram = Histogram::new(0.0, settings.max_duration, settings.duration_bins);
+
+    let mut runs = Vec::with_capacity(input_dirs.len());
+    let mut input_files = Vec::with_capacity(input_dirs.len());
+
+    for input_dir in &input_dirs {
+        let run = Run::open(input_dir)?;
+
+        let (live_time, live_time_source) = run_live_time(&run)?;
+
+        if live_time <= 0.0 {
+            println!("{}", format!("{} | No live time, skipping", run.name()).yellow());
+            continue;
+        }
+
+        let metadata = run.cluster_metadata(&settings.cluster_filename)?;
+
+        for cluster in &metadata {
+            size_histogram.fill(cluster.hits as f64);
+            tot_histogram.fill(f64::from(cluster.sum_tot));
+            duration_histogram.fill(cluster.duration);
+        }
+
+        println!(
+            "{} | {} Clusters | {:.3} s Live Time ({})",
+            run.name(),
+            metadata.len().separated_string(),
+            live_time,
+            live_time_source
+        );
+
+        runs.push(RunRecord {
+            run: run.name().to_owned(),
+            live_time,
+            live_time_source,
+            clusters: metadata.len(),
+            rate: metadata.len() as f64 / live_time,
+        });
+
+        input_files.push(input_dir.join(format!("{}.csv", settings.cluster_filename)));
+    }
+
+    if runs.is_empty() {
+        println!("{}", "No runs with live time to aggregate!".red());
+        return Ok(());
+    }
+
+    //
+    // Write histograms, spectra are normalised by the total live time so that each run is weighted by its live time
+    //
+    let live_time: f64 = runs.iter().map(|x| x.live_time).sum();
+    let clusters: usize = runs.iter().map(|x| x.clusters).sum();
+
+    size_histogram.write(&output_dir.join("cluster_size.csv"), live_time)?;
+    tot_histogram.write(&output_dir.join("sum_tot.csv"), live_time)?;
+    duration_histogram.write(&output_dir.join("duration.csv"), live_time)?;
+
+    write_rate_histogram(&output_dir.join("cluster_rate.csv"), &runs, settings.rate_bins)?;
+
+    let mut csv_writer = csv::Writer::from_path(output_dir.join("runs.csv"))?;
+
+    for run in &runs {
+        csv_writer.serialize(run)?;
+    }
+
+    csv_writer.flush()?;
+
+    write_settings_toml(&output_dir.join("aggregate.toml"), &settings, &input_files)?;
+
+    println!(
+        "{}",
+        format!(
+            "\nAggregated {} clusters from {} runs ({:.3} s live time, {:.3} Hz) into {}\n",
+            clusters.separated_string(),
+            runs.len(),
+            live_time,
+            clusters as f64 / live_time,
+            output_dir.display()
+        )
+        .bold()
+    );
+
+    Ok(())
+}
+
+/// Live time of a run (s), from the live time tool if it has been run and the first to last hit otherwise
+fn run_live_time(run: &Run) -> io::Result<(f64, &'static str)> {
+    let exposure = run.summary()?.pointer("/live_time/exposure_time").and_then(|x| x.as_u64());
+
+    if let Some(exposure) = exposure {
+        return Ok((exposure as f64 / 1e9, "live_time"));
+    }
+
+    let duration = match run.hits_time_range()? {
+        Some((first_toa, last_toa)) => (last_toa - first_toa) as f64 * TOA_CLOCK_TO_NS / 1e9,
+        None => 0.0,
+    };
+
+    Ok((duration, "duration"))
+}
+
+fn write_rate_histogram(path: &Path, runs: &[RunRecord], bins: usize) -> io::Result<()> {
+    let max_rate = runs.iter().map(|x| x.rate).fold(0.0, f64::max);
+
+    // Keeps the bins from collapsing when every run has no clusters
+    let width = if max_rate > 0.0 { max_rate / bins as f64 } else { 1.0 };
+
+    let mut histogram: Vec<_> = (0..bins)
+        .map(|i| RateBin {
+            rate_min: i as f64 * width,
+            rate_max: (i + 1) as f64 * width,
+            runs: 0,
+            live_time: 0.0,
+        })
+        .collect();
+
+    for run in runs {
+        // The highest rate falls on the upper edge of the last bin
+        let i = ((run.rate / width) as usize).min(bins - 1);
+
+        histogram[i].runs += 1;
+        histogram[i].live_time += run.live_time;
+    }
+
+    let mut csv_writer = csv::Writer::from_path(path)?;
+
+    for bin in &histogram {
+        csv_writer.serialize(bin)?;
+    }
+
+    csv_writer.flush()
+}
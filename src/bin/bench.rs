@@ -0,0 +1,253 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * -------------------------
+ * Timepix Benchmark Harness
+ * -------------------------
+ *
+ * timepix-spidr-data-parser/src/bin/bench.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::env;
+use std::fs;
+use std::io;
+use std::process;
+use std::time::Instant;
+
+use clap;
+use colored::Colorize;
+use indicatif::ProgressBar;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use separator::Separatable as _;
+
+use timepix_spidr_data_parser::*;
+
+/// Standard scenarios, so results can be compared between versions
+#[derive(Clone, Copy, Debug)]
+enum Scenario {
+    Sparse, // Isolated noise hits spread evenly over the matrix
+    Dense,  // High rate of isolated hits, stressing the sorting and clustering windows
+    Tracks, // Particle tracks of tens of neighbouring hits
+}
+
+impl Scenario {
+    const ALL: [Scenario; 3] = [Scenario::Sparse, Scenario::Dense, Scenario::Tracks];
+
+    fn name(self) -> &'static str {
+        match self {
+            Scenario::Sparse => "sparse",
+            Scenario::Dense => "dense",
+            Scenario::Tracks => "tracks",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Scenario> {
+        Scenario::ALL.iter().cloned().find(|x| x.name() == name)
+    }
+}
+
+fn main() -> io::Result<()> {
+    println!(
+        "\n-------------------------\n{}\n-------------------------\n",
+        "Timepix Benchmark Harness".bold()
+    );
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        .arg(
+            clap::Arg::with_name("scenario")
+                .help("Runs a single scenario (sparse, dense or tracks) (default is all)")
+                .long("scenario")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("hits")
+                .help("Number of hits to generate per scenario (default is 1M)")
+                .long("hits")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("seed")
+                .help("Seed for the generated data (default is 0)")
+                .long("seed")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let scenarios = match matches.value_of("scenario") {
+        Some(name) => match Scenario::parse(name) {
+            Some(scenario) => vec![scenario],
+            None => {
+                println!("{}", format!("Unknown scenario '{}'", name).red());
+                process::exit(1);
+            }
+        },
+        None => Scenario::ALL.to_vec(),
+    };
+
+    let n_hits = matches.value_of("hits").and_then(parse_human_readable_number).unwrap_or(1_000_000);
+    let seed = matches.value_of("seed").and_then(|x| x.parse::<u64>().ok()).unwrap_or(0);
+
+    for scenario in scenarios {
+        println!("{} ({} hits)", scenario.name().bold(), n_hits.separated_string());
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let hits = generate_hits(scenario, n_hits, &mut rng);
+
+        bench_scenario(&hits, &mut rng)?;
+
+        println!();
+    }
+
+    Ok(())
+}
+
+/// Generates time ordered hits for a scenario
+fn generate_hits(scenario: Scenario, n_hits: usize, rng: &mut StdRng) -> Vec<Hit> {
+    let mut hits = Vec::with_capacity(n_hits);
+    let mut toa: u64 = 0;
+
+    while hits.len() < n_hits {
+        match scenario {
+            Scenario::Sparse | Scenario::Dense => {
+                // Mean spacing of 10 µs or 50 ns (1.5625 ns units)
+                let mean_gap = if let Scenario::Sparse = scenario { 6_400 } else { 32 };
+
+                toa += rng.gen_range(0, 2 * mean_gap);
+
+                hits.push(Hit {
+                    toa,
+                    tot: rng.gen_range(1, 40) * TOT_ADU_TO_NS,
+                    col: rng.gen_range(0, 256),
+                    row: rng.gen_range(0, 256),
+                    flags: HitFlags::empty(),
+                });
+            }
+            Scenario::Tracks => {
+                toa += rng.gen_range(0, 6_400);
+
+                let length = rng.gen_range(10, 60);
+                let (mut col, mut row) = (rng.gen_range(0.0, 256.0), rng.gen_range(0.0, 256.0));
+                let angle: f64 = rng.gen_range(0.0, std::f64::consts::PI * 2.0);
+
+                for i in 0..length {
+                    if !(0.0..256.0).contains(&col) || !(0.0..256.0).contains(&row) || hits.len() == n_hits {
+                        break;
+                    }
+
+                    hits.push(Hit {
+                        toa: toa + i * rng.gen_range(0, 4),
+                        tot: rng.gen_range(5, 100) * TOT_ADU_TO_NS,
+                        col: col as u16,
+                        row: row as u16,
+                        flags: HitFlags::empty(),
+                    });
+
+                    col += angle.cos();
+                    row += angle.sin();
+                }
+            }
+        }
+    }
+
+    hits.sort();
+    hits
+}
+
+/// Encodes a hit as a raw pixel packet (the inverse of `decode_hit`, with the timestamp
+/// packets left out)
+fn encode_hit(hit: &Hit) -> u64 {
+    let coarse = hit.toa / 16;
+    let ftoa = (16 - hit.toa % 16) & 0xF;
+
+    let dcol = u64::from(hit.col & !1);
+    let spix = u64::from(hit.row & !3);
+    let pix = u64::from(hit.col & 1) * 4 + u64::from(hit.row & 3);
+
+    (0xA << 60)
+        | (dcol << 52)
+        | (spix << 45)
+        | (pix << 44)
+        | ((coarse & 0x3FFF) << 30)
+        | ((u64::from(hit.tot / TOT_ADU_TO_NS) & 0x3FF) << 20)
+        | (ftoa << 16)
+        | ((coarse >> 14) & 0xFFFF)
+}
+
+fn bench_scenario(hits: &[Hit], rng: &mut StdRng) -> io::Result<()> {
+    // Decode
+    let packets: Vec<u64> = hits.iter().map(encode_hit).collect();
+
+    let start = Instant::now();
+    let mut toa_extender = ToaExtender::new();
+    let decoded: Vec<Hit> = packets
+        .iter()
+        .map(|&packet| toa_extender.decode_hit(packet, DEFAULT_CLOCK_PHASES))
+        .collect();
+    print_result("decode", decoded.len(), packets.len() * 8, start);
+
+    // Sort, hits come out of the readout roughly but not exactly in time order
+    let mut unsorted = hits.to_vec();
+    for block in unsorted.chunks_mut(64) {
+        for i in (1..block.len()).rev() {
+            block.swap(i, rng.gen_range(0, i + 1));
+        }
+    }
+
+    let start = Instant::now();
+    unsorted.sort();
+    print_result("sort", unsorted.len(), unsorted.len() * 16, start);
+
+    // Write
+    let file_path = env::temp_dir().join(format!("timepix_bench_{}.bin", process::id()));
+    let mut file = fs::File::create(&file_path)?;
+
+    let start = Instant::now();
+    for chunk in hits.chunks(BUFFER_SIZE) {
+        write_hits_to_file(&mut file, chunk)?;
+    }
+    file.sync_all()?;
+    print_result("write", hits.len(), hits.len() * 16, start);
+
+    fs::remove_file(&file_path)?;
+
+    // Cluster, with the clustering tool's default settings
+    let settings = ClusterSettings {
+        min_cluster_hits: 1,
+        min_cluster_tot: 1,
+        max_pixel_gap: 3,
+        max_toa_gap: (5_000.0 / TOA_CLOCK_TO_NS) as u32,
+        toa_window: (1_000_000.0 / TOA_CLOCK_TO_NS) as u32,
+        max_cluster_hits: None,
+        max_cluster_duration: None,
+        oversize_policy: OversizePolicy::Truncate,
+    };
+
+    let progress_bar = ProgressBar::hidden();
+    let mut hits_iterator = hits.iter().cloned();
+
+    let start = Instant::now();
+    let n_clusters = FindClusterIterator::new("bench", &mut hits_iterator, &progress_bar, &settings).count();
+    print_result("cluster", hits.len(), hits.len() * 16, start);
+
+    println!("  ({} clusters found)", n_clusters.separated_string());
+
+    Ok(())
+}
+
+fn print_result(stage: &str, n_hits: usize, n_bytes: usize, start: Instant) {
+    let seconds = start.elapsed().as_secs_f64();
+
+    println!(
+        "  {:<8} {:>8.3} s {:>10.2} Mhits/s {:>10.1} MB/s",
+        stage,
+        seconds,
+        n_hits as f64 / 1e6 / seconds,
+        n_bytes as f64 / 1e6 / seconds
+    );
+}
@@ -0,0 +1,538 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * -------------------------------
+ * Timepix Cluster Compaction Tool
+ * -------------------------------
+ *
+ * timepix-spidr-data-parser/src/bin/cluster_compaction_tool.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+
+use clap;
+use colored::Colorize;
+use glob::glob;
+use indicatif::ProgressBar;
+use separator::Separatable as _;
+use serde::Serialize;
+
+use timepix_spidr_data_parser::*;
+
+#[derive(Clone, Serialize)]
+struct Settings {
+    input_filename: String,
+    output_filename: String,
+    min_cluster_hits: usize,
+    max_cluster_hits: Option<usize>,
+    min_cluster_tot: u32,
+    exclude_flags: u8,
+    start_time: Option<f64>,
+    end_time: Option<f64>,
+    #[serde(skip)]
+    read_buffer: usize, // bytes
+}
+
+trait HasChild {
+    fn has_child(&self, file_name: &str) -> io::Result<bool>;
+}
+
+impl HasChild for Path {
+    fn has_child(&self, file_name: &str) -> io::Result<bool> {
+        if !self.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::Other, "Not directory"));
+        }
+
+        for entry in self.read_dir()? {
+            if entry?.file_name() == file_name {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+fn main() -> io::Result<()> {
+    println!(
+        "\n-------------------------------\n{}\n-------------------------------\n",
+        "Timepix Cluster Compaction Tool".bold()
+    );
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(
+            clap::Arg::with_name("INPUT")
+                .help("Sets the input directory pattern")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("input-filename")
+                .help("Sets the input filename (without extension!) to use (default is 'clusters')")
+                .long("input-filename")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("output-filename")
+                .help("Sets the output filename (without extension!) to use")
+                .long("output-filename")
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("min-cluster-hits")
+                .help("Minimum cluster size in hits (default is 0)")
+                .long("min-cluster-hits")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("max-cluster-hits")
+                .help("Maximum cluster size in hits (default is no limit)")
+                .long("max-cluster-hits")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("min-cluster-tot")
+                .help("Minimum cluster size in ToT (ns) (default is 0)")
+                .long("min-cluster-tot")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("exclude-flags")
+                .help("Removes clusters with any of the given flag bits set (eg. 1 for truncated clusters, 2 for pile-up events)")
+                .long("exclude-flags")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("start-time")
+                .help("Removes clusters starting before this time (ns)")
+                .long("start-time")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("end-time")
+                .help("Removes clusters starting at or after this time (ns)")
+                .long("end-time")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("manifest")
+                .help("Writes a CSV manifest of the processed runs and their outcomes")
+                .long("manifest")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("dry-run")
+                .help("Shows output of what runs will be processed without changing any data")
+                .long("dry-run"),
+        )
+        .arg(clap::Arg::with_name("disable-mt").help("Disables multi-threading").long("disable-mt"))
+        .arg(
+            clap::Arg::with_name("force")
+                .help("Processes runs even if they are locked by another process")
+                .long("force"),
+        )
+        .arg(
+            clap::Arg::with_name("on-complete")
+                .help("Shell command to run after each run completes, with '{run_dir}' and '{summary}' replaced by the run directory and summary file paths")
+                .long("on-complete")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("sync")
+                .help("Flushes the metadata CSV after every cluster, so it never lags behind the binary file")
+                .long("sync"),
+        )
+        .arg(
+            clap::Arg::with_name("flush-records")
+                .help("Number of clusters written between flushes of the metadata CSV (default is 10k)")
+                .long("flush-records")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("flush-interval")
+                .help("Longest time between flushes of the metadata CSV (s) (default is 5)")
+                .long("flush-interval")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("read-buffer")
+                .help("Size of the blocks input files are read in, larger blocks help on network filesystems (bytes) (default is 1.6M)")
+                .long("read-buffer")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("min-free-space")
+                .help("Free space to keep on the output filesystem, runs pause and then abort below it (GB) (default is 1)")
+                .long("min-free-space")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("low-space-wait")
+                .help("Time to wait for disk space to be freed before aborting a run (s) (default is 600)")
+                .long("low-space-wait")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("job-index")
+                .help("Index of this task in a batch array job (0 to job-count - 1), only its share of the matched runs are processed")
+                .long("job-index")
+                .takes_value(true)
+                .requires("job-count"),
+        )
+        .arg(
+            clap::Arg::with_name("job-count")
+                .help("Number of tasks in a batch array job the matched runs are shared between")
+                .long("job-count")
+                .takes_value(true)
+                .requires("job-index"),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_glob_str = matches.value_of("INPUT").unwrap();
+
+    let settings = {
+        let input_filename = matches.value_of("input-filename").unwrap_or("clusters").to_owned();
+        let output_filename = matches.value_of("output-filename").unwrap().to_owned();
+
+        assert!(input_filename != output_filename);
+
+        let min_cluster_hits = matches.value_of("min-cluster-hits").and_then(parse_human_readable_number).unwrap_or(0);
+        let max_cluster_hits = matches.value_of("max-cluster-hits").and_then(parse_human_readable_number);
+        let min_cluster_tot = matches.value_of("min-cluster-tot").and_then(parse_human_readable_number).unwrap_or(0);
+        let exclude_flags = matches.value_of("exclude-flags").and_then(|x| x.parse::<u8>().ok()).unwrap_or(0);
+
+        let start_time = matches.value_of("start-time").and_then(parse_human_readable_number::<u64>).map(|x| x as f64);
+        let end_time = matches.value_of("end-time").and_then(parse_human_readable_number::<u64>).map(|x| x as f64);
+
+        if let (Some(start), Some(end)) = (start_time, end_time) {
+            assert!(start < end);
+        }
+
+        let read_buffer = matches
+            .value_of("read-buffer")
+            .and_then(parse_human_readable_number::<usize>)
+            .map_or(DEFAULT_READ_BUFFER_SIZE, |x| x.max(16));
+
+        Settings {
+            input_filename,
+            output_filename,
+            min_cluster_hits,
+            max_cluster_hits,
+            min_cluster_tot,
+            exclude_flags,
+            start_time,
+            end_time,
+            read_buffer,
+        }
+    };
+
+    let dry_run = matches.is_present("dry-run");
+    let manifest = matches.value_of("manifest").map(|x| x.to_owned());
+    let run_options = RunOptions {
+        disable_mt: matches.is_present("disable-mt"),
+        force: matches.is_present("force"),
+        on_complete: matches.value_of("on-complete").map(|x| x.to_owned()),
+    };
+    let disk_space = {
+        let defaults = DiskSpaceLimits::default();
+
+        DiskSpaceLimits {
+            min_free: matches.value_of("min-free-space").and_then(|x| x.parse::<f64>().ok()).map_or(defaults.min_free, |x| (x * 1e9) as u64),
+            max_pause: matches
+                .value_of("low-space-wait")
+                .and_then(|x| x.parse::<f64>().ok())
+                .map_or(defaults.max_pause, Duration::from_secs_f64),
+        }
+    };
+    let flush_policy = if matches.is_present("sync") {
+        FlushPolicy::sync()
+    } else {
+        let defaults = FlushPolicy::default();
+
+        FlushPolicy {
+            records: matches.value_of("flush-records").and_then(parse_human_readable_number::<usize>).map_or(defaults.records, |x| x.max(1)),
+            interval: matches
+                .value_of("flush-interval")
+                .and_then(|x| x.parse::<f64>().ok())
+                .map_or(defaults.interval, Duration::from_secs_f64),
+        }
+    };
+    let job_partition = match JobPartition::from_args(matches.value_of("job-index"), matches.value_of("job-count")) {
+        Ok(job_partition) => job_partition,
+        Err(err) => {
+            println!("{}", err.red());
+            process::exit(1);
+        }
+    };
+
+    //
+    // Parse input file list
+    //
+    if job_partition.count > 1 {
+        println!("Array job task {} of {}", job_partition.index, job_partition.count);
+    }
+
+    let matched_dirs: Vec<_> = glob(input_glob_str)
+        .expect("Failed to read input file glob pattern")
+        .filter_map(|x| x.ok()) // Check glob worked
+        .enumerate()
+        .filter(|(i, _)| job_partition.contains(*i)) // Check is in this task's share of an array job
+        .map(|(_, x)| x)
+        .filter(|x| x.is_dir()) // Check is directory
+        .filter(|x| x.has_child(&format!("{}.bin", settings.input_filename)).unwrap())
+        .filter(|x| x.has_child(&format!("{}.csv", settings.input_filename)).unwrap())
+        .collect();
+
+    // Runs with output made with the same settings are skipped, output made with other settings is
+    // replaced (as is the partial output of an interrupted run)
+    let settings_hash = settings_hash(&settings)?;
+
+    let mut input_dirs = Vec::new();
+    let mut replaced_dirs = Vec::new();
+    let mut existing_dirs = Vec::new();
+
+    for dir in matched_dirs {
+        match existing_output(&dir.join(format!("{}.bin", settings.output_filename)), &settings_hash) {
+            ExistingOutput::None => input_dirs.push(dir),
+            ExistingOutput::DifferentSettings => {
+                replaced_dirs.push(dir.clone());
+                input_dirs.push(dir);
+            }
+            ExistingOutput::SameSettings => existing_dirs.push(dir),
+        }
+    }
+
+    if input_dirs.is_empty() && existing_dirs.is_empty() {
+        println!("No input directories matched!");
+        return Ok(());
+    }
+
+    println!("Matched {} input directories", input_dirs.len());
+
+    if dry_run {
+        let mut plan = DryRunPlan::new();
+
+        for input_dir in &input_dirs {
+            let input_bytes = input_dir.join(format!("{}.bin", settings.input_filename)).metadata()?.len();
+            let hits = Run::open(input_dir)?.cluster_hit_count(&settings.input_filename).ok();
+
+            if replaced_dirs.contains(input_dir) {
+                plan.replacing(input_dir, input_bytes, hits);
+            } else {
+                plan.run(input_dir, input_bytes, hits);
+            }
+        }
+
+        for existing_dir in &existing_dirs {
+            plan.skipped_same_settings(existing_dir);
+        }
+
+        plan.print();
+    } else {
+        for existing_dir in &existing_dirs {
+            println!("{}", format!("{} | Skipped (output exists with the same settings)", existing_dir.display()).yellow());
+        }
+
+        for replaced_dir in &replaced_dirs {
+            println!("{}", format!("{} | Replacing output made with different settings", replaced_dir.display()).yellow());
+        }
+
+        if input_dirs.is_empty() {
+            return Ok(());
+        }
+
+        install_interrupt_handler();
+
+        let mut jobs = Vec::new();
+        let mut estimated_size = 0;
+
+        for input_dir in input_dirs {
+            let input_bytes = input_dir.join(format!("{}.bin", settings.input_filename)).metadata()?.len();
+
+            estimated_size += input_bytes; // At most a copy of the input
+
+            jobs.push(RunJob::with_byte_progress(input_dir, (), input_bytes));
+        }
+
+        // Assumes all the runs are on the same filesystem
+        if let Err(err) = check_free_space(&jobs[0].run_dir, estimated_size, &disk_space) {
+            println!("{}", err.to_string().red().bold());
+            process::exit(1);
+        }
+
+        let results = process_runs(jobs, run_options, move |run_dir, _, progress_bar| {
+            process_run(run_dir, settings.clone(), disk_space, flush_policy, progress_bar)
+        });
+
+        if let Some(manifest) = &manifest {
+            write_run_manifest(Path::new(manifest), &results)?;
+        }
+
+        if let Some(exit_code) = report_run_results(&results) {
+            process::exit(exit_code);
+        }
+    }
+
+    Ok(())
+}
+
+fn passes_cuts(metadata: &ClusterMetadata, settings: &Settings) -> bool {
+    if metadata.hits < settings.min_cluster_hits || metadata.sum_tot < settings.min_cluster_tot {
+        return false;
+    }
+
+    if let Some(max) = settings.max_cluster_hits {
+        if metadata.hits > max {
+            return false;
+        }
+    }
+
+    if metadata.flags & settings.exclude_flags != 0 {
+        return false;
+    }
+
+    if let Some(start) = settings.start_time {
+        if metadata.time < start {
+            return false;
+        }
+    }
+
+    if let Some(end) = settings.end_time {
+        if metadata.time >= end {
+            return false;
+        }
+    }
+
+    true
+}
+
+fn process_run(
+    run_dir: &Path,
+    settings: Settings,
+    disk_space: DiskSpaceLimits,
+    flush_policy: FlushPolicy,
+    progress_bar: ProgressBar,
+) -> io::Result<()> {
+    let run_name = run_dir.file_stem().unwrap().to_str().unwrap();
+    let run = Run::open(run_dir)?;
+
+    let input_data_file_path = run_dir.join(format!("{}.bin", settings.input_filename));
+    let input_metadata = run.cluster_metadata(&settings.input_filename)?;
+
+    // Errors for files with signed ToA values, which the writer cannot copy through
+    let cluster_iterator = run.clusters_with_buffer_size(&settings.input_filename, settings.read_buffer)?;
+
+    let output_data_file_path = run_dir.join(format!("{}.bin", settings.output_filename));
+    let output_csv_file_path = run_dir.join(format!("{}.csv", settings.output_filename));
+    let output_toml_file_path = run_dir.join(format!("{}.toml", settings.output_filename));
+
+    let mut output_data_file = fs::File::create(&output_data_file_path)?;
+
+    // Setup CSV file
+    let mut csv_writer = MetadataCsvWriter::create(&output_csv_file_path, flush_policy)?;
+
+    // Write metadata to TOML file
+    write_settings_toml(&output_toml_file_path, &settings, &[&input_data_file_path])?;
+
+    let mut clusters_read = 0;
+    let mut clusters_written = 0;
+    let mut accumulated_file_size = 0;
+    let mut last_time = None;
+    let mut stopped = None;
+    let mut disk_space_monitor = DiskSpaceMonitor::new(run_dir, disk_space);
+
+    progress_bar.set_message(&format!("| 0 Clusters Kept | {}", run_name));
+
+    for (metadata, cluster) in input_metadata.iter().zip(cluster_iterator) {
+        if is_interrupted() {
+            stopped = Some(interrupted_error());
+            break;
+        }
+
+        if let Err(err) = disk_space_monitor.check(&progress_bar) {
+            stopped = Some(err);
+            break;
+        }
+
+        progress_bar.inc(((cluster.len() + 1) * 16) as u64);
+        clusters_read += 1;
+
+        if cluster.len() != metadata.hits {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Cluster {} has {} hits but the metadata lists {}", metadata.event, cluster.len(), metadata.hits),
+            ));
+        }
+
+        if !passes_cuts(metadata, &settings) {
+            continue;
+        }
+
+        // ToA values are copied through untouched (files with ToA values relative to the start of
+        // their window stay relative)
+        write_cluster_to_file(&mut output_data_file, &cluster, 0)?;
+
+        csv_writer.serialize(ClusterMetadata {
+            offset: accumulated_file_size,
+            ..metadata.clone()
+        })?;
+
+        clusters_written += 1;
+        accumulated_file_size += (cluster.len() + 1) * 16;
+        last_time = Some(metadata.time);
+
+        progress_bar.set_message(&format!("| {} Clusters Kept | {}", clusters_written.separated_string(), run_name));
+    }
+
+    csv_writer.flush()?;
+
+    if let Some(err) = stopped {
+        let checkpoint = Checkpoint {
+            written: clusters_written,
+            last_time,
+        };
+        write_partial_marker(&output_data_file_path, &checkpoint)?;
+
+        progress_bar.finish_with_message(&format!("| {} | {} Clusters Kept | {}", err, clusters_written.separated_string(), run_name));
+
+        return Err(err);
+    }
+
+    clear_partial_marker(&output_data_file_path)?;
+
+    if clusters_read != input_metadata.len() {
+        println!(
+            "{}",
+            format!(
+                "WARNING: Read {} clusters from '{}' but the metadata lists {}",
+                clusters_read,
+                input_data_file_path.display(),
+                input_metadata.len()
+            )
+            .yellow()
+        );
+    }
+
+    progress_bar.finish_with_message(&format!(
+        "| Done | {} of {} Clusters Kept | {}",
+        clusters_written.separated_string(),
+        clusters_read.separated_string(),
+        run_name
+    ));
+
+    Ok(())
+}
@@ -0,0 +1,290 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * -----------------------------
+ * Timepix Cluster Hits Exporter
+ * -----------------------------
+ *
+ * timepix-spidr-data-parser/src/bin/cluster_hits_export.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+
+use clap;
+use colored::Colorize;
+use glob::glob;
+use separator::Separatable as _;
+use serde::Serialize;
+
+use timepix_spidr_data_parser::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    Csv,
+    Parquet,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Parquet => "parquet",
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct Settings {
+    input_filename: String,
+    output_filename: String,
+    format: Format,
+}
+
+/// Row of the CSV, a hit with the run and cluster/event it belongs to
+#[derive(Serialize)]
+struct ClusterHitRow<'a> {
+    run_id: &'a str,
+    cluster_id: usize, // Event number of the cluster in the metadata CSV
+    col: u16,
+    row: u16,
+    toa: u64,
+    tot: u32,
+    flags: HitFlags,
+}
+
+enum TableWriter {
+    Csv(csv::Writer<fs::File>),
+    #[cfg(feature = "parquet")]
+    Parquet(ClusterHitsParquetWriter),
+}
+
+impl TableWriter {
+    fn write_cluster(&mut self, run_id: &str, cluster_id: usize, cluster: &[Hit]) -> io::Result<()> {
+        match self {
+            TableWriter::Csv(writer) => {
+                for hit in cluster {
+                    writer.serialize(ClusterHitRow {
+                        run_id,
+                        cluster_id,
+                        col: hit.col,
+                        row: hit.row,
+                        toa: hit.toa,
+                        tot: hit.tot,
+                        flags: hit.flags,
+                    })?;
+                }
+
+                Ok(())
+            }
+            #[cfg(feature = "parquet")]
+            TableWriter::Parquet(writer) => writer.write_cluster(run_id, cluster_id as u64, cluster),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            TableWriter::Csv(mut writer) => writer.flush(),
+            #[cfg(feature = "parquet")]
+            TableWriter::Parquet(writer) => writer.finish(),
+        }
+    }
+}
+
+fn main() -> io::Result<()> {
+    println!(
+        "\n-----------------------------\n{}\n-----------------------------\n",
+        "Timepix Cluster Hits Exporter".bold()
+    );
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(
+            clap::Arg::with_name("INPUT")
+                .help("Sets the input directory pattern")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("input-filename")
+                .help("Sets the cluster/event filename (without extension!) to export (default is 'clusters')")
+                .long("input-filename")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("output-filename")
+                .help("Sets the output filename (without extension!) to use (default is '<input-filename>_hits')")
+                .long("output-filename")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("format")
+                .help("Writes the table as CSV or Parquet (default is csv, parquet needs the 'parquet' feature)")
+                .long("format")
+                .possible_values(&["csv", "parquet"])
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("dry-run")
+                .help("Shows output of what runs will be processed without changing any data")
+                .long("dry-run"),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_glob_str = matches.value_of("INPUT").unwrap();
+
+    let settings = {
+        let input_filename = matches.value_of("input-filename").unwrap_or("clusters").to_owned();
+        let output_filename = matches
+            .value_of("output-filename")
+            .map_or_else(|| format!("{}_hits", input_filename), |x| x.to_owned());
+
+        let format = match matches.value_of("format") {
+            Some("parquet") => Format::Parquet,
+            _ => Format::Csv,
+        };
+
+        if format == Format::Parquet && !cfg!(feature = "parquet") {
+            println!("{}", "--format parquet needs the 'parquet' feature".red());
+            process::exit(1);
+        }
+
+        Settings {
+            input_filename,
+            output_filename,
+            format,
+        }
+    };
+
+    let dry_run = matches.is_present("dry-run");
+
+    //
+    // Parse input file list
+    //
+    let matched_dirs: Vec<_> = glob(input_glob_str)
+        .expect("Failed to read input file glob pattern")
+        .filter_map(|x| x.ok()) // Check glob worked
+        .filter(|x| x.is_dir()) // Check is directory
+        .filter(|x| x.join(format!("{}.bin", settings.input_filename)).exists())
+        .filter(|x| x.join(format!("{}.csv", settings.input_filename)).exists())
+        .collect();
+
+    // Runs with existing output files are skipped
+    let (input_dirs, existing_dirs): (Vec<_>, Vec<_>) = matched_dirs
+        .into_iter()
+        .partition(|x| !x.join(format!("{}.{}", settings.output_filename, settings.format.extension())).exists());
+
+    if input_dirs.is_empty() && (!dry_run || existing_dirs.is_empty()) {
+        println!("No input directories matched!");
+        return Ok(());
+    }
+
+    println!("Matched {} input directories", input_dirs.len());
+
+    if dry_run {
+        let mut plan = DryRunPlan::new();
+
+        for input_dir in &input_dirs {
+            let input_bytes = input_dir.join(format!("{}.bin", settings.input_filename)).metadata()?.len();
+
+            // Upper bound, the file also holds a header for each cluster
+            plan.run(input_dir, input_bytes, Some(input_bytes / 16));
+        }
+
+        for existing_dir in &existing_dirs {
+            plan.skipped(existing_dir);
+        }
+
+        plan.print();
+
+        return Ok(());
+    }
+
+    for input_dir in input_dirs {
+        let run_name = input_dir.file_stem().unwrap().to_str().unwrap();
+
+        let timer = ProcessingTimer::start(&input_dir);
+
+        let hits_written = match process_run(&input_dir, &settings) {
+            Ok(hits_written) => hits_written,
+            Err(e) => {
+                println!("{}", format!("{} | Failed to export hits: {}", run_name, e).red());
+                process::exit(1);
+            }
+        };
+
+        timer.finish(input_dir.join(format!("{}.bin", settings.input_filename)).metadata()?.len())?;
+
+        println!("{} | {} Hits Written", run_name, hits_written.separated_string());
+    }
+
+    Ok(())
+}
+
+fn process_run(run_dir: &Path, settings: &Settings) -> io::Result<usize> {
+    let input_data_file_path = run_dir.join(format!("{}.bin", settings.input_filename));
+    let output_data_file_path = run_dir.join(format!("{}.{}", settings.output_filename, settings.format.extension()));
+    let output_toml_file_path = run_dir.join(format!("{}.toml", settings.output_filename));
+
+    let run = Run::open(run_dir)?;
+    let metadata = run.cluster_metadata(&settings.input_filename)?;
+
+    // Errors for files with signed ToA values, the exported ToA columns being unsigned
+    let mut clusters = run.clusters(&settings.input_filename)?;
+
+    // Write metadata to TOML file
+    write_settings_toml(
+        &output_toml_file_path,
+        settings,
+        &[&input_data_file_path, &run_dir.join(format!("{}.csv", settings.input_filename))],
+    )?;
+
+    let mut writer = match settings.format {
+        Format::Csv => TableWriter::Csv(csv::Writer::from_path(&output_data_file_path)?),
+        #[cfg(feature = "parquet")]
+        Format::Parquet => TableWriter::Parquet(ClusterHitsParquetWriter::create(&output_data_file_path)?),
+        #[cfg(not(feature = "parquet"))]
+        Format::Parquet => unreachable!(),
+    };
+
+    let mut hits_written = 0;
+
+    // Metadata rows are in the same order as the clusters in the binary file
+    for row in &metadata {
+        let cluster = match clusters.next() {
+            Some(cluster) => cluster,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{:#?} has fewer clusters than its metadata CSV", input_data_file_path),
+                ))
+            }
+        };
+
+        // Empty events from trigger extraction have no hits to export
+        if cluster.is_empty() {
+            continue;
+        }
+
+        let run_id = if row.run_id.is_empty() { run.name() } else { &row.run_id };
+
+        writer.write_cluster(run_id, row.event, &cluster)?;
+
+        hits_written += cluster.len();
+    }
+
+    writer.finish()?;
+
+    Ok(hits_written)
+}
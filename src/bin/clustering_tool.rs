@@ -10,37 +10,46 @@
  * Authors: Jared Vann
  */
 
-use std::collections::VecDeque;
-use std::convert::TryFrom;
 use std::fs;
 use std::io;
 use std::path::Path;
+use std::process;
+use std::time::Duration;
 
-use bit_vec::BitVec;
 use clap;
 use colored::Colorize;
 use glob::glob;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use rayon;
+use indicatif::ProgressBar;
 use separator::Separatable as _;
 use serde::Serialize;
-use toml;
 
 use timepix_spidr_data_parser::*;
 
-const HITS_BUFFER_SIZE: usize = 1_000_000;
-
 #[derive(Clone, Serialize)]
 struct Settings {
     output_filename: String,
+    input_clusters: Option<String>,
     max_clusters: Option<usize>,
-    min_cluster_hits: usize,
-    min_cluster_tot: u32,
-    max_pixel_gap: u32,
-    max_toa_gap: u32,
-    min_hit_tot: u32,
-    toa_window: u32,
     relative_toa: bool,
+    uuids: bool,
+    #[serde(flatten)]
+    clustering: ClusterSettings,
+    #[serde(flatten)]
+    filter: HitFilter,
+    flat_field: Option<FlatField>,
+    energy_calibration: Option<EnergyCalibration>,
+    #[serde(skip)]
+    read_buffer: usize, // bytes
+}
+
+impl Settings {
+    /// Name of the file in each run directory the hits are read from
+    fn input_file_name(&self) -> String {
+        match &self.input_clusters {
+            Some(input_clusters) => format!("{}.bin", input_clusters),
+            None => "hits.bin".to_owned(),
+        }
+    }
 }
 
 trait HasChild {
@@ -86,6 +95,12 @@ fn main() -> io::Result<()> {
                 .long("filename")
                 .takes_value(true),
         )
+        .arg(
+            clap::Arg::with_name("input-clusters")
+                .help("Re-clusters the hits of an existing cluster/event file (without extension!, eg. 'clusters' or 'trigger_events') rather than hits.bin")
+                .long("input-clusters")
+                .takes_value(true),
+        )
         .arg(
             clap::Arg::with_name("max-clusters")
                 .help("Maximum number of clusters to find (default is all)")
@@ -122,23 +137,138 @@ fn main() -> io::Result<()> {
                 .long("min-hit-tot")
                 .takes_value(true),
         )
+        .arg(
+            clap::Arg::with_name("roi")
+                .help("Only clusters hits within a region of the matrix, given as 'col_min:col_max,row_min:row_max' (inclusive)")
+                .long("roi")
+                .takes_value(true),
+        )
         .arg(
             clap::Arg::with_name("toa-window")
                 .help("Maximum distance in time to look when clustering (ns)")
                 .long("toa-window")
                 .takes_value(true),
         )
+        .arg(
+            clap::Arg::with_name("max-cluster-hits")
+                .help("Maximum cluster size in hits before the cluster is cut short (default is no limit)")
+                .long("max-cluster-hits")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("max-cluster-duration")
+                .help("Maximum cluster duration in ToA before the cluster is cut short (ns) (default is no limit)")
+                .long("max-cluster-duration")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("oversize-policy")
+                .help("What to do with clusters exceeding the size/duration limits (default is 'truncate')")
+                .long("oversize-policy")
+                .possible_values(&["drop", "truncate"])
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("flat-field")
+                .help("Corrects each hit's ToT with the per-pixel gains of a gain map CSV (from gain_map_tool)")
+                .long("flat-field")
+                .takes_value(true)
+                .conflicts_with("input-clusters"),
+        )
+        .arg(
+            clap::Arg::with_name("energy-calibration")
+                .help("Adds the cluster energy (keV) to the metadata CSV from a per-pixel calibration CSV (col, row, a, b, c, t)")
+                .long("energy-calibration")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("pixel-mask")
+                .help("Removes hits from pixels that are not 'ok' in a pixel status map CSV (from hot_pixel_search)")
+                .long("pixel-mask")
+                .takes_value(true),
+        )
         .arg(
             clap::Arg::with_name("relative-toa")
                 .help("Set ToA values relative to the start of acquisition window (default is false)")
                 .long("relative-toa"),
         )
+        .arg(
+            clap::Arg::with_name("uuids")
+                .help("Identify clusters with random UUIDs rather than the run ID and event number")
+                .long("uuids"),
+        )
+        .arg(
+            clap::Arg::with_name("manifest")
+                .help("Writes a CSV manifest of the processed runs and their outcomes")
+                .long("manifest")
+                .takes_value(true),
+        )
         .arg(
             clap::Arg::with_name("dry-run")
                 .help("Shows output of what runs will be processed without changing any data")
                 .long("dry-run"),
         )
         .arg(clap::Arg::with_name("disable-mt").help("Disables multi-threading").long("disable-mt"))
+        .arg(
+            clap::Arg::with_name("force")
+                .help("Processes runs even if they are locked by another process")
+                .long("force"),
+        )
+        .arg(
+            clap::Arg::with_name("on-complete")
+                .help("Shell command to run after each run completes, with '{run_dir}' and '{summary}' replaced by the run directory and summary file paths")
+                .long("on-complete")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("sync")
+                .help("Flushes the metadata CSV after every event, so it never lags behind the binary file")
+                .long("sync"),
+        )
+        .arg(
+            clap::Arg::with_name("flush-records")
+                .help("Number of events written between flushes of the metadata CSV (default is 10k)")
+                .long("flush-records")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("flush-interval")
+                .help("Longest time between flushes of the metadata CSV (s) (default is 5)")
+                .long("flush-interval")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("read-buffer")
+                .help("Size of the blocks input files are read in, larger blocks help on network filesystems (bytes) (default is 1.6M)")
+                .long("read-buffer")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("min-free-space")
+                .help("Free space to keep on the output filesystem, runs pause and then abort below it (GB) (default is 1)")
+                .long("min-free-space")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("low-space-wait")
+                .help("Time to wait for disk space to be freed before aborting a run (s) (default is 600)")
+                .long("low-space-wait")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("job-index")
+                .help("Index of this task in a batch array job (0 to job-count - 1), only its share of the matched runs are processed")
+                .long("job-index")
+                .takes_value(true)
+                .requires("job-count"),
+        )
+        .arg(
+            clap::Arg::with_name("job-count")
+                .help("Number of tasks in a batch array job the matched runs are shared between")
+                .long("job-count")
+                .takes_value(true)
+                .requires("job-index"),
+        )
         .get_matches();
 
     //
@@ -148,6 +278,12 @@ fn main() -> io::Result<()> {
 
     let settings = {
         let output_filename = matches.value_of("filename").unwrap_or("clusters").to_owned();
+        let input_clusters = matches.value_of("input-clusters").map(|x| x.to_owned());
+
+        if input_clusters.as_ref() == Some(&output_filename) {
+            println!("{}", format!("Output filename '{}' is the same as the input cluster file", output_filename).red());
+            process::exit(1);
+        }
 
         let max_clusters = matches.value_of("max-clusters").and_then(parse_human_readable_number);
         let min_cluster_hits = matches.value_of("min-cluster-hits").and_then(parse_human_readable_number).unwrap_or(1); // 1 hit
@@ -159,39 +295,164 @@ fn main() -> io::Result<()> {
         let max_pixel_gap = matches.value_of("max-pixel-gap").and_then(parse_human_readable_number).unwrap_or(3);
         let max_toa_gap = (matches.value_of("max-toa-gap").and_then(parse_human_readable_number).unwrap_or(5_000) as f64 / TOA_CLOCK_TO_NS) as u32; // 5µs
         let min_hit_tot = matches.value_of("min-hit-tot").and_then(parse_human_readable_number).unwrap_or(0); // 0 ns
+        let roi = matches.value_of("roi").map(|x| Roi::parse(x).expect("Invalid region of interest"));
         let toa_window = (matches.value_of("toa-window").and_then(parse_human_readable_number).unwrap_or(1_000_000) as f64 / TOA_CLOCK_TO_NS) as u32; // 500µs
 
         let relative_toa = matches.is_present("relative-toa");
+        let uuids = matches.is_present("uuids");
+
+        let max_cluster_hits = matches.value_of("max-cluster-hits").and_then(parse_human_readable_number);
+        let max_cluster_duration = matches
+            .value_of("max-cluster-duration")
+            .and_then(parse_human_readable_number::<u32>)
+            .map(|x| (x as f64 / TOA_CLOCK_TO_NS) as u32);
+
+        if let Some(max) = max_cluster_hits {
+            if max < min_cluster_hits {
+                println!("{}", format!("Maximum cluster hits ({}) is less than the minimum ({})", max, min_cluster_hits).red());
+                process::exit(1);
+            }
+        }
+
+        let flat_field = match matches.value_of("flat-field") {
+            Some(file) => Some(FlatField::from_gain_map(Path::new(file))?),
+            None => None,
+        };
+
+        let energy_calibration = match matches.value_of("energy-calibration") {
+            Some(file) => Some(EnergyCalibration::from_csv(Path::new(file))?),
+            None => None,
+        };
+
+        let pixel_mask = match matches.value_of("pixel-mask") {
+            Some(file) => Some(PixelMask::from_csv(Path::new(file))?),
+            None => None,
+        };
+
+        let oversize_policy = match matches.value_of("oversize-policy") {
+            Some("drop") => OversizePolicy::Drop,
+            _ => OversizePolicy::Truncate,
+        };
+
+        let read_buffer = matches
+            .value_of("read-buffer")
+            .and_then(parse_human_readable_number::<usize>)
+            .map_or(DEFAULT_READ_BUFFER_SIZE, |x| x.max(16));
 
         Settings {
             output_filename,
+            input_clusters,
             max_clusters,
-            min_cluster_hits,
-            min_cluster_tot,
-            max_pixel_gap,
-            max_toa_gap,
-            min_hit_tot,
-            toa_window,
             relative_toa,
+            uuids,
+            clustering: ClusterSettings {
+                min_cluster_hits,
+                min_cluster_tot,
+                max_pixel_gap,
+                max_toa_gap,
+                toa_window,
+                max_cluster_hits,
+                max_cluster_duration,
+                oversize_policy,
+            },
+            filter: HitFilter {
+                min_hit_tot,
+                roi,
+                pixel_mask,
+            },
+            flat_field,
+            energy_calibration,
+            read_buffer,
         }
     };
 
     let dry_run = matches.is_present("dry-run");
-    let disable_mt = matches.is_present("disable-mt");
+    let manifest = matches.value_of("manifest").map(|x| x.to_owned());
+    let run_options = RunOptions {
+        disable_mt: matches.is_present("disable-mt"),
+        force: matches.is_present("force"),
+        on_complete: matches.value_of("on-complete").map(|x| x.to_owned()),
+    };
+    let disk_space = {
+        let defaults = DiskSpaceLimits::default();
+
+        DiskSpaceLimits {
+            min_free: matches.value_of("min-free-space").and_then(|x| x.parse::<f64>().ok()).map_or(defaults.min_free, |x| (x * 1e9) as u64),
+            max_pause: matches
+                .value_of("low-space-wait")
+                .and_then(|x| x.parse::<f64>().ok())
+                .map_or(defaults.max_pause, Duration::from_secs_f64),
+        }
+    };
+    let flush_policy = if matches.is_present("sync") {
+        FlushPolicy::sync()
+    } else {
+        let defaults = FlushPolicy::default();
+
+        FlushPolicy {
+            records: matches.value_of("flush-records").and_then(parse_human_readable_number::<usize>).map_or(defaults.records, |x| x.max(1)),
+            interval: matches
+                .value_of("flush-interval")
+                .and_then(|x| x.parse::<f64>().ok())
+                .map_or(defaults.interval, Duration::from_secs_f64),
+        }
+    };
+    let job_partition = match JobPartition::from_args(matches.value_of("job-index"), matches.value_of("job-count")) {
+        Ok(job_partition) => job_partition,
+        Err(err) => {
+            println!("{}", err.red());
+            process::exit(1);
+        }
+    };
 
     //
     // Parse input file list
     //
-    let input_dirs: Vec<_> = glob(input_glob_str)
+    if job_partition.count > 1 {
+        println!("Array job task {} of {}", job_partition.index, job_partition.count);
+    }
+
+    let matched_dirs: Vec<_> = glob(input_glob_str)
         .expect("Failed to read input file glob pattern")
         .filter_map(|x| x.ok()) // Check glob worked
+        .enumerate()
+        .filter(|(i, _)| job_partition.contains(*i)) // Check is in this task's share of an array job
+        .map(|(_, x)| x)
         .filter(|x| x.is_dir()) // Check is directory
-        .filter(|x| x.has_child("hits.bin").unwrap())
-        // Check doesnt have existing output files
-        .filter(|x| !x.has_child(&format!("{}.bin", settings.output_filename)).unwrap())
+        .filter(|x| x.has_child(&settings.input_file_name()).unwrap())
+        // Check input ToA values have not been rebased
+        .filter(|x| match &settings.input_clusters {
+            Some(input_clusters) if has_relative_toa(x, input_clusters) => {
+                println!(
+                    "{}",
+                    format!("Skipping '{}' as its input file was written with relative ToA values", x.display()).yellow()
+                );
+                false
+            }
+            _ => true,
+        })
         .collect();
 
-    if input_dirs.is_empty() {
+    // Runs with output made with the same settings are skipped, output made with other settings is
+    // replaced (as is the partial output of an interrupted run)
+    let settings_hash = settings_hash(&settings)?;
+
+    let mut input_dirs = Vec::new();
+    let mut replaced_dirs = Vec::new();
+    let mut existing_dirs = Vec::new();
+
+    for dir in matched_dirs {
+        match existing_output(&dir.join(format!("{}.bin", settings.output_filename)), &settings_hash) {
+            ExistingOutput::None => input_dirs.push(dir),
+            ExistingOutput::DifferentSettings => {
+                replaced_dirs.push(dir.clone());
+                input_dirs.push(dir);
+            }
+            ExistingOutput::SameSettings => existing_dirs.push(dir),
+        }
+    }
+
+    if input_dirs.is_empty() && existing_dirs.is_empty() {
         println!("No input directories matched!");
         return Ok(());
     }
@@ -199,44 +460,116 @@ fn main() -> io::Result<()> {
     println!("Matched {} input directories", input_dirs.len());
 
     if dry_run {
-        println!("");
+        let mut plan = DryRunPlan::new();
 
-        for input_dir in input_dirs {
-            println!("{}", input_dir.to_str().unwrap());
+        for input_dir in &input_dirs {
+            let input_bytes = input_dir.join(settings.input_file_name()).metadata()?.len();
+            let hits = match &settings.input_clusters {
+                Some(input_clusters) => Run::open(input_dir)?.cluster_hit_count(input_clusters).ok(),
+                None => Run::open(input_dir)?.hit_count().ok(),
+            };
+
+            if replaced_dirs.contains(input_dir) {
+                plan.replacing(input_dir, input_bytes, hits);
+            } else {
+                plan.run(input_dir, input_bytes, hits);
+            }
+        }
+
+        for existing_dir in &existing_dirs {
+            plan.skipped_same_settings(existing_dir);
         }
+
+        plan.print();
     } else {
-        let multi_progress = MultiProgress::new();
-        let sty = ProgressStyle::default_bar()
-            .template(PROGRESS_BAR_TEMPLATE)
-            .progress_chars(PROGRESS_BAR_CHARS);
+        for existing_dir in &existing_dirs {
+            println!("{}", format!("{} | Skipped (output exists with the same settings)", existing_dir.display()).yellow());
+        }
+
+        for replaced_dir in &replaced_dirs {
+            println!("{}", format!("{} | Replacing output made with different settings", replaced_dir.display()).yellow());
+        }
+
+        if input_dirs.is_empty() {
+            return Ok(());
+        }
+
+        install_interrupt_handler();
 
-        let disable_mt = disable_mt || input_dirs.len() == 1;
+        let mut jobs = Vec::new();
+        let mut estimated_size = 0;
 
         for input_dir in input_dirs {
-            let n_hits = input_dir.join("hits.bin").metadata().unwrap().len() / 16;
+            let input_bytes = input_dir.join(settings.input_file_name()).metadata().unwrap().len();
 
-            let progress_bar = ProgressBar::new(n_hits);
-            progress_bar.set_style(sty.clone());
+            estimated_size += input_bytes * 2; // Every hit plus at worst a header per hit
 
-            if disable_mt {
-                process_run(&input_dir, settings.clone(), progress_bar).unwrap();
-            } else {
-                let progress_bar = multi_progress.add(progress_bar);
-                let s = settings.clone();
-                rayon::spawn(move || process_run(&input_dir, s, progress_bar).unwrap());
-            }
+            jobs.push(RunJob::new(input_dir, (), input_bytes, input_bytes / 16));
+        }
+
+        // Assumes all the runs are on the same filesystem
+        if let Err(err) = check_free_space(&jobs[0].run_dir, estimated_size, &disk_space) {
+            println!("{}", err.to_string().red().bold());
+            process::exit(1);
         }
 
-        multi_progress.join().unwrap();
+        let results = process_runs(jobs, run_options, move |run_dir, _, progress_bar| {
+            process_run(run_dir, settings.clone(), disk_space, flush_policy, progress_bar)
+        });
+
+        if let Some(manifest) = &manifest {
+            write_run_manifest(Path::new(manifest), &results)?;
+        }
+
+        if let Some(exit_code) = report_run_results(&results) {
+            process::exit(exit_code);
+        }
     }
 
     Ok(())
 }
 
-fn process_run(run_dir: &Path, settings: Settings, progress_bar: ProgressBar) -> io::Result<()> {
+/// Checks the settings TOML written alongside a cluster/event file for rebased ToA values, which
+/// cannot be flattened back onto a single timeline.
+fn has_relative_toa(run_dir: &Path, filename: &str) -> bool {
+    match Run::open(run_dir).and_then(|run| run.settings(filename)) {
+        Ok(Some(value)) => value.get("relative_toa").and_then(|x| x.as_bool()).unwrap_or(false),
+        _ => false,
+    }
+}
+
+fn process_run(
+    run_dir: &Path,
+    settings: Settings,
+    disk_space: DiskSpaceLimits,
+    flush_policy: FlushPolicy,
+    progress_bar: ProgressBar,
+) -> io::Result<()> {
     let run_name = run_dir.file_stem().unwrap().to_str().unwrap();
 
-    let mut hits_iterator = ReadHitsIterator::new(&run_dir.join("hits.bin"));
+    let input_data_file_path = run_dir.join(settings.input_file_name());
+
+    let hits: Box<dyn Iterator<Item = Hit>> = match &settings.input_clusters {
+        Some(input_clusters) => {
+            let run = Run::open(run_dir)?;
+
+            // The `time` column holds the earliest ToA of each cluster/event (its first hit or
+            // window start)
+            let starts: Vec<u64> = run.cluster_metadata(input_clusters)?.iter().map(|x| (x.time / TOA_CLOCK_TO_NS) as u64).collect();
+
+            // Errors for files with signed ToA values, as the hits are clustered on unsigned ToA values
+            let clusters = run.clusters_with_buffer_size(input_clusters, settings.read_buffer)?;
+
+            Box::new(FlattenClustersIterator::new(clusters, &starts))
+        }
+        None => Box::new(ReadHitsIterator::with_buffer_size(&input_data_file_path, settings.read_buffer)),
+    };
+
+    // Hit cuts are applied to the corrected ToT
+    let mut hits_iterator = settings.filter.apply(hits.map(|hit| match &settings.flat_field {
+        Some(flat_field) => flat_field.correct(hit),
+        None => hit,
+    }));
 
     let output_data_file_path = run_dir.join(format!("{}{}", settings.output_filename, ".bin"));
     let output_csv_file_path = run_dir.join(format!("{}{}", settings.output_filename, ".csv"));
@@ -245,30 +578,33 @@ fn process_run(run_dir: &Path, settings: Settings, progress_bar: ProgressBar) ->
     let mut output_data_file = fs::File::create(&output_data_file_path)?;
 
     // Setup CSV file
-    let output_csv_file = fs::File::create(&output_csv_file_path)?;
-    let mut csv_writer = csv::Writer::from_writer(output_csv_file);
+    let mut csv_writer = MetadataCsvWriter::create(&output_csv_file_path, flush_policy)?;
 
     // Write metadata to TOML file
-    fs::write(&output_toml_file_path, toml::to_string(&settings).unwrap())?;
+    write_settings_toml(&output_toml_file_path, &settings, &[&input_data_file_path])?;
 
     let mut clusters_written = 0;
     let mut accumulated_file_size = 0;
 
-    let find_cluster_iterator = FindClusterIterator::new(
-        &run_name,
-        &mut hits_iterator,
-        &progress_bar,
-        settings.min_cluster_hits as u32,
-        settings.min_cluster_tot,
-        settings.min_hit_tot,
-        settings.max_pixel_gap,
-        settings.max_toa_gap,
-        settings.toa_window,
-    );
+    let find_cluster_iterator = FindClusterIterator::new(&run_name, &mut hits_iterator, &progress_bar, &settings.clustering);
 
     progress_bar.set_message(&format!("| 0 Clusters Found | {}", run_name));
 
-    for cluster in find_cluster_iterator {
+    let mut last_time = None;
+    let mut stopped = None;
+    let mut disk_space_monitor = DiskSpaceMonitor::new(run_dir, disk_space);
+
+    for (cluster, flags) in find_cluster_iterator {
+        if is_interrupted() {
+            stopped = Some(interrupted_error());
+            break;
+        }
+
+        if let Err(err) = disk_space_monitor.check(&progress_bar) {
+            stopped = Some(err);
+            break;
+        }
+
         let start_time = cluster[0].toa;
         let end_time = cluster[cluster.len() - 1].toa;
 
@@ -277,18 +613,36 @@ fn process_run(run_dir: &Path, settings: Settings, progress_bar: ProgressBar) ->
         write_cluster_to_file(&mut output_data_file, &cluster, toa_adjustment)?;
         clusters_written += 1;
 
-        csv_writer.serialize(ClusterMetadata {
+        let metadata = ClusterMetadata {
             event: clusters_written,
             time: start_time as f64 * TOA_CLOCK_TO_NS,
             duration: (end_time - start_time) as f64 * TOA_CLOCK_TO_NS,
             hits: cluster.len(),
             sum_tot: cluster.iter().map(|hit| hit.tot).sum(),
             offset: accumulated_file_size,
-        })?;
+            flags,
+            time_islands: 0,
+            run_id: run_name.to_owned(),
+            uid: make_cluster_uid(run_name, clusters_written, settings.uuids),
+            external_id: None,
+            min_col: None,
+            max_col: None,
+            min_row: None,
+            max_row: None,
+            centroid_col: None,
+            centroid_row: None,
+            trigger_time: None,
+            window_start: None,
+            window_end: None,
+            hit_fraction: None,
+            energy: settings.energy_calibration.as_ref().and_then(|x| x.cluster_energy(&cluster)),
+        }
+        .with_position(&cluster);
 
-        csv_writer.flush()?;
+        csv_writer.serialize(metadata)?;
 
         accumulated_file_size += (cluster.len() + 1) * 16;
+        last_time = Some(start_time as f64 * TOA_CLOCK_TO_NS);
 
         if let Some(max) = settings.max_clusters {
             if clusters_written == max {
@@ -297,169 +651,23 @@ fn process_run(run_dir: &Path, settings: Settings, progress_bar: ProgressBar) ->
         }
     }
 
-    progress_bar.finish_with_message(&format!("| Done | {} Clusters Found | {}", clusters_written.separated_string(), run_name));
-
-    Ok(())
-}
-
-pub struct FindClusterIterator<'a> {
-    run_name: &'a str,
-    hits_iterator: &'a mut ReadHitsIterator,
-    hits_buffer: VecDeque<Hit>,
-    hits_processed: VecDeque<bool>,
+    csv_writer.flush()?;
 
-    progress_bar: &'a ProgressBar,
+    if let Some(err) = stopped {
+        let checkpoint = Checkpoint {
+            written: clusters_written,
+            last_time,
+        };
+        write_partial_marker(&output_data_file_path, &checkpoint)?;
 
-    min_cluster_hits: u32,
-    min_hit_tot: u32,
-    min_sum_tot: u32,
-    max_pixel_gap: u32,
-    max_toa_gap: u32,
-    toa_window: u32,
+        progress_bar.finish_with_message(&format!("| {} | {} Clusters Found | {}", err, clusters_written.separated_string(), run_name));
 
-    n_clusters: usize,
-    total_hits_processed: usize,
-}
-
-impl<'a> FindClusterIterator<'a> {
-    pub fn new(
-        run_name: &'a str,
-        hits_iterator: &'a mut ReadHitsIterator,
-        progress_bar: &'a ProgressBar,
-        min_cluster_hits: u32,
-        min_sum_tot: u32,
-        min_hit_tot: u32,
-        max_pixel_gap: u32,
-        max_toa_gap: u32,
-        toa_window: u32,
-    ) -> FindClusterIterator<'a> {
-        let mut hits_buffer = VecDeque::with_capacity(HITS_BUFFER_SIZE);
-        let mut hits_processed = VecDeque::with_capacity(HITS_BUFFER_SIZE);
-
-        for _ in 0..HITS_BUFFER_SIZE {
-            if let Some(hit) = hits_iterator.next() {
-                hits_buffer.push_back(hit);
-                hits_processed.push_back(false);
-            }
-        }
-
-        FindClusterIterator {
-            run_name,
-            hits_iterator,
-            hits_buffer,
-            hits_processed,
-            progress_bar,
-            min_cluster_hits,
-            min_sum_tot,
-            min_hit_tot,
-            max_pixel_gap,
-            max_toa_gap,
-            toa_window,
-            n_clusters: 0,
-            total_hits_processed: 0,
-        }
+        return Err(err);
     }
-}
-
-impl<'a> Iterator for FindClusterIterator<'a> {
-    type Item = Vec<Hit>;
-
-    fn next(&mut self) -> Option<Vec<Hit>> {
-        // Iterate over all hits
-        while !self.hits_buffer.is_empty() {
-            if self.total_hits_processed % 1_000 == 0 {
-                self.progress_bar.set_position(u64::try_from(self.total_hits_processed).unwrap());
-            }
-
-            let current_hit = self.hits_buffer.pop_front().unwrap();
-            let is_processed = self.hits_processed.pop_front().unwrap();
-
-            while let Some(hit) = self.hits_iterator.next() {
-                if hit.tot > self.min_hit_tot {
-                    self.hits_buffer.push_back(hit);
-                    self.hits_processed.push_back(false);
-                    break;
-                }
-            }
-
-            if self.hits_buffer.is_empty() {
-                return None;
-            }
-
-            // Skip if this hit has already been processed
-            if is_processed {
-                continue;
-            }
-
-            let start_toa = current_hit.toa;
-
-            let mut cluster = Vec::with_capacity(self.min_cluster_hits as usize);
-
-            let mut hits_stack = VecDeque::<usize>::with_capacity(1000);
-            let mut is_in_stack = BitVec::from_elem(self.hits_buffer.len(), false);
-
-            // Add initial hit to the stack
-            hits_stack.push_back(0);
 
-            // Build a stack of neighbouring hits and iterate until all hits in
-            // the stack have been processed.
-            while !hits_stack.is_empty() {
-                let j = hits_stack.pop_front().unwrap();
-                is_in_stack.set(j, false);
+    clear_partial_marker(&output_data_file_path)?;
 
-                let hit1 = self.hits_buffer[j];
-
-                cluster.push(hit1);
-
-                self.hits_processed[j] = true;
-                self.total_hits_processed += 1;
-
-                // Iterate over all hits (need to start from i and not j to allow
-                // algorithm to look back in time for complex geometries)
-                for k in 0..self.hits_buffer.len() {
-                    if self.hits_processed[k] || is_in_stack[k] {
-                        continue;
-                    }
-
-                    let hit2 = self.hits_buffer[k];
-
-                    // Check hit is within overall time window (ie. 400us drift)
-                    if (hit2.toa - start_toa) > u64::from(self.toa_window) {
-                        break;
-                    }
-
-                    let toa_diff = (i64::try_from(hit2.toa).unwrap() - i64::try_from(hit1.toa).unwrap()).abs();
-                    let col_diff = (i64::from(hit1.col) - i64::from(hit2.col)).abs();
-                    let row_diff = (i64::from(hit1.row) - i64::from(hit2.row)).abs();
-
-                    // Check hit is spatially nearby
-                    if toa_diff < i64::from(self.max_toa_gap) && col_diff as u32 <= self.max_pixel_gap && row_diff as u32 <= self.max_pixel_gap {
-                        hits_stack.push_back(k);
-                        is_in_stack.set(k, true);
-                    }
-                }
-            }
-
-            if cluster.len() < self.min_cluster_hits as usize {
-                continue;
-            }
-
-            let sum_tot: u32 = cluster.iter().map(|hit| hit.tot).sum();
-
-            if sum_tot < self.min_sum_tot {
-                continue;
-            }
-
-            cluster.sort();
-
-            self.n_clusters += 1;
-
-            self.progress_bar
-                .set_message(&format!("| {} Clusters Found | {}", self.n_clusters.separated_string(), self.run_name));
-
-            return Some(cluster);
-        }
+    progress_bar.finish_with_message(&format!("| Done | {} Clusters Found | {}", clusters_written.separated_string(), run_name));
 
-        None
-    }
+    Ok(())
 }
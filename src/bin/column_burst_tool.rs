@@ -0,0 +1,280 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * -------------------------
+ * Timepix Column Burst Tool
+ * -------------------------
+ *
+ * timepix-spidr-data-parser/src/bin/column_burst_tool.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use clap;
+use colored::Colorize;
+use glob::glob;
+use indicatif::{ProgressBar, ProgressStyle};
+use separator::Separatable as _;
+use serde::Serialize;
+
+use timepix_spidr_data_parser::*;
+
+#[derive(Clone, Serialize)]
+struct Settings {
+    slice_width: f64,
+    threshold: f64,
+    min_hits: u32,
+    masked_filename: Option<String>,
+}
+
+/// Summary of the bursts found, written to the run's `summary.json`
+#[derive(Serialize)]
+struct ColumnBurstSummary {
+    slices: usize,
+    bursts: usize,
+    burst_hits: u64,
+    noisy_columns: Vec<u16>,
+    hits_masked: Option<usize>,
+}
+
+fn main() -> io::Result<()> {
+    println!(
+        "\n-------------------------\n{}\n-------------------------\n",
+        "Timepix Column Burst Tool".bold()
+    );
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(
+            clap::Arg::with_name("INPUT")
+                .help("Sets the input directory pattern")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("slice-width")
+                .help("Width of the time slices the column rates are compared in (ns) (default is 1,000,000)")
+                .long("slice-width")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("threshold")
+                .help("Columns with more than this many times the median column's hits in a slice are bursting (default is 20)")
+                .long("threshold")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("min-hits")
+                .help("Minimum hits in a slice for a column to be bursting (default is 50)")
+                .long("min-hits")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("mask")
+                .help("Writes a copy of the hits without those in bursting columns during the bursts")
+                .long("mask"),
+        )
+        .arg(
+            clap::Arg::with_name("masked-filename")
+                .help("Sets the masked hits filename (without extension!) to use (default is 'hits_burst_masked')")
+                .long("masked-filename")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("dry-run")
+                .help("Shows output of what runs will be processed without changing any data")
+                .long("dry-run"),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_glob_str = matches.value_of("INPUT").unwrap();
+
+    let settings = {
+        let slice_width = matches
+            .value_of("slice-width")
+            .and_then(parse_human_readable_number::<u64>)
+            .unwrap_or(1_000_000) as f64;
+        let threshold = matches.value_of("threshold").and_then(|x| x.parse::<f64>().ok()).unwrap_or(20.0);
+        let min_hits = matches.value_of("min-hits").and_then(parse_human_readable_number).unwrap_or(50);
+
+        let masked_filename = if matches.is_present("mask") {
+            Some(matches.value_of("masked-filename").unwrap_or("hits_burst_masked").to_owned())
+        } else {
+            None
+        };
+
+        assert!(slice_width > 0.0);
+
+        Settings {
+            slice_width,
+            threshold,
+            min_hits,
+            masked_filename,
+        }
+    };
+
+    let dry_run = matches.is_present("dry-run");
+
+    //
+    // Parse input file list
+    //
+    let matched_dirs: Vec<_> = glob(input_glob_str)
+        .expect("Failed to read input file glob pattern")
+        .filter_map(|x| x.ok()) // Check glob worked
+        .filter(|x| x.is_dir()) // Check is directory
+        .filter(|x| x.join("hits.bin").exists())
+        .collect();
+
+    // Runs with existing output files are skipped
+    let (input_dirs, existing_dirs): (Vec<_>, Vec<_>) = matched_dirs.into_iter().partition(|x| !x.join("column_bursts.csv").exists());
+
+    if input_dirs.is_empty() && (!dry_run || existing_dirs.is_empty()) {
+        println!("No input directories matched!");
+        return Ok(());
+    }
+
+    println!("Matched {} input directories", input_dirs.len());
+
+    if dry_run {
+        let mut plan = DryRunPlan::new();
+
+        for input_dir in &input_dirs {
+            let input_bytes = input_dir.join("hits.bin").metadata()?.len();
+            let hits = Run::open(input_dir)?.hit_count().ok();
+
+            plan.run(input_dir, input_bytes, hits);
+        }
+
+        for existing_dir in &existing_dirs {
+            plan.skipped(existing_dir);
+        }
+
+        plan.print();
+
+        return Ok(());
+    }
+
+    let sty = ProgressStyle::default_bar()
+        .template(PROGRESS_BAR_TEMPLATE)
+        .progress_chars(PROGRESS_BAR_CHARS);
+
+    for input_dir in input_dirs {
+        let input_bytes = input_dir.join("hits.bin").metadata()?.len();
+
+        let progress_bar = ProgressBar::new(input_bytes / 16);
+        progress_bar.set_style(sty.clone());
+
+        let timer = ProcessingTimer::start(&input_dir);
+
+        process_run(&input_dir, &settings, &progress_bar)?;
+
+        timer.finish(input_bytes)?;
+    }
+
+    Ok(())
+}
+
+fn process_run(run_dir: &Path, settings: &Settings, progress_bar: &ProgressBar) -> io::Result<()> {
+    let run_name = run_dir.file_stem().unwrap().to_str().unwrap();
+
+    let input_data_file_path = run_dir.join("hits.bin");
+
+    let mut detector = ColumnBurstDetector::new((settings.slice_width / TOA_CLOCK_TO_NS) as u64, settings.threshold, settings.min_hits);
+
+    progress_bar.set_message(&format!("| Finding Bursts | {}", run_name));
+
+    for (i, hit) in ReadHitsIterator::new(&input_data_file_path).enumerate() {
+        detector.add(&hit);
+
+        if i % BUFFER_SIZE == 0 {
+            progress_bar.set_position(i as u64);
+        }
+    }
+
+    detector.finish();
+
+    let mut csv_writer = csv::Writer::from_path(run_dir.join("column_bursts.csv"))?;
+
+    for burst in &detector.bursts {
+        csv_writer.serialize(burst)?;
+    }
+
+    csv_writer.flush()?;
+
+    write_settings_toml(&run_dir.join("column_bursts.toml"), settings, &[&input_data_file_path])?;
+
+    // Remove the hits of bursting columns while they are bursting
+    let hits_masked = match &settings.masked_filename {
+        Some(masked_filename) => {
+            progress_bar.set_position(0);
+            progress_bar.set_message(&format!("| Masking Bursts | {}", run_name));
+
+            let output_data_file_path = run_dir.join(format!("{}.bin", masked_filename));
+
+            let mut output_data_file = fs::File::create(&output_data_file_path)?;
+            let mut hits_index = HitsIndexBuilder::new();
+
+            let mask = ColumnBurstMask::new(&detector.bursts);
+
+            let mut hits_masked = 0;
+            let mut hits = Vec::with_capacity(BUFFER_SIZE);
+
+            for (i, hit) in ReadHitsIterator::new(&input_data_file_path).enumerate() {
+                if mask.is_masked(&hit) {
+                    hits_masked += 1;
+                } else {
+                    hits.push(hit);
+                }
+
+                if hits.len() == BUFFER_SIZE {
+                    hits_index.add(&hits);
+                    write_hits_to_file(&mut output_data_file, &hits)?;
+                    hits.clear();
+
+                    progress_bar.set_position(i as u64);
+                }
+            }
+
+            hits_index.add(&hits);
+            write_hits_to_file(&mut output_data_file, &hits)?;
+
+            hits_index.write(&output_data_file_path)?;
+
+            Some(hits_masked)
+        }
+        None => None,
+    };
+
+    let mut noisy_columns: Vec<u16> = detector.bursts.iter().map(|x| x.col).collect();
+    noisy_columns.sort();
+    noisy_columns.dedup();
+
+    let summary = ColumnBurstSummary {
+        slices: detector.slices,
+        bursts: detector.bursts.len(),
+        burst_hits: detector.bursts.iter().map(|x| u64::from(x.hits)).sum(),
+        noisy_columns,
+        hits_masked,
+    };
+
+    update_run_summary(run_dir, "column_bursts", &summary)?;
+
+    progress_bar.finish_with_message(&format!(
+        "| Done | {} Bursts in {} Columns | {}",
+        summary.bursts.separated_string(),
+        summary.noisy_columns.len(),
+        run_name
+    ));
+
+    Ok(())
+}
@@ -0,0 +1,634 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * --------------------------
+ * Timepix Run Cross-Checker
+ * --------------------------
+ *
+ * timepix-spidr-data-parser/src/bin/crosscheck.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use clap;
+use colored::Colorize;
+use glob::glob;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use separator::Separatable as _;
+use serde::Serialize;
+
+use timepix_spidr_data_parser::*;
+
+/// Size of a raw data packet (bytes)
+const PACKET_SIZE: u64 = 8;
+
+/// Outcome of comparing the counts `raw_data_parser` recorded for a run with its files
+#[derive(Default, Serialize)]
+struct CrossCheck {
+    hits: u64,
+    triggers: u64,
+    raw_packets: Option<u64>, // Packets the raw files hold, from their sizes
+    sample: Option<SampleCheck>,
+    problems: Vec<String>,
+}
+
+impl CrossCheck {
+    fn problem(&mut self, problem: String) {
+        self.problems.push(problem);
+    }
+
+    fn passed(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+/// Hits re-decoded from a random sample of the hit packets in the raw files and looked for in the
+/// hits file
+#[derive(Default, Serialize)]
+struct SampleCheck {
+    sampled: usize,
+    removed: usize, // Hot pixels, which the parser always removes
+    found: usize,
+    mismatched: usize, // Found at the same pixel and time with a different ToT
+    missing: usize,
+    max_missing: usize, // Allowed for the hits the parser filtered out (gating, dedup, masks, ...)
+}
+
+fn main() -> io::Result<()> {
+    println!(
+        "\n--------------------------\n{}\n--------------------------\n",
+        "Timepix Run Cross-Checker".bold()
+    );
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(
+            clap::Arg::with_name("INPUT")
+                .help("Sets the input directory pattern")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("skip-raw")
+                .help("Does not read the raw files, only checking the hits and triggers files against summary.json")
+                .long("skip-raw"),
+        )
+        .arg(
+            clap::Arg::with_name("verify-sample")
+                .help("Re-decodes this many hit packets picked at random from each run's raw files and checks the hits are in the hits file")
+                .long("verify-sample")
+                .takes_value(true)
+                .conflicts_with("skip-raw"),
+        )
+        .arg(
+            clap::Arg::with_name("seed")
+                .help("Seed for picking the hits of --verify-sample (default is random)")
+                .long("seed")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("dry-run")
+                .help("Shows output of what runs will be processed without changing any data")
+                .long("dry-run"),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_glob_str = matches.value_of("INPUT").unwrap();
+
+    let skip_raw = matches.is_present("skip-raw");
+    let verify_sample = matches.value_of("verify-sample").and_then(parse_human_readable_number::<usize>);
+    let dry_run = matches.is_present("dry-run");
+
+    let mut rng = match matches.value_of("seed").and_then(|x| x.parse::<u64>().ok()) {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+
+    //
+    // Parse input file list
+    //
+    let input_dirs: Vec<_> = glob(input_glob_str)
+        .expect("Failed to read input file glob pattern")
+        .filter_map(|x| x.ok()) // Check glob worked
+        .filter(|x| x.is_dir()) // Check is directory
+        .filter(|x| Run::open(x).map_or(false, |run| run.hits_path().exists()))
+        .collect();
+
+    if input_dirs.is_empty() {
+        println!("No input directories matched!");
+        return Ok(());
+    }
+
+    println!("Matched {} input directories", input_dirs.len());
+
+    if dry_run {
+        let mut plan = DryRunPlan::new();
+
+        for input_dir in &input_dirs {
+            let run = Run::open(input_dir)?;
+
+            plan.run(input_dir, input_bytes(&run, skip_raw), run.hit_count().ok());
+        }
+
+        plan.print();
+
+        return Ok(());
+    }
+
+    let mut failed_runs = 0;
+
+    for input_dir in input_dirs {
+        let run = Run::open(&input_dir)?;
+
+        let timer = ProcessingTimer::start(&input_dir);
+
+        let check = crosscheck_run(&run, skip_raw, verify_sample, &mut rng)?;
+
+        update_run_summary(&input_dir, "crosscheck", &check)?;
+
+        timer.finish(input_bytes(&run, skip_raw))?;
+
+        let mut counts = format!(
+            "{} | {} Hits | {} Triggers | {} Raw Packets",
+            run.name(),
+            check.hits.separated_string(),
+            check.triggers.separated_string(),
+            check.raw_packets.map_or_else(|| "?".to_owned(), |x| x.separated_string())
+        );
+
+        if let Some(sample) = &check.sample {
+            counts += &format!(
+                " | {} of {} Sampled Hits Found",
+                sample.found.separated_string(),
+                sample.sampled.separated_string()
+            );
+        }
+
+        if check.passed() {
+            println!("{}", format!("{} | OK", counts).green());
+            continue;
+        }
+
+        failed_runs += 1;
+
+        println!("{}", format!("{} | {} Problems", counts, check.problems.len()).red());
+
+        for problem in &check.problems {
+            println!("  - {}", problem);
+        }
+    }
+
+    if failed_runs > 0 {
+        println!("{}", format!("\n{} runs failed the cross-check", failed_runs).red().bold());
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Bytes read to check a run, its hits file and unless skipped its raw files
+fn input_bytes(run: &Run, skip_raw: bool) -> u64 {
+    let hits_bytes = run.hits_path().metadata().map_or(0, |x| x.len());
+
+    if skip_raw {
+        return hits_bytes;
+    }
+
+    hits_bytes + raw_files(run).iter().filter_map(|(path, _)| input_file_len(path).ok()).sum::<u64>()
+}
+
+/// Raw files a run was parsed from and their sizes at the time, from the provenance in `hits.toml`
+fn raw_files(run: &Run) -> Vec<(PathBuf, u64)> {
+    let settings = match run.settings("hits") {
+        Ok(Some(settings)) => settings,
+        _ => return Vec::new(),
+    };
+
+    let input_files = settings
+        .get("provenance")
+        .and_then(|x| x.get("input_files"))
+        .and_then(|x| x.as_array())
+        .cloned()
+        .unwrap_or_default();
+
+    input_files
+        .iter()
+        .filter_map(|x| {
+            let path = x.get("path")?.as_str()?;
+            let size = x.get("size")?.as_integer()?;
+
+            Some((PathBuf::from(path), size as u64))
+        })
+        .collect()
+}
+
+/// Compares the counts in a run's `summary.json` with the records in its hits and triggers files
+/// and the packets in its raw files, and optionally looks for a sample of the raw hits in the
+/// hits file.
+fn crosscheck_run(run: &Run, skip_raw: bool, verify_sample: Option<usize>, rng: &mut StdRng) -> io::Result<CrossCheck> {
+    let mut check = CrossCheck::default();
+
+    let hits_path = run.hits_path();
+    let hits_name = hits_path.file_name().unwrap().to_string_lossy().into_owned();
+
+    if has_partial_marker(&hits_path) {
+        check.problem(format!("{} is the partial output of an interrupted parse", hits_name));
+    }
+
+    //
+    // Hits
+    //
+    let hits_bytes = hits_path.metadata()?.len();
+
+    check.hits = hits_bytes / HIT_RECORD_SIZE as u64;
+
+    if hits_bytes % HIT_RECORD_SIZE as u64 != 0 {
+        check.problem(format!(
+            "{} ends with an incomplete record ({} bytes)",
+            hits_name,
+            hits_bytes % HIT_RECORD_SIZE as u64
+        ));
+    }
+
+    //
+    // Triggers, in the binary file and its human readable copy
+    //
+    let mut trigger_counts = Vec::new();
+
+    let triggers_bin_path = run.triggers_path("bin");
+    if triggers_bin_path.exists() {
+        trigger_counts.push((triggers_bin_path.clone(), read_trigger_records(&triggers_bin_path)?.len() as u64));
+    }
+
+    let triggers_csv_path = run.triggers_path("csv");
+    if triggers_csv_path.exists() {
+        trigger_counts.push((triggers_csv_path.clone(), count_csv_records(&triggers_csv_path)?));
+    }
+
+    check.triggers = trigger_counts.first().map_or(0, |(_, count)| *count);
+
+    //
+    // Counts recorded when the run was parsed
+    //
+    let summary = run.summary()?;
+
+    let counts: RecordCounts = match summary.get(RECORD_COUNTS_SECTION) {
+        Some(counts) => serde_json::from_value(counts.clone())?,
+        None => {
+            check.problem("summary.json has no record counts, the run was parsed before they were recorded".to_owned());
+            return Ok(check);
+        }
+    };
+
+    if check.hits != counts.hits {
+        check.problem(format!(
+            "{} has {} hits, summary.json {}",
+            hits_name,
+            check.hits.separated_string(),
+            counts.hits.separated_string()
+        ));
+    }
+
+    if counts.hits > counts.hit_packets {
+        check.problem(format!(
+            "summary.json has more hits written ({}) than hit packets read ({})",
+            counts.hits.separated_string(),
+            counts.hit_packets.separated_string()
+        ));
+    }
+
+    if trigger_counts.is_empty() && counts.triggers > 0 {
+        check.problem(format!(
+            "{} is missing, summary.json has {} triggers",
+            triggers_csv_path.file_name().unwrap().to_string_lossy(),
+            counts.triggers.separated_string()
+        ));
+    }
+
+    for (path, count) in &trigger_counts {
+        if *count != counts.triggers {
+            check.problem(format!(
+                "{} has {} triggers, summary.json {}",
+                path.file_name().unwrap().to_string_lossy(),
+                count.separated_string(),
+                counts.triggers.separated_string()
+            ));
+        }
+    }
+
+    if skip_raw {
+        return Ok(check);
+    }
+
+    //
+    // Raw files, which must be as they were when parsed and hold the packets that were read
+    //
+    let raw_files = raw_files(run);
+
+    if raw_files.is_empty() {
+        check.problem("hits.toml does not list the raw files the run was parsed from".to_owned());
+        return Ok(check);
+    }
+
+    let mut data_bytes = 0;
+    let mut raw_files_intact = true;
+
+    for (path, size) in &raw_files {
+        match input_file_len(path) {
+            Ok(len) if len == *size => data_bytes += raw_data_bytes(path)?,
+            Ok(len) => {
+                check.problem(format!("Raw file '{}' is {} bytes, {} when it was parsed", path.display(), len, size));
+                raw_files_intact = false;
+            }
+            Err(_) => {
+                check.problem(format!("Raw file '{}' is missing", path.display()));
+                raw_files_intact = false;
+            }
+        }
+    }
+
+    if raw_files_intact {
+        // Incomplete packets at the ends of files and corrupted data skipped over are not packets
+        let recovery = summary.get("packet_recovery");
+        let recovered_bytes = |name: &str| recovery.and_then(|x| x.get(name)?.as_u64()).unwrap_or(0);
+
+        let packet_bytes = data_bytes.saturating_sub(recovered_bytes("truncated_bytes") + recovered_bytes("skipped_bytes"));
+
+        check.raw_packets = Some(packet_bytes / PACKET_SIZE);
+
+        if packet_bytes / PACKET_SIZE != counts.packets {
+            check.problem(format!(
+                "The raw files hold {} packets, summary.json {}",
+                (packet_bytes / PACKET_SIZE).separated_string(),
+                counts.packets.separated_string()
+            ));
+        }
+
+        if let Some(n_samples) = verify_sample {
+            verify_hits_sample(run, &raw_files, &counts, n_samples, rng, &mut check)?;
+        }
+    }
+
+    Ok(check)
+}
+
+/// Re-decodes a random sample of the hit packets in a run's raw files and looks for each hit in
+/// the hits file, which shows the parse was faithful without parsing the run again.
+///
+/// The ToA of a packet is only known modulo the pixel time rollover period without the state built
+/// up from the packets before it. The offset the parser added to each file's hits (from
+/// `file_toa_offsets`) is a whole number of periods apart from the ToA in the hits file, unless the
+/// global timestamp was reset, so each hit is looked for at every period in the run.
+fn verify_hits_sample(
+    run: &Run,
+    raw_files: &[(PathBuf, u64)],
+    counts: &RecordCounts,
+    n_samples: usize,
+    rng: &mut StdRng,
+    check: &mut CrossCheck,
+) -> io::Result<()> {
+    let summary = run.summary()?;
+    let settings = run.settings("hits")?.unwrap_or_else(|| toml::Value::Table(toml::value::Table::new()));
+
+    let resets = summary.get("time_extension").and_then(|x| x.get("resets")?.as_u64()).unwrap_or(0);
+
+    if resets > 0 {
+        check.problem(format!(
+            "Hits can not be sampled from a run whose global timestamp was reset ({} resets)",
+            resets
+        ));
+        return Ok(());
+    }
+
+    let file_offsets: Vec<u64> = summary
+        .get("file_toa_offsets")
+        .and_then(|x| serde_json::from_value(x.clone()).ok())
+        .unwrap_or_else(|| vec![0; raw_files.len().min(1)]);
+
+    if file_offsets.len() != raw_files.len() {
+        check.problem("summary.json does not record the ToA offset of each raw file, so hits can not be sampled".to_owned());
+        return Ok(());
+    }
+
+    // Offset of each file's hits, modulo the rollover period
+    let file_offsets: Vec<u64> = file_offsets
+        .iter()
+        .scan(0, |offset, x| {
+            *offset = (*offset + x) % TOA_ROLLOVER_PERIOD;
+            Some(*offset)
+        })
+        .collect();
+
+    // Corrections the parser applied, reloaded from the files it read them from
+    let correction_file = |name: &str| settings.get(name).and_then(|x| x.get("file")?.as_str()).map(PathBuf::from);
+
+    let flat_field = match correction_file("flat_field").map(|x| FlatField::from_gain_map(&x)) {
+        Some(Err(err)) => {
+            check.problem(format!(
+                "The gain map the run was parsed with could not be read, so hits can not be sampled: {}",
+                err
+            ));
+            return Ok(());
+        }
+        flat_field => flat_field.and_then(|x| x.ok()),
+    };
+
+    let timing_offsets = match correction_file("timing_offsets").map(|x| TimingOffsets::from_csv(&x)) {
+        Some(Err(err)) => {
+            check.problem(format!(
+                "The timing offsets the run was parsed with could not be read, so hits can not be sampled: {}",
+                err
+            ));
+            return Ok(());
+        }
+        timing_offsets => timing_offsets.and_then(|x| x.ok()),
+    };
+
+    let resync = settings.get("resync").and_then(|x| x.as_bool()).unwrap_or(false);
+    let clock_phases = settings.get("clock_phases").and_then(|x| x.as_integer()).map(|x| x as u32);
+    let acq_mode: Option<AcqMode> = settings.get("acq_mode").and_then(|x| x.clone().try_into().ok());
+
+    //
+    // Pick the hit packets, each equally likely (reservoir sampling)
+    //
+    let mut samples: Vec<(usize, u64)> = Vec::with_capacity(n_samples); // File index and packet
+    let mut file_decoding = Vec::new(); // Clock phases and acquisition mode of each file
+    let mut hit_packets = 0;
+
+    for (file_index, (path, _)) in raw_files.iter().enumerate() {
+        let mut file = RawFile::open(path)?;
+        let spidr_header = read_spidr_header(&mut file)?;
+
+        // As the parser decoded them, the command line settings taking priority over the header
+        file_decoding.push((
+            clock_phases.or_else(|| spidr_header.clock_phases()).unwrap_or(DEFAULT_CLOCK_PHASES),
+            acq_mode.or_else(|| spidr_header.acq_mode()).unwrap_or(AcqMode::ToaTot),
+        ));
+
+        let mut packets = ReadRawPacketIterator::new(file, resync);
+
+        for packet in &mut packets {
+            let header = (packet >> 60) & 0xF;
+
+            if header != 0xA && header != 0xB {
+                continue;
+            }
+
+            hit_packets += 1;
+
+            if samples.len() < n_samples {
+                samples.push((file_index, packet));
+            } else {
+                let i = rng.gen_range(0, hit_packets);
+
+                if i < n_samples {
+                    samples[i] = (file_index, packet);
+                }
+            }
+        }
+
+        if let Some(err) = packets.take_error() {
+            return Err(err);
+        }
+    }
+
+    //
+    // Look for the hits in one pass through the hits file
+    //
+    let mut sample = SampleCheck::default();
+
+    // Expected ToT of the sampled hits, by pixel and ToA modulo the rollover period
+    let mut expected: HashMap<(u16, u16, u64), Vec<u32>> = HashMap::new();
+
+    for (file_index, packet) in samples {
+        let (clock_phases, acq_mode) = file_decoding[file_index];
+
+        let hit = decode_hit_with_mode(packet, 0, clock_phases, acq_mode);
+
+        sample.sampled += 1;
+
+        if HOT_PIXELS.iter().any(|(hcol, hrow)| *hcol == hit.col && *hrow == hit.row) {
+            sample.removed += 1;
+            continue;
+        }
+
+        // Kept a period clear of zero so the timing correction can not saturate
+        let mut hit = Hit {
+            toa: (hit.toa + file_offsets[file_index]) % TOA_ROLLOVER_PERIOD + TOA_ROLLOVER_PERIOD,
+            ..hit
+        };
+
+        if let Some(flat_field) = &flat_field {
+            if acq_mode == AcqMode::ToaTot {
+                hit = flat_field.correct(hit);
+            }
+        }
+
+        if let Some(timing_offsets) = &timing_offsets {
+            hit = timing_offsets.correct(hit);
+        }
+
+        expected
+            .entry((hit.col, hit.row, hit.toa % TOA_ROLLOVER_PERIOD))
+            .or_default()
+            .push(hit.tot);
+    }
+
+    let hits_path = run.hits_path();
+
+    // ToT found for each sampled hit with the same pixel and time, the last one seen if none match
+    let mut found: HashMap<(u16, u16, u64), Vec<Option<u32>>> = HashMap::new();
+
+    for hit in run.hits()? {
+        let key = (hit.col, hit.row, hit.toa % TOA_ROLLOVER_PERIOD);
+
+        if let Some(tots) = expected.get(&key) {
+            let found_tots = found.entry(key).or_insert_with(|| vec![None; tots.len()]);
+
+            for (tot, found_tot) in tots.iter().zip(found_tots.iter_mut()) {
+                if *found_tot != Some(*tot) {
+                    *found_tot = Some(hit.tot);
+                }
+            }
+        }
+    }
+
+    for (key, tots) in &expected {
+        for (i, tot) in tots.iter().enumerate() {
+            match found.get(key).and_then(|x| x[i]) {
+                Some(found_tot) if found_tot == *tot => sample.found += 1,
+                Some(_) => sample.mismatched += 1,
+                None => sample.missing += 1,
+            }
+        }
+    }
+
+    // Hits filtered out by the parser are missing as they should be, so as many are allowed as
+    // would be expected from the fraction filtered out (to three standard deviations)
+    let n_checked = (sample.sampled - sample.removed) as f64;
+    let filtered = if counts.hit_packets > 0 {
+        (counts.hit_packets - counts.hits.min(counts.hit_packets)) as f64 / counts.hit_packets as f64
+    } else {
+        0.0
+    };
+
+    sample.max_missing = (n_checked * filtered + 3.0 * (n_checked * filtered * (1.0 - filtered)).sqrt()).ceil() as usize;
+
+    if sample.mismatched > 0 {
+        check.problem(format!(
+            "{} of {} sampled hits have a different ToT in {}",
+            sample.mismatched.separated_string(),
+            sample.sampled.separated_string(),
+            hits_path.file_name().unwrap().to_string_lossy()
+        ));
+    }
+
+    if sample.missing > sample.max_missing {
+        check.problem(format!(
+            "{} of {} sampled hits are missing from {} (at most {} expected from the hits filtered out)",
+            sample.missing.separated_string(),
+            sample.sampled.separated_string(),
+            hits_path.file_name().unwrap().to_string_lossy(),
+            sample.max_missing.separated_string()
+        ));
+    }
+
+    check.sample = Some(sample);
+
+    Ok(())
+}
+
+/// Bytes of packet data in a raw file after its header, decompressing it if it is compressed
+fn raw_data_bytes(path: &Path) -> io::Result<u64> {
+    let mut file = RawFile::open(path)?;
+
+    read_spidr_header(&mut file)?;
+
+    io::copy(&mut file, &mut io::sink())
+}
+
+/// Number of records in a CSV file, not counting the header
+fn count_csv_records(path: &Path) -> io::Result<u64> {
+    let mut rdr = csv::Reader::from_reader(fs::File::open(path)?);
+    let mut count = 0;
+
+    for result in rdr.records() {
+        result?;
+        count += 1;
+    }
+
+    Ok(count)
+}
@@ -0,0 +1,313 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * -----------------------
+ * Timepix Energy Spectrum
+ * -----------------------
+ *
+ * timepix-spidr-data-parser/src/bin/energy_spectrum.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::io;
+use std::path::Path;
+
+use clap;
+use colored::Colorize;
+use glob::glob;
+use separator::Separatable as _;
+use serde::Serialize;
+
+use timepix_spidr_data_parser::*;
+
+#[derive(Clone, Serialize)]
+struct Settings {
+    cluster_filename: String,
+    output_filename: String,
+    min_energy: f64, // keV
+    max_energy: f64, // keV
+    bins: usize,
+    energy_calibration: Option<EnergyCalibration>, // Energies are read from the metadata CSV if not set
+}
+
+#[derive(Serialize)]
+struct SpectrumBin {
+    energy_min: f64,
+    energy_max: f64,
+    clusters: u64,
+}
+
+struct Spectrum {
+    counts: Vec<u64>,
+    underflow: u64,
+    overflow: u64,
+    uncalibrated: u64, // Clusters without an energy
+}
+
+impl Spectrum {
+    fn new(bins: usize) -> Spectrum {
+        Spectrum {
+            counts: vec![0; bins],
+            underflow: 0,
+            overflow: 0,
+            uncalibrated: 0,
+        }
+    }
+
+    fn fill(&mut self, energy: Option<f64>, settings: &Settings) {
+        let energy = match energy {
+            Some(x) => x,
+            None => {
+                self.uncalibrated += 1;
+                return;
+            }
+        };
+
+        if energy < settings.min_energy {
+            self.underflow += 1;
+            return;
+        }
+
+        let i = ((energy - settings.min_energy) / (settings.max_energy - settings.min_energy) * settings.bins as f64) as usize;
+
+        match self.counts.get_mut(i) {
+            Some(count) => *count += 1,
+            None => self.overflow += 1,
+        }
+    }
+
+    fn add(&mut self, other: &Spectrum) {
+        for (count, other_count) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += other_count;
+        }
+
+        self.underflow += other.underflow;
+        self.overflow += other.overflow;
+        self.uncalibrated += other.uncalibrated;
+    }
+
+    fn clusters(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    fn write(&self, path: &Path, settings: &Settings) -> io::Result<()> {
+        let mut csv_writer = csv::Writer::from_path(path)?;
+
+        let width = (settings.max_energy - settings.min_energy) / settings.bins as f64;
+
+        for (i, count) in self.counts.iter().enumerate() {
+            csv_writer.serialize(SpectrumBin {
+                energy_min: settings.min_energy + i as f64 * width,
+                energy_max: settings.min_energy + (i + 1) as f64 * width,
+                clusters: *count,
+            })?;
+        }
+
+        csv_writer.flush()
+    }
+}
+
+fn main() -> io::Result<()> {
+    println!(
+        "\n-----------------------\n{}\n-----------------------\n",
+        "Timepix Energy Spectrum".bold()
+    );
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(
+            clap::Arg::with_name("INPUT")
+                .help("Sets the input directory pattern")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("OUTPUT")
+                .help("Sets the output CSV file for the spectrum of all the runs")
+                .required(true)
+                .index(2),
+        )
+        .arg(
+            clap::Arg::with_name("cluster-filename")
+                .help("Sets the cluster filename (without extension!) to use (default is 'clusters')")
+                .long("cluster-filename")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("energy-calibration")
+                .help("Calibrates the hits of each cluster with a per-pixel calibration CSV (col, row, a, b, c, t) rather than using the energy column of the metadata CSV")
+                .long("energy-calibration")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("min-energy")
+                .help("Lower edge of the spectrum (keV) (default is 0)")
+                .long("min-energy")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("max-energy")
+                .help("Upper edge of the spectrum (keV) (default is 1000)")
+                .long("max-energy")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("bins")
+                .help("Number of bins in the spectrum (default is 500)")
+                .long("bins")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("output-filename")
+                .help("Sets the filename (without extension!) of the spectrum written to each run (default is 'energy_spectrum')")
+                .long("output-filename")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_glob_str = matches.value_of("INPUT").unwrap();
+    let output_file_path = Path::new(matches.value_of("OUTPUT").unwrap());
+
+    let settings = {
+        let cluster_filename = matches.value_of("cluster-filename").unwrap_or("clusters").to_owned();
+        let output_filename = matches.value_of("output-filename").unwrap_or("energy_spectrum").to_owned();
+
+        let energy_calibration = match matches.value_of("energy-calibration") {
+            Some(file) => Some(EnergyCalibration::from_csv(Path::new(file))?),
+            None => None,
+        };
+
+        let min_energy = matches.value_of("min-energy").map_or(0.0, |x| x.parse::<f64>().unwrap());
+        let max_energy = matches.value_of("max-energy").map_or(1000.0, |x| x.parse::<f64>().unwrap());
+        let bins = matches.value_of("bins").map_or(500, |x| x.parse::<usize>().unwrap());
+
+        assert!(min_energy < max_energy && bins > 0);
+
+        Settings {
+            cluster_filename,
+            output_filename,
+            min_energy,
+            max_energy,
+            bins,
+            energy_calibration,
+        }
+    };
+
+    //
+    // Parse input file list
+    //
+    let input_dirs: Vec<_> = glob(input_glob_str)
+        .expect("Failed to read input file glob pattern")
+        .filter_map(|x| x.ok()) // Check glob worked
+        .filter(|x| x.is_dir()) // Check is directory
+        .filter(|x| x.join(format!("{}.csv", settings.cluster_filename)).exists())
+        .filter(|x| settings.energy_calibration.is_none() || x.join(format!("{}.bin", settings.cluster_filename)).exists())
+        .collect();
+
+    if input_dirs.is_empty() {
+        println!("No input directories matched!");
+        return Ok(());
+    }
+
+    println!("Matched {} input directories", input_dirs.len());
+
+    //
+    // Check output file does not already exist
+    //
+    if output_file_path.exists() {
+        println!("{}", format!("Given output file '{}' already exists!", output_file_path.display()).red());
+        return Ok(());
+    }
+
+    let mut total = Spectrum::new(settings.bins);
+    let mut input_files = Vec::with_capacity(input_dirs.len());
+
+    for input_dir in &input_dirs {
+        let run_name = input_dir.file_stem().unwrap().to_str().unwrap();
+
+        let spectrum = match &settings.energy_calibration {
+            Some(energy_calibration) => {
+                let input_data_file_path = input_dir.join(format!("{}.bin", settings.cluster_filename));
+                let mut spectrum = Spectrum::new(settings.bins);
+
+                // Empty events from trigger extraction have no energy to histogram
+                for cluster in ReadClusterIterator::new(input_data_file_path.to_str().unwrap()).filter(|x| !x.is_empty()) {
+                    spectrum.fill(energy_calibration.cluster_energy(&cluster), &settings);
+                }
+
+                input_files.push(input_data_file_path);
+                spectrum
+            }
+            None => {
+                let metadata = Run::open(input_dir)?.cluster_metadata(&settings.cluster_filename)?;
+                let mut spectrum = Spectrum::new(settings.bins);
+
+                for cluster in metadata.iter().filter(|x| x.hits > 0) {
+                    spectrum.fill(cluster.energy, &settings);
+                }
+
+                input_files.push(input_dir.join(format!("{}.csv", settings.cluster_filename)));
+                spectrum
+            }
+        };
+
+        let output_csv_file_path = input_dir.join(format!("{}.csv", settings.output_filename));
+        let output_toml_file_path = input_dir.join(format!("{}.toml", settings.output_filename));
+
+        spectrum.write(&output_csv_file_path, &settings)?;
+        write_settings_toml(&output_toml_file_path, &settings, &input_files[input_files.len() - 1..])?;
+
+        println!("{} | {} Clusters in Spectrum", run_name, spectrum.clusters().separated_string());
+
+        print_excluded(run_name, &spectrum);
+
+        total.add(&spectrum);
+    }
+
+    total.write(output_file_path, &settings)?;
+    write_settings_toml(&output_file_path.with_extension("toml"), &settings, &input_files)?;
+
+    println!(
+        "{}",
+        format!(
+            "\nHistogrammed {} clusters from {} runs into {}\n",
+            total.clusters().separated_string(),
+            input_dirs.len(),
+            output_file_path.display()
+        )
+        .bold()
+    );
+
+    print_excluded("All runs", &total);
+
+    Ok(())
+}
+
+fn print_excluded(name: &str, spectrum: &Spectrum) {
+    if spectrum.underflow + spectrum.overflow > 0 {
+        println!(
+            "{}",
+            format!(
+                "{} | {} clusters below and {} above the spectrum range",
+                name,
+                spectrum.underflow.separated_string(),
+                spectrum.overflow.separated_string()
+            )
+            .yellow()
+        );
+    }
+
+    if spectrum.uncalibrated > 0 {
+        println!(
+            "{}",
+            format!("{} | {} clusters without an energy", name, spectrum.uncalibrated.separated_string()).yellow()
+        );
+    }
+}
@@ -0,0 +1,448 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * ---------------------
+ * Timepix Frame Builder
+ * ---------------------
+ *
+ * timepix-spidr-data-parser/src/bin/frame_builder.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::io;
+use std::path::Path;
+
+use clap;
+use colored::Colorize;
+use glob::glob;
+use separator::Separatable as _;
+use serde::Serialize;
+
+use timepix_spidr_data_parser::*;
+
+/// What each pixel of a frame holds
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Mode {
+    /// Number of hits
+    Counts,
+    /// Sum of the ToT of the hits
+    Tot,
+}
+
+/// File format of the frame stack
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    /// (frames, 256, 256) u32 array
+    Npy,
+    /// Multi-page 16 or 32-bit TIFF
+    Tiff,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Npy => "npy",
+            Format::Tiff => "tiff",
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct Settings {
+    output_filename: String,
+    exposure: f64, // Frame duration (us)
+    mode: Mode,
+    format: Format,
+    tiff_sample_format: Option<TiffSampleFormat>,
+    start_time: Option<u64>, // Start of the first frame (ns), the first hit if not set
+    end_time: Option<u64>,   // No frames are started after this (ns), the last hit if not set
+    max_frames: Option<usize>,
+}
+
+/// Row of the per-frame CSV
+#[derive(Serialize)]
+struct FrameRecord {
+    frame: usize,
+    start: f64, // ns
+    hits: usize,
+    sum_tot: u64,
+}
+
+enum FrameWriter {
+    Npy(NpyU32Writer),
+    Tiff(TiffStackWriter),
+}
+
+impl FrameWriter {
+    fn write(&mut self, frame: &[u32]) -> io::Result<()> {
+        match self {
+            FrameWriter::Npy(writer) => writer.write(frame),
+            FrameWriter::Tiff(writer) => writer.write_page(frame),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            FrameWriter::Npy(writer) => writer.finish(),
+            FrameWriter::Tiff(writer) => writer.finish(),
+        }
+    }
+}
+
+struct RunResult {
+    frames: usize,
+    hits: usize,
+    late_hits: usize, // Hits out of time order, earlier than the frame being filled
+}
+
+fn main() -> io::Result<()> {
+    println!("\n---------------------\n{}\n---------------------\n", "Timepix Frame Builder".bold());
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(
+            clap::Arg::with_name("INPUT")
+                .help("Sets the input directory pattern")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("exposure")
+                .help("Duration of each frame (us)")
+                .long("exposure")
+                .required(true)
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("sum-tot")
+                .help("Fills the frames with the summed ToT of the hits instead of the number of hits")
+                .long("sum-tot"),
+        )
+        .arg(
+            clap::Arg::with_name("format")
+                .help("Writes the frames as a (frames, 256, 256) array or a multi-page TIFF (default is npy)")
+                .long("format")
+                .possible_values(&["npy", "tiff"])
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("tiff-bits")
+                .help("Bits per pixel of TIFF frames, pixels above 65535 are clipped in 16-bit frames (default is 32)")
+                .long("tiff-bits")
+                .possible_values(&["16", "32"])
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("start-time")
+                .help("Start of the first frame (ns) (default is the first hit)")
+                .long("start-time")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("end-time")
+                .help("End of the frames, the last frame is cut short (ns) (default is the last hit)")
+                .long("end-time")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("max-frames")
+                .help("Maximum number of frames to build per run")
+                .long("max-frames")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("min-free-space")
+                .help("Free space to keep on the output filesystem, runs are not started without room for their frames (GB) (default is 1)")
+                .long("min-free-space")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("output-filename")
+                .help("Sets the output filename (without extension!) to use (default is 'frames')")
+                .long("output-filename")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("dry-run")
+                .help("Shows output of what runs will be processed without changing any data")
+                .long("dry-run"),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_glob_str = matches.value_of("INPUT").unwrap();
+
+    let settings = {
+        let output_filename = matches.value_of("output-filename").unwrap_or("frames").to_owned();
+
+        let exposure: f64 = matches.value_of("exposure").and_then(|x| x.parse().ok()).unwrap();
+
+        assert!(exposure > 0.0);
+
+        let mode = if matches.is_present("sum-tot") { Mode::Tot } else { Mode::Counts };
+
+        let format = match matches.value_of("format") {
+            Some("tiff") => Format::Tiff,
+            _ => Format::Npy,
+        };
+
+        let tiff_sample_format = match (format, matches.value_of("tiff-bits")) {
+            (Format::Npy, _) => None,
+            (Format::Tiff, Some("16")) => Some(TiffSampleFormat::U16),
+            (Format::Tiff, _) => Some(TiffSampleFormat::U32),
+        };
+
+        let start_time = matches.value_of("start-time").map(|x| parse_human_readable_number(x).unwrap());
+        let end_time = matches.value_of("end-time").map(|x| parse_human_readable_number(x).unwrap());
+
+        if let (Some(start_time), Some(end_time)) = (start_time, end_time) {
+            assert!(start_time < end_time);
+        }
+
+        let max_frames = matches.value_of("max-frames").map(|x| x.parse::<usize>().unwrap());
+
+        Settings {
+            output_filename,
+            exposure,
+            mode,
+            format,
+            tiff_sample_format,
+            start_time,
+            end_time,
+            max_frames,
+        }
+    };
+
+    let disk_space = {
+        let defaults = DiskSpaceLimits::default();
+
+        DiskSpaceLimits {
+            min_free: matches
+                .value_of("min-free-space")
+                .and_then(|x| x.parse::<f64>().ok())
+                .map_or(defaults.min_free, |x| (x * 1e9) as u64),
+            ..defaults
+        }
+    };
+
+    let dry_run = matches.is_present("dry-run");
+
+    //
+    // Parse input file list
+    //
+    let matched_dirs: Vec<_> = glob(input_glob_str)
+        .expect("Failed to read input file glob pattern")
+        .filter_map(|x| x.ok()) // Check glob worked
+        .filter(|x| x.is_dir()) // Check is directory
+        .filter(|x| x.join("hits.bin").exists())
+        .collect();
+
+    // Runs with existing output files are skipped
+    let (input_dirs, existing_dirs): (Vec<_>, Vec<_>) = matched_dirs
+        .into_iter()
+        .partition(|x| !x.join(format!("{}.{}", settings.output_filename, settings.format.extension())).exists());
+
+    if input_dirs.is_empty() && (!dry_run || existing_dirs.is_empty()) {
+        println!("No input directories matched!");
+        return Ok(());
+    }
+
+    println!("Matched {} input directories", input_dirs.len());
+
+    if dry_run {
+        let mut plan = DryRunPlan::new();
+
+        for input_dir in &input_dirs {
+            let input_bytes = input_dir.join("hits.bin").metadata()?.len();
+            let hits = Run::open(input_dir)?.hit_count().ok();
+
+            plan.run(input_dir, input_bytes, hits);
+        }
+
+        for existing_dir in &existing_dirs {
+            plan.skipped(existing_dir);
+        }
+
+        plan.print();
+
+        return Ok(());
+    }
+
+    for input_dir in input_dirs {
+        let run_name = input_dir.file_stem().unwrap().to_str().unwrap();
+
+        let timer = ProcessingTimer::start(&input_dir);
+
+        let result = match process_run(&input_dir, &settings, &disk_space) {
+            Ok(Some(result)) => result,
+            Ok(None) => {
+                println!("{} | No hits in the frame time range, skipping", run_name);
+                continue;
+            }
+            Err(e) => {
+                println!("{}", format!("{} | Failed to build frames: {}", run_name, e).red());
+                std::process::exit(1);
+            }
+        };
+
+        timer.finish(input_dir.join("hits.bin").metadata()?.len())?;
+
+        println!(
+            "{} | {} Frames Written | {} Hits",
+            run_name,
+            result.frames.separated_string(),
+            result.hits.separated_string()
+        );
+
+        if result.late_hits > 0 {
+            println!(
+                "{}",
+                format!(
+                    "{} | {} hits out of time order were dropped",
+                    run_name,
+                    result.late_hits.separated_string()
+                )
+                .yellow()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn process_run(run_dir: &Path, settings: &Settings, disk_space: &DiskSpaceLimits) -> io::Result<Option<RunResult>> {
+    let input_data_file_path = run_dir.join("hits.bin");
+    let output_data_file_path = run_dir.join(format!("{}.{}", settings.output_filename, settings.format.extension()));
+    let output_csv_file_path = run_dir.join(format!("{}.csv", settings.output_filename));
+    let output_toml_file_path = run_dir.join(format!("{}.toml", settings.output_filename));
+
+    let run = Run::open(run_dir)?;
+
+    let (first_toa, last_toa) = match run.hits_time_range()? {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    let start_toa = settings.start_time.map_or(first_toa, |x| (x as f64 / TOA_CLOCK_TO_NS) as u64);
+    let end_toa = settings.end_time.map_or(last_toa + 1, |x| (x as f64 / TOA_CLOCK_TO_NS) as u64);
+
+    if end_toa <= start_toa {
+        return Ok(None);
+    }
+
+    let exposure_toa = settings.exposure * 1e3 / TOA_CLOCK_TO_NS;
+
+    let mut frames = ((end_toa - start_toa) as f64 / exposure_toa).ceil() as usize;
+
+    if let Some(max_frames) = settings.max_frames {
+        frames = frames.min(max_frames);
+    }
+
+    if frames == 0 {
+        return Ok(None);
+    }
+
+    // A long run split into short frames is easily larger than the disk
+    let pixel_bytes = if settings.tiff_sample_format == Some(TiffSampleFormat::U16) {
+        2
+    } else {
+        4
+    };
+
+    check_free_space(run_dir, frames as u64 * 256 * 256 * pixel_bytes, disk_space)?;
+
+    let mut writer = match (settings.format, settings.tiff_sample_format) {
+        (Format::Tiff, Some(sample_format)) => {
+            FrameWriter::Tiff(TiffStackWriter::create(&output_data_file_path, 256, 256, sample_format, frames as u64)?)
+        }
+        _ => FrameWriter::Npy(NpyU32Writer::create(&output_data_file_path, &[frames, 256, 256])?),
+    };
+
+    let mut csv_writer = csv::Writer::from_path(&output_csv_file_path)?;
+
+    // Write metadata to TOML file
+    write_settings_toml(&output_toml_file_path, settings, &[&input_data_file_path])?;
+
+    let mut hits_iterator = run.hits()?;
+    hits_iterator.seek_to_time(start_toa)?;
+
+    let mut result = RunResult {
+        frames: 0,
+        hits: 0,
+        late_hits: 0,
+    };
+
+    let mut frame = vec![0u32; 256 * 256];
+    let mut frame_hits = 0;
+    let mut frame_tot = 0u64;
+
+    let mut write_frame = |frame: &mut Vec<u32>, frame_hits: &mut usize, frame_tot: &mut u64, result: &mut RunResult| -> io::Result<()> {
+        writer.write(frame)?;
+
+        csv_writer.serialize(FrameRecord {
+            frame: result.frames,
+            start: (start_toa as f64 + result.frames as f64 * exposure_toa) * TOA_CLOCK_TO_NS,
+            hits: *frame_hits,
+            sum_tot: *frame_tot,
+        })?;
+
+        result.frames += 1;
+        result.hits += *frame_hits;
+
+        frame.iter_mut().for_each(|x| *x = 0);
+        *frame_hits = 0;
+        *frame_tot = 0;
+
+        Ok(())
+    };
+
+    for hit in hits_iterator.take_while(|hit| hit.toa < end_toa) {
+        if hit.toa < start_toa {
+            result.late_hits += 1;
+            continue;
+        }
+
+        let index = ((hit.toa - start_toa) as f64 / exposure_toa) as usize;
+
+        if index >= frames {
+            break;
+        }
+
+        if index < result.frames {
+            result.late_hits += 1;
+            continue;
+        }
+
+        while result.frames < index {
+            write_frame(&mut frame, &mut frame_hits, &mut frame_tot, &mut result)?;
+        }
+
+        let pixel = &mut frame[hit.row as usize * 256 + hit.col as usize];
+
+        *pixel = match settings.mode {
+            Mode::Counts => pixel.saturating_add(1),
+            Mode::Tot => pixel.saturating_add(hit.tot),
+        };
+
+        frame_hits += 1;
+        frame_tot += u64::from(hit.tot);
+    }
+
+    // Remaining frames, including any empty ones at the end
+    while result.frames < frames {
+        write_frame(&mut frame, &mut frame_hits, &mut frame_tot, &mut result)?;
+    }
+
+    writer.finish()?;
+    csv_writer.flush()?;
+
+    Ok(Some(result))
+}
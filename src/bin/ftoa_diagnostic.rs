@@ -0,0 +1,198 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * -----------------------------
+ * Timepix fToA Phase Diagnostic
+ * -----------------------------
+ *
+ * timepix-spidr-data-parser/src/bin/ftoa_diagnostic.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::io;
+use std::path::Path;
+
+use clap;
+use colored::Colorize;
+use separator::Separatable as _;
+
+use timepix_spidr_data_parser::*;
+
+fn main() -> io::Result<()> {
+    println!(
+        "\n-----------------------------\n{}\n-----------------------------\n",
+        "Timepix fToA Phase Diagnostic".bold()
+    );
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(clap::Arg::with_name("INPUT").help("Sets the input file pattern").required(true).index(1))
+        .arg(
+            clap::Arg::with_name("OUTPUT")
+                .help("Sets the output CSV file to use")
+                .required(true)
+                .index(2),
+        )
+        .arg(
+            clap::Arg::with_name("superpixel")
+                .help("Histograms each 2x4 superpixel rather than each column")
+                .long("superpixel"),
+        )
+        .arg(
+            clap::Arg::with_name("clock-phases")
+                .help("Number of column clock phases (1, 2, 4, 8 or 16) (default is read from the file header, or 16)")
+                .long("clock-phases")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("packets")
+                .help("Number of packets to process (default is all)")
+                .short("n")
+                .long("packets")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_glob_str = matches.value_of("INPUT").unwrap();
+    let output_file_path = Path::new(matches.value_of("OUTPUT").unwrap());
+
+    let superpixel = matches.is_present("superpixel");
+    let clock_phases = matches.value_of("clock-phases").and_then(|x| x.parse::<u32>().ok());
+    let max_packets = matches.value_of("packets").and_then(parse_human_readable_number::<usize>);
+
+    if let Some(clock_phases) = clock_phases {
+        assert!(clock_phases.is_power_of_two() && clock_phases <= 16);
+    }
+
+    //
+    // Parse input file list
+    //
+    let input_files: Vec<_> = glob_input_files(input_glob_str)?
+        .into_iter()
+        .filter(|x| is_raw_data_file(x)) // Check extension is .dat (or .dat.gz/.dat.zst)
+        .collect();
+
+    if input_files.is_empty() {
+        println!("No input files matched!");
+        return Ok(());
+    }
+
+    println!("Matched {} input files", input_files.len());
+
+    if output_file_path.exists() {
+        println!("{}", format!("Given output file '{}' already exists!", output_file_path.display()).red());
+        return Ok(());
+    }
+
+    // Regions are whole columns, or 2 column by 4 row superpixels
+    let (width, height) = if superpixel { (2, 4) } else { (1, 256) };
+    let (n_cols, n_rows) = (256 / width, 256 / height);
+
+    let mut histograms = vec![[0_u64; 16]; n_cols * n_rows];
+
+    let mut packets_parsed = 0;
+    let mut hits_parsed: u64 = 0;
+    let mut header_clock_phases = None;
+
+    'files: for input_file in &input_files {
+        let mut file = RawFile::open(input_file)?;
+
+        let spidr_header = read_spidr_header(&mut file)?;
+        header_clock_phases = header_clock_phases.or_else(|| spidr_header.clock_phases());
+
+        let mut packets = ReadRawPacketIterator::new(file, false);
+
+        for packet in &mut packets {
+            if let Some(max) = max_packets {
+                if packets_parsed == max {
+                    break 'files;
+                }
+            }
+
+            packets_parsed += 1;
+
+            let header = (packet >> 60) & 0xF;
+
+            if header != 0xA && header != 0xB {
+                continue;
+            }
+
+            let hit = decode_hit(packet, 0, 1);
+            let ftoa = ((packet & 0x0000_0000_000F_0000) >> 16) as usize;
+
+            if hit.col > 255 || hit.row > 255 {
+                continue;
+            }
+
+            histograms[(hit.row as usize / height) * n_cols + hit.col as usize / width][ftoa] += 1;
+            hits_parsed += 1;
+        }
+
+        if let Some(err) = packets.take_error() {
+            return Err(err);
+        }
+    }
+
+    println!("Parsed {} packets", packets_parsed.separated_string());
+    println!("Histogrammed {} hits", hits_parsed.separated_string());
+
+    // Command line setting takes priority over the PLL configuration in the header
+    let clock_phases = clock_phases.or(header_clock_phases).unwrap_or(DEFAULT_CLOCK_PHASES);
+
+    let mut csv_writer = csv::Writer::from_path(output_file_path)?;
+
+    let mut header = vec!["col".to_owned(), "row".to_owned(), "phase_correction".to_owned(), "mean_ftoa".to_owned()];
+    header.extend((0..16).map(|x| format!("ftoa_{}", x)));
+    csv_writer.write_record(&header)?;
+
+    // Mean fToA of the columns in each clock phase, which should step with the phase correction
+    let mut phase_sums = vec![(0_u64, 0_u64); clock_phases as usize];
+
+    for (i, histogram) in histograms.iter().enumerate() {
+        let (col, row) = ((i % n_cols) * width, (i / n_cols) * height);
+
+        let hits: u64 = histogram.iter().sum();
+        let sum: u64 = histogram.iter().enumerate().map(|(ftoa, count)| ftoa as u64 * count).sum();
+        let mean_ftoa = if hits > 0 { sum as f64 / hits as f64 } else { 0.0 };
+
+        let phase = (col / 2) % clock_phases as usize;
+        phase_sums[phase].0 += hits;
+        phase_sums[phase].1 += sum;
+
+        let mut record = vec![
+            col.to_string(),
+            row.to_string(),
+            column_phase_correction(col as u16, clock_phases).to_string(),
+            format!("{:.3}", mean_ftoa),
+        ];
+        record.extend(histogram.iter().map(|x| x.to_string()));
+        csv_writer.write_record(&record)?;
+    }
+
+    csv_writer.flush()?;
+
+    println!("\nClock phases: {}\n", clock_phases);
+    println!("Phase | Correction | Mean fToA");
+
+    for (phase, (hits, sum)) in phase_sums.iter().enumerate() {
+        let mean_ftoa = if *hits > 0 { *sum as f64 / *hits as f64 } else { 0.0 };
+
+        println!(
+            "{:>5} | {:>10} | {:>9.3}",
+            phase,
+            column_phase_correction(phase as u16 * 2, clock_phases),
+            mean_ftoa
+        );
+    }
+
+    println!("{}", format!("\nWrote fToA histograms to {}\n", output_file_path.display()).bold());
+
+    Ok(())
+}
@@ -0,0 +1,310 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * ----------------------
+ * Timepix Gain Map Tool
+ * ----------------------
+ *
+ * timepix-spidr-data-parser/src/bin/gain_map_tool.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::io;
+use std::path::Path;
+
+use clap;
+use colored::Colorize;
+use glob::glob;
+use indicatif::{ProgressBar, ProgressStyle};
+use separator::Separatable as _;
+use serde::Serialize;
+
+use timepix_spidr_data_parser::*;
+
+#[derive(Clone, Serialize)]
+struct Settings {
+    output_filename: String,
+    min_hits: u64,
+    outlier_sigma: f64,
+    png: bool,
+}
+
+/// Row of the gain map CSV
+#[derive(Serialize)]
+struct PixelGain {
+    col: u16,
+    row: u16,
+    hits: u64,
+    mean_tot: f64,
+    rms_tot: f64,
+    relative_gain: f64,
+    outlier: bool,
+}
+
+/// Summary of a gain map, written to the run's `summary.json`
+#[derive(Serialize)]
+struct GainMapSummary {
+    hits: u64,
+    median_mean_tot: f64,
+    mad_mean_tot: f64,
+    low_statistics_pixels: usize,
+    outlier_pixels: usize,
+}
+
+fn main() -> io::Result<()> {
+    println!("\n---------------------\n{}\n---------------------\n", "Timepix Gain Map Tool".bold());
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(
+            clap::Arg::with_name("INPUT")
+                .help("Sets the input directory pattern (of flat-field runs)")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("output-filename")
+                .help("Sets the output filename (without extension!) to use (default is 'gain_map')")
+                .long("output-filename")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("min-hits")
+                .help("Minimum hits for a pixel's gain to be measured, pixels with fewer are flagged (default is 10)")
+                .long("min-hits")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("outlier-sigma")
+                .help("Flags pixels whose mean ToT is more than this many (robust) standard deviations from the median (default is 5)")
+                .long("outlier-sigma")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("png")
+                .help("Also writes the relative gain map as a PNG image")
+                .long("png"),
+        )
+        .arg(
+            clap::Arg::with_name("dry-run")
+                .help("Shows output of what runs will be processed without changing any data")
+                .long("dry-run"),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_glob_str = matches.value_of("INPUT").unwrap();
+
+    let settings = {
+        let output_filename = matches.value_of("output-filename").unwrap_or("gain_map").to_owned();
+        let min_hits = matches.value_of("min-hits").and_then(parse_human_readable_number).unwrap_or(10);
+        let outlier_sigma = matches.value_of("outlier-sigma").and_then(|x| x.parse::<f64>().ok()).unwrap_or(5.0);
+        let png = matches.is_present("png");
+
+        Settings {
+            output_filename,
+            min_hits,
+            outlier_sigma,
+            png,
+        }
+    };
+
+    let dry_run = matches.is_present("dry-run");
+
+    //
+    // Parse input file list
+    //
+    let matched_dirs: Vec<_> = glob(input_glob_str)
+        .expect("Failed to read input file glob pattern")
+        .filter_map(|x| x.ok()) // Check glob worked
+        .filter(|x| x.is_dir()) // Check is directory
+        .filter(|x| x.join("hits.bin").exists())
+        .collect();
+
+    // Runs with existing output files are skipped
+    let (input_dirs, existing_dirs): (Vec<_>, Vec<_>) = matched_dirs
+        .into_iter()
+        .partition(|x| !x.join(format!("{}.csv", settings.output_filename)).exists());
+
+    if input_dirs.is_empty() && (!dry_run || existing_dirs.is_empty()) {
+        println!("No input directories matched!");
+        return Ok(());
+    }
+
+    println!("Matched {} input directories", input_dirs.len());
+
+    if dry_run {
+        let mut plan = DryRunPlan::new();
+
+        for input_dir in &input_dirs {
+            let input_bytes = input_dir.join("hits.bin").metadata()?.len();
+            let hits = Run::open(input_dir)?.hit_count().ok();
+
+            plan.run(input_dir, input_bytes, hits);
+        }
+
+        for existing_dir in &existing_dirs {
+            plan.skipped(existing_dir);
+        }
+
+        plan.print();
+
+        return Ok(());
+    }
+
+    let sty = ProgressStyle::default_bar()
+        .template(PROGRESS_BAR_TEMPLATE)
+        .progress_chars(PROGRESS_BAR_CHARS);
+
+    for input_dir in input_dirs {
+        let input_bytes = input_dir.join("hits.bin").metadata()?.len();
+
+        let progress_bar = ProgressBar::new(input_bytes / 16);
+        progress_bar.set_style(sty.clone());
+
+        let timer = ProcessingTimer::start(&input_dir);
+
+        process_run(&input_dir, &settings, &progress_bar)?;
+
+        timer.finish(input_bytes)?;
+    }
+
+    Ok(())
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let mid = values.len() / 2;
+
+    if values.len() % 2 == 0 {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+fn process_run(run_dir: &Path, settings: &Settings, progress_bar: &ProgressBar) -> io::Result<()> {
+    let run_name = run_dir.file_stem().unwrap().to_str().unwrap();
+
+    let mut hits = vec![0_u64; 256 * 256];
+    let mut sum_tot = vec![0_f64; 256 * 256];
+    let mut sum_tot_sq = vec![0_f64; 256 * 256];
+
+    let mut hits_read: u64 = 0;
+
+    progress_bar.set_message(&format!("| Accumulating ToT | {}", run_name));
+
+    for hit in ReadHitsIterator::new(&run_dir.join("hits.bin")) {
+        let i = hit.row as usize * 256 + hit.col as usize;
+        let tot = f64::from(hit.tot);
+
+        hits[i] += 1;
+        sum_tot[i] += tot;
+        sum_tot_sq[i] += tot * tot;
+
+        hits_read += 1;
+
+        if hits_read % BUFFER_SIZE as u64 == 0 {
+            progress_bar.set_position(hits_read);
+        }
+    }
+
+    let mean_tot: Vec<f64> = (0..256 * 256)
+        .map(|i| if hits[i] > 0 { sum_tot[i] / hits[i] as f64 } else { 0.0 })
+        .collect();
+
+    let rms_tot: Vec<f64> = (0..256 * 256)
+        .map(|i| {
+            if hits[i] > 0 {
+                (sum_tot_sq[i] / hits[i] as f64 - mean_tot[i] * mean_tot[i]).max(0.0).sqrt()
+            } else {
+                0.0
+            }
+        })
+        .collect();
+
+    // Robust estimate of the spread of the pixel means, so the outliers themselves don't widen it
+    let mut measured: Vec<f64> = (0..256 * 256).filter(|i| hits[*i] >= settings.min_hits).map(|i| mean_tot[i]).collect();
+
+    let median_mean_tot = median(&mut measured);
+    let mad_mean_tot = median(&mut measured.iter().map(|x| (x - median_mean_tot).abs()).collect::<Vec<_>>());
+    let sigma = 1.4826 * mad_mean_tot;
+
+    let output_csv_file_path = run_dir.join(format!("{}.csv", settings.output_filename));
+    let output_toml_file_path = run_dir.join(format!("{}.toml", settings.output_filename));
+
+    // Write metadata to TOML file
+    write_settings_toml(&output_toml_file_path, settings, &[run_dir.join("hits.bin")])?;
+
+    let mut csv_writer = csv::Writer::from_path(&output_csv_file_path)?;
+
+    let mut relative_gain = vec![0_f64; 256 * 256];
+    let mut low_statistics_pixels = 0;
+    let mut outlier_pixels = 0;
+
+    for i in 0..256 * 256 {
+        let low_statistics = hits[i] < settings.min_hits;
+
+        if !low_statistics && median_mean_tot > 0.0 {
+            relative_gain[i] = mean_tot[i] / median_mean_tot;
+        }
+
+        let outlier = low_statistics || (mean_tot[i] - median_mean_tot).abs() > settings.outlier_sigma * sigma;
+
+        if low_statistics {
+            low_statistics_pixels += 1;
+        } else if outlier {
+            outlier_pixels += 1;
+        }
+
+        csv_writer.serialize(PixelGain {
+            col: (i % 256) as u16,
+            row: (i / 256) as u16,
+            hits: hits[i],
+            mean_tot: mean_tot[i],
+            rms_tot: rms_tot[i],
+            relative_gain: relative_gain[i],
+            outlier,
+        })?;
+    }
+
+    csv_writer.flush()?;
+
+    if settings.png {
+        // Relative gains of 0 to 2 are mapped onto the full greyscale range
+        let pixels: Vec<u8> = relative_gain.iter().map(|x| (x * 127.5).round().min(255.0) as u8).collect();
+
+        write_greyscale_png(&run_dir.join(format!("{}.png", settings.output_filename)), 256, 256, &pixels)?;
+    }
+
+    let summary = GainMapSummary {
+        hits: hits_read,
+        median_mean_tot,
+        mad_mean_tot,
+        low_statistics_pixels,
+        outlier_pixels,
+    };
+
+    update_run_summary(run_dir, "gain_map", &summary)?;
+
+    progress_bar.finish_with_message(&format!(
+        "| Done | {} Hits | {} Outlier Pixels | {} Low Statistics Pixels | {}",
+        hits_read.separated_string(),
+        outlier_pixels.separated_string(),
+        low_statistics_pixels.separated_string(),
+        run_name
+    ));
+
+    Ok(())
+}
@@ -13,11 +13,10 @@
 use std::fs;
 use std::io;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use clap;
 use colored::Colorize;
-use glob::glob;
 use itertools::Itertools;
 use separator::Separatable as _;
 
@@ -48,6 +47,11 @@ fn main() -> io::Result<()> {
                 .long("sum-tot")
                 .conflicts_with("sum-hits"),
         )
+        .arg(
+            clap::Arg::with_name("centroids")
+                .help("Fills the map with cluster centroids from cluster files (eg. 'runs/*/clusters.bin'), weighted by cluster ToT with '--sum-tot'")
+                .long("centroids"),
+        )
         .arg(
             clap::Arg::with_name("packets")
                 .help("Number of packets to process (default is all)")
@@ -55,6 +59,44 @@ fn main() -> io::Result<()> {
                 .long("packets")
                 .takes_value(true),
         )
+        .arg(
+            clap::Arg::with_name("compare")
+                .help("Also writes difference and ratio maps against another file pattern or a reference heatmap CSV")
+                .long("compare")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("normalise")
+                .help("Divides the map by the live time, giving rates per pixel (Hz)")
+                .long("normalise"),
+        )
+        .arg(
+            clap::Arg::with_name("gates")
+                .help("Gates CSV to take the live time from when normalising (default is the first to last ToA)")
+                .long("gates")
+                .requires("normalise")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("format")
+                .help("Writes the maps as CSV or TIFF (default is csv)")
+                .long("format")
+                .possible_values(&["csv", "tiff"])
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("tiff-bits")
+                .help("Bits per pixel of TIFF hit/ToT maps, pixels above 65535 are clipped in 16-bit maps (default is 32)")
+                .long("tiff-bits")
+                .possible_values(&["16", "32"])
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("pixel-mask")
+                .help("Removes hits from pixels that are not 'ok' in a pixel status map CSV (from hot_pixel_search)")
+                .long("pixel-mask")
+                .takes_value(true),
+        )
         .get_matches();
 
     //
@@ -71,16 +113,27 @@ fn main() -> io::Result<()> {
     let max_packets = matches.value_of("packets").and_then(parse_human_readable_number::<usize>);
     // .and_then(|x| usize::try_from(x).ok());
 
+    let sum_tot = matches.is_present("sum-tot");
+    let centroids = matches.is_present("centroids");
+    let normalise = matches.is_present("normalise");
+
+    // Rates, differences and ratios are not whole numbers so are always written as floats
+    let tiff_sample_format = match matches.value_of("format") {
+        Some("tiff") if normalise => Some(TiffSampleFormat::F32),
+        Some("tiff") if matches.value_of("tiff-bits") == Some("16") => Some(TiffSampleFormat::U16),
+        Some("tiff") => Some(TiffSampleFormat::U32),
+        _ => None,
+    };
+
+    let gates = match matches.value_of("gates") {
+        Some(file) => Some(read_gate_data(&PathBuf::from(file))?),
+        None => None,
+    };
+
     //
     // Parse input file list
     //
-    let input_files: Vec<_> = glob(input_glob_str)
-        .expect("Failed to read input file glob pattern")
-        .filter_map(|x| x.ok()) // Check glob worked
-        .filter(|x| x.is_file()) // Check is file
-        .filter(|x| x.extension().is_some()) // Check has file extension
-        .filter(|x| x.extension().unwrap() == "dat") // Check extension is .dat
-        .collect();
+    let input_files = find_input_files(input_glob_str, centroids)?;
 
     if input_files.is_empty() {
         println!("No input files matched!");
@@ -99,30 +152,26 @@ fn main() -> io::Result<()> {
         return Ok(());
     }
 
-    let (packets_parsed, hits, _) = read_raw_data(&input_files, ReadRawDataMode::HitsOnly, max_packets, &HOT_PIXELS)?;
+    let mut masked_pixels = HOT_PIXELS.to_vec();
 
-    println!("Parsed {} packets", packets_parsed.separated_string());
-    println!("Loaded {} hits", hits.len().separated_string());
+    if let Some(file) = matches.value_of("pixel-mask") {
+        masked_pixels.extend(PixelMask::from_csv(Path::new(file))?.masked_pixel_list());
+    }
 
-    let mut heatmap: [u64; 256 * 256] = [0; 256 * 256];
+    let hits = HitSoA::from(load_hits(&input_files, centroids, max_packets, &masked_pixels)?);
 
-    if matches.is_present("sum-tot") {
-        for hit in hits.iter() {
-            heatmap[hit.row as usize * 256 + hit.col as usize] += u64::from(hit.tot);
-        }
-    } else {
-        for hit in hits.iter() {
-            heatmap[hit.row as usize * 256 + hit.col as usize] += 1;
-        }
-    }
+    let mut heatmap = fill_heatmap(&hits, sum_tot);
 
-    let mut output_file = fs::File::create(output_file_path)?;
+    if normalise {
+        let live_time = live_time_seconds(&hits, gates.as_ref().map(|x| &x[..]));
 
-    for row in 0..256 {
-        let row_string = heatmap[row * 256..(row + 1) * 256].iter().map(|x| x.to_string()).join(",");
-        writeln!(&mut output_file, "{}", row_string)?;
+        println!("Live time: {:.3} s", live_time);
+
+        normalise_heatmap(&mut heatmap, live_time);
     }
 
+    write_heatmap(output_file_path, &heatmap, tiff_sample_format)?;
+
     println!(
         "{}",
         format!(
@@ -133,5 +182,207 @@ fn main() -> io::Result<()> {
         .bold()
     );
 
+    if let Some(compare_str) = matches.value_of("compare") {
+        // Compare against a previously generated heatmap, or build one from other raw data
+        let reference = if compare_str.ends_with(".csv") {
+            read_heatmap(Path::new(compare_str))?
+        } else {
+            let compare_files = find_input_files(compare_str, centroids)?;
+
+            if compare_files.is_empty() {
+                println!("{}", "No comparison files matched!".red());
+                return Ok(());
+            }
+
+            println!("Matched {} comparison files", compare_files.len());
+
+            let compare_hits = HitSoA::from(load_hits(&compare_files, centroids, max_packets, &masked_pixels)?);
+
+            let mut reference = fill_heatmap(&compare_hits, sum_tot);
+
+            if normalise {
+                normalise_heatmap(&mut reference, live_time_seconds(&compare_hits, None));
+            }
+
+            reference
+        };
+
+        let difference: Vec<f64> = heatmap.iter().zip(reference.iter()).map(|(x, r)| x - r).collect();
+
+        // Pixels empty in the reference have no defined ratio
+        let ratio: Vec<f64> = heatmap
+            .iter()
+            .zip(reference.iter())
+            .map(|(x, r)| if *r == 0.0 { f64::NAN } else { x / r })
+            .collect();
+
+        let difference_file_path = sibling_path(output_file_path, "difference");
+        let ratio_file_path = sibling_path(output_file_path, "ratio");
+
+        let tiff_sample_format = tiff_sample_format.map(|_| TiffSampleFormat::F32);
+
+        write_heatmap(&difference_file_path, &difference, tiff_sample_format)?;
+        write_heatmap(&ratio_file_path, &ratio, tiff_sample_format)?;
+
+        println!(
+            "{}",
+            format!(
+                "Compared against {} and output to {} and {}\n",
+                compare_str,
+                difference_file_path.display(),
+                ratio_file_path.display()
+            )
+            .bold()
+        );
+    }
+
+    Ok(())
+}
+
+fn find_input_files(glob_str: &str, centroids: bool) -> io::Result<Vec<PathBuf>> {
+    let input_files = glob_input_files(glob_str)?
+        .into_iter()
+        .filter(|x| {
+            // Check extension is .dat (or .bin for clusters)
+            if centroids {
+                x.extension().map_or(false, |x| x == "bin")
+            } else {
+                is_raw_data_file(x)
+            }
+        })
+        .collect();
+
+    Ok(input_files)
+}
+
+/// Reads the hits to fill the map with, either from raw data or as the centroids of clusters
+fn load_hits(input_files: &[PathBuf], centroids: bool, max_packets: Option<usize>, masked_pixels: &[(u16, u16)]) -> io::Result<Vec<Hit>> {
+    if !centroids {
+        let (packets_parsed, hits, _) = read_raw_data(input_files, ReadRawDataMode::HitsOnly, max_packets, masked_pixels)?;
+
+        println!("Parsed {} packets", packets_parsed.separated_string());
+        println!("Loaded {} hits", hits.len().separated_string());
+
+        return Ok(hits);
+    }
+
+    let mut hits = Vec::new();
+
+    for input_file in input_files {
+        for cluster in ReadClusterIterator::new(input_file.to_str().unwrap()) {
+            let cluster: Vec<Hit> = cluster.into_iter().filter(|hit| !masked_pixels.contains(&(hit.col, hit.row))).collect();
+
+            if let Some(centroid) = cluster_centroid(&cluster) {
+                hits.push(centroid);
+            }
+        }
+    }
+
+    println!("Loaded {} cluster centroids", hits.len().separated_string());
+
+    Ok(hits)
+}
+
+/// ToT weighted centroid of a cluster, as a hit carrying the cluster's first ToA and total ToT
+fn cluster_centroid(cluster: &[Hit]) -> Option<Hit> {
+    let sum_tot: u32 = cluster.iter().map(|hit| hit.tot).sum();
+
+    if sum_tot == 0 {
+        return None;
+    }
+
+    let col = cluster.iter().map(|hit| f64::from(hit.col) * f64::from(hit.tot)).sum::<f64>() / f64::from(sum_tot);
+    let row = cluster.iter().map(|hit| f64::from(hit.row) * f64::from(hit.tot)).sum::<f64>() / f64::from(sum_tot);
+
+    Some(Hit {
+        col: col.round() as u16,
+        row: row.round() as u16,
+        toa: cluster.iter().map(|hit| hit.toa).min().unwrap(),
+        tot: sum_tot,
+        flags: HitFlags::empty(),
+    })
+}
+
+fn fill_heatmap(hits: &HitSoA, sum_tot: bool) -> Vec<f64> {
+    let mut heatmap = vec![0.0; 256 * 256];
+
+    hits.fill_occupancy(&mut heatmap, sum_tot);
+
+    heatmap
+}
+
+/// Live time (s) of the hits, from the exposure of the gates if given and the first to last ToA otherwise
+fn live_time_seconds(hits: &HitSoA, gates: Option<&[Gate]>) -> f64 {
+    let (first, last) = hits.time_range().unwrap_or((0, 0));
+
+    let run_start = (first as f64 * TOA_CLOCK_TO_NS) as u64;
+    let run_end = (last as f64 * TOA_CLOCK_TO_NS) as u64;
+
+    let live_time = match gates {
+        Some(gates) => calculate_live_time("gates", gates, run_start, run_end).exposure_time,
+        None => run_end - run_start,
+    };
+
+    live_time as f64 / 1e9
+}
+
+fn normalise_heatmap(heatmap: &mut [f64], live_time: f64) {
+    if live_time <= 0.0 {
+        println!("{}", "WARNING: Live time is zero, the heatmap has not been normalised".yellow());
+        return;
+    }
+
+    for x in heatmap.iter_mut() {
+        *x /= live_time;
+    }
+}
+
+/// Path next to `path` with `suffix` added to the file name (eg. `map_ratio.csv` for `map.csv`)
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap().to_str().unwrap();
+
+    match path.extension() {
+        Some(extension) => path.with_file_name(format!("{}_{}.{}", stem, suffix, extension.to_str().unwrap())),
+        None => path.with_file_name(format!("{}_{}", stem, suffix)),
+    }
+}
+
+/// Writes the map as CSV, or as a TIFF image if a sample format is given
+fn write_heatmap(path: &Path, heatmap: &[f64], tiff_sample_format: Option<TiffSampleFormat>) -> io::Result<()> {
+    if let Some(sample_format) = tiff_sample_format {
+        return write_tiff(path, 256, 256, sample_format, heatmap);
+    }
+
+    let mut output_file = fs::File::create(path)?;
+
+    for row in 0..256 {
+        let row_string = heatmap[row * 256..(row + 1) * 256].iter().map(|x| x.to_string()).join(",");
+        writeln!(&mut output_file, "{}", row_string)?;
+    }
+
     Ok(())
 }
+
+fn read_heatmap(path: &Path) -> io::Result<Vec<f64>> {
+    let mut heatmap = Vec::with_capacity(256 * 256);
+
+    for line in fs::read_to_string(path)?.lines() {
+        for value in line.split(',') {
+            let value = value
+                .trim()
+                .parse::<f64>()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid value in heatmap {:#?}: {}", path, err)))?;
+
+            heatmap.push(value);
+        }
+    }
+
+    if heatmap.len() != 256 * 256 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Heatmap {:#?} has {} values rather than 256x256", path, heatmap.len()),
+        ));
+    }
+
+    Ok(heatmap)
+}
@@ -0,0 +1,184 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * -------------------------
+ * Timepix Hits CSV Exporter
+ * -------------------------
+ *
+ * timepix-spidr-data-parser/src/bin/hits_to_csv.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use clap;
+use colored::Colorize;
+use separator::Separatable as _;
+use serde::Serialize;
+
+use timepix_spidr_data_parser::*;
+
+/// Row of the CSV, with the columns of the acquisition mode the hits were recorded in and the UTC
+/// time of the hit with `--absolute-time`
+#[derive(Serialize)]
+struct HitRow {
+    toa: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tot: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    itot: Option<u32>, // ns
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event_count: Option<u32>,
+    col: u16,
+    row: u16,
+    flags: HitFlags,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    utc_ns: Option<i64>, // ns since the Unix epoch
+}
+
+impl HitRow {
+    fn new(hit: &Hit, acq_mode: AcqMode, start_time: Option<&RunStartTime>) -> HitRow {
+        let (tot, itot, event_count) = match acq_mode {
+            AcqMode::ToaTot => (Some(hit.tot), None, None),
+            AcqMode::Toa => (None, None, None),
+            AcqMode::EventCount => {
+                let (itot, event_count) = decode_event_count_field(hit.tot);
+                (None, Some(itot), Some(event_count))
+            }
+        };
+
+        HitRow {
+            toa: hit.toa,
+            tot,
+            itot,
+            event_count,
+            col: hit.col,
+            row: hit.row,
+            flags: hit.flags,
+            utc_ns: start_time.map(|x| x.utc_ns(hit.toa as f64 * TOA_CLOCK_TO_NS)),
+        }
+    }
+}
+
+fn main() -> io::Result<()> {
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(clap::Arg::with_name("INPUT").help("Sets the input hits file").required(true).index(1))
+        .arg(
+            clap::Arg::with_name("OUTPUT")
+                .help("Sets the output CSV file (default is to write to stdout)")
+                .index(2),
+        )
+        .arg(
+            clap::Arg::with_name("head")
+                .help("Only exports the first N hits")
+                .long("head")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("time-range")
+                .help("Only exports hits within the time range 'start:end' (ns)")
+                .long("time-range")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("absolute-time")
+                .help("Adds a 'utc_ns' column with the UTC time of each hit (ns since the Unix epoch), from the start time of the run")
+                .long("absolute-time"),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_file = PathBuf::from(matches.value_of("INPUT").unwrap());
+    let output_file = matches.value_of("OUTPUT");
+
+    let head = matches.value_of("head").and_then(parse_human_readable_number::<usize>);
+
+    let time_range = matches.value_of("time-range").map(|x| {
+        let mut parts = x.splitn(2, ':').map(parse_human_readable_number::<u64>);
+
+        match (parts.next(), parts.next()) {
+            (Some(Some(start)), Some(Some(end))) if start < end => (start, end),
+            _ => panic!("Time range must be given as 'start:end' (ns)"),
+        }
+    });
+
+    let run = Run::open(input_file.parent().unwrap_or_else(|| Path::new(".")));
+
+    // Hits files outside a run directory are taken to be ToA and ToT
+    let acq_mode = match &run {
+        Ok(run) => run.acq_mode()?,
+        Err(_) => AcqMode::ToaTot,
+    };
+
+    let start_time = if matches.is_present("absolute-time") {
+        match run?.start_time()? {
+            Some(start_time) => Some(start_time),
+            None => {
+                eprintln!("{}", "The run start time is not recorded, reparse the run with raw_data_parser for --absolute-time".red());
+                process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    // Keep stdout clean for piping when no output file is given
+    if output_file.is_some() {
+        println!(
+            "\n-------------------------\n{}\n-------------------------\n",
+            "Timepix Hits CSV Exporter".bold()
+        );
+    }
+
+    let mut hits_iterator = ReadHitsIterator::new(&input_file);
+
+    let end_toa = match time_range {
+        Some((start, end)) => {
+            hits_iterator.seek_to_time((start as f64 / TOA_CLOCK_TO_NS) as u64)?;
+            (end as f64 / TOA_CLOCK_TO_NS) as u64
+        }
+        None => u64::MAX,
+    };
+
+    let hits = hits_iterator.take_while(|hit| hit.toa < end_toa).take(head.unwrap_or(usize::MAX));
+
+    let hits_written = match output_file {
+        Some(output_file) => write_csv(csv::Writer::from_writer(fs::File::create(output_file)?), hits, acq_mode, start_time.as_ref())?,
+        None => write_csv(csv::Writer::from_writer(io::stdout()), hits, acq_mode, start_time.as_ref())?,
+    };
+
+    if let Some(output_file) = output_file {
+        println!("Wrote {} hits to {}", hits_written.separated_string(), output_file);
+    }
+
+    Ok(())
+}
+
+fn write_csv<W: io::Write, I: Iterator<Item = Hit>>(
+    mut csv_writer: csv::Writer<W>,
+    hits: I,
+    acq_mode: AcqMode,
+    start_time: Option<&RunStartTime>,
+) -> io::Result<usize> {
+    let mut hits_written = 0;
+
+    for hit in hits {
+        csv_writer.serialize(HitRow::new(&hit, acq_mode, start_time))?;
+
+        hits_written += 1;
+    }
+
+    csv_writer.flush()?;
+
+    Ok(hits_written)
+}
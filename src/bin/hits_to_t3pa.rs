@@ -0,0 +1,55 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * --------------------------
+ * Timepix Hits t3pa Exporter
+ * --------------------------
+ *
+ * timepix-spidr-data-parser/src/bin/hits_to_t3pa.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use clap;
+use colored::Colorize;
+use separator::Separatable as _;
+
+use timepix_spidr_data_parser::*;
+
+fn main() -> io::Result<()> {
+    println!(
+        "\n--------------------------\n{}\n--------------------------\n",
+        "Timepix Hits t3pa Exporter".bold()
+    );
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(clap::Arg::with_name("INPUT").help("Sets the input hits file").required(true).index(1))
+        .arg(
+            clap::Arg::with_name("OUTPUT")
+                .help("Sets the output t3pa file to write")
+                .required(true)
+                .index(2),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_file = PathBuf::from(matches.value_of("INPUT").unwrap());
+    let output_file = matches.value_of("OUTPUT").unwrap();
+
+    let output = io::BufWriter::new(fs::File::create(output_file)?);
+    let hits_written = write_t3pa(output, ReadHitsIterator::new(&input_file))?;
+
+    println!("Wrote {} hits to {}", hits_written.separated_string(), output_file);
+
+    Ok(())
+}
@@ -10,17 +10,11 @@
  * Authors: Jared Vann
  */
 
-use std::cmp;
-use std::fs;
 use std::io;
 use std::io::prelude::*;
-use std::io::SeekFrom;
-use std::mem;
 
-use byteorder::{LittleEndian, ReadBytesExt};
 use clap;
 use colored::Colorize;
-use glob::glob;
 use separator::Separatable as _;
 
 use timepix_spidr_data_parser::*;
@@ -37,22 +31,48 @@ fn main() -> io::Result<()> {
     let matches = clap::App::new("")
         // General options
         .arg(clap::Arg::with_name("INPUT").help("Sets the input file pattern").required(true).index(1))
+        .arg(
+            clap::Arg::with_name("status-output")
+                .help("Sets the pixel status map CSV to write (default is 'pixel_status.csv')")
+                .long("status-output")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("hot-factor")
+                .help("Pixels with more than this many times the median hits are hot (default is 10)")
+                .long("hot-factor")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("dead-fraction")
+                .help("Pixels with at most this fraction of the median hits are dead (default is 0.01)")
+                .long("dead-fraction")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("noisy-column-factor")
+                .help("Columns with more than this many times the median column hits are noisy (default is 3)")
+                .long("noisy-column-factor")
+                .takes_value(true),
+        )
         .get_matches();
 
     //
     // Read command line options
     //
     let input_glob_str = matches.value_of("INPUT").unwrap();
+    let status_output = matches.value_of("status-output").unwrap_or("pixel_status.csv");
+
+    let hot_factor = matches.value_of("hot-factor").and_then(|x| x.parse::<f64>().ok()).unwrap_or(10.0);
+    let dead_fraction = matches.value_of("dead-fraction").and_then(|x| x.parse::<f64>().ok()).unwrap_or(0.01);
+    let noisy_column_factor = matches.value_of("noisy-column-factor").and_then(|x| x.parse::<f64>().ok()).unwrap_or(3.0);
 
     //
     // Parse input file list
     //
-    let input_files: Vec<_> = glob(input_glob_str)
-        .expect("Failed to read input file glob pattern")
-        .filter_map(|x| x.ok()) // Check glob worked
-        .filter(|x| x.is_file()) // Check is file
-        .filter(|x| x.extension().is_some()) // Check has file extension
-        .filter(|x| x.extension().unwrap() == "dat") // Check extension is .dat
+    let input_files: Vec<_> = glob_input_files(input_glob_str)?
+        .into_iter()
+        .filter(|x| is_raw_data_file(x)) // Check extension is .dat (or .dat.gz/.dat.zst)
         .collect();
 
     if input_files.is_empty() {
@@ -62,8 +82,6 @@ fn main() -> io::Result<()> {
 
     println!("Matched {} input files", input_files.len());
 
-    let mut buffer: [u8; BUFFER_SIZE * 8] = [0; BUFFER_SIZE * 8];
-
     let mut packets_parsed: u64 = 0;
 
     let mut pixel_grid: [(u16, u16, u64); 256*256] = [(0,0,0); 256*256];
@@ -78,59 +96,69 @@ fn main() -> io::Result<()> {
     for (i, data_file) in input_files.iter().enumerate() {
         println!("Reading data file {}/{}", i + 1, input_files.len());
 
-        let mut file = fs::File::open(&data_file)?;
+        let mut file = RawFile::open(data_file)?;
+        read_spidr_header(&mut file)?;
 
-        // First bytes in file are spidr ID and subsequent header size
-        let _spidr_id = file.read_u32::<LittleEndian>()?;
-        let spidr_header_size = file.read_u32::<LittleEndian>()?;
-        let spidr_header_size = cmp::min(spidr_header_size, 66304);
+        let mut packets = ReadRawPacketIterator::new(file, false);
 
-        // Skip header
-        file.seek(SeekFrom::Current(i64::from(spidr_header_size)))?;
+        for data in &mut packets {
+            packets_parsed += 1;
 
-        loop {
-            let bytes_read = file.read(&mut buffer)?;
-
-            // Check there are still some bytes to process
-            if bytes_read == 0 {
-                break;
+            if packets_parsed % 10_000_000 == 0 {
+                println!(
+                    "Data file {}/{}; Processed {} packets",
+                    i + 1,
+                    input_files.len(),
+                    packets_parsed.separated_string()
+                );
             }
 
-            // Check that number of bytes is exactly divisible by the size of a hit
-            if bytes_read % 8 != 0 {
-                panic!("Bytes read from {:#?} is not divisible by 8 (the size of a hit)", data_file);
+            let header = ((data & 0xF000_0000_0000_0000) >> 60) & 0xF;
+
+            if header == 0xA || header == 0xB {
+                // Calculate col and row
+                let dcol = (data & 0x0FE0_0000_0000_0000) >> 52; //(16+28+9-1)
+                let spix = (data & 0x001F_8000_0000_0000) >> 45; //(16+28+3-2)
+                let pix = (data & 0x0000_7000_0000_0000) >> 44; //(16+28)
+                let col = (dcol + pix / 4) as usize;
+                let row = (spix + (pix & 0x3)) as usize;
+
+                pixel_grid[row*256 + col].2 += 1;
             }
+        }
 
-            let hit_data = unsafe { mem::transmute::<[u8; BUFFER_SIZE * 8], [u64; BUFFER_SIZE]>(buffer) };
+        if let Some(err) = packets.take_error() {
+            return Err(err);
+        }
+    }
 
-            for data in hit_data.iter().take(bytes_read / 8) {
-                packets_parsed += 1;
+    let status = classify_pixels(&pixel_grid, hot_factor, dead_fraction, noisy_column_factor);
 
-                if packets_parsed % 10_000_000 == 0 {
-                    println!(
-                        "Data file {}/{}; Processed {} packets",
-                        i + 1,
-                        input_files.len(),
-                        packets_parsed.separated_string()
-                    );
-                }
+    let mut csv_writer = csv::Writer::from_path(status_output)?;
 
-                let header = ((data & 0xF000_0000_0000_0000) >> 60) & 0xF;
+    for (pixel, status) in pixel_grid.iter().zip(status.iter()) {
+        csv_writer.serialize(PixelStatusRecord {
+            col: pixel.0,
+            row: pixel.1,
+            hits: pixel.2,
+            status: *status,
+        })?;
+    }
 
-                if header == 0xA || header == 0xB {
-                    // Calculate col and row
-                    let dcol = (data & 0x0FE0_0000_0000_0000) >> 52; //(16+28+9-1)
-                    let spix = (data & 0x001F_8000_0000_0000) >> 45; //(16+28+3-2)
-                    let pix = (data & 0x0000_7000_0000_0000) >> 44; //(16+28)
-                    let col = (dcol + pix / 4) as usize;
-                    let row = (spix + (pix & 0x3)) as usize;
+    csv_writer.flush()?;
 
-                    pixel_grid[row*256 + col].2 += 1;
-                }
-            }
-        }
+    let status_names = [
+        ("Hot", PixelStatus::Hot),
+        ("Dead", PixelStatus::Dead),
+        ("Noisy column", PixelStatus::NoisyColumn),
+    ];
+
+    for (name, pixel_status) in status_names.iter() {
+        println!("{} pixels: {}", name, status.iter().filter(|x| *x == pixel_status).count());
     }
 
+    println!("Wrote pixel status map to {}\n", status_output);
+
     pixel_grid.sort_by(|a, b| b.2.cmp(&a.2));
 
     let mut file = std::fs::File::create("hot_pixels.csv")?;
@@ -147,3 +175,46 @@ fn main() -> io::Result<()> {
 
     Ok(())
 }
+
+fn median(values: &mut [u64]) -> f64 {
+    values.sort();
+
+    let mid = values.len() / 2;
+
+    if values.len() % 2 == 0 {
+        (values[mid - 1] + values[mid]) as f64 / 2.0
+    } else {
+        values[mid] as f64
+    }
+}
+
+/// Classifies each pixel of a flat illumination run by how its hits compare to the median pixel,
+/// and marks the remaining pixels of columns far busier than the median column as noisy.
+fn classify_pixels(pixel_grid: &[(u16, u16, u64)], hot_factor: f64, dead_fraction: f64, noisy_column_factor: f64) -> Vec<PixelStatus> {
+    let median_hits = median(&mut pixel_grid.iter().map(|x| x.2).collect::<Vec<_>>());
+
+    let mut column_hits = vec![0_u64; 256];
+
+    for pixel in pixel_grid {
+        column_hits[pixel.0 as usize] += pixel.2;
+    }
+
+    let median_column_hits = median(&mut column_hits.clone());
+
+    pixel_grid
+        .iter()
+        .map(|(col, _, hits)| {
+            let hits = *hits as f64;
+
+            if hits > hot_factor * median_hits {
+                PixelStatus::Hot
+            } else if hits <= dead_fraction * median_hits {
+                PixelStatus::Dead
+            } else if column_hits[*col as usize] as f64 > noisy_column_factor * median_column_hits {
+                PixelStatus::NoisyColumn
+            } else {
+                PixelStatus::Ok
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,136 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * ----------------
+ * Timepix Run List
+ * ----------------
+ *
+ * timepix-spidr-data-parser/src/bin/list_runs.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::io;
+use std::path::Path;
+use std::process;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use clap;
+use colored::Colorize;
+use separator::Separatable as _;
+
+use timepix_spidr_data_parser::*;
+
+fn main() -> io::Result<()> {
+    println!("\n----------------\n{}\n----------------\n", "Timepix Run List".bold());
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(
+            clap::Arg::with_name("INPUT")
+                .help("Sets the output directory tree to search for runs")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("after")
+                .help("Only lists runs started at or after this time ('YYYY-MM-DD' or 'YYYY-MM-DD HH:MM:SS')")
+                .long("after")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("before")
+                .help("Only lists runs started before this time ('YYYY-MM-DD' or 'YYYY-MM-DD HH:MM:SS')")
+                .long("before")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("device")
+                .help("Only lists runs taken with this chip (e.g. W0005_E09)")
+                .long("device")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("run")
+                .help("Only lists runs whose directory name contains this text")
+                .long("run")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("with")
+                .help("Only lists runs that have this cluster/event file (without extension!), e.g. 'clusters'")
+                .long("with")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_dir = Path::new(matches.value_of("INPUT").unwrap());
+
+    let query = RunQuery {
+        after: matches.value_of("after").map(|x| parse_time_arg("after", x)),
+        before: matches.value_of("before").map(|x| parse_time_arg("before", x)),
+        device: matches.value_of("device").map(|x| x.to_owned()),
+        name: matches.value_of("run").map(|x| x.to_owned()),
+        cluster_file: matches.value_of("with").map(|x| x.to_owned()),
+    };
+
+    let index = RunIndex::scan(input_dir)?;
+    let runs = index.query(&query);
+
+    if runs.is_empty() {
+        println!("No runs matched! ({} runs found)", index.len());
+        return Ok(());
+    }
+
+    println!(
+        "{:<40} {:<19} {:<10} {:>14} {:<8} {:<5} Cluster Files",
+        "Run", "Start Time", "Device", "Hits", "Triggers", "Gates"
+    );
+
+    for run in &runs {
+        let start_time = run.start_time.map_or("-".to_owned(), |x| x.format("%Y-%m-%d %H:%M:%S").to_string());
+        let yes_no = |x: bool| if x { "yes" } else { "no" };
+        let cluster_files = if run.cluster_files.is_empty() { "-".to_owned() } else { run.cluster_files.join(", ") };
+
+        let line = format!(
+            "{:<40} {:<19} {:<10} {:>14} {:<8} {:<5} {}",
+            run.dir_name(),
+            start_time,
+            run.device.as_deref().unwrap_or("-"),
+            run.hits.separated_string(),
+            yes_no(run.triggers),
+            yes_no(run.gates),
+            cluster_files
+        );
+
+        if run.partial {
+            println!("{} {}", line, "(partial)".yellow());
+        } else {
+            println!("{}", line);
+        }
+    }
+
+    println!("{}", format!("\nListed {} of {} runs", runs.len(), index.len()).bold());
+
+    Ok(())
+}
+
+fn parse_time_arg(name: &str, value: &str) -> NaiveDateTime {
+    let time = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S")
+        .ok()
+        .or_else(|| NaiveDate::parse_from_str(value, "%Y-%m-%d").ok().and_then(|x| x.and_hms_opt(0, 0, 0)));
+
+    match time {
+        Some(time) => time,
+        None => {
+            println!("{}", format!("Invalid --{} time '{}'", name, value).red());
+            process::exit(1);
+        }
+    }
+}
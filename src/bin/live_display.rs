@@ -0,0 +1,468 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * --------------------------
+ * Timepix Live Event Display
+ * --------------------------
+ *
+ * timepix-spidr-data-parser/src/bin/live_display.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap;
+use colored::Colorize;
+use separator::Separatable as _;
+use serde::Serialize;
+use tungstenite::{Message, WebSocket};
+
+use timepix_spidr_data_parser::*;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100); // Wait for more data once the followed file has been read to its end
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(1); // Clients that cannot keep up are dropped
+
+struct Settings {
+    listen: String,
+    update_interval: Duration,
+    max_clusters: usize,
+    sort_window: u64, // ToA clock ticks
+    from_start: bool,
+    clustering: ClusterSettings,
+}
+
+/// Sent to every client as JSON each update interval
+#[derive(Serialize)]
+struct LiveUpdate<'a> {
+    file: &'a str,
+    time: f64,     // Time of the latest data (s)
+    interval: f64, // Wall clock time since the last update (s)
+    hits: usize,
+    triggers: usize,
+    clusters: usize,
+    hit_rate: f64,                     // Hz
+    trigger_rate: f64,                 // Hz
+    cluster_rate: f64,                 // Hz
+    late_hits: usize,                  // Hits that arrived further out of order than the sort window and were dropped
+    heatmap: Vec<[u32; 3]>,            // (col, row, hits) of every pixel hit during the interval
+    recent_clusters: Vec<LiveCluster>, // Most recent clusters completed during the interval
+}
+
+#[derive(Serialize)]
+struct LiveCluster {
+    time: f64,     // s
+    duration: f64, // ns
+    sum_tot: u32,  // ns
+    flags: u8,
+    hits: Vec<[u64; 4]>, // (col, row, ToA after the first hit (ns), ToT (ns))
+}
+
+impl LiveCluster {
+    fn new(cluster: &[Hit], flags: u8) -> LiveCluster {
+        let first_toa = cluster[0].toa;
+        let last_toa = cluster[cluster.len() - 1].toa;
+
+        LiveCluster {
+            time: first_toa as f64 * TOA_CLOCK_TO_NS / 1e9,
+            duration: (last_toa - first_toa) as f64 * TOA_CLOCK_TO_NS,
+            sum_tot: cluster.iter().map(|hit| hit.tot).sum(),
+            flags,
+            hits: cluster
+                .iter()
+                .map(|hit| {
+                    [
+                        u64::from(hit.col),
+                        u64::from(hit.row),
+                        ((hit.toa - first_toa) as f64 * TOA_CLOCK_TO_NS) as u64,
+                        u64::from(hit.tot),
+                    ]
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Counts since the last update
+struct IntervalStats {
+    hits: usize,
+    triggers: usize,
+    clusters: usize,
+    late_hits: usize,
+    heatmap: Vec<u32>,
+    recent_clusters: VecDeque<(Vec<Hit>, u8)>,
+}
+
+impl IntervalStats {
+    fn new() -> IntervalStats {
+        IntervalStats {
+            hits: 0,
+            triggers: 0,
+            clusters: 0,
+            late_hits: 0,
+            heatmap: vec![0; 256 * 256],
+            recent_clusters: VecDeque::new(),
+        }
+    }
+
+    fn add_hit(&mut self, hit: &Hit) {
+        self.hits += 1;
+
+        if hit.col <= 255 && hit.row <= 255 {
+            self.heatmap[usize::from(hit.row) * 256 + usize::from(hit.col)] += 1;
+        }
+    }
+
+    fn add_cluster(&mut self, cluster: Vec<Hit>, flags: u8, max_clusters: usize) {
+        self.clusters += 1;
+
+        self.recent_clusters.push_back((cluster, flags));
+
+        if self.recent_clusters.len() > max_clusters {
+            self.recent_clusters.pop_front();
+        }
+    }
+
+    fn update<'a>(&self, file: &'a str, toa: u64, interval: f64) -> LiveUpdate<'a> {
+        let heatmap = self
+            .heatmap
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(i, count)| [(i % 256) as u32, (i / 256) as u32, *count])
+            .collect();
+
+        LiveUpdate {
+            file,
+            time: toa as f64 * TOA_CLOCK_TO_NS / 1e9,
+            interval,
+            hits: self.hits,
+            triggers: self.triggers,
+            clusters: self.clusters,
+            hit_rate: self.hits as f64 / interval,
+            trigger_rate: self.triggers as f64 / interval,
+            cluster_rate: self.clusters as f64 / interval,
+            late_hits: self.late_hits,
+            heatmap,
+            recent_clusters: self
+                .recent_clusters
+                .iter()
+                .map(|(cluster, flags)| LiveCluster::new(cluster, *flags))
+                .collect(),
+        }
+    }
+}
+
+struct Client {
+    address: SocketAddr,
+    websocket: WebSocket<TcpStream>,
+}
+
+fn main() -> io::Result<()> {
+    println!(
+        "\n--------------------------\n{}\n--------------------------\n",
+        "Timepix Live Event Display".bold()
+    );
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(
+            clap::Arg::with_name("INPUT")
+                .help("Sets the raw data file pattern, the newest matching file is followed as it is written")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("listen")
+                .help("Address to serve the websocket on (default is 0.0.0.0:9001)")
+                .long("listen")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("update-interval")
+                .help("Time between updates sent to clients (ms) (default is 1000)")
+                .long("update-interval")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("max-clusters")
+                .help("Maximum number of the most recent clusters sent with each update (default is 20)")
+                .long("max-clusters")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("sort-window")
+                .help("Time hits are held back to be sorted back into time order before clustering (µs) (default is 500)")
+                .long("sort-window")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("from-start")
+                .help("Reads the first file from its start rather than from the data written after starting")
+                .long("from-start"),
+        )
+        // Clustering options
+        .arg(
+            clap::Arg::with_name("min-cluster-hits")
+                .help("Minimum cluster size in hits")
+                .long("min-cluster-hits")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("max-pixel-gap")
+                .help("Maximum gap in pixels to include in cluster")
+                .long("max-pixel-gap")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("max-toa-gap")
+                .help("Maximum gap in ToA to include in cluster (ns)")
+                .long("max-toa-gap")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("toa-window")
+                .help("Maximum distance in time to look when clustering (ns), clusters are shown once this has elapsed")
+                .long("toa-window")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_glob_str = matches.value_of("INPUT").unwrap();
+
+    let settings = {
+        let listen = matches.value_of("listen").unwrap_or("0.0.0.0:9001").to_owned();
+        let update_interval = Duration::from_millis(matches.value_of("update-interval").map_or(1000, |x| x.parse::<u64>().unwrap()));
+        let max_clusters = matches.value_of("max-clusters").map_or(20, |x| x.parse::<usize>().unwrap());
+        let sort_window = (matches.value_of("sort-window").map_or(500.0, |x| x.parse::<f64>().unwrap()) * 1000.0 / TOA_CLOCK_TO_NS) as u64;
+        let from_start = matches.is_present("from-start");
+
+        let min_cluster_hits = matches.value_of("min-cluster-hits").and_then(parse_human_readable_number).unwrap_or(1); // 1 hit
+        let max_pixel_gap = matches.value_of("max-pixel-gap").and_then(parse_human_readable_number).unwrap_or(3);
+        let max_toa_gap = (matches.value_of("max-toa-gap").and_then(parse_human_readable_number).unwrap_or(5_000) as f64 / TOA_CLOCK_TO_NS) as u32; // 5µs
+        let toa_window = (matches.value_of("toa-window").and_then(parse_human_readable_number).unwrap_or(1_000_000) as f64 / TOA_CLOCK_TO_NS) as u32; // 1ms
+
+        assert!(min_cluster_hits > 0);
+        assert!(update_interval > Duration::from_millis(0));
+
+        Settings {
+            listen,
+            update_interval,
+            max_clusters,
+            sort_window,
+            from_start,
+            clustering: ClusterSettings {
+                min_cluster_hits,
+                min_cluster_tot: 0,
+                max_pixel_gap,
+                max_toa_gap,
+                toa_window,
+                max_cluster_hits: None,
+                max_cluster_duration: None,
+                oversize_policy: OversizePolicy::Truncate,
+            },
+        }
+    };
+
+    install_interrupt_handler();
+
+    //
+    // Accept websocket clients in the background
+    //
+    let listener = match TcpListener::bind(&settings.listen) {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("{}", format!("Failed to listen on {}: {}", settings.listen, e).red());
+            process::exit(1);
+        }
+    };
+
+    let clients = Arc::new(Mutex::new(Vec::new()));
+    let latest_update = Arc::new(Mutex::new(None));
+
+    {
+        let clients = Arc::clone(&clients);
+        let latest_update = Arc::clone(&latest_update);
+
+        thread::spawn(move || accept_clients(listener, clients, latest_update));
+    }
+
+    println!("Serving live events on ws://{}", settings.listen);
+
+    //
+    // Follow the raw data, clustering it as it arrives
+    //
+    let mut follower = RawDataFollower::new(input_glob_str, settings.from_start);
+
+    // Hits arrive roughly in time order, they are held back for the sort window to put them in order
+    let mut sort_buffer = BinaryHeap::new();
+    let mut latest_toa = 0;
+    let mut last_released_toa = 0;
+
+    let mut stream = ClusterStream::new(&settings.clustering);
+
+    let mut stats = IntervalStats::new();
+    let mut last_update = Instant::now();
+
+    let mut total_hits = 0;
+    let mut waiting_for_file = false;
+
+    while !is_interrupted() {
+        let decoded = match follower.read() {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                println!("{}", e.to_string().yellow());
+                None
+            }
+        };
+
+        match decoded {
+            Some((hits, triggers)) => {
+                stats.triggers += triggers.len();
+                total_hits += hits.len();
+
+                for hit in hits {
+                    stats.add_hit(&hit);
+                    latest_toa = latest_toa.max(hit.toa);
+
+                    if hit.toa < last_released_toa {
+                        stats.late_hits += 1;
+                        continue;
+                    }
+
+                    sort_buffer.push(Reverse(hit));
+                }
+            }
+            None => {
+                // Move on to a newer file once the DAQ starts one, the current file having been read to its end
+                match follower.follow_newest() {
+                    Ok(true) => {
+                        println!("Following {}", follower.path().unwrap().display());
+                        waiting_for_file = false;
+                        continue;
+                    }
+                    Ok(false) if !waiting_for_file && follower.path().is_none() => {
+                        println!("Waiting for raw files matching '{}'", input_glob_str);
+                        waiting_for_file = true;
+                    }
+                    Ok(false) => (),
+                    Err(e) => println!("{}", e.to_string().yellow()),
+                }
+
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+
+        // The global timestamp keeps moving while the detector is quiet, so clusters are still completed
+        if let Some(timeline_toa) = follower.timeline_toa() {
+            latest_toa = latest_toa.max(timeline_toa);
+        }
+
+        let release_toa = latest_toa.saturating_sub(settings.sort_window);
+
+        while let Some(Reverse(hit)) = sort_buffer.peek() {
+            if hit.toa > release_toa {
+                break;
+            }
+
+            last_released_toa = hit.toa;
+            stream.push(sort_buffer.pop().unwrap().0);
+        }
+
+        stream.advance_to(release_toa);
+
+        while let Some((cluster, flags)) = stream.next_completed() {
+            stats.add_cluster(cluster, flags, settings.max_clusters);
+        }
+
+        //
+        // Send an update to every client
+        //
+        let elapsed = last_update.elapsed();
+
+        if elapsed >= settings.update_interval {
+            let file = follower.path().map_or_else(String::new, |x| x.display().to_string());
+            let update = serde_json::to_string(&stats.update(&file, latest_toa, elapsed.as_secs_f64()))?;
+
+            broadcast(&clients, &update);
+            *latest_update.lock().unwrap() = Some(update);
+
+            stats = IntervalStats::new();
+            last_update = Instant::now();
+        }
+    }
+
+    println!(
+        "Stopped after {} hits and {} clusters",
+        total_hits.separated_string(),
+        stream.clusters_found().separated_string()
+    );
+
+    Ok(())
+}
+
+fn accept_clients(listener: TcpListener, clients: Arc<Mutex<Vec<Client>>>, latest_update: Arc<Mutex<Option<String>>>) {
+    for stream in listener.incoming().filter_map(|x| x.ok()) {
+        let clients = Arc::clone(&clients);
+        let latest_update = Arc::clone(&latest_update);
+
+        // Handshakes are done on their own thread so a stalled client does not hold up the others
+        thread::spawn(move || {
+            let address = match stream.peer_addr() {
+                Ok(address) => address,
+                Err(_) => return,
+            };
+
+            if stream.set_read_timeout(Some(CLIENT_TIMEOUT)).is_err() || stream.set_write_timeout(Some(CLIENT_TIMEOUT)).is_err() {
+                return;
+            }
+
+            let mut websocket = match tungstenite::accept(stream) {
+                Ok(websocket) => websocket,
+                Err(e) => {
+                    println!("{}", format!("Websocket handshake with {} failed: {}", address, e).yellow());
+                    return;
+                }
+            };
+
+            // New clients are sent the latest update straight away rather than after the next interval
+            let update = latest_update.lock().unwrap().clone();
+
+            if let Some(update) = update {
+                if websocket.send(Message::Text(update)).is_err() {
+                    return;
+                }
+            }
+
+            println!("Client connected from {}", address);
+
+            clients.lock().unwrap().push(Client { address, websocket });
+        });
+    }
+}
+
+fn broadcast(clients: &Mutex<Vec<Client>>, update: &str) {
+    clients
+        .lock()
+        .unwrap()
+        .retain_mut(|client| match client.websocket.send(Message::Text(update.to_owned())) {
+            Ok(()) => true,
+            Err(e) => {
+                println!("Client {} disconnected ({})", client.address, e);
+                false
+            }
+        });
+}
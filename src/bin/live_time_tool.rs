@@ -0,0 +1,214 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * ----------------------
+ * Timepix Live Time Tool
+ * ----------------------
+ *
+ * timepix-spidr-data-parser/src/bin/live_time_tool.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+
+use clap;
+use colored::Colorize;
+use glob::glob;
+use toml;
+
+use timepix_spidr_data_parser::*;
+
+fn main() -> io::Result<()> {
+    println!(
+        "\n----------------------\n{}\n----------------------\n",
+        "Timepix Live Time Tool".bold()
+    );
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(
+            clap::Arg::with_name("INPUT")
+                .help("Sets the input directory pattern")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("window-size")
+                .help("Acquisition window in us after each trigger, for runs without gates (default is taken from the trigger events TOML)")
+                .long("window-size")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("post-trigger-percent")
+                .help("Set the percentage of the acquistion window to place after the trigger (default is 100)")
+                .long("post-trigger-percent")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("look-behind")
+                .help("Time before each trigger in the window, for runs without gates (us) (default is 0)")
+                .long("look-behind")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("look-ahead")
+                .help("Time after each trigger in the window, for runs without gates (us) (default is 0)")
+                .long("look-ahead")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("events-filename")
+                .help("Sets the trigger events filename (without extension!) to take the window from (default is 'trigger_events')")
+                .long("events-filename")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("dry-run")
+                .help("Shows output of what runs will be processed without changing any data")
+                .long("dry-run"),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_glob_str = matches.value_of("INPUT").unwrap();
+
+    let window = parse_trigger_window(
+        matches.value_of("look-behind"),
+        matches.value_of("look-ahead"),
+        matches.value_of("window-size"),
+        matches.value_of("post-trigger-percent"),
+    );
+
+    let window = match window {
+        Ok(window) => window,
+        Err(err) => {
+            println!("{}", err.red());
+            process::exit(1);
+        }
+    };
+
+    let events_filename = matches.value_of("events-filename").unwrap_or("trigger_events");
+
+    let dry_run = matches.is_present("dry-run");
+
+    //
+    // Parse input file list
+    //
+    let input_dirs: Vec<_> = glob(input_glob_str)
+        .expect("Failed to read input file glob pattern")
+        .filter_map(|x| x.ok()) // Check glob worked
+        .filter(|x| x.is_dir()) // Check is directory
+        .filter(|x| x.join("gates.csv").exists() || x.join("triggers.bin").exists() || x.join("triggers.csv").exists())
+        .collect();
+
+    if input_dirs.is_empty() {
+        println!("No input directories matched!");
+        return Ok(());
+    }
+
+    println!("Matched {} input directories", input_dirs.len());
+
+    if dry_run {
+        let mut plan = DryRunPlan::new();
+
+        for input_dir in &input_dirs {
+            plan.run(input_dir, run_file_bytes(input_dir, &["gates.csv", "triggers.bin", "triggers.csv"]), None);
+        }
+
+        plan.print();
+
+        return Ok(());
+    }
+
+    for input_dir in input_dirs {
+        let run_name = input_dir.file_stem().unwrap().to_str().unwrap();
+
+        let timer = ProcessingTimer::start(&input_dir);
+
+        let live_time = process_run(&input_dir, window, events_filename)?;
+
+        timer.finish(run_file_bytes(&input_dir, &["gates.csv", "triggers.bin", "triggers.csv"]))?;
+
+        match live_time {
+            Some(live_time) => println!(
+                "{} | {} | Exposure {:.3} s | Dead Time {:.3} s | Duty Cycle {:.2}%",
+                run_name,
+                live_time.source,
+                live_time.exposure_time as f64 * 1e-9,
+                live_time.dead_time as f64 * 1e-9,
+                live_time.duty_cycle * 100.0
+            ),
+            None => println!(
+                "{}",
+                format!("{} | Skipped (no gates and no trigger window size known)", run_name).yellow()
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the trigger window (look behind, look ahead) from the settings TOML of a trigger events file.
+fn read_window_from_toml(run_dir: &Path, events_filename: &str) -> Option<(u64, u64)> {
+    let value = fs::read_to_string(run_dir.join(format!("{}.toml", events_filename)))
+        .ok()?
+        .parse::<toml::Value>()
+        .ok()?;
+
+    let window_look_behind = value.get("window_look_behind")?.as_integer()? as u64;
+    let window_look_ahead = value.get("window_look_ahead")?.as_integer()? as u64;
+
+    Some((window_look_behind, window_look_ahead))
+}
+
+fn process_run(run_dir: &Path, window: Option<(u64, u64)>, events_filename: &str) -> io::Result<Option<LiveTime>> {
+    let gates_file_path = run_dir.join("gates.csv");
+
+    let (source, intervals) = if gates_file_path.exists() {
+        ("gates", read_gate_data(&gates_file_path)?)
+    } else {
+        let (window_look_behind, window_look_ahead) = match window.or_else(|| read_window_from_toml(run_dir, events_filename)) {
+            Some(window) => window,
+            None => return Ok(None),
+        };
+
+        let windows = read_run_triggers(run_dir)?
+            .iter()
+            .map(|trigger| Gate {
+                start: trigger.time.saturating_sub(window_look_behind),
+                end: trigger.time + window_look_ahead,
+            })
+            .collect();
+
+        ("trigger_windows", windows)
+    };
+
+    if intervals.is_empty() {
+        return Ok(None);
+    }
+
+    // Run span taken from the first and last hits where available
+    let hits_file_path = run_dir.join("hits.bin");
+
+    let hits_time_range = if hits_file_path.exists() { read_hits_time_range(&hits_file_path)? } else { None };
+
+    let (run_start, run_end) = match hits_time_range {
+        Some((first, last)) => ((first as f64 * TOA_CLOCK_TO_NS) as u64, (last as f64 * TOA_CLOCK_TO_NS) as u64),
+        None => (u64::MAX, 0),
+    };
+
+    let live_time = calculate_live_time(source, &intervals, run_start, run_end);
+
+    update_run_summary(run_dir, "live_time", &live_time)?;
+
+    Ok(Some(live_time))
+}
@@ -0,0 +1,124 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * -----------------------
+ * Timepix Hits Merge Tool
+ * -----------------------
+ *
+ * timepix-spidr-data-parser/src/bin/merge_hits.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap;
+use colored::Colorize;
+use glob::glob;
+use indicatif::{ProgressBar, ProgressStyle};
+use separator::Separatable as _;
+use serde::Serialize;
+
+use timepix_spidr_data_parser::*;
+
+#[derive(Serialize)]
+struct Settings {
+    input_files: Vec<PathBuf>,
+}
+
+fn main() -> io::Result<()> {
+    println!(
+        "\n-----------------------\n{}\n-----------------------\n",
+        "Timepix Hits Merge Tool".bold()
+    );
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(
+            clap::Arg::with_name("INPUT")
+                .help("Sets the input hits file pattern (eg. 'runs/*/chunks/*/hits.bin')")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("OUTPUT")
+                .help("Sets the output hits file to write")
+                .required(true)
+                .index(2),
+        )
+        .arg(
+            clap::Arg::with_name("dry-run")
+                .help("Shows output of what files will be processed without changing any data")
+                .long("dry-run"),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_glob_str = matches.value_of("INPUT").unwrap();
+    let output_file = Path::new(matches.value_of("OUTPUT").unwrap());
+
+    let dry_run = matches.is_present("dry-run");
+
+    if output_file.exists() {
+        println!("{}", format!("Output file '{}' already exists!", output_file.display()).red());
+        return Ok(());
+    }
+
+    //
+    // Parse input file list
+    //
+    let input_files: Vec<_> = glob(input_glob_str)
+        .expect("Failed to read input file glob pattern")
+        .filter_map(|x| x.ok()) // Check glob worked
+        .filter(|x| x.is_file()) // Check is file
+        .filter(|x| x.extension().is_some()) // Check has file extension
+        .filter(|x| x.extension().unwrap() == "bin") // Check extension is .bin
+        .collect();
+
+    if input_files.is_empty() {
+        println!("No input files matched!");
+        return Ok(());
+    }
+
+    println!("Matched {} input files", input_files.len());
+
+    if dry_run {
+        // Every hit is written once
+        let mut plan = DryRunPlan::new().output_dir(output_file.parent().unwrap()).output_ratio(1.0);
+
+        for input_file in &input_files {
+            let input_bytes = input_file.metadata()?.len();
+
+            plan.run(input_file, input_bytes, Some(input_bytes / HIT_RECORD_SIZE as u64));
+        }
+
+        plan.print();
+
+        return Ok(());
+    }
+
+    let n_hits: u64 = input_files.iter().map(|x| x.metadata().unwrap().len() / 16).sum();
+
+    let progress_bar = ProgressBar::new(n_hits);
+    progress_bar.set_style(
+        ProgressStyle::default_bar()
+            .template(PROGRESS_BAR_TEMPLATE)
+            .progress_chars(PROGRESS_BAR_CHARS),
+    );
+
+    let hits_written = merge_hits_files(&input_files, output_file, &progress_bar)?;
+
+    progress_bar.finish_with_message(&format!("| Done | {} Hits Merged", hits_written.separated_string()));
+
+    // Write metadata to TOML file
+    let settings = Settings { input_files };
+    write_settings_toml(&output_file.with_extension("toml"), &settings, &settings.input_files)?;
+
+    Ok(())
+}
@@ -0,0 +1,403 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * ----------------------
+ * Timepix ML Export Tool
+ * ----------------------
+ *
+ * timepix-spidr-data-parser/src/bin/ml_export.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap;
+use colored::Colorize;
+use glob::glob;
+use indicatif::{ProgressBar, ProgressStyle};
+use separator::Separatable as _;
+use serde::{Deserialize, Serialize};
+
+use timepix_spidr_data_parser::*;
+
+/// Layout of the exported tensors
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    /// ToT summed into (time bin, row, col) voxels
+    Voxels,
+    /// Zero-padded list of (col, row, time, ToT) points
+    Points,
+}
+
+#[derive(Clone, Serialize)]
+struct Settings {
+    input_filename: String,
+    output_dirname: String,
+    format: Format,
+    time_bins: usize,
+    time_bin_width: f64,
+    max_points: usize,
+    batch_size: usize,
+    labels_file: Option<PathBuf>,
+}
+
+impl Settings {
+    /// Shape of a single event tensor
+    fn event_shape(&self) -> Vec<usize> {
+        match self.format {
+            Format::Voxels => vec![self.time_bins, 256, 256],
+            Format::Points => vec![self.max_points, 4],
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Label {
+    event: usize,
+    label: i64,
+}
+
+/// Row of `events.csv`, locating each exported event in the batch files
+#[derive(Serialize)]
+struct ExportedEvent {
+    run: String,
+    event: usize,
+    uid: String,
+    batch: usize,
+    index: usize,
+    hits: usize,
+    truncated: bool,
+    label: Option<i64>,
+}
+
+fn main() -> io::Result<()> {
+    println!("\n----------------------\n{}\n----------------------\n", "Timepix ML Export Tool".bold());
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(
+            clap::Arg::with_name("INPUT")
+                .help("Sets the input directory pattern")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("input-filename")
+                .help("Sets the input filename (without extension!) to use (default is 'clusters')")
+                .long("input-filename")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("output-dirname")
+                .help("Sets the directory within each run to write the dataset to (default is 'ml_export')")
+                .long("output-dirname")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("format")
+                .help("Exports events as ToT voxels (N, T, 256, 256) or zero-padded points (N, P, 4) (default is voxels)")
+                .long("format")
+                .possible_values(&["voxels", "points"])
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("time-bins")
+                .help("Number of time bins in each voxel tensor (default is 16)")
+                .long("time-bins")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("time-bin-width")
+                .help("Width of each voxel time bin (ns) (default is 100)")
+                .long("time-bin-width")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("max-points")
+                .help("Number of points in each point list, longer events are truncated (default is 512)")
+                .long("max-points")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("batch-size")
+                .help("Number of events in each .npy file (default is 64)")
+                .long("batch-size")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("labels")
+                .help("CSV file with 'event' and 'label' columns, only labelled events are exported")
+                .long("labels")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("dry-run")
+                .help("Shows output of what runs will be processed without changing any data")
+                .long("dry-run"),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_glob_str = matches.value_of("INPUT").unwrap();
+
+    let settings = {
+        let input_filename = matches.value_of("input-filename").unwrap_or("clusters").to_owned();
+        let output_dirname = matches.value_of("output-dirname").unwrap_or("ml_export").to_owned();
+
+        let format = match matches.value_of("format") {
+            Some("points") => Format::Points,
+            _ => Format::Voxels,
+        };
+
+        let time_bins = matches.value_of("time-bins").and_then(parse_human_readable_number).unwrap_or(16);
+        let time_bin_width = matches.value_of("time-bin-width").and_then(|x| x.parse::<f64>().ok()).unwrap_or(100.0);
+        let max_points = matches.value_of("max-points").and_then(parse_human_readable_number).unwrap_or(512);
+        let batch_size = matches.value_of("batch-size").and_then(parse_human_readable_number).unwrap_or(64);
+        let labels_file = matches.value_of("labels").map(PathBuf::from);
+
+        assert!(time_bins > 0 && time_bin_width > 0.0 && max_points > 0 && batch_size > 0);
+
+        Settings {
+            input_filename,
+            output_dirname,
+            format,
+            time_bins,
+            time_bin_width,
+            max_points,
+            batch_size,
+            labels_file,
+        }
+    };
+
+    let labels = match &settings.labels_file {
+        Some(labels_file) => {
+            let mut rdr = csv::Reader::from_path(labels_file)?;
+            let mut labels = HashMap::new();
+
+            for result in rdr.deserialize() {
+                let label: Label = result?;
+                labels.insert(label.event, label.label);
+            }
+
+            println!("Read {} labels", labels.len());
+
+            Some(labels)
+        }
+        None => None,
+    };
+
+    let dry_run = matches.is_present("dry-run");
+
+    //
+    // Parse input file list
+    //
+    let matched_dirs: Vec<_> = glob(input_glob_str)
+        .expect("Failed to read input file glob pattern")
+        .filter_map(|x| x.ok()) // Check glob worked
+        .filter(|x| x.is_dir()) // Check is directory
+        .filter(|x| x.join(format!("{}.bin", settings.input_filename)).exists())
+        .filter(|x| x.join(format!("{}.csv", settings.input_filename)).exists())
+        .collect();
+
+    // Runs with existing output files are skipped
+    let (input_dirs, existing_dirs): (Vec<_>, Vec<_>) = matched_dirs.into_iter().partition(|x| !x.join(&settings.output_dirname).exists());
+
+    if input_dirs.is_empty() && (!dry_run || existing_dirs.is_empty()) {
+        println!("No input directories matched!");
+        return Ok(());
+    }
+
+    println!("Matched {} input directories", input_dirs.len());
+
+    if dry_run {
+        let mut plan = DryRunPlan::new();
+
+        for input_dir in &input_dirs {
+            let input_bytes = input_dir.join(format!("{}.bin", settings.input_filename)).metadata()?.len();
+            let hits = Run::open(input_dir)?.cluster_hit_count(&settings.input_filename).ok();
+
+            plan.run(input_dir, input_bytes, hits);
+        }
+
+        for existing_dir in &existing_dirs {
+            plan.skipped(existing_dir);
+        }
+
+        plan.print();
+
+        return Ok(());
+    }
+
+    let sty = ProgressStyle::default_bar()
+        .template(PROGRESS_BAR_TEMPLATE)
+        .progress_chars(PROGRESS_BAR_CHARS);
+
+    for input_dir in input_dirs {
+        let mut rdr = csv::Reader::from_path(input_dir.join(format!("{}.csv", settings.input_filename)))?;
+        let input_metadata: Vec<ClusterMetadata> = rdr.deserialize().map(|x| x.unwrap()).collect();
+
+        let progress_bar = ProgressBar::new(input_metadata.len() as u64);
+        progress_bar.set_style(sty.clone());
+
+        let timer = ProcessingTimer::start(&input_dir);
+
+        process_run(&input_dir, &input_metadata, &settings, labels.as_ref(), &progress_bar)?;
+
+        timer.finish(input_dir.join(format!("{}.bin", settings.input_filename)).metadata()?.len())?;
+    }
+
+    Ok(())
+}
+
+/// Fills the tensor for a single event, returning whether any hits did not fit in it
+fn fill_event_tensor(tensor: &mut [f32], cluster: &[Hit], settings: &Settings) -> bool {
+    let mut truncated = false;
+
+    for (i, hit) in cluster.iter().enumerate() {
+        // ToA values are counted from the earliest hit of the event (`Run::relative_clusters`)
+        let time = hit.toa as f64 * TOA_CLOCK_TO_NS;
+
+        match settings.format {
+            Format::Voxels => {
+                let bin = (time / settings.time_bin_width) as usize;
+
+                if bin >= settings.time_bins || hit.col > 255 || hit.row > 255 {
+                    truncated = true;
+                    continue;
+                }
+
+                tensor[(bin * 256 + hit.row as usize) * 256 + hit.col as usize] += hit.tot as f32;
+            }
+            Format::Points => {
+                if i >= settings.max_points {
+                    truncated = true;
+                    break;
+                }
+
+                let point = &mut tensor[i * 4..(i + 1) * 4];
+                point[0] = f32::from(hit.col);
+                point[1] = f32::from(hit.row);
+                point[2] = time as f32;
+                point[3] = hit.tot as f32;
+            }
+        }
+    }
+
+    truncated
+}
+
+fn write_batch(output_dir: &Path, batch: usize, settings: &Settings, tensors: &[f32], labels: &[i64]) -> io::Result<()> {
+    let event_shape = settings.event_shape();
+    let n_events = tensors.len() / event_shape.iter().product::<usize>();
+
+    let mut shape = vec![n_events];
+    shape.extend(event_shape);
+
+    let mut file = fs::File::create(output_dir.join(format!("batch_{:04}.npy", batch)))?;
+    write_npy_f32(&mut file, &shape, tensors)?;
+
+    if settings.labels_file.is_some() {
+        let mut file = fs::File::create(output_dir.join(format!("batch_{:04}_labels.npy", batch)))?;
+        write_npy_i64(&mut file, &[labels.len()], labels)?;
+    }
+
+    Ok(())
+}
+
+fn process_run(
+    run_dir: &Path,
+    input_metadata: &[ClusterMetadata],
+    settings: &Settings,
+    labels: Option<&HashMap<usize, i64>>,
+    progress_bar: &ProgressBar,
+) -> io::Result<()> {
+    let run_name = run_dir.file_stem().unwrap().to_str().unwrap();
+
+    let input_data_file_path = run_dir.join(format!("{}.bin", settings.input_filename));
+    // Only the times within each event are used, so files with signed ToA values can be read too
+    let cluster_iterator = Run::open(run_dir)?
+        .relative_clusters(&settings.input_filename)?
+        .map(|(_, cluster)| cluster);
+
+    let output_dir = run_dir.join(&settings.output_dirname);
+    fs::create_dir_all(&output_dir)?;
+
+    // Write metadata to TOML file
+    write_settings_toml(&output_dir.join("ml_export.toml"), settings, &[&input_data_file_path])?;
+
+    let mut csv_writer = csv::Writer::from_path(output_dir.join("events.csv"))?;
+
+    let event_size: usize = settings.event_shape().iter().product();
+
+    let mut batch = 0;
+    let mut tensors: Vec<f32> = Vec::with_capacity(settings.batch_size * event_size);
+    let mut batch_labels = Vec::with_capacity(settings.batch_size);
+
+    let mut events_exported = 0;
+
+    progress_bar.set_message(&format!("| 0 Events Exported | {}", run_name));
+
+    for (metadata, cluster) in input_metadata.iter().zip(cluster_iterator) {
+        progress_bar.inc(1);
+
+        let label = match labels {
+            Some(labels) => match labels.get(&metadata.event) {
+                Some(label) => Some(*label),
+                None => continue,
+            },
+            None => None,
+        };
+
+        let index = tensors.len() / event_size;
+
+        tensors.resize(tensors.len() + event_size, 0.0);
+        let truncated = fill_event_tensor(&mut tensors[index * event_size..], &cluster, settings);
+
+        if let Some(label) = label {
+            batch_labels.push(label);
+        }
+
+        csv_writer.serialize(ExportedEvent {
+            run: run_name.to_owned(),
+            event: metadata.event,
+            uid: metadata.uid.clone(),
+            batch,
+            index,
+            hits: cluster.len(),
+            truncated,
+            label,
+        })?;
+
+        events_exported += 1;
+
+        if index + 1 == settings.batch_size {
+            write_batch(&output_dir, batch, settings, &tensors, &batch_labels)?;
+
+            batch += 1;
+            tensors.clear();
+            batch_labels.clear();
+
+            progress_bar.set_message(&format!("| {} Events Exported | {}", events_exported.separated_string(), run_name));
+        }
+    }
+
+    if !tensors.is_empty() {
+        write_batch(&output_dir, batch, settings, &tensors, &batch_labels)?;
+    }
+
+    csv_writer.flush()?;
+
+    progress_bar.finish_with_message(&format!("| Done | {} Events Exported | {}", events_exported.separated_string(), run_name));
+
+    Ok(())
+}
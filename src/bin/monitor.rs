@@ -0,0 +1,603 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * ----------------------
+ * Timepix Online Monitor
+ * ----------------------
+ *
+ * timepix-spidr-data-parser/src/bin/monitor.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{SecondsFormat, Utc};
+use clap;
+use colored::Colorize;
+use serde::Serialize;
+
+use timepix_spidr_data_parser::*;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100); // Wait for more data once the followed file has been read to its end
+#[cfg(feature = "webhook")]
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+const ALARM_EXIT_CODE: i32 = 2; // With --exit-on-alarm, so a supervisor can tell an alarm from a failure
+
+struct Settings {
+    interval: Duration,
+    window_slices: usize, // Intervals in the rolling window
+    hot_pixel_factor: f64,
+    min_hot_pixel_hits: u32,
+    min_rate_fraction: f64,
+    webhook: Option<String>,
+    alarm_log: Option<PathBuf>,
+    exit_on_alarm: bool,
+    from_start: bool,
+}
+
+/// Values the rolling rates are compared to (Hz), any not given are learned from the first full window
+struct Reference {
+    hit_rate: Option<f64>,
+    trigger_rate: Option<f64>,
+    pixel_rates: Option<Vec<f64>>,
+}
+
+impl Reference {
+    /// Rates of a run processed offline, over the time from its first to last hit
+    fn from_run(dir: &Path) -> io::Result<Reference> {
+        let run = Run::open(dir)?;
+
+        let duration = match run.hits_time_range()? {
+            Some((first_toa, last_toa)) if last_toa > first_toa => (last_toa - first_toa) as f64 * TOA_CLOCK_TO_NS / 1e9,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Reference run {:#?} has no hits to take rates from", dir),
+                ))
+            }
+        };
+
+        let mut pixel_counts = vec![0u64; 256 * 256];
+        let mut hits = 0;
+
+        for hit in run.hits()?.filter(|hit| hit.col <= 255 && hit.row <= 255) {
+            pixel_counts[usize::from(hit.row) * 256 + usize::from(hit.col)] += 1;
+            hits += 1;
+        }
+
+        Ok(Reference {
+            hit_rate: Some(hits as f64 / duration),
+            trigger_rate: Some(run.triggers()?.len() as f64 / duration),
+            pixel_rates: Some(pixel_counts.iter().map(|x| *x as f64 / duration).collect()),
+        })
+    }
+
+    /// Fills in the values not given from the rolling window, returning the names of those learned
+    fn learn(&mut self, rolling: &RollingOccupancy) -> Vec<&'static str> {
+        let mut learned = Vec::new();
+
+        if self.hit_rate.is_none() {
+            self.hit_rate = Some(rolling.hit_rate());
+            learned.push("hit rate");
+        }
+
+        if self.trigger_rate.is_none() {
+            self.trigger_rate = Some(rolling.trigger_rate());
+            learned.push("trigger rate");
+        }
+
+        if self.pixel_rates.is_none() {
+            self.pixel_rates = Some((0..256 * 256).map(|i| rolling.pixel_rate(i)).collect());
+            learned.push("pixel rates");
+        }
+
+        learned
+    }
+
+    /// Rate a pixel is compared to, at least the mean pixel rate so that pixels that were quiet in
+    /// the reference are not hot as soon as they see a few hits
+    fn pixel_rate(&self, i: usize) -> f64 {
+        let mean_rate = self.hit_rate.unwrap_or(0.0) / (256.0 * 256.0);
+
+        self.pixel_rates.as_ref().map_or(mean_rate, |x| x[i].max(mean_rate))
+    }
+}
+
+/// Hits and triggers seen during one interval
+struct Slice {
+    hits: usize,
+    triggers: usize,
+    duration: f64, // s
+    occupancy: Vec<u32>,
+}
+
+impl Slice {
+    fn new() -> Slice {
+        Slice {
+            hits: 0,
+            triggers: 0,
+            duration: 0.0,
+            occupancy: vec![0; 256 * 256],
+        }
+    }
+
+    fn add_hit(&mut self, hit: &Hit) {
+        self.hits += 1;
+
+        if hit.col <= 255 && hit.row <= 255 {
+            self.occupancy[usize::from(hit.row) * 256 + usize::from(hit.col)] += 1;
+        }
+    }
+}
+
+/// Occupancy map and rates over the last few intervals (wall clock time, so a stalled DAQ shows
+/// up as a rate drop)
+struct RollingOccupancy {
+    slices: VecDeque<Slice>,
+    max_slices: usize,
+    hits: usize,
+    triggers: usize,
+    duration: f64, // s
+    occupancy: Vec<u32>,
+}
+
+impl RollingOccupancy {
+    fn new(max_slices: usize) -> RollingOccupancy {
+        RollingOccupancy {
+            slices: VecDeque::with_capacity(max_slices + 1),
+            max_slices,
+            hits: 0,
+            triggers: 0,
+            duration: 0.0,
+            occupancy: vec![0; 256 * 256],
+        }
+    }
+
+    fn push(&mut self, slice: Slice) {
+        self.hits += slice.hits;
+        self.triggers += slice.triggers;
+        self.duration += slice.duration;
+
+        for (total, count) in self.occupancy.iter_mut().zip(slice.occupancy.iter()) {
+            *total += count;
+        }
+
+        self.slices.push_back(slice);
+
+        if self.slices.len() > self.max_slices {
+            let oldest = self.slices.pop_front().unwrap();
+
+            self.hits -= oldest.hits;
+            self.triggers -= oldest.triggers;
+            self.duration -= oldest.duration;
+
+            for (total, count) in self.occupancy.iter_mut().zip(oldest.occupancy.iter()) {
+                *total -= count;
+            }
+        }
+    }
+
+    fn is_full(&self) -> bool {
+        self.slices.len() == self.max_slices
+    }
+
+    fn hit_rate(&self) -> f64 {
+        self.hits as f64 / self.duration
+    }
+
+    fn trigger_rate(&self) -> f64 {
+        self.triggers as f64 / self.duration
+    }
+
+    fn pixel_rate(&self, i: usize) -> f64 {
+        f64::from(self.occupancy[i]) / self.duration
+    }
+
+    /// Pixels with enough hits in the window and a rate above `factor` times their reference rate
+    fn hot_pixels(&self, reference: &Reference, settings: &Settings) -> Vec<bool> {
+        (0..256 * 256)
+            .map(|i| self.occupancy[i] >= settings.min_hot_pixel_hits && self.pixel_rate(i) > settings.hot_pixel_factor * reference.pixel_rate(i))
+            .collect()
+    }
+}
+
+/// Raised when a check starts failing and again when it is cleared, written to the alarm log
+/// and posted to the webhook as JSON
+#[derive(Serialize)]
+struct Alarm {
+    time: String, // UTC
+    alarm: &'static str,
+    cleared: bool,
+    text: String,           // Also makes the body a valid Slack/Mattermost message
+    rate: Option<f64>,      // Hz
+    reference: Option<f64>, // Hz
+    pixels: Vec<(u16, u16)>,
+}
+
+/// Checks failing at the last interval
+struct AlarmState {
+    hit_rate_low: bool,
+    trigger_rate_low: bool,
+    hot_pixels: Vec<bool>,
+}
+
+fn main() -> io::Result<()> {
+    println!("\n----------------------\n{}\n----------------------\n", "Timepix Online Monitor".bold());
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(
+            clap::Arg::with_name("INPUT")
+                .help("Sets the raw data file pattern, the newest matching file is followed as it is written")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("interval")
+                .help("Time between checks (s) (default is 10)")
+                .long("interval")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("window")
+                .help("Length of the rolling window the occupancy and rates are taken over (s) (default is 60)")
+                .long("window")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("from-start")
+                .help("Reads the first file from its start rather than from the data written after starting")
+                .long("from-start"),
+        )
+        // Reference options
+        .arg(
+            clap::Arg::with_name("reference")
+                .help("Takes the reference hit, trigger and pixel rates from a good run already processed by the raw_data_parser (otherwise they are learned from the first full window)")
+                .long("reference")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("reference-hit-rate")
+                .help("Sets the reference hit rate (Hz)")
+                .long("reference-hit-rate")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("reference-trigger-rate")
+                .help("Sets the reference trigger rate (Hz)")
+                .long("reference-trigger-rate")
+                .takes_value(true),
+        )
+        // Alarm options
+        .arg(
+            clap::Arg::with_name("min-rate-fraction")
+                .help("Raises an alarm when the hit or trigger rate drops below this fraction of its reference (default is 0.5)")
+                .long("min-rate-fraction")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("hot-pixel-factor")
+                .help("Raises an alarm when a pixel's rate goes above this many times its reference rate (default is 10)")
+                .long("hot-pixel-factor")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("min-hot-pixel-hits")
+                .help("Hits a pixel needs in the window before it can be hot (default is 20)")
+                .long("min-hot-pixel-hits")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("webhook")
+                .help("Posts each alarm as JSON to this URL")
+                .long("webhook")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("alarm-log")
+                .help("Appends each alarm as a line of JSON to this file")
+                .long("alarm-log")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("exit-on-alarm")
+                .help("Exits with code 2 after the first alarm")
+                .long("exit-on-alarm"),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_glob_str = matches.value_of("INPUT").unwrap();
+
+    let settings = {
+        let interval = matches.value_of("interval").map_or(10.0, |x| x.parse::<f64>().unwrap());
+        let window = matches.value_of("window").map_or(60.0, |x| x.parse::<f64>().unwrap());
+
+        assert!(interval > 0.0 && window >= interval);
+
+        if matches.is_present("webhook") && !cfg!(feature = "webhook") {
+            println!("{}", "--webhook needs the 'webhook' feature".red());
+            process::exit(1);
+        }
+
+        Settings {
+            interval: Duration::from_secs_f64(interval),
+            window_slices: (window / interval).round() as usize,
+            hot_pixel_factor: matches.value_of("hot-pixel-factor").map_or(10.0, |x| x.parse::<f64>().unwrap()),
+            min_hot_pixel_hits: matches.value_of("min-hot-pixel-hits").map_or(20, |x| x.parse::<u32>().unwrap()),
+            min_rate_fraction: matches.value_of("min-rate-fraction").map_or(0.5, |x| x.parse::<f64>().unwrap()),
+            webhook: matches.value_of("webhook").map(|x| x.to_owned()),
+            alarm_log: matches.value_of("alarm-log").map(PathBuf::from),
+            exit_on_alarm: matches.is_present("exit-on-alarm"),
+            from_start: matches.is_present("from-start"),
+        }
+    };
+
+    let mut reference = match matches.value_of("reference") {
+        Some(dir) => match Reference::from_run(Path::new(dir)) {
+            Ok(reference) => reference,
+            Err(e) => {
+                println!("{}", format!("Failed to read reference run: {}", e).red());
+                process::exit(1);
+            }
+        },
+        None => Reference {
+            hit_rate: None,
+            trigger_rate: None,
+            pixel_rates: None,
+        },
+    };
+
+    if let Some(hit_rate) = matches.value_of("reference-hit-rate") {
+        reference.hit_rate = Some(hit_rate.parse::<f64>().unwrap());
+    }
+
+    if let Some(trigger_rate) = matches.value_of("reference-trigger-rate") {
+        reference.trigger_rate = Some(trigger_rate.parse::<f64>().unwrap());
+    }
+
+    install_interrupt_handler();
+
+    //
+    // Follow the raw data, checking the rolling window each interval
+    //
+    let mut follower = RawDataFollower::new(input_glob_str, settings.from_start);
+
+    let mut rolling = RollingOccupancy::new(settings.window_slices);
+    let mut slice = Slice::new();
+    let mut slice_start = Instant::now();
+
+    let mut state = AlarmState {
+        hit_rate_low: false,
+        trigger_rate_low: false,
+        hot_pixels: vec![false; 256 * 256],
+    };
+
+    let mut alarms_raised = 0;
+    let mut waiting_for_file = false;
+
+    while !is_interrupted() {
+        let decoded = match follower.read() {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                println!("{}", e.to_string().yellow());
+                None
+            }
+        };
+
+        match decoded {
+            Some((hits, triggers)) => {
+                for hit in &hits {
+                    slice.add_hit(hit);
+                }
+
+                slice.triggers += triggers.len();
+            }
+            None => {
+                // Move on to a newer file once the DAQ starts one, the current file having been read to its end
+                match follower.follow_newest() {
+                    Ok(true) => {
+                        println!("Following {}", follower.path().unwrap().display());
+                        waiting_for_file = false;
+                        continue;
+                    }
+                    Ok(false) if !waiting_for_file && follower.path().is_none() => {
+                        println!("Waiting for raw files matching '{}'", input_glob_str);
+                        waiting_for_file = true;
+                    }
+                    Ok(false) => (),
+                    Err(e) => println!("{}", e.to_string().yellow()),
+                }
+
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+
+        if slice_start.elapsed() < settings.interval {
+            continue;
+        }
+
+        slice.duration = slice_start.elapsed().as_secs_f64();
+        rolling.push(slice);
+
+        slice = Slice::new();
+        slice_start = Instant::now();
+
+        if !rolling.is_full() {
+            println!("Filling rolling window ({}/{})", rolling.slices.len(), settings.window_slices);
+            continue;
+        }
+
+        let learned = reference.learn(&rolling);
+
+        if !learned.is_empty() {
+            println!("Learned reference {} from the first window", learned.join(", "));
+        }
+
+        for alarm in check(&rolling, &reference, &settings, &mut state) {
+            dispatch_alarm(&alarm, &settings);
+
+            if !alarm.cleared {
+                alarms_raised += 1;
+
+                if settings.exit_on_alarm {
+                    process::exit(ALARM_EXIT_CODE);
+                }
+            }
+        }
+
+        println!(
+            "{} | {:.1} Hz Hits | {:.1} Hz Triggers | {} Hot Pixels",
+            Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            rolling.hit_rate(),
+            rolling.trigger_rate(),
+            state.hot_pixels.iter().filter(|x| **x).count()
+        );
+    }
+
+    println!("Stopped after raising {} alarms", alarms_raised);
+
+    Ok(())
+}
+
+/// Compares the rolling window to the reference, returning alarms for the checks that have
+/// started or stopped failing since the last interval
+fn check(rolling: &RollingOccupancy, reference: &Reference, settings: &Settings, state: &mut AlarmState) -> Vec<Alarm> {
+    let mut alarms = Vec::new();
+
+    let rates = [
+        ("hit_rate", "Hit", rolling.hit_rate(), reference.hit_rate, &mut state.hit_rate_low),
+        (
+            "trigger_rate",
+            "Trigger",
+            rolling.trigger_rate(),
+            reference.trigger_rate,
+            &mut state.trigger_rate_low,
+        ),
+    ];
+
+    for (alarm, name, rate, reference_rate, low) in rates {
+        // No alarm for rates that are zero in the reference, eg. runs without triggers
+        let reference_rate = match reference_rate.filter(|x| *x > 0.0) {
+            Some(x) => x,
+            None => continue,
+        };
+
+        let is_low = rate < settings.min_rate_fraction * reference_rate;
+
+        if is_low == *low {
+            continue;
+        }
+
+        *low = is_low;
+
+        let text = if is_low {
+            format!("{} rate dropped to {:.1} Hz (reference {:.1} Hz)", name, rate, reference_rate)
+        } else {
+            format!("{} rate recovered to {:.1} Hz (reference {:.1} Hz)", name, rate, reference_rate)
+        };
+
+        alarms.push(new_alarm(alarm, !is_low, text, Some(rate), Some(reference_rate), Vec::new()));
+    }
+
+    let hot_pixels = rolling.hot_pixels(reference, settings);
+
+    let pixels = |i: usize| ((i % 256) as u16, (i / 256) as u16);
+    let new_hot: Vec<_> = (0..256 * 256).filter(|i| hot_pixels[*i] && !state.hot_pixels[*i]).map(pixels).collect();
+    let cleared: Vec<_> = (0..256 * 256).filter(|i| !hot_pixels[*i] && state.hot_pixels[*i]).map(pixels).collect();
+
+    if !new_hot.is_empty() {
+        let text = format!("{} pixels went hot: {}", new_hot.len(), format_pixels(&new_hot));
+        alarms.push(new_alarm("hot_pixels", false, text, None, None, new_hot));
+    }
+
+    if !cleared.is_empty() {
+        let text = format!("{} pixels are no longer hot: {}", cleared.len(), format_pixels(&cleared));
+        alarms.push(new_alarm("hot_pixels", true, text, None, None, cleared));
+    }
+
+    state.hot_pixels = hot_pixels;
+
+    alarms
+}
+
+fn new_alarm(alarm: &'static str, cleared: bool, text: String, rate: Option<f64>, reference: Option<f64>, pixels: Vec<(u16, u16)>) -> Alarm {
+    Alarm {
+        time: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        alarm,
+        cleared,
+        text,
+        rate,
+        reference,
+        pixels,
+    }
+}
+
+/// Lists the first few pixels of an alarm as (col, row)
+fn format_pixels(pixels: &[(u16, u16)]) -> String {
+    let listed: Vec<_> = pixels.iter().take(10).map(|(col, row)| format!("({}, {})", col, row)).collect();
+
+    if pixels.len() > listed.len() {
+        format!("{}, ...", listed.join(", "))
+    } else {
+        listed.join(", ")
+    }
+}
+
+/// Logs the alarm and sends it on, failures to send are reported without stopping the monitor
+fn dispatch_alarm(alarm: &Alarm, settings: &Settings) {
+    if alarm.cleared {
+        println!("{}", format!("{} | Cleared | {}", alarm.time, alarm.text).green());
+    } else {
+        println!("{}", format!("{} | ALARM | {}", alarm.time, alarm.text).red().bold());
+    }
+
+    let json = serde_json::to_string(alarm).unwrap();
+
+    if let Some(alarm_log) = &settings.alarm_log {
+        let written = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(alarm_log)
+            .and_then(|mut file| writeln!(file, "{}", json));
+
+        if let Err(e) = written {
+            println!("{}", format!("Failed to write alarm to {:#?}: {}", alarm_log, e).yellow());
+        }
+    }
+
+    if let Some(webhook) = &settings.webhook {
+        if let Err(e) = post_alarm(webhook, &json) {
+            println!("{}", format!("Failed to post alarm to webhook: {}", e).yellow());
+        }
+    }
+}
+
+#[cfg(feature = "webhook")]
+fn post_alarm(webhook: &str, json: &str) -> Result<(), String> {
+    ureq::post(webhook)
+        .timeout(WEBHOOK_TIMEOUT)
+        .set("Content-Type", "application/json")
+        .send_string(json)
+        .map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+#[cfg(not(feature = "webhook"))]
+fn post_alarm(_webhook: &str, _json: &str) -> Result<(), String> {
+    unreachable!()
+}
@@ -0,0 +1,301 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * -------------------------------
+ * Timepix Point Cloud Export Tool
+ * -------------------------------
+ *
+ * timepix-spidr-data-parser/src/bin/point_cloud_export.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::io::Write as _;
+use std::path::Path;
+
+use clap;
+use colored::Colorize;
+use glob::glob;
+use indicatif::{ProgressBar, ProgressStyle};
+use separator::Separatable as _;
+use serde::Serialize;
+
+use timepix_spidr_data_parser::*;
+
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    /// ASCII PLY, for MeshLab and ParaView
+    Ply,
+    /// Legacy ASCII VTK polydata, for ParaView
+    Vtk,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Ply => "ply",
+            Format::Vtk => "vtk",
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct Settings {
+    input_filename: String,
+    output_dirname: String,
+    format: Format,
+    z_scale: f64,
+    max_events: Option<usize>,
+}
+
+fn main() -> io::Result<()> {
+    println!(
+        "\n-------------------------------\n{}\n-------------------------------\n",
+        "Timepix Point Cloud Export Tool".bold()
+    );
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(
+            clap::Arg::with_name("INPUT")
+                .help("Sets the input directory pattern")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("input-filename")
+                .help("Sets the input filename (without extension!) to use (default is 'clusters')")
+                .long("input-filename")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("output-dirname")
+                .help("Sets the directory within each run to write the point clouds to (default is 'point_clouds')")
+                .long("output-dirname")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("format")
+                .help("Point cloud file format (default is ply)")
+                .long("format")
+                .possible_values(&["ply", "vtk"])
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("z-scale")
+                .help("Scale from time (ns) to the z axis in pixel units (default is 1)")
+                .long("z-scale")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("max-events")
+                .help("Only exports the first N events of each run")
+                .long("max-events")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("dry-run")
+                .help("Shows output of what runs will be processed without changing any data")
+                .long("dry-run"),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_glob_str = matches.value_of("INPUT").unwrap();
+
+    let settings = {
+        let input_filename = matches.value_of("input-filename").unwrap_or("clusters").to_owned();
+        let output_dirname = matches.value_of("output-dirname").unwrap_or("point_clouds").to_owned();
+
+        let format = match matches.value_of("format") {
+            Some("vtk") => Format::Vtk,
+            _ => Format::Ply,
+        };
+
+        let z_scale = matches.value_of("z-scale").and_then(|x| x.parse::<f64>().ok()).unwrap_or(1.0);
+        let max_events = matches.value_of("max-events").and_then(parse_human_readable_number);
+
+        Settings {
+            input_filename,
+            output_dirname,
+            format,
+            z_scale,
+            max_events,
+        }
+    };
+
+    let dry_run = matches.is_present("dry-run");
+
+    //
+    // Parse input file list
+    //
+    let matched_dirs: Vec<_> = glob(input_glob_str)
+        .expect("Failed to read input file glob pattern")
+        .filter_map(|x| x.ok()) // Check glob worked
+        .filter(|x| x.is_dir()) // Check is directory
+        .filter(|x| x.join(format!("{}.bin", settings.input_filename)).exists())
+        .filter(|x| x.join(format!("{}.csv", settings.input_filename)).exists())
+        .collect();
+
+    // Runs with existing output files are skipped
+    let (input_dirs, existing_dirs): (Vec<_>, Vec<_>) = matched_dirs.into_iter().partition(|x| !x.join(&settings.output_dirname).exists());
+
+    if input_dirs.is_empty() && (!dry_run || existing_dirs.is_empty()) {
+        println!("No input directories matched!");
+        return Ok(());
+    }
+
+    println!("Matched {} input directories", input_dirs.len());
+
+    if dry_run {
+        let mut plan = DryRunPlan::new();
+
+        for input_dir in &input_dirs {
+            let input_bytes = input_dir.join(format!("{}.bin", settings.input_filename)).metadata()?.len();
+            let hits = Run::open(input_dir)?.cluster_hit_count(&settings.input_filename).ok();
+
+            plan.run(input_dir, input_bytes, hits);
+        }
+
+        for existing_dir in &existing_dirs {
+            plan.skipped(existing_dir);
+        }
+
+        plan.print();
+
+        return Ok(());
+    }
+
+    let sty = ProgressStyle::default_bar()
+        .template(PROGRESS_BAR_TEMPLATE)
+        .progress_chars(PROGRESS_BAR_CHARS);
+
+    for input_dir in input_dirs {
+        let mut rdr = csv::Reader::from_path(input_dir.join(format!("{}.csv", settings.input_filename)))?;
+        let input_metadata: Vec<ClusterMetadata> = rdr.deserialize().map(|x| x.unwrap()).collect();
+
+        let n_events = settings.max_events.map_or(input_metadata.len(), |x| x.min(input_metadata.len()));
+
+        let progress_bar = ProgressBar::new(n_events as u64);
+        progress_bar.set_style(sty.clone());
+
+        let timer = ProcessingTimer::start(&input_dir);
+
+        process_run(&input_dir, &input_metadata[..n_events], &settings, &progress_bar)?;
+
+        timer.finish(input_dir.join(format!("{}.bin", settings.input_filename)).metadata()?.len())?;
+    }
+
+    Ok(())
+}
+
+/// Points of an event as (col, row, z) with the ToT of each, z being the time since the first hit
+fn event_points(cluster: &[Hit], z_scale: f64) -> Vec<(u16, u16, f64, u32)> {
+    // ToA values are counted from the earliest hit of the event (`Run::relative_clusters`)
+    cluster
+        .iter()
+        .map(|hit| {
+            let time = hit.toa as f64 * TOA_CLOCK_TO_NS;
+            (hit.col, hit.row, time * z_scale, hit.tot)
+        })
+        .collect()
+}
+
+fn write_ply(path: &Path, metadata: &ClusterMetadata, points: &[(u16, u16, f64, u32)]) -> io::Result<()> {
+    let mut buf = Vec::new();
+
+    writeln!(buf, "ply")?;
+    writeln!(buf, "format ascii 1.0")?;
+    writeln!(buf, "comment event {}", metadata.event)?;
+    writeln!(buf, "comment uid {}", metadata.uid)?;
+    writeln!(buf, "element vertex {}", points.len())?;
+    writeln!(buf, "property float x")?;
+    writeln!(buf, "property float y")?;
+    writeln!(buf, "property float z")?;
+    writeln!(buf, "property float tot")?;
+    writeln!(buf, "end_header")?;
+
+    for (col, row, z, tot) in points {
+        writeln!(buf, "{} {} {} {}", col, row, z, tot)?;
+    }
+
+    fs::write(path, buf)
+}
+
+fn write_vtk(path: &Path, metadata: &ClusterMetadata, points: &[(u16, u16, f64, u32)]) -> io::Result<()> {
+    let mut buf = Vec::new();
+
+    writeln!(buf, "# vtk DataFile Version 3.0")?;
+    writeln!(buf, "Timepix event {} ({})", metadata.event, metadata.uid)?;
+    writeln!(buf, "ASCII")?;
+    writeln!(buf, "DATASET POLYDATA")?;
+    writeln!(buf, "POINTS {} float", points.len())?;
+
+    for (col, row, z, _) in points {
+        writeln!(buf, "{} {} {}", col, row, z)?;
+    }
+
+    // Vertex cells so each point is rendered without a glyph filter
+    writeln!(buf, "VERTICES {} {}", points.len(), points.len() * 2)?;
+
+    for i in 0..points.len() {
+        writeln!(buf, "1 {}", i)?;
+    }
+
+    writeln!(buf, "POINT_DATA {}", points.len())?;
+    writeln!(buf, "SCALARS tot float 1")?;
+    writeln!(buf, "LOOKUP_TABLE default")?;
+
+    for (_, _, _, tot) in points {
+        writeln!(buf, "{}", tot)?;
+    }
+
+    fs::write(path, buf)
+}
+
+fn process_run(run_dir: &Path, input_metadata: &[ClusterMetadata], settings: &Settings, progress_bar: &ProgressBar) -> io::Result<()> {
+    let run_name = run_dir.file_stem().unwrap().to_str().unwrap();
+
+    let input_data_file_path = run_dir.join(format!("{}.bin", settings.input_filename));
+    // Only the times within each event are used, so files with signed ToA values can be read too
+    let cluster_iterator = Run::open(run_dir)?
+        .relative_clusters(&settings.input_filename)?
+        .map(|(_, cluster)| cluster);
+
+    let output_dir = run_dir.join(&settings.output_dirname);
+    fs::create_dir_all(&output_dir)?;
+
+    // Write metadata to TOML file
+    write_settings_toml(&output_dir.join("point_clouds.toml"), settings, &[&input_data_file_path])?;
+
+    let mut events_exported = 0;
+
+    progress_bar.set_message(&format!("| 0 Events Exported | {}", run_name));
+
+    for (metadata, cluster) in input_metadata.iter().zip(cluster_iterator) {
+        let points = event_points(&cluster, settings.z_scale);
+        let path = output_dir.join(format!("event_{:06}.{}", metadata.event, settings.format.extension()));
+
+        match settings.format {
+            Format::Ply => write_ply(&path, metadata, &points)?,
+            Format::Vtk => write_vtk(&path, metadata, &points)?,
+        }
+
+        events_exported += 1;
+
+        progress_bar.inc(1);
+        progress_bar.set_message(&format!("| {} Events Exported | {}", events_exported.separated_string(), run_name));
+    }
+
+    progress_bar.finish_with_message(&format!("| Done | {} Events Exported | {}", events_exported.separated_string(), run_name));
+
+    Ok(())
+}
@@ -0,0 +1,241 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * ----------------------
+ * Timepix Raw Chunk Tool
+ * ----------------------
+ *
+ * timepix-spidr-data-parser/src/bin/raw_chunk_tool.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use clap;
+use colored::Colorize;
+use indicatif::{ProgressBar, ProgressStyle};
+use separator::Separatable as _;
+use serde::Serialize;
+
+use timepix_spidr_data_parser::*;
+
+const CHECKPOINTS_FILENAME: &str = "checkpoints.json";
+
+/// Written to `hits.toml` alongside the stitched hits
+#[derive(Serialize)]
+struct Settings {
+    input_file: PathBuf,
+    chunk_size: u64, // bytes
+    chunks: usize,
+}
+
+fn main() -> io::Result<()> {
+    println!("\n----------------------\n{}\n----------------------\n", "Timepix Raw Chunk Tool".bold());
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(
+            clap::Arg::with_name("INPUT")
+                .help("Sets the raw data file to process in chunks (uncompressed, local or an object URL)")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("OUTPUT")
+                .help("Sets the directory holding the checkpoints, the output of each chunk and the stitched output")
+                .required(true)
+                .index(2),
+        )
+        // Checkpoint options
+        .arg(
+            clap::Arg::with_name("chunk-size")
+                .help("Takes a decoder checkpoint every this many bytes of the raw file, eg. 256M or 1B (default is 256M)")
+                .long("chunk-size")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("previous")
+                .help("Carries the timeline on from the end of the checkpoints of the previous file in the run")
+                .long("previous")
+                .takes_value(true),
+        )
+        // Chunk options
+        .arg(
+            clap::Arg::with_name("chunk")
+                .help("Decodes this chunk from its checkpoint, rather than taking the checkpoints")
+                .long("chunk")
+                .takes_value(true)
+                .conflicts_with_all(&["chunk-size", "previous", "stitch"]),
+        )
+        .arg(
+            clap::Arg::with_name("stitch")
+                .help("Stitches the output of all the chunks together, rather than taking the checkpoints")
+                .long("stitch")
+                .conflicts_with_all(&["chunk-size", "previous"]),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_file = Path::new(matches.value_of("INPUT").unwrap());
+    let output_dir = Path::new(matches.value_of("OUTPUT").unwrap());
+
+    let checkpoints_path = output_dir.join(CHECKPOINTS_FILENAME);
+
+    let result = if let Some(chunk) = matches.value_of("chunk") {
+        decode_chunk(input_file, output_dir, chunk.parse::<usize>().unwrap())
+    } else if matches.is_present("stitch") {
+        stitch_chunks(input_file, output_dir)
+    } else {
+        let chunk_size = matches
+            .value_of("chunk-size")
+            .map_or(256_000_000, |x| parse_human_readable_number::<u64>(x).unwrap());
+
+        if checkpoints_path.exists() {
+            println!("{}", format!("Checkpoints file '{}' already exists!", checkpoints_path.display()).red());
+            return Ok(());
+        }
+
+        take_checkpoints(input_file, output_dir, chunk_size, matches.value_of("previous").map(Path::new))
+    };
+
+    if let Err(e) = result {
+        println!("{}", format!("{} | Failed: {}", input_file.display(), e).red());
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Decodes the whole file once, writing the decoder state at the start of each chunk
+fn take_checkpoints(input_file: &Path, output_dir: &Path, chunk_size: u64, previous: Option<&Path>) -> io::Result<()> {
+    let previous = match previous {
+        Some(previous) => Some(DecoderCheckpoints::read(previous)?),
+        None => None,
+    };
+
+    let checkpoints = DecoderCheckpoints::create(input_file, chunk_size, previous.as_ref().map(|x| x.end_state()))?;
+
+    fs::create_dir_all(output_dir)?;
+    checkpoints.write(&output_dir.join(CHECKPOINTS_FILENAME))?;
+
+    println!(
+        "{} | {} Chunks | {} Bytes",
+        input_file.display(),
+        checkpoints.chunks(),
+        checkpoints.file_len.separated_string()
+    );
+
+    Ok(())
+}
+
+/// Decodes a single chunk, writing its hits sorted by ToA and its triggers to their own directory
+fn decode_chunk(input_file: &Path, output_dir: &Path, chunk: usize) -> io::Result<()> {
+    let checkpoints = DecoderCheckpoints::read(&output_dir.join(CHECKPOINTS_FILENAME))?;
+
+    let chunk_dir = chunk_dir(output_dir, chunk);
+
+    if chunk_dir.join("hits.bin").exists() {
+        println!("Chunk {} has already been decoded", chunk);
+        return Ok(());
+    }
+
+    let (mut hits, triggers) = checkpoints.decode_chunk(input_file, chunk)?;
+
+    // Stable, so hits with the same time keep the order they were read out in
+    hits.sort_by_key(|x| x.toa);
+
+    fs::create_dir_all(&chunk_dir)?;
+
+    write_triggers_to_csv(&mut fs::File::create(chunk_dir.join("triggers.csv"))?, &triggers)?;
+
+    // Hits written last, so an interrupted chunk is decoded again
+    let mut hits_index = HitsIndexBuilder::new();
+    hits_index.add(&hits);
+
+    write_hits_to_file(&mut fs::File::create(chunk_dir.join("hits.bin"))?, &hits)?;
+    hits_index.write(&chunk_dir.join("hits.bin"))?;
+
+    println!(
+        "Chunk {} of {} | {} Hits | {} Triggers",
+        chunk,
+        checkpoints.chunks(),
+        hits.len().separated_string(),
+        triggers.len().separated_string()
+    );
+
+    Ok(())
+}
+
+/// Merges the hits of the chunks (which overlap a little in time, as the hits are read out
+/// slightly out of order) and joins their triggers, once every chunk has been decoded
+fn stitch_chunks(input_file: &Path, output_dir: &Path) -> io::Result<()> {
+    let checkpoints = DecoderCheckpoints::read(&output_dir.join(CHECKPOINTS_FILENAME))?;
+
+    let output_file = output_dir.join("hits.bin");
+
+    if output_file.exists() {
+        println!("{}", format!("Output file '{}' already exists!", output_file.display()).red());
+        return Ok(());
+    }
+
+    let chunk_dirs: Vec<_> = (0..checkpoints.chunks()).map(|x| chunk_dir(output_dir, x)).collect();
+
+    let missing: Vec<_> = (0..chunk_dirs.len()).filter(|x| !chunk_dirs[*x].join("hits.bin").exists()).collect();
+
+    if !missing.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Chunks {:?} have not been decoded yet", missing),
+        ));
+    }
+
+    let mut triggers = Vec::new();
+
+    for chunk_dir in &chunk_dirs {
+        triggers.extend(read_trigger_data(&chunk_dir.join("triggers.csv"))?);
+    }
+
+    write_triggers_to_csv(&mut fs::File::create(output_dir.join("triggers.csv"))?, &triggers)?;
+
+    let hits_files: Vec<_> = chunk_dirs.iter().map(|x| x.join("hits.bin")).collect();
+
+    let n_hits: u64 = hits_files.iter().map(|x| x.metadata().unwrap().len() / HIT_RECORD_SIZE as u64).sum();
+
+    let progress_bar = ProgressBar::new(n_hits);
+    progress_bar.set_style(
+        ProgressStyle::default_bar()
+            .template(PROGRESS_BAR_TEMPLATE)
+            .progress_chars(PROGRESS_BAR_CHARS),
+    );
+
+    let hits_written = merge_hits_files(&hits_files, &output_file, &progress_bar)?;
+
+    progress_bar.finish_with_message(&format!(
+        "| Done | {} Hits | {} Triggers",
+        hits_written.separated_string(),
+        triggers.len().separated_string()
+    ));
+
+    // Write metadata to TOML file
+    let settings = Settings {
+        input_file: input_file.to_path_buf(),
+        chunk_size: checkpoints.chunk_size,
+        chunks: checkpoints.chunks(),
+    };
+    write_settings_toml(&output_dir.join("hits.toml"), &settings, &[input_file])?;
+
+    Ok(())
+}
+
+fn chunk_dir(output_dir: &Path, chunk: usize) -> PathBuf {
+    output_dir.join(format!("chunk{:04}", chunk))
+}
@@ -13,35 +13,144 @@
 #[macro_use]
 extern crate lazy_static;
 
-use std::cmp;
-use std::collections::VecDeque;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
 use std::io;
-use std::io::prelude::*;
-use std::io::SeekFrom;
 use std::mem;
+use std::panic;
 use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
+use std::time::Duration;
 
-use byteorder::{LittleEndian, ReadBytesExt};
 use chrono::prelude::*;
+use chrono_tz::Tz;
 use clap;
 use colored::Colorize;
-use glob::glob;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use rayon;
+use indicatif::ProgressBar;
 use regex::Regex;
 use separator::Separatable as _;
+use serde::Serialize;
 
 use timepix_spidr_data_parser::*;
 
-const BATCH_SIZE: usize = 1_000_000;
-const SKIM_OFF: usize = 800_000;
+const DEFAULT_SORT_BATCH: usize = 1_000_000;
+const DEFAULT_SORT_OVERLAP: usize = 200_000;
+const DECODE_CHUNK_SIZE: usize = 100_000; // Hits sent from the decode stage to the sort stage at a time
+const PIPELINE_DEPTH: usize = 4; // Chunks queued between stages before the stage feeding them blocks
+
+/// Format of the hits written for each run, alongside `hits.bin` which the other tools read
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum OutputFormat {
+    Bin,
+    Nexus, // Also writes `hits.nxs` with the hits as NeXus NXevent_data
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct Settings {
+    hits_format: u32,
+    output_format: OutputFormat,
+    timezone: String,                // Time zone of the times in the raw file names
+    absolute_time: bool,             // Add the UTC time of each trigger to `triggers.csv`
+    output_template: OutputTemplate, // Directory of each run under the output directory
+    hits_filename: String,           // Name of `hits.bin` and its index, without the extension
+    triggers_filename: String,       // Name of `triggers.bin`/`triggers.csv`, without the extension
+    hit_origins: bool,
+    dump_control_packets: bool,
+    gated: bool,
+    resync: bool,
+    dedup_tolerance: Option<u64>,
+    clock_phases: Option<u32>,
+    acq_mode: Option<AcqMode>, // Overrides the mode in the file headers
+    prescale: Option<usize>,
+    max_pixel_rate: Option<f64>,
+    pixel_rate_window: f64,
+    flat_field: Option<FlatField>,
+    timing_offsets: Option<TimingOffsets>,
+    pixel_mask: Option<PixelMask>,
+    sort_batch: usize,   // Hits collected in the conveyor before it is sorted
+    sort_overlap: usize, // Hits held back after each sort to be sorted with the next batch
+}
+
+/// Hits that arrived after later hits had already been written, so are out of order in
+/// `hits.bin`, a sign the sort overlap is too small for the data
+#[derive(Default, Serialize)]
+struct SortCounters {
+    batch: usize,
+    overlap: usize,
+    mis_sorted: usize,
+    max_mis_sort_ns: f64, // How far the worst mis-sorted hit was behind the hits already written
+}
+
+/// Row of `control_packets.csv`, a control or heartbeat packet and where it is in the raw files
+#[derive(Serialize)]
+struct ControlPacketRow {
+    file_index: usize,
+    offset: u64, // bytes
+    #[serde(rename = "type")]
+    packet_type: String,
+    packet: String,
+}
+
+/// Corrupted data skipped over while reading the raw files of a run (bytes)
+#[derive(Default, Serialize)]
+struct PacketRecovery {
+    truncated_bytes: usize,
+    skipped_bytes: usize,
+    resyncs: usize,
+    skipped_files: usize, // Header truncated or missing
+}
+
+/// Writes every Nth hit of a run to `hits_prescaled.bin` as a quicklook sub-sample
+struct PrescaledWriter {
+    prescale: usize,
+    hits_seen: usize,
+    data_file: PathBuf,
+    file: fs::File,
+    index: HitsIndexBuilder,
+}
+
+impl PrescaledWriter {
+    fn new(data_file: &Path, prescale: usize) -> io::Result<PrescaledWriter> {
+        let data_file = data_file.to_owned();
+
+        Ok(PrescaledWriter {
+            prescale,
+            hits_seen: 0,
+            file: fs::File::create(&data_file)?,
+            data_file,
+            index: HitsIndexBuilder::new(),
+        })
+    }
+
+    fn write(&mut self, hits: &[Hit]) -> io::Result<()> {
+        // Offset of the first kept hit in this batch, counting from the start of the run
+        let first = (self.prescale - self.hits_seen % self.prescale) % self.prescale;
+
+        self.hits_seen += hits.len();
+
+        let hits: Vec<Hit> = hits.iter().skip(first).step_by(self.prescale).copied().collect();
+
+        self.index.add(&hits);
+        write_hits_to_file(&mut self.file, &hits)
+    }
+
+    fn finish(self) -> io::Result<()> {
+        self.index.write(&self.data_file)
+    }
+}
+
+/// Fields that can be used in `--output-template`
+const OUTPUT_TEMPLATE_FIELDS: [&str; 5] = ["datetime", "date", "time", "run_name", "device"];
 
 #[derive(Clone, Debug)]
 struct FileInfo {
     run_name: Option<String>,
     device: String,
-    start_time: DateTime<Utc>, // TODO: check is UTC
+    start_time: RunStartTime,
     file_in_run: u32,
     path: PathBuf,
 }
@@ -69,12 +178,205 @@ fn main() -> io::Result<()> {
                 .index(2),
         )
         .arg(clap::Arg::with_name("disable-mt").help("Disables multi-threading").long("disable-mt"))
+        .arg(
+            clap::Arg::with_name("force")
+                .help("Processes runs even if they are locked by another process, or with 'run <name>' reparses the named runs (comma separated) even if they have already been parsed")
+                .long("force")
+                .takes_value(true)
+                .min_values(0)
+                .max_values(2),
+        )
+        .arg(
+            clap::Arg::with_name("on-complete")
+                .help("Shell command to run after each run completes, with '{run_dir}' and '{summary}' replaced by the run directory and summary file paths")
+                .long("on-complete")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("min-free-space")
+                .help("Free space to keep on the output filesystem, runs pause and then abort below it (GB) (default is 1)")
+                .long("min-free-space")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("low-space-wait")
+                .help("Time to wait for disk space to be freed before aborting a run (s) (default is 600)")
+                .long("low-space-wait")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("job-index")
+                .help("Index of this task in a batch array job (0 to job-count - 1), only its share of the matched runs are processed")
+                .long("job-index")
+                .takes_value(true)
+                .requires("job-count"),
+        )
+        .arg(
+            clap::Arg::with_name("job-count")
+                .help("Number of tasks in a batch array job the matched runs are shared between")
+                .long("job-count")
+                .takes_value(true)
+                .requires("job-index"),
+        )
+        .arg(
+            clap::Arg::with_name("manifest")
+                .help("Writes a CSV manifest of the processed runs and their outcomes")
+                .long("manifest")
+                .takes_value(true),
+        )
         .arg(
             clap::Arg::with_name("dry-run")
                 .help("Shows output of what runs will be processed without changing any data")
                 .long("dry-run"),
         )
-        .arg(clap::Arg::with_name("overwrite").help("Overwrites any previous data").long("overwrite"))
+        .arg(
+            clap::Arg::with_name("overwrite")
+                .help("Overwrites any previous data, or only the given products of the matched runs ('hits' reparses them, 'clusters' removes their cluster/event files) (default is all)")
+                .long("overwrite")
+                .takes_value(true)
+                .min_values(0)
+                .use_delimiter(true)
+                .possible_values(&["all", "hits", "clusters"]),
+        )
+        .arg(
+            clap::Arg::with_name("max-overwrite-files")
+                .help("Most files --overwrite may remove without --yes (default is 1000)")
+                .long("max-overwrite-files")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("yes")
+                .help("Confirms an --overwrite that removes more than --max-overwrite-files files")
+                .long("yes"),
+        )
+        .arg(
+            clap::Arg::with_name("clock-phases")
+                .help("Number of column clock phases (1, 2, 4, 8 or 16) (default is read from the file header, or 16)")
+                .long("clock-phases")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("acq-mode")
+                .help("Acquisition mode the data was recorded in, which sets how the ToA and ToT fields are decoded (default is read from the file header, or toa-tot)")
+                .long("acq-mode")
+                .possible_values(&AcqMode::NAMES)
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("resync")
+                .help("Skips corrupted data in raw files by realigning on the next run of valid packets")
+                .long("resync"),
+        )
+        .arg(
+            clap::Arg::with_name("dedup")
+                .help("Removes duplicate hits (same pixel and ToA) caused by retransmitted packets")
+                .long("dedup"),
+        )
+        .arg(
+            clap::Arg::with_name("dedup-tolerance")
+                .help("Maximum ToA difference (ns) for hits on the same pixel to count as duplicates (default is 0)")
+                .long("dedup-tolerance")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("prescale")
+                .help("Additionally writes every Nth hit to 'hits_prescaled.bin' for a quicklook")
+                .long("prescale")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("flat-field")
+                .help("Corrects each hit's ToT with the per-pixel gains of a gain map CSV (from gain_map_tool)")
+                .long("flat-field")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("timing-offsets")
+                .help("Corrects each hit's ToA with the per-column/superpixel offsets of a CSV (from timing_offset_tool)")
+                .long("timing-offsets")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("pixel-mask")
+                .help("Removes hits from pixels that are not 'ok' in a pixel status map CSV (from hot_pixel_search)")
+                .long("pixel-mask")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("max-pixel-rate")
+                .help("Suppresses pixels while their rate is above this (Hz), measured over rolling windows")
+                .long("max-pixel-rate")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("pixel-rate-window")
+                .help("Width of the windows pixel rates are measured over for '--max-pixel-rate' (s) (default is 1)")
+                .long("pixel-rate-window")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("sort-batch")
+                .help("Number of hits sorted back into time order at a time (default is 1M)")
+                .long("sort-batch")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("sort-overlap")
+                .help("Number of hits held back after each sort to be sorted with the next batch, increase for data that is further out of order (default is 200k)")
+                .long("sort-overlap")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("gated")
+                .help("Only keeps hits within gate intervals (TDC2 rising to falling edge)")
+                .long("gated"),
+        )
+        .arg(
+            clap::Arg::with_name("dump-control-packets")
+                .help("Debugging aid, writes every control/heartbeat packet with its raw file and byte offset to 'control_packets.csv'")
+                .long("dump-control-packets"),
+        )
+        .arg(
+            clap::Arg::with_name("hit-origins")
+                .help("Debugging aid, records the raw file and byte offset of the packet of every hit in 'hit_origins.bin'")
+                .long("hit-origins"),
+        )
+        .arg(
+            clap::Arg::with_name("timezone")
+                .help("Time zone the DAQ wrote the times in the raw file names in, as an IANA name (e.g. 'Europe/London') (default is UTC)")
+                .long("timezone")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("absolute-time")
+                .help("Adds a 'utc_ns' column with the UTC time of each trigger (ns since the Unix epoch) to 'triggers.csv'")
+                .long("absolute-time"),
+        )
+        .arg(
+            clap::Arg::with_name("output-template")
+                .help("Sets the directory of each run under the output directory, from the fields {datetime}, {date}, {time}, {run_name} and {device} (default is '{datetime}_{run_name}')")
+                .long("output-template")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("hits-filename")
+                .help("Sets the name of the hits file in each run directory, without the extension (default is 'hits')")
+                .long("hits-filename")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("triggers-filename")
+                .help("Sets the name of the triggers files in each run directory, without the extension (default is 'triggers')")
+                .long("triggers-filename")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("output-format")
+                .help("Also writes the hits of each run as NeXus NXevent_data to 'hits.nxs' if 'nexus' (default is bin)")
+                .long("output-format")
+                .takes_value(true)
+                .possible_values(&["bin", "nexus"]),
+        )
         .get_matches();
 
     //
@@ -86,27 +388,171 @@ fn main() -> io::Result<()> {
     let output_dir = Path::new(output_dir_str);
 
     let dry_run = matches.is_present("dry-run");
-    let disable_mt = matches.is_present("disable-mt");
+    let manifest = matches.value_of("manifest").map(|x| x.to_owned());
+    let overwrite = Overwrite::from_args(matches.is_present("overwrite"), matches.values_of("overwrite"));
+    let max_overwrite_files = matches
+        .value_of("max-overwrite-files")
+        .and_then(|x| x.parse::<usize>().ok())
+        .unwrap_or(DEFAULT_MAX_OVERWRITE_FILES);
+    let confirmed = matches.is_present("yes");
+    let forced_runs: Vec<String> = match matches.values_of("force").map(|x| x.collect::<Vec<_>>()).unwrap_or_default().as_slice() {
+        [] => Vec::new(),
+        ["run", names] => names.split(',').map(|x| x.to_owned()).collect(),
+        _ => {
+            println!("{}", "--force takes no value, or 'run <name>' to reparse a run".red());
+            process::exit(1);
+        }
+    };
+    let run_options = RunOptions {
+        disable_mt: matches.is_present("disable-mt"),
+        force: matches.is_present("force") && forced_runs.is_empty(),
+        on_complete: matches.value_of("on-complete").map(|x| x.to_owned()),
+    };
+    let disk_space = {
+        let defaults = DiskSpaceLimits::default();
+
+        DiskSpaceLimits {
+            min_free: matches.value_of("min-free-space").and_then(|x| x.parse::<f64>().ok()).map_or(defaults.min_free, |x| (x * 1e9) as u64),
+            max_pause: matches
+                .value_of("low-space-wait")
+                .and_then(|x| x.parse::<f64>().ok())
+                .map_or(defaults.max_pause, Duration::from_secs_f64),
+        }
+    };
+    let job_partition = match JobPartition::from_args(matches.value_of("job-index"), matches.value_of("job-count")) {
+        Ok(job_partition) => job_partition,
+        Err(err) => {
+            println!("{}", err.red());
+            process::exit(1);
+        }
+    };
 
-    if !dry_run && matches.is_present("overwrite") {
-        println!("Removing existing contents of output directory");
-        fs::remove_dir_all(output_dir).unwrap();
-    }
+    let timezone = match parse_timezone(matches.value_of("timezone").unwrap_or("UTC")) {
+        Ok(timezone) => timezone,
+        Err(err) => {
+            println!("{}", err.red());
+            process::exit(1);
+        }
+    };
 
-    if !dry_run && !output_dir.exists() {
-        if let Err(err) = fs::create_dir_all(output_dir) {
-            println!("{}", format!("Could not create output directory: '{}'!", err).red());
-            return Ok(());
+    let settings = {
+        let output_format = match matches.value_of("output-format") {
+            Some("nexus") => OutputFormat::Nexus,
+            _ => OutputFormat::Bin,
+        };
+
+        if output_format == OutputFormat::Nexus && !cfg!(feature = "nexus") {
+            println!("{}", "--output-format nexus needs the 'nexus' feature".red());
+            process::exit(1);
         }
-    }
 
-    let file_infos: Vec<_> = glob(input_glob_str)
-        .expect("Failed to read input file glob pattern")
-        .filter_map(|x| x.ok()) // Check glob worked
-        .filter(|x| x.is_file()) // Check is file
-        .filter(|x| x.extension().is_some()) // Check has file extension
-        .filter(|x| x.extension().unwrap() == "dat") // Check extension is .dat
-        .filter_map(parse_file_name)
+        let output_template = match OutputTemplate::parse(matches.value_of("output-template").unwrap_or(DEFAULT_RUN_DIR_TEMPLATE), &OUTPUT_TEMPLATE_FIELDS) {
+            Ok(output_template) => output_template,
+            Err(err) => {
+                println!("{}", err.red());
+                process::exit(1);
+            }
+        };
+
+        let hits_filename = product_filename(matches.value_of("hits-filename"), "hits");
+        let triggers_filename = product_filename(matches.value_of("triggers-filename"), "triggers");
+
+        let hit_origins = matches.is_present("hit-origins");
+        let dump_control_packets = matches.is_present("dump-control-packets");
+        let gated = matches.is_present("gated");
+        let resync = matches.is_present("resync");
+
+        let dedup_tolerance = if matches.is_present("dedup") {
+            let tolerance = matches.value_of("dedup-tolerance").and_then(|x| x.parse::<f64>().ok()).unwrap_or(0.0);
+            Some((tolerance / TOA_CLOCK_TO_NS) as u64)
+        } else {
+            None
+        };
+
+        let clock_phases = match matches.value_of("clock-phases").map(|x| x.parse::<u32>()) {
+            Some(Ok(clock_phases)) if clock_phases.is_power_of_two() && clock_phases <= 16 => Some(clock_phases),
+            Some(_) => {
+                println!("{}", "--clock-phases must be 1, 2, 4, 8 or 16".red());
+                process::exit(1);
+            }
+            None => None,
+        };
+
+        let acq_mode = matches.value_of("acq-mode").map(|x| AcqMode::parse(x).unwrap());
+
+        let prescale = matches.value_of("prescale").and_then(parse_human_readable_number::<usize>);
+
+        if let Some(prescale) = prescale {
+            assert!(prescale > 0);
+        }
+
+        let flat_field = match matches.value_of("flat-field") {
+            Some(file) => Some(FlatField::from_gain_map(Path::new(file))?),
+            None => None,
+        };
+
+        let pixel_mask = match matches.value_of("pixel-mask") {
+            Some(file) => Some(PixelMask::from_csv(Path::new(file))?),
+            None => None,
+        };
+
+        let max_pixel_rate = matches.value_of("max-pixel-rate").and_then(|x| x.parse::<f64>().ok());
+        let pixel_rate_window = matches.value_of("pixel-rate-window").and_then(|x| x.parse::<f64>().ok()).unwrap_or(1.0);
+
+        assert!(pixel_rate_window > 0.0);
+
+        let timing_offsets = match matches.value_of("timing-offsets") {
+            Some(file) => Some(TimingOffsets::from_csv(Path::new(file))?),
+            None => None,
+        };
+
+        let sort_batch = match matches.value_of("sort-batch") {
+            Some(x) => parse_human_readable_number::<usize>(x).filter(|&x| x > 0),
+            None => Some(DEFAULT_SORT_BATCH),
+        };
+        let sort_overlap = match matches.value_of("sort-overlap") {
+            Some(x) => parse_human_readable_number::<usize>(x),
+            None => Some(DEFAULT_SORT_OVERLAP.min(sort_batch.unwrap_or(0) / 2)),
+        };
+
+        let (sort_batch, sort_overlap) = match (sort_batch, sort_overlap) {
+            (Some(batch), Some(overlap)) if overlap < batch => (batch, overlap),
+            _ => {
+                println!("{}", "--sort-batch must be a positive number of hits and --sort-overlap smaller than it".red());
+                process::exit(1);
+            }
+        };
+
+        Settings {
+            hits_format: HITS_FORMAT_VERSION,
+            output_format,
+            timezone: timezone.name().to_owned(),
+            absolute_time: matches.is_present("absolute-time"),
+            output_template,
+            hits_filename,
+            triggers_filename,
+            hit_origins,
+            dump_control_packets,
+            gated,
+            resync,
+            dedup_tolerance,
+            clock_phases,
+            acq_mode,
+            prescale,
+            flat_field,
+            timing_offsets,
+            pixel_mask,
+            max_pixel_rate,
+            pixel_rate_window,
+            sort_batch,
+            sort_overlap,
+        }
+    };
+
+    let file_infos: Vec<_> = glob_input_files(input_glob_str)?
+        .into_iter()
+        .filter(|x| is_raw_data_file(x)) // Check extension is .dat (or .dat.gz/.dat.zst)
+        .filter_map(|x| parse_file_name(x, timezone))
         .collect();
 
     if file_infos.is_empty() {
@@ -116,7 +562,41 @@ fn main() -> io::Result<()> {
 
     println!("Matched {} input files", file_infos.len());
 
-    let mut grouped_file_infos: Vec<(Vec<&FileInfo>, PathBuf)> = Vec::new();
+    let input_files: Vec<PathBuf> = file_infos.iter().map(|x| x.path.clone()).collect();
+
+    if let Err(err) = check_output_outside_input(input_glob_str, &input_files, output_dir) {
+        println!("{}", err.to_string().red());
+        process::exit(1);
+    }
+
+    if overwrite.all {
+        let n_files = count_files(output_dir)?;
+
+        if dry_run {
+            println!("Would remove the existing contents of the output directory ({} files)", n_files);
+        } else {
+            confirm_overwrite(n_files, max_overwrite_files, confirmed);
+
+            println!("Removing existing contents of output directory ({} files)", n_files);
+
+            if output_dir.exists() {
+                fs::remove_dir_all(output_dir)?;
+            }
+        }
+    }
+
+    if !dry_run && !output_dir.exists() {
+        if let Err(err) = fs::create_dir_all(output_dir) {
+            println!("{}", format!("Could not create output directory: '{}'!", err).red());
+            return Ok(());
+        }
+    }
+
+    if job_partition.count > 1 {
+        println!("Array job task {} of {}", job_partition.index, job_partition.count);
+    }
+
+    let mut runs: Vec<(Vec<&FileInfo>, PathBuf)> = Vec::new();
 
     let mut i = 0;
     while i < file_infos.len() {
@@ -130,8 +610,6 @@ fn main() -> io::Result<()> {
 
         let mut run_file_infos = vec![info];
 
-        let datetime_str = file_infos[i].start_time.format("%Y-%m-%d_%H-%M-%S");
-
         if i < file_infos.len() - 1 {
             for j in 2.. {
                 if i == file_infos.len() - 1 {
@@ -140,7 +618,7 @@ fn main() -> io::Result<()> {
 
                 let next_info = &file_infos[i + 1];
 
-                if next_info.file_in_run == j && next_info.run_name == info.run_name {
+                if next_info.file_in_run == j && next_info.run_name == info.run_name && next_info.device == info.device {
                     run_file_infos.push(next_info);
                     i += 1;
                 } else {
@@ -149,70 +627,332 @@ fn main() -> io::Result<()> {
             }
         }
 
-        let run_output_dir = output_dir.join(match &info.run_name {
-            Some(run_name) => format!("{}_{}", datetime_str, run_name),
-            None => format!("{}", datetime_str),
-        });
+        let run_output_dir = output_dir.join(settings.output_template.render(&output_template_values(info)));
 
-        if !run_output_dir.exists() {
-            grouped_file_infos.push((run_file_infos, run_output_dir));
+        if run_output_dir == output_dir {
+            println!("{}", format!("Output template '{}' is empty for '{}'", settings.output_template.as_str(), info.path.display()).red());
+            process::exit(1);
         }
 
+        runs.push((run_file_infos, run_output_dir));
+
         i += 1;
     }
 
+    // Done over every run, not just this task's share, so all the tasks of an array job agree
+    let run_notes = disambiguate_run_dirs(&mut runs);
+
+    for (_, run_output_dir) in &runs {
+        if let Some(note) = run_notes.get(run_output_dir) {
+            println!("{}", format!("{} | {}", run_output_dir.display(), note).yellow());
+        }
+    }
+
+    let mut grouped_file_infos: Vec<(Vec<&FileInfo>, PathBuf)> = Vec::new();
+    let mut existing_run_dirs: Vec<PathBuf> = Vec::new();
+    let mut cluster_run_dirs: Vec<PathBuf> = Vec::new(); // Runs to remove the cluster/event files of
+    let mut n_overwritten_files = 0; // Files of earlier processing replaced or removed by --overwrite/--force run
+
+    for (run_index, (run_file_infos, run_output_dir)) in runs.into_iter().enumerate() {
+        // Interrupted runs are redone, runs are shared between array job tasks in the order they are grouped
+        if !job_partition.contains(run_index) {
+            continue;
+        }
+
+        let info = run_file_infos[0];
+        let forced = forced_runs.iter().any(|x| Some(x) == info.run_name.as_ref() || run_output_dir.ends_with(x));
+
+        // Everything is removed first with a plain --overwrite, which a dry run does not do
+        let exists = run_output_dir.exists() && !overwrite.all;
+
+        if exists && overwrite.clusters {
+            cluster_run_dirs.push(run_output_dir.clone());
+        }
+
+        let partial = has_partial_marker(&hits_file_path(&run_output_dir, &settings));
+
+        if exists && !partial && (overwrite.hits || forced) {
+            n_overwritten_files += hits_products(&run_output_dir, &settings).iter().filter(|x| x.exists()).count();
+        }
+
+        if !exists || partial || overwrite.hits || forced {
+            grouped_file_infos.push((run_file_infos, run_output_dir));
+        } else {
+            existing_run_dirs.push(run_output_dir);
+        }
+    }
+
     let n_runs = grouped_file_infos.len();
 
     println!("Grouped files into {} runs", n_runs);
 
+    for run_dir in &cluster_run_dirs {
+        n_overwritten_files += Run::open(run_dir)?.cluster_files()?.len() * 3; // Data, metadata CSV and settings
+    }
+
+    if n_overwritten_files > 0 {
+        if dry_run {
+            println!("Would replace or remove {} files of earlier processing", n_overwritten_files);
+        } else {
+            confirm_overwrite(n_overwritten_files, max_overwrite_files, confirmed);
+        }
+    }
+
+    // Cluster/event files made from the hits of an earlier parse, to be remade by the other tools
+    for run_dir in &cluster_run_dirs {
+        let names = Run::open(run_dir)?.cluster_files()?;
+
+        if names.is_empty() {
+            continue;
+        }
+
+        if dry_run {
+            println!("{} | Would remove cluster/event files: {}", run_dir.display(), names.join(", "));
+        } else {
+            remove_cluster_files(run_dir, &names, run_options.force)?;
+
+            println!("{} | Removed cluster/event files: {}", run_dir.display(), names.join(", "));
+        }
+    }
+
     if dry_run {
+        let mut plan = DryRunPlan::new().output_dir(output_dir);
+
         for (run_file_infos, run_output_dir) in grouped_file_infos {
             println!("\nOutput dir: {}\nFiles:", run_output_dir.to_str().unwrap());
 
-            for file_info in run_file_infos {
+            for file_info in &run_file_infos {
                 println!("  - {}", file_info.path.to_str().unwrap());
             }
+
+            let run_file_paths: Vec<_> = run_file_infos.iter().map(|x| &x.path).collect();
+            let n_bytes = total_file_bytes(&run_file_paths)?;
+
+            // At most one hit per 8 byte packet
+            plan.run(&run_output_dir, n_bytes, Some(n_bytes / 8));
+        }
+
+        for run_output_dir in existing_run_dirs {
+            plan.skipped(&run_output_dir);
         }
+
+        plan.print();
     } else {
-        let sty = ProgressStyle::default_bar()
-            .template(PROGRESS_BAR_TEMPLATE)
-            .progress_chars(PROGRESS_BAR_CHARS);
+        install_interrupt_handler();
+
+        let mut jobs = Vec::new();
+        let mut estimated_size = 0;
+
+        for (run_file_infos, run_output_dir) in grouped_file_infos {
+            let run_file_paths: Vec<_> = run_file_infos.iter().map(|x| &x.path).collect();
+            let n_bytes = total_file_bytes(&run_file_paths)?;
 
-        let disable_mt = disable_mt || grouped_file_infos.len() == 1;
+            let run_file_infos: Vec<FileInfo> = run_file_infos.into_iter().cloned().collect();
 
-        if disable_mt {
-            for (run_file_infos, run_output_dir) in grouped_file_infos {
-                let n_bytes: u64 = run_file_infos.iter().map(|x| x.path.metadata().unwrap().len()).sum();
-                let n_packets = (n_bytes - 66304 * run_file_infos.len() as u64) / 8;
+            // Run directories nested by the output template need their parent for the run lock
+            fs::create_dir_all(run_output_dir.parent().unwrap())?;
 
-                let progress_bar = ProgressBar::new(n_packets);
-                progress_bar.set_style(sty.clone());
+            estimated_size += n_bytes * 2; // 16 byte hits from 8 byte packets
 
-                process_run(run_file_infos, run_output_dir, progress_bar).unwrap();
+            jobs.push(RunJob::with_byte_progress(run_output_dir, run_file_infos, n_bytes));
+        }
+
+        if let Err(err) = check_free_space(output_dir, estimated_size, &disk_space) {
+            println!("{}", err.to_string().red().bold());
+            process::exit(1);
+        }
+
+        let mut results = process_runs(jobs, run_options, move |run_output_dir, run_file_infos, progress_bar| {
+            if settings.hit_origins {
+                process_run::<TracedHit>(run_output_dir, run_file_infos, settings.clone(), disk_space, progress_bar)
+            } else {
+                process_run::<Hit>(run_output_dir, run_file_infos, settings.clone(), disk_space, progress_bar)
+            }
+        });
+
+        if let Some(manifest) = &manifest {
+            for result in &mut results {
+                result.note = run_notes.get(&result.run).cloned();
             }
-        } else {
-            let multi_progress = MultiProgress::new();
 
-            rayon::scope(|s| {
-                for (run_file_infos, run_output_dir) in grouped_file_infos {
-                    let n_bytes: u64 = run_file_infos.iter().map(|x| x.path.metadata().unwrap().len()).sum();
-                    let n_packets = (n_bytes - 66304 * run_file_infos.len() as u64) / 8;
+            write_run_manifest(Path::new(manifest), &results)?;
+        }
 
-                    let progress_bar = multi_progress.add(ProgressBar::new(n_packets));
-                    progress_bar.set_style(sty.clone());
+        if let Some(exit_code) = report_run_results(&results) {
+            process::exit(exit_code);
+        }
+    }
 
-                    s.spawn(move |_| process_run(run_file_infos, run_output_dir, progress_bar).unwrap());
-                }
+    Ok(())
+}
+
+/// Previous data `--overwrite` replaces, all of it (the whole output directory) unless products
+/// are given
+#[derive(Clone, Copy, Debug, Default)]
+struct Overwrite {
+    all: bool,
+    hits: bool,
+    clusters: bool,
+}
+
+impl Overwrite {
+    fn from_args(present: bool, values: Option<clap::Values>) -> Overwrite {
+        let values: Vec<&str> = values.map(|x| x.collect()).unwrap_or_default();
+
+        Overwrite {
+            all: present && (values.is_empty() || values.contains(&"all")),
+            hits: values.contains(&"hits"),
+            clusters: values.contains(&"clusters"),
+        }
+    }
+}
+
+/// Name of a data product from the command line, which must be a plain file name
+fn product_filename(name: Option<&str>, default: &str) -> String {
+    let name = name.unwrap_or(default);
+
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        println!("{}", format!("Invalid file name '{}', it must not be empty or contain path separators", name).red());
+        process::exit(1);
+    }
+
+    name.to_owned()
+}
+
+/// Values of the `--output-template` fields for a run, from its first file
+fn output_template_values(info: &FileInfo) -> Vec<(&'static str, String)> {
+    vec![
+        ("datetime", info.start_time.file_time.format("%Y-%m-%d_%H-%M-%S").to_string()),
+        ("date", info.start_time.file_time.format("%Y-%m-%d").to_string()),
+        ("time", info.start_time.file_time.format("%H-%M-%S").to_string()),
+        ("run_name", info.run_name.clone().unwrap_or_default()),
+        ("device", info.device.clone()),
+    ]
+}
+
+/// Gives runs that would be written to the same directory (e.g. runs started in the same second
+/// on two devices, or with the DAQ clock not set) directories of their own, adding the device ID
+/// when the runs are from different devices and then a counter for any still sharing a name.
+/// Returns a note of each run renamed, for the manifest.
+fn disambiguate_run_dirs(runs: &mut [(Vec<&FileInfo>, PathBuf)]) -> HashMap<PathBuf, String> {
+    let mut groups: HashMap<PathBuf, Vec<usize>> = HashMap::new();
+
+    for (i, (_, run_output_dir)) in runs.iter().enumerate() {
+        groups.entry(run_output_dir.clone()).or_default().push(i);
+    }
+
+    let mut taken: HashSet<PathBuf> = groups.keys().cloned().collect();
+    let mut notes = HashMap::new();
+
+    // In the order the runs were grouped, so the names do not depend on the hash map order
+    let mut collisions: Vec<(PathBuf, Vec<usize>)> = groups.into_iter().filter(|(_, x)| x.len() > 1).collect();
+    collisions.sort_by_key(|(_, x)| x[0]);
+
+    for (run_output_dir, indices) in collisions {
+        let devices: HashSet<&str> = indices.iter().map(|&i| runs[i].0[0].device.as_str()).collect();
+        let file_name = run_output_dir.file_name().unwrap().to_string_lossy().into_owned();
+
+        taken.remove(&run_output_dir);
+
+        for &i in &indices {
+            let first_file = &runs[i].0[0];
+
+            let name = if devices.len() > 1 { format!("{}_{}", file_name, first_file.device) } else { file_name.clone() };
+
+            let mut new_dir = run_output_dir.with_file_name(&name);
+            let mut n = 2;
+
+            while taken.contains(&new_dir) {
+                new_dir = run_output_dir.with_file_name(format!("{}_{}", name, n));
+                n += 1;
+            }
+
+            taken.insert(new_dir.clone());
+
+            if new_dir != run_output_dir {
+                notes.insert(
+                    new_dir.clone(),
+                    format!(
+                        "Run starting with '{}' renamed from '{}', which {} runs would share",
+                        first_file.path.display(),
+                        run_output_dir.display(),
+                        indices.len()
+                    ),
+                );
+            }
 
-                multi_progress.join().unwrap();
-            });
+            runs[i].1 = new_dir;
         }
     }
 
+    notes
+}
+
+fn hits_file_path(run_output_dir: &Path, settings: &Settings) -> PathBuf {
+    run_output_dir.join(format!("{}.bin", settings.hits_filename))
+}
+
+/// Files written into a run directory by the parser, replaced when a run is reparsed. Anything
+/// else in the directory (e.g. the products of the other tools) is left alone.
+fn hits_products(run_output_dir: &Path, settings: &Settings) -> Vec<PathBuf> {
+    let hits_file_path = hits_file_path(run_output_dir, settings);
+
+    vec![
+        hits_index_path(&hits_file_path),
+        hits_file_path.with_extension("nxs"),
+        run_output_dir.join("hits.toml"),
+        run_output_dir.join(format!("{}_prescaled.bin", settings.hits_filename)),
+        hits_index_path(&run_output_dir.join(format!("{}_prescaled.bin", settings.hits_filename))),
+        run_output_dir.join("hit_origins.bin"),
+        run_output_dir.join("control_packets.csv"),
+        run_output_dir.join("timestamps.csv"),
+        run_output_dir.join(format!("{}.bin", settings.triggers_filename)),
+        run_output_dir.join(format!("{}.csv", settings.triggers_filename)),
+        run_output_dir.join("gates.csv"),
+        run_output_dir.join("hot_pixel_mask_log.csv"),
+        hits_file_path,
+    ]
+}
+
+/// Stops before an `--overwrite` removes more files than expected, unless confirmed with `--yes`
+fn confirm_overwrite(n_files: usize, max_files: usize, confirmed: bool) {
+    if n_files > max_files && !confirmed {
+        println!(
+            "{}",
+            format!(
+                "--overwrite would remove {} files, more than the {} allowed by --max-overwrite-files. Give --yes to confirm.",
+                n_files, max_files
+            )
+            .red()
+        );
+        process::exit(1);
+    }
+}
+
+/// Removes the cluster/event files of a run, holding its lock so they are not removed from under
+/// a tool still writing them
+fn remove_cluster_files(run_dir: &Path, names: &[String], force: bool) -> io::Result<()> {
+    let _lock = RunLock::acquire(run_dir, force)?;
+
+    for name in names {
+        let data_file_path = run_dir.join(format!("{}.bin", name));
+
+        for extension in &["csv", "toml"] {
+            let file_path = data_file_path.with_extension(extension);
+
+            if file_path.exists() {
+                fs::remove_file(file_path)?;
+            }
+        }
+
+        clear_partial_marker(&data_file_path)?;
+        fs::remove_file(data_file_path)?;
+    }
+
     Ok(())
 }
 
-fn parse_file_name(path: PathBuf) -> Option<FileInfo> {
+fn parse_file_name(path: PathBuf, timezone: Tz) -> Option<FileInfo> {
     let path = path.to_owned();
 
     let path_str = match path.to_str() {
@@ -238,7 +978,15 @@ fn parse_file_name(path: PathBuf) -> Option<FileInfo> {
     let second = caps.get(8).unwrap().as_str().parse::<u32>().unwrap();
     let file_in_run = caps.get(9).unwrap().as_str().parse::<u32>().unwrap();
 
-    let start_time = Utc.ymd(year, month, day).and_hms(hour, minute, second);
+    let file_time = NaiveDate::from_ymd_opt(year, month, day)?.and_hms_opt(hour, minute, second)?;
+
+    let start_time = match RunStartTime::resolve(file_time, timezone) {
+        Ok(start_time) => start_time,
+        Err(err) => {
+            println!("{}", format!("Skipping '{}': {}", path.display(), err).yellow());
+            return None;
+        }
+    };
 
     Some(FileInfo {
         run_name,
@@ -260,216 +1008,793 @@ fn vecdeque_insertion_sort<T: Ord>(list: &mut VecDeque<T>) {
     }
 }
 
-fn process_run(file_infos: Vec<&FileInfo>, run_output_dir: PathBuf, progress_bar: ProgressBar) -> io::Result<()> {
+/// Checks whether a time (ns) falls within a completed gate or after the start of a gate that is
+/// still open. Completed gates are ordered by start time.
+fn is_in_gate(time: u64, gates: &[Gate], open_gate_start: Option<u64>) -> bool {
+    if let Some(start) = open_gate_start {
+        if time >= start {
+            return true;
+        }
+    }
+
+    match gates.binary_search_by(|gate| gate.start.cmp(&time)) {
+        Ok(_) => true,
+        Err(0) => false,
+        Err(i) => gates[i - 1].contains(time),
+    }
+}
+
+/// Hits as they pass through the pipeline, tagged with the packet they came from when
+/// `--hit-origins` is set. Plain hits carry nothing extra, so tracing costs nothing when it is off.
+trait PipelineHit: Copy + Ord + Send + 'static {
+    fn new(hit: Hit, origin: HitOrigin) -> Self;
+
+    fn hit(&self) -> &Hit;
+
+    fn hit_mut(&mut self) -> &mut Hit;
+
+    /// Separates a batch into its hits and their origins (if traced)
+    fn split(batch: Vec<Self>) -> (Vec<Hit>, Option<Vec<HitOrigin>>);
+}
+
+impl PipelineHit for Hit {
+    fn new(hit: Hit, _origin: HitOrigin) -> Hit {
+        hit
+    }
+
+    fn hit(&self) -> &Hit {
+        self
+    }
+
+    fn hit_mut(&mut self) -> &mut Hit {
+        self
+    }
+
+    fn split(batch: Vec<Hit>) -> (Vec<Hit>, Option<Vec<HitOrigin>>) {
+        (batch, None)
+    }
+}
+
+/// Ordered by the hit alone, so traced hits are sorted exactly as plain hits would be
+#[derive(Clone, Copy, Debug)]
+struct TracedHit {
+    hit: Hit,
+    origin: HitOrigin,
+}
+
+impl Ord for TracedHit {
+    fn cmp(&self, other: &TracedHit) -> Ordering {
+        self.hit.cmp(&other.hit)
+    }
+}
+
+impl PartialOrd for TracedHit {
+    fn partial_cmp(&self, other: &TracedHit) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for TracedHit {
+    fn eq(&self, other: &TracedHit) -> bool {
+        self.hit == other.hit
+    }
+}
+
+impl Eq for TracedHit {}
+
+impl PipelineHit for TracedHit {
+    fn new(hit: Hit, origin: HitOrigin) -> TracedHit {
+        TracedHit { hit, origin }
+    }
+
+    fn hit(&self) -> &Hit {
+        &self.hit
+    }
+
+    fn hit_mut(&mut self) -> &mut Hit {
+        &mut self.hit
+    }
+
+    fn split(batch: Vec<TracedHit>) -> (Vec<Hit>, Option<Vec<HitOrigin>>) {
+        let (hits, origins) = batch.into_iter().map(|x| (x.hit, x.origin)).unzip();
+
+        (hits, Some(origins))
+    }
+}
+
+/// Sent from the decode stage to the sort stage, in the order read from the raw files
+enum DecodedData<T> {
+    Hits(Vec<T>),
+    GateOpened(u64), // ns
+    GateClosed(u64), // ns
+}
+
+/// State handed back by the sort stage once the decode stage has finished
+struct SortStageOutput {
+    gates: Vec<Gate>,
+    deduplicator: Option<HitDeduplicator>,
+    counters: SortCounters,
+}
+
+/// State handed back by the write stage once the sort stage has finished
+struct WriteStageOutput {
+    hits_index: HitsIndexBuilder,
+    prescaled_writer: Option<PrescaledWriter>,
+    written: usize,
+    last_hit: Option<Hit>,
+}
+
+/// Puts decoded hits back in time order with the conveyor, dropping hits outside gates and
+/// duplicates, and passes them on to the write stage. With `--gated` the hits after the last gate
+/// edge received are held back until a later edge (or the end of the run) covers them, as an edge
+/// still to come may open or close a gate before them.
+fn sort_stage<T: PipelineHit>(
+    receiver: Receiver<DecodedData<T>>,
+    sender: SyncSender<Vec<T>>,
+    settings: &Settings,
+    mut deduplicator: Option<HitDeduplicator>,
+) -> SortStageOutput {
+    let mut hit_conveyor: VecDeque<T> = VecDeque::with_capacity(settings.sort_batch);
+    let mut gates = Vec::new();
+    let mut open_gate_start: Option<u64> = None;
+    let mut gate_edges_until: Option<u64> = None; // ns, time of the last gate edge received
+    let mut gate_held: VecDeque<T> = VecDeque::new();
+
+    let gated = settings.gated;
+    let mut last_sorted_toa: Option<u64> = None; // Latest hit passed on to the write stage
+    let mut counters = SortCounters {
+        batch: settings.sort_batch,
+        overlap: settings.sort_overlap,
+        ..SortCounters::default()
+    };
+
+    for data in receiver.iter() {
+        match data {
+            DecodedData::Hits(hits) => {
+                for hit in hits {
+                    let toa = hit.hit().toa;
+
+                    if let Some(last_sorted_toa) = last_sorted_toa.filter(|&last_sorted_toa| toa < last_sorted_toa) {
+                        counters.mis_sorted += 1;
+                        counters.max_mis_sort_ns = counters.max_mis_sort_ns.max((last_sorted_toa - toa) as f64 * TOA_CLOCK_TO_NS);
+                    }
+
+                    hit_conveyor.push_back(hit);
+
+                    if hit_conveyor.len() < settings.sort_batch {
+                        continue;
+                    }
+
+                    vecdeque_insertion_sort(&mut hit_conveyor);
+
+                    let temp = hit_conveyor.split_off(settings.sort_batch - settings.sort_overlap);
+                    let batch = mem::replace(&mut hit_conveyor, temp);
+
+                    last_sorted_toa = batch.back().map(|hit| hit.hit().toa).max(last_sorted_toa);
+
+                    let batch = if gated {
+                        hold_for_gates(&mut gate_held, batch, gate_edges_until)
+                    } else {
+                        batch
+                    };
+                    let batch = filter_batch(batch, gated, &gates, open_gate_start, &mut deduplicator);
+
+                    // Write stage has failed, its error is picked up when it is joined
+                    if sender.send(batch).is_err() {
+                        return SortStageOutput { gates, deduplicator, counters };
+                    }
+
+                    hit_conveyor.reserve(settings.sort_batch);
+                }
+            }
+            // TDC2 rising edge opens a gate
+            DecodedData::GateOpened(time) => {
+                open_gate_start = Some(time);
+                gate_edges_until = Some(time).max(gate_edges_until);
+            }
+            // TDC2 falling edge closes the open gate
+            DecodedData::GateClosed(time) => {
+                if let Some(start) = open_gate_start.take() {
+                    gates.push(Gate { start, end: time });
+                }
+
+                gate_edges_until = Some(time).max(gate_edges_until);
+            }
+        }
+    }
+
+    // Sort and pass on remaining hits, along with the held back hits now every gate is known
+    vecdeque_insertion_sort(&mut hit_conveyor);
+
+    gate_held.append(&mut hit_conveyor);
+
+    let batch = filter_batch(gate_held, gated, &gates, open_gate_start, &mut deduplicator);
+    let _ = sender.send(batch);
+
+    SortStageOutput { gates, deduplicator, counters }
+}
+
+/// Adds a sorted batch to the hits held back for the gates, returning the held hits up to the
+/// last gate edge received, the gates they fall in being known
+fn hold_for_gates<T: PipelineHit>(gate_held: &mut VecDeque<T>, mut batch: VecDeque<T>, gate_edges_until: Option<u64>) -> VecDeque<T> {
+    gate_held.append(&mut batch);
+
+    let covered = match gate_edges_until {
+        Some(until) => gate_held
+            .iter()
+            .take_while(|hit| (hit.hit().toa as f64 * TOA_CLOCK_TO_NS) as u64 <= until)
+            .count(),
+        None => 0,
+    };
+
+    gate_held.drain(..covered).collect()
+}
+
+/// Drops hits outside the gates and duplicates from a sorted batch
+fn filter_batch<T: PipelineHit>(
+    batch: VecDeque<T>,
+    gated: bool,
+    gates: &[Gate],
+    open_gate_start: Option<u64>,
+    deduplicator: &mut Option<HitDeduplicator>,
+) -> Vec<T> {
+    let mut batch = Vec::from(batch);
+
+    if gated {
+        batch.retain(|hit| is_in_gate((hit.hit().toa as f64 * TOA_CLOCK_TO_NS) as u64, gates, open_gate_start));
+    }
+
+    if let Some(deduplicator) = deduplicator.as_mut() {
+        batch.retain_mut(|hit| {
+            let duplicate = deduplicator.is_duplicate(hit.hit());
+
+            if !duplicate {
+                hit.hit_mut().flags.insert(HitFlags::DEDUPLICATED);
+            }
+
+            !duplicate
+        });
+    }
+
+    batch
+}
+
+/// Writes sorted hits to `hits.bin`, its index, the prescaled sub-sample and the origins of
+/// traced hits to `hit_origins.bin`
+fn write_stage<T: PipelineHit>(
+    receiver: Receiver<Vec<T>>,
+    mut output_file: fs::File,
+    mut hits_index: HitsIndexBuilder,
+    mut prescaled_writer: Option<PrescaledWriter>,
+    mut origins_file: Option<fs::File>,
+) -> io::Result<WriteStageOutput> {
+    let mut written = 0;
+    let mut last_hit = None;
+
+    for batch in receiver.iter() {
+        let (hits, origins) = T::split(batch);
+
+        hits_index.add(&hits);
+        write_hits_to_file(&mut output_file, &hits)?;
+
+        if let (Some(origins_file), Some(origins)) = (origins_file.as_mut(), origins) {
+            write_hit_origins_to_file(origins_file, &origins)?;
+        }
+
+        if let Some(prescaled_writer) = prescaled_writer.as_mut() {
+            prescaled_writer.write(&hits)?;
+        }
+
+        written += hits.len();
+        last_hit = hits.last().cloned().or(last_hit);
+    }
+
+    Ok(WriteStageOutput {
+        hits_index,
+        prescaled_writer,
+        written,
+        last_hit,
+    })
+}
+
+/// Pixels next to a hot pixel (listed in `HOT_PIXELS` or masked), indexed by `row * 256 + col`
+fn hot_neighbour_map(pixel_mask: Option<&PixelMask>) -> Vec<bool> {
+    let mut map = vec![false; 256 * 256];
+
+    let mut hot_pixels = HOT_PIXELS.to_vec();
+
+    if let Some(pixel_mask) = pixel_mask {
+        hot_pixels.extend(pixel_mask.masked_pixel_list());
+    }
+
+    for (col, row) in hot_pixels {
+        for neighbour_col in col.saturating_sub(1)..=(col + 1).min(255) {
+            for neighbour_row in row.saturating_sub(1)..=(row + 1).min(255) {
+                map[usize::from(neighbour_row) * 256 + usize::from(neighbour_col)] = true;
+            }
+        }
+    }
+
+    map
+}
+
+/// Waits for a stage thread to finish, carrying on any panic so the run is reported as failed
+fn join_stage<T>(stage: thread::JoinHandle<T>) -> T {
+    stage.join().unwrap_or_else(|payload| panic::resume_unwind(payload))
+}
+
+fn process_run<T: PipelineHit>(run_output_dir: &Path, file_infos: Vec<FileInfo>, settings: Settings, disk_space: DiskSpaceLimits, progress_bar: ProgressBar) -> io::Result<()> {
     let run_name = file_infos[0].path.file_stem().unwrap().to_str().unwrap().split("W00").nth(0).unwrap();
 
     progress_bar.set_message(&format!("| 0 Hits Parsed | 0 Triggers Parsed | 0 Hot Pixels Removed | {}", run_name));
 
     let data_files: Vec<_> = file_infos.iter().map(|x| x.path.to_owned()).collect();
 
-    let mut hit_conveyor: VecDeque<Hit> = VecDeque::with_capacity(BATCH_SIZE);
     let mut triggers = Vec::new();
 
-    let mut buffer: [u8; BUFFER_SIZE * 8] = [0; BUFFER_SIZE * 8];
+    let mut trigger_time_decoder = TdcTimeDecoder::new();
+
+    let mut toa_extender = ToaExtender::new();
+    let mut timestamp_decoder = TimestampDecoder::new();
+    let mut acq_mode = None;
+    let mut timestamps = Vec::new();
+    let mut packet_recovery = PacketRecovery::default();
+    let mut control_packets = ControlPacketCounts::new();
+    let mut control_packets_writer = None;
+    let deduplicator = settings.dedup_tolerance.map(HitDeduplicator::new);
 
-    let mut prev_trigtime_coarse: u64 = 0;
-    let mut trigtime_global_ext: u64 = 0;
+    let hot_neighbours = hot_neighbour_map(settings.pixel_mask.as_ref());
 
-    let mut long_time: u64 = 0;
-    let mut longtime_lsb: u64 = 0;
+    let mut hot_pixel_suppressor = settings.max_pixel_rate.map(|rate| {
+        let window = (settings.pixel_rate_window * 1e9 / TOA_CLOCK_TO_NS) as u64;
+        RollingHotPixelSuppressor::new(window, (rate * settings.pixel_rate_window) as u32)
+    });
 
+    let mut packets_read: usize = 0;
     let mut hits_parsed: usize = 0;
-    let mut packets_parsed: usize = 0;
     let mut triggers_parsed: usize = 0;
     let mut hot_pixels_removed: usize = 0;
 
-    fs::create_dir(&run_output_dir)?;
-    let mut output_file = fs::File::create(run_output_dir.join("hits.bin"))?;
+    let mut stopped = None;
+    let mut disk_space_monitor = DiskSpaceMonitor::new(run_output_dir, disk_space);
+
+    let hits_file_path = hits_file_path(run_output_dir, &settings);
 
-    for data_file in data_files {
-        let mut file = fs::File::open(&data_file)?;
+    // Reparsing an already parsed run (--overwrite hits or --force run) or redoing the partial
+    // output of an interrupted one, the previous files are removed so none are left over from
+    // options no longer given
+    for file_path in hits_products(run_output_dir, &settings) {
+        if file_path.exists() {
+            fs::remove_file(file_path)?;
+        }
+    }
 
-        // First bytes in file are spidr ID and subsequent header size
-        let _spidr_id = file.read_u32::<LittleEndian>()?;
-        let spidr_header_size = file.read_u32::<LittleEndian>()?;
-        let spidr_header_size = cmp::min(spidr_header_size, 66304);
+    clear_partial_marker(&hits_file_path)?;
 
-        // Skip header
-        file.seek(SeekFrom::Current(i64::from(spidr_header_size)))?;
+    fs::create_dir_all(run_output_dir)?;
+    let output_file = fs::File::create(&hits_file_path)?;
+    let hits_index = HitsIndexBuilder::new();
 
-        loop {
-            let bytes_read = file.read(&mut buffer)?;
+    // Write metadata to TOML file
+    write_settings_toml(&run_output_dir.join("hits.toml"), &settings, &data_files)?;
+
+    let prescaled_writer = match settings.prescale {
+        Some(prescale) => Some(PrescaledWriter::new(&run_output_dir.join(format!("{}_prescaled.bin", settings.hits_filename)), prescale)?),
+        None => None,
+    };
+
+    let origins_file = if settings.hit_origins {
+        Some(fs::File::create(run_output_dir.join("hit_origins.bin"))?)
+    } else {
+        None
+    };
 
-            // Check there are still some bytes to process
-            if bytes_read == 0 {
+    // Decoding runs on this thread, sorting and writing on their own threads so reading the raw
+    // files is not held up by them. The bounded channels block a stage that gets too far ahead of
+    // the next, keeping memory use bounded.
+    let (decoded_sender, decoded_receiver) = mpsc::sync_channel(PIPELINE_DEPTH);
+    let (sorted_sender, sorted_receiver) = mpsc::sync_channel(PIPELINE_DEPTH);
+
+    let sort_stage = {
+        let settings = settings.clone();
+        thread::spawn(move || sort_stage(decoded_receiver, sorted_sender, &settings, deduplicator))
+    };
+    let write_stage = thread::spawn(move || write_stage::<T>(sorted_receiver, output_file, hits_index, prescaled_writer, origins_file));
+
+    let mut hits_chunk = Vec::with_capacity(DECODE_CHUNK_SIZE);
+    let mut pipeline_closed = false; // A later stage has stopped, its error is picked up when it is joined
+
+    let mut byte_progress = FileByteProgress::new();
+
+    for (file_index, data_file) in data_files.into_iter().enumerate() {
+        let mut file = RawFile::open(&data_file)?;
+        byte_progress.start_file(file.file_len()?);
+
+        // A file the DAQ created but did not get to write the header of holds no data
+        let spidr_header = match read_spidr_header(&mut file) {
+            Ok(spidr_header) => spidr_header,
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                println!("{}", format!("WARNING: Skipping {:#?}, its header is truncated", data_file).yellow());
+
+                toa_extender.start_file();
+                packet_recovery.skipped_files += 1;
+
+                byte_progress.finish_file();
+                progress_bar.set_position(byte_progress.position(0));
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+
+        toa_extender.start_file();
+
+        // Command line setting takes priority over the PLL configuration in the header
+        let clock_phases = settings
+            .clock_phases
+            .or_else(|| spidr_header.clock_phases())
+            .unwrap_or(DEFAULT_CLOCK_PHASES);
+
+        let file_acq_mode = settings.acq_mode.or_else(|| spidr_header.acq_mode()).unwrap_or(AcqMode::ToaTot);
+
+        // The hits of a run are all decoded the same way, so files in a different mode are flagged
+        match acq_mode {
+            None => acq_mode = Some(file_acq_mode),
+            Some(acq_mode) if acq_mode != file_acq_mode => {
+                println!(
+                    "{}",
+                    format!("WARNING: {:#?} was recorded in {:?} mode and the first file of the run in {:?} mode", data_file, file_acq_mode, acq_mode).yellow()
+                );
+            }
+            Some(_) => (),
+        }
+
+        let mut packets = ReadRawPacketIterator::new(file, settings.resync);
+
+        // Not a for loop as the iterator is asked how far through the file it is
+        while let Some(packet) = packets.next() {
+            // Stop ingesting, everything read so far is still written out below
+            if is_interrupted() {
+                stopped = Some(interrupted_error());
                 break;
             }
 
-            // Check that number of bytes is exactly divisible by the size of a hit
-            if bytes_read % 8 != 0 {
-                panic!("Bytes read from {:#?} is not divisible by 8 (the size of a hit)", data_file);
+            if let Err(err) = disk_space_monitor.check(&progress_bar) {
+                stopped = Some(err);
+                break;
             }
 
-            let packet_data = unsafe { mem::transmute::<[u8; BUFFER_SIZE * 8], [u64; BUFFER_SIZE]>(buffer) };
-            let packet_data: Vec<u64> = packet_data.to_owned().to_vec();
+            packets_read += 1;
 
-            for packet in packet_data.iter().take(bytes_read / 8) {
-                packets_parsed += 1;
+            let header = ((packet & 0xF000_0000_0000_0000) >> 60) & 0xF;
 
-                let header = ((packet & 0xF000_0000_0000_0000) >> 60) & 0xF;
+            if header == 0xA || header == 0xB {
+                hits_parsed += 1;
 
-                if header == 0xA || header == 0xB {
-                    hits_parsed += 1;
+                let mut hit = toa_extender.decode_hit_with_mode(packet, clock_phases, file_acq_mode);
 
-                    // Calculate col and row
-                    let dcol = (packet & 0x0FE0_0000_0000_0000) >> 52; //(16+28+9-1)
-                    let spix = (packet & 0x001F_8000_0000_0000) >> 45; //(16+28+3-2)
-                    let pix = (packet & 0x0000_7000_0000_0000) >> 44; //(16+28)
-                    let col = (dcol + pix / 4) as u16;
-                    let row = (spix + (pix & 0x3)) as u16;
+                let masked = settings.pixel_mask.as_ref().map_or(false, |pixel_mask| pixel_mask.is_masked(&hit));
 
-                    if HOT_PIXELS.iter().any(|(hcol, hrow)| *hcol == col && *hrow == row) {
-                        hot_pixels_removed += 1;
+                if masked || HOT_PIXELS.iter().any(|(hcol, hrow)| *hcol == hit.col && *hrow == hit.row) {
+                    hot_pixels_removed += 1;
+                    continue;
+                }
+
+                if let Some(hot_pixel_suppressor) = hot_pixel_suppressor.as_mut() {
+                    if hot_pixel_suppressor.is_suppressed(&hit) {
                         continue;
                     }
+                }
 
-                    let data = (packet & 0x0000_0FFF_FFFF_0000) >> 16;
-
-                    // Calculate ToT
-                    let tot = ((data & 0x0000_3FF0) >> 4) as u32 * 25;
-
-                    // ToA and ToT measurement using 40 MHz counters
-                    // ToA: 14 bits (resolution 25 ns, range 409 µs)
-                    // ToT: 10 bits (resolution 25 ns, range 25 µs)
-                    // Fast ToA measurement (640 MHz counter)
-                    // 4 bits (resolution 1.56 ns, range 25 ns)
-                    // common stop TDC -> subtract fast TDC value from time measurement
-
-                    // Extract timing information
-                    let spidr_time = packet & 0x0000_0000_0000_FFFF;
-                    let temp_toa = (data & 0x0FFF_C000) >> 14;
-                    let temp_toa_fast = data & 0xF;
-                    let temp_toa_coarse = (spidr_time << 14) | temp_toa;
-
-                    // Calculate the global time
-                    let pixel_bits = ((temp_toa_coarse >> 28) & 0x3) as i32; // units 25 ns
-                    let long_time_bits = ((long_time >> 28) & 0x3) as i32; // units 25 ns;
-                    let diff = long_time_bits - pixel_bits;
-
-                    let global_time = match diff {
-                        1 | -3 => ((long_time - 0x1000_0000) & 0xFFFF_C000_0000) | (temp_toa_coarse & 0x3FFF_FFFF),
-                        3 | -1 => ((long_time + 0x1000_0000) & 0xFFFF_C000_0000) | (temp_toa_coarse & 0x3FFF_FFFF),
-                        _ => (long_time & 0xFFFF_C000_0000) | (temp_toa_coarse & 0x3FFF_FFFF),
-                    };
-
-                    // if (global_time >> 28) & 0x3 != pixel_bits as u64 {
-                    //     warn!("{} Checking bits should match!", output_prefix);
-                    // }
-
-                    // Subtract fast toa (ftoa count until the first clock edge, so less counts means later arrival of the hit)
-                    let mut toa = (global_time << 4) - temp_toa_fast;
+                if hit.col == 0 || hit.col == 255 || hit.row == 0 || hit.row == 255 {
+                    hit.flags.insert(HitFlags::EDGE_PIXEL);
+                }
 
-                    // Now correct for the column to column phase shift (todo: check header for number of clock phases)
-                    toa += (u64::from(col) / 2) % 16;
-                    if (col / 2) % 16 == 0 {
-                        toa += 16;
-                    }
+                if hot_neighbours[usize::from(hit.row) * 256 + usize::from(hit.col)] {
+                    hit.flags.insert(HitFlags::HOT_NEIGHBOUR);
+                }
 
-                    hit_conveyor.push_back(Hit { col, row, toa, tot });
+                // Only a measured ToT can be gain corrected
+                let hit = match &settings.flat_field {
+                    Some(flat_field) if file_acq_mode == AcqMode::ToaTot => flat_field.correct(hit),
+                    _ => hit,
+                };
 
-                    if hit_conveyor.len() >= BATCH_SIZE {
-                        vecdeque_insertion_sort(&mut hit_conveyor);
+                // Corrected hits are put back in order by the conveyor sort
+                let hit = match &settings.timing_offsets {
+                    Some(timing_offsets) => timing_offsets.correct(hit),
+                    None => hit,
+                };
 
-                        let temp = hit_conveyor.split_off(SKIM_OFF);
+                // The packet has been consumed, so started 8 bytes back
+                let origin = HitOrigin {
+                    file_index: file_index as u32,
+                    offset: spidr_header.data_offset() as u64 + packets.bytes_consumed() - 8,
+                };
 
-                        write_hits_to_file(&mut output_file, &hit_conveyor.as_slices().0)?;
+                hits_chunk.push(T::new(hit, origin));
 
-                        progress_bar.set_position(packets_parsed as u64);
-                        progress_bar.set_message(&format!(
-                            "| {} Hits Parsed | {} Triggers Parsed | {} Hot Pixels Removed | {}",
-                            hits_parsed.separated_string(),
-                            triggers_parsed.separated_string(),
-                            hot_pixels_removed.separated_string(),
-                            run_name
-                        ));
+                if hits_chunk.len() >= DECODE_CHUNK_SIZE {
+                    let chunk = mem::replace(&mut hits_chunk, Vec::with_capacity(DECODE_CHUNK_SIZE));
 
-                        hit_conveyor = temp;
-                        hit_conveyor.reserve(BATCH_SIZE);
+                    if decoded_sender.send(DecodedData::Hits(chunk)).is_err() {
+                        pipeline_closed = true;
+                        break;
                     }
-                } else if header == 0x4 || header == 0x6 {
-                    let subheader = (packet & 0x0F00_0000_0000_0000) >> 56;
-
-                    // Finding subheader type (F for trigger or 4,5 for time)
-                    if subheader == 0xF
-                    // Trigger information
-                    {
-                        triggers_parsed += 1;
-
-                        let trigtime_coarse = (packet & 0x0000_0FFF_FFFF_F000) >> 12;
-                        let trigtime_fine = (packet >> 5) & 0xF as u64; // phases of 320 MHz clock in bits 5 to 8
-                        let trigtime_fine = ((trigtime_fine - 1 as u64) << 9) / 12 as u64;
-                        let trigtime_fine = (packet & 0x0000_0000_0000_0E00) | (trigtime_fine & 0x0000_0000_0000_01FF as u64);
-
-                        if trigtime_coarse < prev_trigtime_coarse
-                        // 32 time counter wrapped
-                        {
-                            if trigtime_coarse < (prev_trigtime_coarse - 1000) {
-                                trigtime_global_ext += 0x1_0000_0000;
-                            // warn!("{} Coarse trigger time counter wrapped!", output_prefix);
-                            } else {
-                                // warn!("{} Small backward time jump in trigger packet!", output_prefix);
-                            }
-                        }
 
-                        let time = (trigtime_global_ext + trigtime_coarse) | trigtime_fine;
-                        let time = time * 25 as u64;
-
-                        prev_trigtime_coarse = trigtime_coarse;
-
-                        triggers.push(Trigger {
-                            event: triggers_parsed as u32,
-                            time,
-                        });
-
-                        progress_bar.set_message(&format!(
-                            "| {} Hits Parsed | {} Triggers Parsed | {} Hot Pixels Removed | {}",
-                            hits_parsed.separated_string(),
-                            triggers_parsed.separated_string(),
-                            hot_pixels_removed.separated_string(),
-                            run_name
-                        ));
-                    } else if subheader == 0x4 {
-                        // 32 lsb of timestamp
-                        longtime_lsb = (packet & 0x0000_FFFF_FFFF_0000) >> 16;
-                    } else if subheader == 0x5 {
-                        // 32 msb of timestamp
-                        let longtime_msb = (packet & 0x0000_0000_FFFF_0000) << 16;
-                        let tmplongtime = longtime_msb | longtime_lsb;
-
-                        // Now check for large forward jumps in time;
-                        // 0x10000000 corresponds to about 6 seconds
-                        if tmplongtime > (long_time + 0x1000_0000) && long_time > 0 {
-                            long_time = (longtime_msb - 0x1000_0000) | longtime_lsb;
-                        // warn!("{} Large forward time jump!", output_prefix);
-                        } else {
-                            long_time = tmplongtime;
+                    // Position in the file on disk, so compressed files are tracked by their compressed size
+                    progress_bar.set_position(byte_progress.position(packets.get_ref().file_bytes_read()));
+                    progress_bar.set_message(&format!(
+                        "| {} Hits Parsed | {} Triggers Parsed | {} Hot Pixels Removed | {}",
+                        hits_parsed.separated_string(),
+                        triggers_parsed.separated_string(),
+                        hot_pixels_removed.separated_string(),
+                        run_name
+                    ));
+                }
+            } else if header == 0x4 || header == 0x6 {
+                let subheader = (packet & 0x0F00_0000_0000_0000) >> 56;
+
+                // Finding subheader type (F for trigger, E/B for gate edges or 4,5 for time)
+                if subheader == 0xF || (header == 0x6 && (subheader == 0xE || subheader == 0xB))
+                // Trigger/TDC information
+                {
+                    let (trigtime, _) = trigger_time_decoder.decode(packet);
+                    let time = trigtime.as_ns();
+
+                    // TDC2 edges open and close gates, which the sort stage keeps track of
+                    if subheader == 0xE || subheader == 0xB {
+                        let edge = if subheader == 0xE { DecodedData::GateOpened(time) } else { DecodedData::GateClosed(time) };
+
+                        if decoded_sender.send(edge).is_err() {
+                            pipeline_closed = true;
+                            break;
                         }
+                        continue;
                     }
+
+                    triggers_parsed += 1;
+
+                    triggers.push(TriggerRecord {
+                        event: triggers_parsed as u32,
+                        raw_count: ((packet & 0x00FF_F000_0000_0000) >> 44) as u16,
+                        channel: 1,
+                        coarse: trigtime.coarse,
+                        fine: trigtime.fine,
+                    });
+
+                    progress_bar.set_message(&format!(
+                        "| {} Hits Parsed | {} Triggers Parsed | {} Hot Pixels Removed | {}",
+                        hits_parsed.separated_string(),
+                        triggers_parsed.separated_string(),
+                        hot_pixels_removed.separated_string(),
+                        run_name
+                    ));
+                } else if subheader == 0x4 {
+                    // 32 lsb of timestamp
+                    toa_extender.update_time_lsb(packet);
+                } else if subheader == 0x5 {
+                    // 32 msb of timestamp
+                    toa_extender.update_time_msb(packet);
+                } else if header == 0x4 && subheader == TIMESTAMP_LATCH_SUBHEADER {
+                    // Global time of a GPS/White Rabbit pulse-per-second edge
+                    timestamp_decoder.latch(packet);
+                } else if header == 0x4 && subheader == TIMESTAMP_SECONDS_SUBHEADER {
+                    // UTC second the edge started
+                    timestamps.extend(timestamp_decoder.seconds(packet, &toa_extender));
+                }
+            } else {
+                // Control/heartbeat packets
+                control_packets.count(packet);
+
+                if settings.dump_control_packets {
+                    let csv_writer = match control_packets_writer.as_mut() {
+                        Some(csv_writer) => csv_writer,
+                        None => control_packets_writer.insert(csv::Writer::from_path(run_output_dir.join("control_packets.csv"))?),
+                    };
+
+                    csv_writer.serialize(ControlPacketRow {
+                        file_index,
+                        offset: spidr_header.data_offset() as u64 + packets.bytes_consumed() - 8,
+                        packet_type: format!("0x{:02X}", packet_type(packet)),
+                        packet: format!("0x{:016X}", packet),
+                    })?;
                 }
             }
         }
+
+        if packets.truncated_bytes() > 0 {
+            println!(
+                "{}",
+                format!(
+                    "WARNING: {:#?} ends with an incomplete packet ({} bytes ignored)",
+                    data_file,
+                    packets.truncated_bytes()
+                )
+                .yellow()
+            );
+        }
+
+        if packets.skipped_bytes() > 0 {
+            println!(
+                "{}",
+                format!(
+                    "WARNING: Skipped {} bytes of corrupted data in {:#?} ({} resyncs)",
+                    packets.skipped_bytes(),
+                    data_file,
+                    packets.resyncs()
+                )
+                .yellow()
+            );
+        }
+
+        packet_recovery.truncated_bytes += packets.truncated_bytes();
+        packet_recovery.skipped_bytes += packets.skipped_bytes();
+        packet_recovery.resyncs += packets.resyncs();
+
+        // Keep what was read before the error, as for an interrupted run
+        if let Some(err) = packets.take_error() {
+            stopped = Some(io::Error::new(err.kind(), format!("Failed to read {:#?}: {}", data_file, err)));
+        }
+
+        if stopped.is_some() || pipeline_closed {
+            break;
+        }
+
+        byte_progress.finish_file();
+        progress_bar.set_position(byte_progress.position(0));
     }
 
-    // Sort and save remaining hits
-    vecdeque_insertion_sort(&mut hit_conveyor);
-    write_hits_to_file(&mut output_file, &hit_conveyor.as_slices().0)?;
+    // Pass on remaining hits and let the later stages drain
+    if !hits_chunk.is_empty() {
+        let _ = decoded_sender.send(DecodedData::Hits(hits_chunk));
+    }
+
+    drop(decoded_sender);
+
+    let SortStageOutput {
+        mut gates,
+        deduplicator,
+        counters: sort_counters,
+    } = join_stage(sort_stage);
+    let write_output = join_stage(write_stage)?;
+
+    write_output.hits_index.write(&hits_file_path)?;
+
+    if let Some(prescaled_writer) = write_output.prescaled_writer {
+        prescaled_writer.finish()?;
+    }
+
+    // Absolute time of the run, fitted to the timestamp packets in the data if it had any
+    let mut start_time = file_infos[0].start_time.clone();
+
+    if !timestamps.is_empty() {
+        let mut file = fs::File::create(run_output_dir.join("timestamps.csv"))?;
+        write_timestamps_to_csv(&mut file, &timestamps)?;
+
+        let points: Vec<(f64, i64)> = timestamps.iter().map(|x| (x.time, x.utc_ns)).collect();
+        start_time.clock_fit = ClockFit::fit(&points);
+    }
+
+    if timestamp_decoder.unpaired > 0 {
+        println!(
+            "{}",
+            format!("WARNING: {} absolute timestamp packets in {} could not be paired", timestamp_decoder.unpaired, run_name).yellow()
+        );
+    }
+
+    triggers.sort_by_key(|record| record.time());
+    let trigger_records = triggers;
+    let triggers: Vec<Trigger> = trigger_records.iter().copied().map(Trigger::from).collect();
 
     if !triggers.is_empty() {
-        triggers.sort();
+        let mut file = fs::File::create(run_output_dir.join(format!("{}.bin", settings.triggers_filename)))?;
+        write_trigger_records_to_file(&mut file, &trigger_records)?;
+
+        // Human readable copy
+        let mut file = fs::File::create(run_output_dir.join(format!("{}.csv", settings.triggers_filename)))?;
+        if settings.absolute_time {
+            write_triggers_to_csv_with_utc(&mut file, &triggers, &start_time)?;
+        } else {
+            write_triggers_to_csv(&mut file, &triggers)?;
+        }
+    }
+
+    #[cfg(feature = "nexus")]
+    {
+        if settings.output_format == OutputFormat::Nexus {
+            let start_time = start_time.timeline_start().to_rfc3339();
+            let hits = ReadHitsIterator::new(&hits_file_path);
+
+            write_nexus_event_data(&hits_file_path.with_extension("nxs"), hits, &triggers, &start_time)?;
+        }
+    }
+
+    if !gates.is_empty() {
+        gates.sort_by_key(|gate| gate.start);
+
+        let mut file = fs::File::create(run_output_dir.join("gates.csv"))?;
+
+        write_gates_to_csv(&mut file, &gates)?;
+    }
+
+    toa_extender.finish_file();
+
+    update_run_summary(run_output_dir, RUN_START_TIME_SECTION, &start_time)?;
+    update_run_summary(run_output_dir, "acq_mode", &acq_mode.unwrap_or_default())?;
+    update_run_summary(run_output_dir, "time_extension", &toa_extender.counters)?;
+    update_run_summary(run_output_dir, "packet_recovery", &packet_recovery)?;
+    update_run_summary(run_output_dir, "control_packets", &control_packets)?;
+    update_run_summary(
+        run_output_dir,
+        RECORD_COUNTS_SECTION,
+        &RecordCounts {
+            packets: packets_read as u64,
+            hit_packets: hits_parsed as u64,
+            hits: write_output.written as u64,
+            triggers: triggers_parsed as u64,
+        },
+    )?;
+
+    if let Some(mut csv_writer) = control_packets_writer {
+        csv_writer.flush()?;
+    }
+    update_run_summary(run_output_dir, "sorting", &sort_counters)?;
+
+    if sort_counters.mis_sorted > 0 {
+        println!(
+            "{}",
+            format!(
+                "WARNING: {} hits in {} arrived up to {:.0} ns after later hits had been written and are out of order, increase --sort-overlap (currently {})",
+                sort_counters.mis_sorted.separated_string(),
+                run_name,
+                sort_counters.max_mis_sort_ns,
+                sort_counters.overlap.separated_string()
+            )
+            .yellow()
+        );
+    }
+
+    if let Some(deduplicator) = deduplicator {
+        update_run_summary(run_output_dir, "dedup", &deduplicator)?;
+    }
+
+    if let Some(flat_field) = &settings.flat_field {
+        update_run_summary(run_output_dir, "flat_field", flat_field)?;
+    }
+
+    if let Some(hot_pixel_suppressor) = hot_pixel_suppressor {
+        update_run_summary(run_output_dir, "rolling_hot_pixels", &hot_pixel_suppressor)?;
+
+        if !hot_pixel_suppressor.log.is_empty() {
+            let mut csv_writer = csv::Writer::from_path(run_output_dir.join("hot_pixel_mask_log.csv"))?;
+
+            for change in &hot_pixel_suppressor.log {
+                csv_writer.serialize(change)?;
+            }
+
+            csv_writer.flush()?;
+        }
+    }
+
+    if let Some(pixel_mask) = &settings.pixel_mask {
+        update_run_summary(run_output_dir, "pixel_mask", pixel_mask)?;
+    }
+
+    if let Some(timing_offsets) = &settings.timing_offsets {
+        update_run_summary(run_output_dir, "timing_offset_correction", timing_offsets)?;
+    }
+    update_run_summary(run_output_dir, "file_toa_offsets", &toa_extender.file_offsets)?;
+
+    if let Some(err) = stopped {
+        let checkpoint = Checkpoint {
+            written: write_output.written,
+            last_time: write_output.last_hit.map(|hit| hit.toa as f64 * TOA_CLOCK_TO_NS),
+        };
+        write_partial_marker(&hits_file_path, &checkpoint)?;
 
-        let mut file = fs::File::create(run_output_dir.join("triggers.csv"))?;
+        progress_bar.finish_with_message(&format!("| {} | {} Hits Parsed | {}", err, hits_parsed.separated_string(), run_name));
 
-        write_triggers_to_csv(&mut file, &triggers)?;
+        return Err(err);
     }
 
     progress_bar.finish_with_message(&format!(
@@ -0,0 +1,402 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * -----------------------------
+ * Timepix Cluster Index Rebuild
+ * -----------------------------
+ *
+ * timepix-spidr-data-parser/src/bin/rebuild_index.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use clap;
+use colored::Colorize;
+use glob::glob;
+use separator::Separatable as _;
+
+use timepix_spidr_data_parser::*;
+
+fn main() -> io::Result<()> {
+    println!(
+        "\n-----------------------------\n{}\n-----------------------------\n",
+        "Timepix Cluster Index Rebuild".bold()
+    );
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(
+            clap::Arg::with_name("INPUT")
+                .help("Sets the input cluster/event file pattern (eg. 'runs/*/clusters.bin')")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("overwrite")
+                .help("Overwrites any existing metadata CSV files")
+                .long("overwrite"),
+        )
+        .arg(
+            clap::Arg::with_name("dry-run")
+                .help("Shows output of what files will be processed without changing any data")
+                .long("dry-run"),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_glob_str = matches.value_of("INPUT").unwrap();
+
+    let overwrite = matches.is_present("overwrite");
+    let dry_run = matches.is_present("dry-run");
+
+    //
+    // Parse input file list
+    //
+    let matched_files: Vec<_> = glob(input_glob_str)
+        .expect("Failed to read input file glob pattern")
+        .filter_map(|x| x.ok()) // Check glob worked
+        .filter(|x| x.is_file()) // Check is file
+        .filter(|x| x.extension().is_some()) // Check has file extension
+        .filter(|x| x.extension().unwrap() == "bin") // Check extension is .bin
+        .filter(|x| !is_derived_product(x)) // Hits, trigger and hit origin files have no cluster structure
+        .collect();
+
+    // Files with existing output files are skipped unless overwriting
+    let (input_files, existing_files): (Vec<_>, Vec<_>) = matched_files.into_iter().partition(|x| overwrite || !x.with_extension("csv").exists());
+
+    if input_files.is_empty() && (!dry_run || existing_files.is_empty()) {
+        println!("No input files matched!");
+        return Ok(());
+    }
+
+    println!("Matched {} input files", input_files.len());
+
+    if dry_run {
+        let mut plan = DryRunPlan::new();
+
+        for input_file in &input_files {
+            println!("{} -> {}", input_file.display(), input_file.with_extension("csv").display());
+
+            plan.run(input_file, input_file.metadata()?.len(), None);
+        }
+
+        for existing_file in &existing_files {
+            plan.skipped(existing_file);
+        }
+
+        plan.print();
+
+        return Ok(());
+    }
+
+    for input_file in input_files {
+        let timer = ProcessingTimer::start(input_file.parent().unwrap());
+
+        let clusters_indexed = rebuild_index(&input_file)?;
+
+        timer.finish(input_file.metadata()?.len())?;
+
+        println!("Indexed {} clusters from {}", clusters_indexed.separated_string(), input_file.display());
+    }
+
+    Ok(())
+}
+
+/// Binary files written alongside the cluster/event files that are not made of clusters: the hits
+/// of a run (under any `--hits-filename`, with their prescaled sub-sample), its triggers and the
+/// hit origins
+fn is_derived_product(path: &Path) -> bool {
+    let file_name = path.file_name().and_then(|x| x.to_str()).unwrap_or("");
+
+    file_name.starts_with("hits") || file_name == "triggers.bin" || file_name == "hit_origins.bin" || hits_index_path(path).exists()
+}
+
+/// Settings of the tool that wrote a cluster/event file, read from the TOML next to it, for the
+/// columns that cannot be recovered from the hits alone
+enum Product {
+    Clusters {
+        max_cluster_hits: Option<usize>,
+        max_cluster_duration: Option<u64>, // clks
+        max_toa_gap: u64,                  // clks
+        truncate: bool,
+    },
+    Events {
+        absolute_toa: bool,
+        window_look_behind: u64, // ns
+        window_look_ahead: u64,  // ns
+        pile_up_gap: u64,        // clks
+        pile_up_min_hits: usize,
+        window_file: Option<String>,
+    },
+    Unknown,
+}
+
+impl Product {
+    fn read(data_file: &Path) -> Product {
+        let value = match fs::read_to_string(data_file.with_extension("toml"))
+            .ok()
+            .and_then(|x| x.parse::<toml::Value>().ok())
+        {
+            Some(value) => value,
+            None => return Product::Unknown,
+        };
+
+        let integer = |key: &str| value.get(key).and_then(|x| x.as_integer()).map(|x| x as u64);
+        let string = |key: &str| value.get(key).and_then(|x| x.as_str());
+
+        if let (Some(window_look_behind), Some(window_look_ahead)) = (integer("window_look_behind"), integer("window_look_ahead")) {
+            Product::Events {
+                absolute_toa: string("relative_to").map_or(true, |x| x == "absolute"),
+                window_look_behind,
+                window_look_ahead,
+                pile_up_gap: (integer("pile_up_gap").unwrap_or(0) as f64 / TOA_CLOCK_TO_NS) as u64,
+                pile_up_min_hits: integer("pile_up_min_hits").unwrap_or(1) as usize,
+                window_file: string("window_file").map(|x| x.to_owned()),
+            }
+        } else if let Some(max_toa_gap) = integer("max_toa_gap") {
+            Product::Clusters {
+                max_cluster_hits: integer("max_cluster_hits").map(|x| x as usize),
+                max_cluster_duration: integer("max_cluster_duration"),
+                max_toa_gap,
+                truncate: string("oversize_policy") != Some("drop"),
+            }
+        } else {
+            Product::Unknown
+        }
+    }
+
+    /// Flags of a cluster/event, as set by the tool that wrote it
+    fn flags(&self, cluster: &[Hit]) -> (u8, usize) {
+        match *self {
+            Product::Clusters {
+                max_cluster_hits,
+                max_cluster_duration,
+                max_toa_gap,
+                truncate,
+            } => {
+                // Dropped clusters are not written, so only truncated ones carry the flag
+                let duration = match (cluster.first(), cluster.last()) {
+                    (Some(first), Some(last)) => last.toa - first.toa,
+                    _ => 0,
+                };
+
+                let hits_capped = max_cluster_hits.map_or(false, |max| cluster.len() >= max);
+
+                // A neighbouring hit past the cap is within the ToA gap of the last hit, so only
+                // clusters ending that close to the cap can have been cut short by it
+                let duration_capped = max_cluster_duration.map_or(false, |max| duration + max_toa_gap > max);
+
+                let flags = if truncate && (hits_capped || duration_capped) {
+                    CLUSTER_FLAG_TRUNCATED
+                } else {
+                    0
+                };
+
+                (flags, 0)
+            }
+            Product::Events {
+                pile_up_gap,
+                pile_up_min_hits,
+                ..
+            } => {
+                let time_islands = count_time_islands(cluster, pile_up_gap, pile_up_min_hits);
+
+                (if time_islands > 1 { CLUSTER_FLAG_PILE_UP } else { 0 }, time_islands)
+            }
+            Product::Unknown => (0, 0),
+        }
+    }
+}
+
+/// Per-trigger windows the events of a run were extracted with, from the window file named in the
+/// settings TOML or the extra columns of the run's `triggers.csv`, as in `trigger_extraction_tool`
+fn read_run_trigger_windows(run_dir: &Path, window_file: &Option<String>) -> io::Result<HashMap<u32, TriggerWindow>> {
+    let window_file_path = match window_file {
+        Some(window_file) => run_dir.join(window_file),
+        None => {
+            let triggers_file_path = run_dir.join("triggers.csv");
+
+            if !triggers_file_path.exists() || !csv::Reader::from_path(&triggers_file_path)?.headers()?.iter().any(|x| x == "window_size") {
+                return Ok(HashMap::new());
+            }
+
+            triggers_file_path
+        }
+    };
+
+    Ok(read_trigger_windows(&window_file_path)?.into_iter().map(|x| (x.event, x)).collect())
+}
+
+/// Scans a cluster/event binary file and writes the companion metadata CSV next to it.
+///
+/// Flags are restored from the settings TOML written alongside the file. Events with absolute ToA
+/// values are matched back to the trigger whose window holds their first hit, restoring their
+/// event number and times. Empty events (from `--write-all`) have no hits to match, so their event
+/// numbers cannot be recovered. They and any other unmatched events are numbered on from the last
+/// trigger of the run so they cannot collide with a matched event. Otherwise event numbers cannot
+/// be recovered from the binary file so events are numbered sequentially (with unique IDs made
+/// from the run directory name), and times are taken from the first hit in each cluster.
+fn rebuild_index(data_file: &Path) -> io::Result<usize> {
+    // Files are in their run directory, as written by the other tools
+    let run_dir = data_file.parent().unwrap();
+    let run_id = run_dir.file_name().and_then(|x| x.to_str()).unwrap_or("");
+
+    // Hit times counted from the first hit of each cluster, so files with signed ToA values are
+    // read the same way
+    let cluster_iterator = Run::open(run_dir)?.relative_clusters(data_file.file_stem().unwrap().to_str().unwrap())?;
+
+    let product = Product::read(data_file);
+
+    let (triggers, trigger_windows) = match &product {
+        Product::Events {
+            absolute_toa: true,
+            window_file,
+            ..
+        } => (read_run_triggers(run_dir)?, read_run_trigger_windows(run_dir, window_file)?),
+        _ => (Vec::new(), HashMap::new()),
+    };
+
+    let output_csv_file = fs::File::create(data_file.with_extension("csv"))?;
+    let mut csv_writer = csv::Writer::from_writer(output_csv_file);
+
+    let mut clusters_indexed = 0;
+    let mut accumulated_file_size = 0;
+    let mut next_trigger = 0;
+    let trigger_windows_look_behind = trigger_windows.values().map(|x| x.look_behind()).max().unwrap_or(0);
+    let mut events_unmatched = 0;
+
+    for (start_toa, cluster) in cluster_iterator {
+        clusters_indexed += 1;
+
+        let (time, duration) = match cluster.last() {
+            Some(last) => (start_toa as f64 * TOA_CLOCK_TO_NS, last.toa as f64 * TOA_CLOCK_TO_NS),
+            None => (0.0, 0.0),
+        };
+
+        let (flags, time_islands) = product.flags(&cluster);
+
+        let mut metadata = ClusterMetadata {
+            event: clusters_indexed,
+            time,
+            duration,
+            hits: cluster.len(),
+            sum_tot: cluster.iter().map(|hit| hit.tot).sum(),
+            offset: accumulated_file_size,
+            flags,
+            time_islands,
+            run_id: run_id.to_owned(),
+            uid: make_cluster_uid(run_id, clusters_indexed, false),
+            external_id: None,
+            min_col: None,
+            max_col: None,
+            min_row: None,
+            max_row: None,
+            centroid_col: None,
+            centroid_row: None,
+            trigger_time: None,
+            window_start: None,
+            window_end: None,
+            hit_fraction: None,
+            energy: None,
+        }
+        .with_position(&cluster);
+
+        if let Product::Events {
+            absolute_toa: true,
+            window_look_behind,
+            window_look_ahead,
+            ..
+        } = product
+        {
+            let window_of = |trigger: &Trigger| match trigger_windows.get(&trigger.event) {
+                Some(window) => (window.look_behind(), window.look_ahead()),
+                None => (window_look_behind, window_look_ahead),
+            };
+
+            // No window reaches back further than this, so later triggers cannot hold the event
+            let max_look_behind = trigger_windows_look_behind.max(window_look_behind);
+
+            // Events are written in trigger order, so the search carries on from the last match
+            let first_toa = cluster.first().map(|_| start_toa as f64 * TOA_CLOCK_TO_NS);
+
+            let matched = first_toa.and_then(|first_toa| {
+                (next_trigger..triggers.len())
+                    .take_while(|&i| triggers[i].time.saturating_sub(max_look_behind) as f64 <= first_toa)
+                    .find(|&i| {
+                        let (look_behind, look_ahead) = window_of(&triggers[i]);
+
+                        triggers[i].time.saturating_sub(look_behind) as f64 <= first_toa && (triggers[i].time + look_ahead) as f64 >= first_toa
+                    })
+            });
+
+            match matched {
+                Some(i) => {
+                    next_trigger = i + 1;
+
+                    let trigger = triggers[i];
+                    let (look_behind, look_ahead) = window_of(&trigger);
+                    let window_start = trigger.time.saturating_sub(look_behind) as f64;
+
+                    metadata.event = i + 1;
+                    metadata.uid = make_cluster_uid(run_id, i + 1, false);
+                    metadata.time = window_start;
+                    metadata.trigger_time = Some(trigger.time as f64);
+                    metadata.window_start = Some(window_start);
+                    metadata.window_end = Some((trigger.time + look_ahead) as f64);
+                }
+                None => {
+                    events_unmatched += 1;
+
+                    metadata.event = triggers.len() + events_unmatched;
+                    metadata.uid = make_cluster_uid(run_id, metadata.event, false);
+                }
+            }
+        }
+
+        csv_writer.serialize(metadata)?;
+
+        accumulated_file_size += (cluster.len() + 1) * 16;
+    }
+
+    csv_writer.flush()?;
+
+    if events_unmatched > 0 {
+        println!(
+            "{}",
+            format!(
+                "WARNING: {} events in '{}' (including any empty events) were not matched to a trigger and are numbered from {}",
+                events_unmatched.separated_string(),
+                data_file.display(),
+                triggers.len() + 1
+            )
+            .yellow()
+        );
+    }
+
+    let file_size = data_file.metadata()?.len() as usize;
+
+    if accumulated_file_size != file_size {
+        println!(
+            "{}",
+            format!(
+                "WARNING: Only {} of {} bytes in '{}' belong to complete clusters",
+                accumulated_file_size.separated_string(),
+                file_size.separated_string(),
+                data_file.display()
+            )
+            .yellow()
+        );
+    }
+
+    Ok(clusters_indexed)
+}
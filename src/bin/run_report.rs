@@ -0,0 +1,213 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * ------------------
+ * Timepix Run Report
+ * ------------------
+ *
+ * timepix-spidr-data-parser/src/bin/run_report.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::io::Write as _;
+use std::path::Path;
+
+use clap;
+use colored::Colorize;
+use glob::glob;
+use serde::Serialize;
+
+use timepix_spidr_data_parser::*;
+
+/// Statistics of a single run (durations in s, rates in Hz, ToT in ns)
+#[derive(Serialize)]
+struct RunStatistics {
+    run: String,
+    duration: f64,
+    exposure: Option<f64>,
+    hits: u64,
+    hit_rate: f64,
+    triggers: usize,
+    trigger_rate: f64,
+    clusters: Option<usize>,
+    cluster_rate: Option<f64>,
+    mean_cluster_tot: Option<f64>,
+}
+
+fn main() -> io::Result<()> {
+    println!("\n------------------\n{}\n------------------\n", "Timepix Run Report".bold());
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(
+            clap::Arg::with_name("INPUT")
+                .help("Sets the input directory pattern")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("OUTPUT")
+                .help("Sets the output CSV file to use")
+                .required(true)
+                .index(2),
+        )
+        .arg(
+            clap::Arg::with_name("cluster-filename")
+                .help("Sets the cluster filename (without extension!) to report on (default is 'clusters')")
+                .long("cluster-filename")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("html")
+                .help("Also writes the report as a HTML table to this file")
+                .long("html")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_glob_str = matches.value_of("INPUT").unwrap();
+    let output_file_path = Path::new(matches.value_of("OUTPUT").unwrap());
+    let cluster_filename = matches.value_of("cluster-filename").unwrap_or("clusters");
+
+    //
+    // Parse input file list
+    //
+    let input_dirs: Vec<_> = glob(input_glob_str)
+        .expect("Failed to read input file glob pattern")
+        .filter_map(|x| x.ok()) // Check glob worked
+        .filter(|x| x.is_dir()) // Check is directory
+        .filter(|x| x.join("hits.bin").exists())
+        .collect();
+
+    if input_dirs.is_empty() {
+        println!("No input directories matched!");
+        return Ok(());
+    }
+
+    println!("Matched {} input directories", input_dirs.len());
+
+    let mut report = Vec::with_capacity(input_dirs.len());
+
+    for input_dir in &input_dirs {
+        report.push(run_statistics(input_dir, cluster_filename)?);
+    }
+
+    let mut csv_writer = csv::Writer::from_path(output_file_path)?;
+
+    for statistics in &report {
+        csv_writer.serialize(statistics)?;
+    }
+
+    csv_writer.flush()?;
+
+    println!(
+        "{}",
+        format!("\nReported on {} runs in {}", report.len(), output_file_path.display()).bold()
+    );
+
+    if let Some(html_file) = matches.value_of("html") {
+        write_html_report(Path::new(html_file), &report)?;
+
+        println!("{}", format!("HTML report written to {}", html_file).bold());
+    }
+
+    Ok(())
+}
+
+fn run_statistics(run_dir: &Path, cluster_filename: &str) -> io::Result<RunStatistics> {
+    let run_data = Run::open(run_dir)?;
+    let run = run_data.name().to_owned();
+
+    let hits = run_data.hit_count()?;
+
+    let duration = match run_data.hits_time_range()? {
+        Some((first_toa, last_toa)) => (last_toa - first_toa) as f64 * TOA_CLOCK_TO_NS / 1e9,
+        None => 0.0,
+    };
+
+    // Exposure is only known once the live time tool has been run
+    let exposure = run_data
+        .summary()?
+        .pointer("/live_time/exposure_time")
+        .and_then(|x| x.as_u64())
+        .map(|x| x as f64 / 1e9);
+
+    let triggers = run_data.triggers()?.len();
+
+    let (clusters, mean_cluster_tot) = if run_dir.join(format!("{}.csv", cluster_filename)).exists() {
+        let metadata = run_data.cluster_metadata(cluster_filename)?;
+
+        let clusters = metadata.len();
+        let sum_tot: u64 = metadata.iter().map(|x| u64::from(x.sum_tot)).sum();
+
+        let mean_cluster_tot = if clusters > 0 { sum_tot as f64 / clusters as f64 } else { 0.0 };
+
+        (Some(clusters), Some(mean_cluster_tot))
+    } else {
+        (None, None)
+    };
+
+    let rate = |count: f64| if duration > 0.0 { count / duration } else { 0.0 };
+
+    Ok(RunStatistics {
+        run,
+        duration,
+        exposure,
+        hits,
+        hit_rate: rate(hits as f64),
+        triggers,
+        trigger_rate: rate(triggers as f64),
+        clusters,
+        cluster_rate: clusters.map(|x| rate(x as f64)),
+        mean_cluster_tot,
+    })
+}
+
+fn write_html_report(path: &Path, report: &[RunStatistics]) -> io::Result<()> {
+    let mut buf = Vec::new();
+
+    let optional = |x: Option<f64>| x.map_or("-".to_owned(), |x| format!("{:.3}", x));
+
+    writeln!(buf, "<!DOCTYPE html>")?;
+    writeln!(buf, "<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Timepix Run Report</title>")?;
+    writeln!(
+        buf,
+        "<style>table {{ border-collapse: collapse; }} th, td {{ border: 1px solid #999; padding: 2px 6px; text-align: right; }}</style>"
+    )?;
+    writeln!(buf, "</head>\n<body>\n<h1>Timepix Run Report</h1>\n<table>")?;
+    writeln!(
+        buf,
+        "<tr><th>Run</th><th>Duration (s)</th><th>Exposure (s)</th><th>Hits</th><th>Hit Rate (Hz)</th><th>Triggers</th>\
+         <th>Trigger Rate (Hz)</th><th>Clusters</th><th>Cluster Rate (Hz)</th><th>Mean Cluster ToT (ns)</th></tr>"
+    )?;
+
+    for x in report {
+        writeln!(
+            buf,
+            "<tr><td>{}</td><td>{:.3}</td><td>{}</td><td>{}</td><td>{:.3}</td><td>{}</td><td>{:.3}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            x.run,
+            x.duration,
+            optional(x.exposure),
+            x.hits,
+            x.hit_rate,
+            x.triggers,
+            x.trigger_rate,
+            x.clusters.map_or("-".to_owned(), |x| x.to_string()),
+            optional(x.cluster_rate),
+            optional(x.mean_cluster_tot),
+        )?;
+    }
+
+    writeln!(buf, "</table>\n</body>\n</html>")?;
+
+    fs::write(path, buf)
+}
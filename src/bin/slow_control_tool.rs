@@ -0,0 +1,283 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * -------------------------
+ * Timepix Slow Control Tool
+ * -------------------------
+ *
+ * timepix-spidr-data-parser/src/bin/slow_control_tool.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use clap;
+use colored::Colorize;
+use glob::glob;
+use serde::Serialize;
+
+use timepix_spidr_data_parser::*;
+
+/// Prefix of the slow control columns added to time slice CSVs
+const COLUMN_PREFIX: &str = "sc_";
+
+/// Time slice CSVs written by the other tools, annotated if present
+const DEFAULT_TIME_SLICE_FILES: [&str; 2] = ["trigger_rate.csv", "column_bursts.csv"];
+
+/// `slow_control` section of a run's `summary.json`
+#[derive(Serialize)]
+struct SlowControlRunSummary {
+    files: Vec<PathBuf>,
+    start: i64, // UTC ns of the first and last hits the channels are summarised between
+    end: i64,
+    channels: BTreeMap<String, SlowControlSummary>,
+}
+
+fn main() -> io::Result<()> {
+    println!(
+        "\n-------------------------\n{}\n-------------------------\n",
+        "Timepix Slow Control Tool".bold()
+    );
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(
+            clap::Arg::with_name("INPUT")
+                .help("Sets the input directory pattern")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("slow-control")
+                .help("Slow control CSV file with a timestamp column and a column per quantity, can be given more than once")
+                .long("slow-control")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(true),
+        )
+        .arg(
+            clap::Arg::with_name("time-column")
+                .help("Name of the timestamp column of the slow control files (default is 'time')")
+                .long("time-column")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("timezone")
+                .help("Time zone of slow control timestamps without an offset, as an IANA name (e.g. 'Europe/London') (default is UTC)")
+                .long("timezone")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("time-slices")
+                .help("CSV file in each run directory with 'start' and 'end' columns (ns) to annotate, can be given more than once (default is 'trigger_rate.csv' and 'column_bursts.csv')")
+                .long("time-slices")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            clap::Arg::with_name("dry-run")
+                .help("Shows output of what runs will be processed without changing any data")
+                .long("dry-run"),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_glob_str = matches.value_of("INPUT").unwrap();
+    let slow_control_files: Vec<PathBuf> = matches.values_of("slow-control").unwrap().map(PathBuf::from).collect();
+    let time_column = matches.value_of("time-column").unwrap_or("time");
+    let time_slice_files: Vec<String> = match matches.values_of("time-slices") {
+        Some(files) => files.map(|x| x.to_owned()).collect(),
+        None => DEFAULT_TIME_SLICE_FILES.iter().map(|x| x.to_string()).collect(),
+    };
+    let dry_run = matches.is_present("dry-run");
+
+    let timezone = match parse_timezone(matches.value_of("timezone").unwrap_or("UTC")) {
+        Ok(timezone) => timezone,
+        Err(err) => {
+            println!("{}", err.red());
+            process::exit(1);
+        }
+    };
+
+    //
+    // Read slow control data
+    //
+    let mut channels: Vec<SlowControlChannel> = Vec::new();
+
+    for file in &slow_control_files {
+        for channel in read_slow_control_csv(file, time_column, timezone)? {
+            if channels.iter().any(|x| x.name == channel.name) {
+                println!("{}", format!("Slow control channel '{}' is in more than one file", channel.name).red());
+                process::exit(1);
+            }
+
+            channels.push(channel);
+        }
+    }
+
+    println!(
+        "Read {} slow control channels: {}",
+        channels.len(),
+        channels.iter().map(|x| x.name.as_str()).collect::<Vec<_>>().join(", ")
+    );
+
+    //
+    // Parse input file list
+    //
+    let input_dirs: Vec<_> = glob(input_glob_str)
+        .expect("Failed to read input file glob pattern")
+        .filter_map(|x| x.ok()) // Check glob worked
+        .filter(|x| x.is_dir()) // Check is directory
+        .filter(|x| x.join("summary.json").exists())
+        .collect();
+
+    if input_dirs.is_empty() {
+        println!("No input directories matched!");
+        return Ok(());
+    }
+
+    println!("Matched {} input directories", input_dirs.len());
+
+    if dry_run {
+        let mut plan = DryRunPlan::new();
+
+        for input_dir in &input_dirs {
+            plan.run(input_dir, run_file_bytes(input_dir, &time_slice_files), None);
+        }
+
+        plan.print();
+
+        return Ok(());
+    }
+
+    for input_dir in input_dirs {
+        let run = Run::open(&input_dir)?;
+
+        let input_bytes = run_file_bytes(&input_dir, &time_slice_files);
+        let timer = ProcessingTimer::start(&input_dir);
+
+        let start_time = match run.start_time()? {
+            Some(start_time) => start_time,
+            None => {
+                println!("{}", format!("{} | Skipped (run start time not recorded)", run.name()).yellow());
+                continue;
+            }
+        };
+
+        let (first_toa, last_toa) = run.hits_time_range()?.unwrap_or((0, 0));
+
+        let start = start_time.utc_ns(first_toa as f64 * TOA_CLOCK_TO_NS);
+        let end = start_time.utc_ns(last_toa as f64 * TOA_CLOCK_TO_NS);
+
+        let summary = SlowControlRunSummary {
+            files: slow_control_files.clone(),
+            start,
+            end,
+            channels: channels.iter().map(|x| (x.name.clone(), x.summarise(start, end))).collect(),
+        };
+
+        update_run_summary(run.dir(), "slow_control", &summary)?;
+
+        let mut annotated = Vec::new();
+
+        for file_name in &time_slice_files {
+            let file_path = run.dir().join(file_name);
+
+            if file_path.exists() {
+                annotate_time_slices(&file_path, &channels, &start_time)?;
+                annotated.push(file_name.as_str());
+            }
+        }
+
+        timer.finish(input_bytes)?;
+
+        let covered = summary.channels.values().filter(|x| x.start.is_some() && x.end.is_some()).count();
+
+        let line = format!(
+            "{} | {} of {} Channels Cover Run | Annotated: {}",
+            run.name(),
+            covered,
+            channels.len(),
+            if annotated.is_empty() { "-".to_owned() } else { annotated.join(", ") }
+        );
+
+        if covered < channels.len() {
+            println!("{}", line.yellow());
+        } else {
+            println!("{}", line);
+        }
+    }
+
+    Ok(())
+}
+
+/// Adds a column per channel to a time slice CSV with the mean value over each slice, replacing
+/// the columns added by a previous pass. Slices the channel does not cover are left empty.
+fn annotate_time_slices(file_path: &Path, channels: &[SlowControlChannel], start_time: &RunStartTime) -> io::Result<()> {
+    let mut rdr = csv::Reader::from_path(file_path)?;
+
+    let headers = rdr.headers()?.clone();
+
+    let column = |name: &str| {
+        headers.iter().position(|x| x == name).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Time slice file {:#?} has no '{}' column", file_path, name),
+            )
+        })
+    };
+
+    let start_index = column("start")?;
+    let end_index = column("end")?;
+
+    let kept: Vec<usize> = (0..headers.len()).filter(|&i| !headers[i].starts_with(COLUMN_PREFIX)).collect();
+
+    let mut output_headers: Vec<String> = kept.iter().map(|&i| headers[i].to_owned()).collect();
+    output_headers.extend(channels.iter().map(|x| format!("{}{}", COLUMN_PREFIX, x.name)));
+
+    let mut rows = vec![output_headers];
+
+    for result in rdr.records() {
+        let record = result?;
+
+        let time = |i: usize| {
+            record[i]
+                .parse::<f64>()
+                .map(|x| start_time.utc_ns(x))
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid time '{}' in {:#?}", &record[i], file_path)))
+        };
+
+        let (start, end) = (time(start_index)?, time(end_index)?);
+
+        let mut row: Vec<String> = kept.iter().map(|&i| record[i].to_owned()).collect();
+        row.extend(channels.iter().map(|x| x.mean(start, end).map_or(String::new(), |x| x.to_string())));
+
+        rows.push(row);
+    }
+
+    // Written alongside then moved over the original, so it is never left half written
+    let tmp_file_path = file_path.with_extension("csv.tmp");
+
+    let mut csv_writer = csv::Writer::from_writer(fs::File::create(&tmp_file_path)?);
+
+    for row in rows {
+        csv_writer.write_record(&row)?;
+    }
+
+    csv_writer.flush()?;
+    drop(csv_writer);
+
+    fs::rename(tmp_file_path, file_path)
+}
@@ -0,0 +1,270 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * -----------------------
+ * Timepix Hits Split Tool
+ * -----------------------
+ *
+ * timepix-spidr-data-parser/src/bin/split_hits.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use clap;
+use colored::Colorize;
+use glob::glob;
+use separator::Separatable as _;
+use serde::Serialize;
+use toml;
+
+use timepix_spidr_data_parser::*;
+
+#[derive(Clone, Serialize)]
+struct Settings {
+    output_dirname: String,
+    chunk_duration: Option<f64>,
+    max_chunk_size: Option<u64>,
+}
+
+/// Description of a chunk, written to `chunk.toml` in each chunk directory
+#[derive(Serialize)]
+struct Chunk {
+    run_name: String,
+    chunk: usize,
+    hits: usize,
+    start_toa: u64,
+    end_toa: u64,
+}
+
+/// Hits file for a single chunk being written
+struct ChunkWriter {
+    dir: PathBuf,
+    file: fs::File,
+    index: HitsIndexBuilder,
+    chunk: Chunk,
+}
+
+impl ChunkWriter {
+    fn new(run_dir: &Path, settings: &Settings, run_name: &str, chunk: usize, start_toa: u64) -> io::Result<ChunkWriter> {
+        let dir = run_dir.join(&settings.output_dirname).join(format!("{}_chunk{:04}", run_name, chunk));
+
+        fs::create_dir_all(&dir)?;
+
+        Ok(ChunkWriter {
+            file: fs::File::create(dir.join("hits.bin"))?,
+            dir,
+            index: HitsIndexBuilder::new(),
+            chunk: Chunk {
+                run_name: run_name.to_owned(),
+                chunk,
+                hits: 0,
+                start_toa,
+                end_toa: start_toa,
+            },
+        })
+    }
+
+    fn write(&mut self, hits: &[Hit]) -> io::Result<()> {
+        if let Some(last) = hits.last() {
+            self.chunk.hits += hits.len();
+            self.chunk.end_toa = last.toa;
+        }
+
+        self.index.add(hits);
+        write_hits_to_file(&mut self.file, hits)
+    }
+
+    fn finish(self) -> io::Result<Chunk> {
+        self.index.write(&self.dir.join("hits.bin"))?;
+        fs::write(self.dir.join("chunk.toml"), toml::to_string(&self.chunk).unwrap())?;
+
+        Ok(self.chunk)
+    }
+}
+
+fn main() -> io::Result<()> {
+    println!(
+        "\n-----------------------\n{}\n-----------------------\n",
+        "Timepix Hits Split Tool".bold()
+    );
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(
+            clap::Arg::with_name("INPUT")
+                .help("Sets the input directory pattern")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("chunk-duration")
+                .help("Splits the hits into chunks of this duration (s)")
+                .long("chunk-duration")
+                .takes_value(true)
+                .required_unless("max-chunk-size"),
+        )
+        .arg(
+            clap::Arg::with_name("max-chunk-size")
+                .help("Splits the hits into chunks of at most this size (bytes)")
+                .long("max-chunk-size")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("output-dirname")
+                .help("Sets the directory within each run to write the chunks to (default is 'chunks')")
+                .long("output-dirname")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("dry-run")
+                .help("Shows output of what runs will be processed without changing any data")
+                .long("dry-run"),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_glob_str = matches.value_of("INPUT").unwrap();
+
+    let settings = {
+        let output_dirname = matches.value_of("output-dirname").unwrap_or("chunks").to_owned();
+
+        let chunk_duration = matches.value_of("chunk-duration").and_then(|x| x.parse::<f64>().ok());
+        let max_chunk_size = matches.value_of("max-chunk-size").and_then(parse_human_readable_number::<u64>);
+
+        if let Some(chunk_duration) = chunk_duration {
+            assert!(chunk_duration > 0.0);
+        }
+
+        if let Some(max_chunk_size) = max_chunk_size {
+            assert!(max_chunk_size >= 16);
+        }
+
+        Settings {
+            output_dirname,
+            chunk_duration,
+            max_chunk_size,
+        }
+    };
+
+    let dry_run = matches.is_present("dry-run");
+
+    //
+    // Parse input file list
+    //
+    let matched_dirs: Vec<_> = glob(input_glob_str)
+        .expect("Failed to read input file glob pattern")
+        .filter_map(|x| x.ok()) // Check glob worked
+        .filter(|x| x.is_dir()) // Check is directory
+        .filter(|x| x.join("hits.bin").exists())
+        .collect();
+
+    // Runs with existing output files are skipped
+    let (input_dirs, existing_dirs): (Vec<_>, Vec<_>) = matched_dirs.into_iter().partition(|x| !x.join(&settings.output_dirname).exists());
+
+    if input_dirs.is_empty() && (!dry_run || existing_dirs.is_empty()) {
+        println!("No input directories matched!");
+        return Ok(());
+    }
+
+    println!("Matched {} input directories", input_dirs.len());
+
+    if dry_run {
+        let mut plan = DryRunPlan::new();
+
+        for input_dir in &input_dirs {
+            let input_bytes = input_dir.join("hits.bin").metadata()?.len();
+            let hits = Run::open(input_dir)?.hit_count().ok();
+
+            plan.run(input_dir, input_bytes, hits);
+        }
+
+        for existing_dir in &existing_dirs {
+            plan.skipped(existing_dir);
+        }
+
+        plan.print();
+
+        return Ok(());
+    }
+
+    for input_dir in input_dirs {
+        let run_name = input_dir.file_stem().unwrap().to_str().unwrap();
+
+        let timer = ProcessingTimer::start(&input_dir);
+
+        let chunks = process_run(&input_dir, &settings)?;
+
+        timer.finish(input_dir.join("hits.bin").metadata()?.len())?;
+
+        println!(
+            "{} | {} Chunks | {} Hits",
+            run_name,
+            chunks.len(),
+            chunks.iter().map(|x| x.hits).sum::<usize>().separated_string()
+        );
+    }
+
+    Ok(())
+}
+
+fn process_run(run_dir: &Path, settings: &Settings) -> io::Result<Vec<Chunk>> {
+    let run_name = run_dir.file_stem().unwrap().to_str().unwrap();
+
+    let chunk_duration = settings.chunk_duration.map(|x| (x * 1e9 / TOA_CLOCK_TO_NS) as u64);
+    let max_chunk_hits = settings.max_chunk_size.map(|x| (x / 16) as usize);
+
+    let mut chunks = Vec::new();
+    let mut writer: Option<ChunkWriter> = None;
+    let mut hits = Vec::with_capacity(BUFFER_SIZE);
+
+    for hit in ReadHitsIterator::new(&run_dir.join("hits.bin")) {
+        let chunk_full = match &writer {
+            Some(writer) => {
+                let too_long = chunk_duration.map_or(false, |x| hit.toa >= writer.chunk.start_toa + x);
+                let too_big = max_chunk_hits.map_or(false, |x| writer.chunk.hits + hits.len() >= x);
+
+                too_long || too_big
+            }
+            None => true,
+        };
+
+        if chunk_full || hits.len() == BUFFER_SIZE {
+            if let Some(writer) = writer.as_mut() {
+                writer.write(&hits)?;
+                hits.clear();
+            }
+        }
+
+        if chunk_full {
+            if let Some(writer) = writer.take() {
+                chunks.push(writer.finish()?);
+            }
+
+            // Chunks of a fixed duration are aligned to the start of the run
+            let start_toa = match (chunk_duration, chunks.first()) {
+                (Some(x), Some(first)) => first.start_toa + (hit.toa - first.start_toa) / x * x,
+                _ => hit.toa,
+            };
+
+            writer = Some(ChunkWriter::new(run_dir, settings, run_name, chunks.len(), start_toa)?);
+        }
+
+        hits.push(hit);
+    }
+
+    if let Some(mut writer) = writer {
+        writer.write(&hits)?;
+        chunks.push(writer.finish()?);
+    }
+
+    Ok(chunks)
+}
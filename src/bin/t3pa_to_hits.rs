@@ -0,0 +1,69 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * --------------------------
+ * Timepix t3pa Hits Importer
+ * --------------------------
+ *
+ * timepix-spidr-data-parser/src/bin/t3pa_to_hits.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use clap;
+use colored::Colorize;
+use separator::Separatable as _;
+
+use timepix_spidr_data_parser::*;
+
+fn main() -> io::Result<()> {
+    println!(
+        "\n--------------------------\n{}\n--------------------------\n",
+        "Timepix t3pa Hits Importer".bold()
+    );
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(
+            clap::Arg::with_name("INPUT")
+                .help("Sets the input t3pa file (as written by SoPhy/Pixet)")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("OUTPUT")
+                .help("Sets the output hits file to write")
+                .required(true)
+                .index(2),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_file = Path::new(matches.value_of("INPUT").unwrap());
+    let output_file = Path::new(matches.value_of("OUTPUT").unwrap());
+
+    let mut hits = read_t3pa(input_file)?;
+
+    // Hits files are always ToA sorted
+    hits.sort();
+
+    let mut output_data_file = fs::File::create(output_file)?;
+    write_hits_to_file(&mut output_data_file, &hits)?;
+
+    let mut hits_index = HitsIndexBuilder::new();
+    hits_index.add(&hits);
+    hits_index.write(output_file)?;
+
+    println!("Wrote {} hits to {}", hits.len().separated_string(), output_file.display());
+
+    Ok(())
+}
@@ -0,0 +1,217 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * ----------------------
+ * Timepix Hits Thin Tool
+ * ----------------------
+ *
+ * timepix-spidr-data-parser/src/bin/thin_hits.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use clap;
+use colored::Colorize;
+use glob::glob;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use separator::Separatable as _;
+use serde::Serialize;
+
+use timepix_spidr_data_parser::*;
+
+#[derive(Clone, Serialize)]
+struct Settings {
+    output_filename: String,
+    fraction: Option<f64>,
+    prescale: Option<usize>,
+    seed: u64,
+}
+
+fn main() -> io::Result<()> {
+    println!("\n----------------------\n{}\n----------------------\n", "Timepix Hits Thin Tool".bold());
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(
+            clap::Arg::with_name("INPUT")
+                .help("Sets the input directory pattern")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("fraction")
+                .help("Keeps a random sample of this fraction of the hits (0 - 1)")
+                .long("fraction")
+                .takes_value(true)
+                .required_unless("prescale")
+                .conflicts_with("prescale"),
+        )
+        .arg(
+            clap::Arg::with_name("prescale")
+                .help("Keeps every Nth hit")
+                .long("prescale")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("seed")
+                .help("Seed for the random sampling, so the same hits are kept each time (default is 0)")
+                .long("seed")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("output-filename")
+                .help("Sets the output filename (without extension!) to use (default is 'hits_thinned')")
+                .long("output-filename")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("dry-run")
+                .help("Shows output of what runs will be processed without changing any data")
+                .long("dry-run"),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_glob_str = matches.value_of("INPUT").unwrap();
+
+    let settings = {
+        let output_filename = matches.value_of("output-filename").unwrap_or("hits_thinned").to_owned();
+
+        assert!(output_filename != "hits");
+
+        let fraction = matches.value_of("fraction").and_then(|x| x.parse::<f64>().ok());
+        let prescale = matches.value_of("prescale").and_then(parse_human_readable_number::<usize>);
+        let seed = matches.value_of("seed").and_then(|x| x.parse::<u64>().ok()).unwrap_or(0);
+
+        if let Some(fraction) = fraction {
+            assert!(fraction > 0.0 && fraction <= 1.0);
+        }
+
+        if let Some(prescale) = prescale {
+            assert!(prescale > 0);
+        }
+
+        Settings {
+            output_filename,
+            fraction,
+            prescale,
+            seed,
+        }
+    };
+
+    let dry_run = matches.is_present("dry-run");
+
+    //
+    // Parse input file list
+    //
+    let matched_dirs: Vec<_> = glob(input_glob_str)
+        .expect("Failed to read input file glob pattern")
+        .filter_map(|x| x.ok()) // Check glob worked
+        .filter(|x| x.is_dir()) // Check is directory
+        .filter(|x| x.join("hits.bin").exists())
+        .collect();
+
+    // Runs with existing output files are skipped
+    let (input_dirs, existing_dirs): (Vec<_>, Vec<_>) = matched_dirs
+        .into_iter()
+        .partition(|x| !x.join(format!("{}.bin", settings.output_filename)).exists());
+
+    if input_dirs.is_empty() && (!dry_run || existing_dirs.is_empty()) {
+        println!("No input directories matched!");
+        return Ok(());
+    }
+
+    println!("Matched {} input directories", input_dirs.len());
+
+    if dry_run {
+        let mut plan = DryRunPlan::new();
+
+        for input_dir in &input_dirs {
+            let input_bytes = input_dir.join("hits.bin").metadata()?.len();
+            let hits = Run::open(input_dir)?.hit_count().ok();
+
+            plan.run(input_dir, input_bytes, hits);
+        }
+
+        for existing_dir in &existing_dirs {
+            plan.skipped(existing_dir);
+        }
+
+        plan.print();
+
+        return Ok(());
+    }
+
+    for input_dir in input_dirs {
+        let run_name = input_dir.file_stem().unwrap().to_str().unwrap();
+
+        let timer = ProcessingTimer::start(&input_dir);
+
+        let hits_written = process_run(&input_dir, &settings)?;
+
+        timer.finish(input_dir.join("hits.bin").metadata()?.len())?;
+
+        println!("{} | {} Hits Written", run_name, hits_written.separated_string());
+    }
+
+    Ok(())
+}
+
+fn process_run(run_dir: &Path, settings: &Settings) -> io::Result<usize> {
+    let input_data_file_path = run_dir.join("hits.bin");
+    let output_data_file_path = run_dir.join(format!("{}.bin", settings.output_filename));
+    let output_toml_file_path = run_dir.join(format!("{}.toml", settings.output_filename));
+
+    let mut output_data_file = fs::File::create(&output_data_file_path)?;
+    let mut hits_index = HitsIndexBuilder::new();
+
+    // Write metadata to TOML file
+    write_settings_toml(&output_toml_file_path, settings, &[&input_data_file_path])?;
+
+    // Seeded per run so each run's sample is reproducible on its own
+    let mut rng = StdRng::seed_from_u64(settings.seed);
+
+    let mut hits_written = 0;
+    let mut hits = Vec::with_capacity(BUFFER_SIZE);
+
+    for (i, hit) in ReadHitsIterator::new(&input_data_file_path).enumerate() {
+        let keep = match (settings.fraction, settings.prescale) {
+            (Some(fraction), _) => rng.gen::<f64>() < fraction,
+            (None, Some(prescale)) => i % prescale == 0,
+            (None, None) => true,
+        };
+
+        if !keep {
+            continue;
+        }
+
+        hits.push(hit);
+
+        if hits.len() == BUFFER_SIZE {
+            hits_index.add(&hits);
+            write_hits_to_file(&mut output_data_file, &hits)?;
+
+            hits_written += hits.len();
+            hits.clear();
+        }
+    }
+
+    hits_index.add(&hits);
+    write_hits_to_file(&mut output_data_file, &hits)?;
+
+    hits_written += hits.len();
+
+    hits_index.write(&output_data_file_path)?;
+
+    Ok(hits_written)
+}
@@ -0,0 +1,335 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * --------------------------
+ * Timepix Timing Offset Tool
+ * --------------------------
+ *
+ * timepix-spidr-data-parser/src/bin/timing_offset_tool.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::io;
+use std::path::Path;
+
+use clap;
+use colored::Colorize;
+use glob::glob;
+use indicatif::{ProgressBar, ProgressStyle};
+use separator::Separatable as _;
+use serde::Serialize;
+
+use timepix_spidr_data_parser::*;
+
+/// Size of the regions sharing a timing offset
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Granularity {
+    Column,
+    /// 2x4 pixel superpixels
+    Superpixel,
+}
+
+impl Granularity {
+    /// Width and height of each region (pixels)
+    fn region_size(self) -> (u16, u16) {
+        match self {
+            Granularity::Column => (1, 256),
+            Granularity::Superpixel => (2, 4),
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct Settings {
+    input_filename: String,
+    output_filename: String,
+    granularity: Granularity,
+    min_event_hits: usize,
+    max_offset: f64,
+}
+
+#[derive(Serialize)]
+struct TimingOffsetSummary {
+    events: usize,
+    hits: u64,
+    regions_measured: usize,
+    max_abs_offset: f64,
+}
+
+fn main() -> io::Result<()> {
+    println!(
+        "\n--------------------------\n{}\n--------------------------\n",
+        "Timepix Timing Offset Tool".bold()
+    );
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(
+            clap::Arg::with_name("INPUT")
+                .help("Sets the input directory pattern (of runs with prompt-flash events)")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("input-filename")
+                .help("Sets the input filename (without extension!) to use (default is 'clusters')")
+                .long("input-filename")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("output-filename")
+                .help("Sets the output filename (without extension!) to use (default is 'timing_offsets')")
+                .long("output-filename")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("granularity")
+                .help("Measures an offset for each column or each 2x4 superpixel (default is column)")
+                .long("granularity")
+                .possible_values(&["column", "superpixel"])
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("min-event-hits")
+                .help("Minimum hits for an event to be used as a prompt flash (default is 100)")
+                .long("min-event-hits")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("max-offset")
+                .help("Ignores hits further than this from the event's median ToA (ns) (default is 200)")
+                .long("max-offset")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("dry-run")
+                .help("Shows output of what runs will be processed without changing any data")
+                .long("dry-run"),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_glob_str = matches.value_of("INPUT").unwrap();
+
+    let settings = {
+        let input_filename = matches.value_of("input-filename").unwrap_or("clusters").to_owned();
+        let output_filename = matches.value_of("output-filename").unwrap_or("timing_offsets").to_owned();
+
+        let granularity = match matches.value_of("granularity") {
+            Some("superpixel") => Granularity::Superpixel,
+            _ => Granularity::Column,
+        };
+
+        let min_event_hits = matches.value_of("min-event-hits").and_then(parse_human_readable_number).unwrap_or(100);
+        let max_offset = matches.value_of("max-offset").and_then(|x| x.parse::<f64>().ok()).unwrap_or(200.0);
+
+        Settings {
+            input_filename,
+            output_filename,
+            granularity,
+            min_event_hits,
+            max_offset,
+        }
+    };
+
+    let dry_run = matches.is_present("dry-run");
+
+    //
+    // Parse input file list
+    //
+    let matched_dirs: Vec<_> = glob(input_glob_str)
+        .expect("Failed to read input file glob pattern")
+        .filter_map(|x| x.ok()) // Check glob worked
+        .filter(|x| x.is_dir()) // Check is directory
+        .filter(|x| x.join(format!("{}.bin", settings.input_filename)).exists())
+        .filter(|x| x.join(format!("{}.csv", settings.input_filename)).exists())
+        .collect();
+
+    // Runs with existing output files are skipped
+    let (input_dirs, existing_dirs): (Vec<_>, Vec<_>) = matched_dirs
+        .into_iter()
+        .partition(|x| !x.join(format!("{}.csv", settings.output_filename)).exists());
+
+    if input_dirs.is_empty() && (!dry_run || existing_dirs.is_empty()) {
+        println!("No input directories matched!");
+        return Ok(());
+    }
+
+    println!("Matched {} input directories", input_dirs.len());
+
+    if dry_run {
+        let mut plan = DryRunPlan::new();
+
+        for input_dir in &input_dirs {
+            let input_bytes = input_dir.join(format!("{}.bin", settings.input_filename)).metadata()?.len();
+            let hits = Run::open(input_dir)?.cluster_hit_count(&settings.input_filename).ok();
+
+            plan.run(input_dir, input_bytes, hits);
+        }
+
+        for existing_dir in &existing_dirs {
+            plan.skipped(existing_dir);
+        }
+
+        plan.print();
+
+        return Ok(());
+    }
+
+    let sty = ProgressStyle::default_bar()
+        .template(PROGRESS_BAR_TEMPLATE)
+        .progress_chars(PROGRESS_BAR_CHARS);
+
+    for input_dir in input_dirs {
+        let mut rdr = csv::Reader::from_path(input_dir.join(format!("{}.csv", settings.input_filename)))?;
+        let input_metadata: Vec<ClusterMetadata> = rdr.deserialize().map(|x| x.unwrap()).collect();
+
+        let progress_bar = ProgressBar::new(input_metadata.len() as u64);
+        progress_bar.set_style(sty.clone());
+
+        let timer = ProcessingTimer::start(&input_dir);
+
+        process_run(&input_dir, &input_metadata, &settings, &progress_bar)?;
+
+        timer.finish(input_dir.join(format!("{}.bin", settings.input_filename)).metadata()?.len())?;
+    }
+
+    Ok(())
+}
+
+fn process_run(run_dir: &Path, input_metadata: &[ClusterMetadata], settings: &Settings, progress_bar: &ProgressBar) -> io::Result<()> {
+    let run_name = run_dir.file_stem().unwrap().to_str().unwrap();
+
+    let input_data_file_path = run_dir.join(format!("{}.bin", settings.input_filename));
+    // Only the times within each event are used, so files with signed ToA values can be read too
+    let cluster_iterator = Run::open(run_dir)?
+        .relative_clusters(&settings.input_filename)?
+        .map(|(_, cluster)| cluster);
+
+    let (width, height) = settings.granularity.region_size();
+    let (n_cols, n_rows) = (256 / width as usize, 256 / height as usize);
+
+    let mut hits = vec![0_u64; n_cols * n_rows];
+    let mut sum_offset = vec![0_f64; n_cols * n_rows];
+    let mut sum_offset_sq = vec![0_f64; n_cols * n_rows];
+
+    let mut events_used = 0;
+    let mut hits_used: u64 = 0;
+
+    progress_bar.set_message(&format!("| 0 Events Used | {}", run_name));
+
+    for (metadata, cluster) in input_metadata.iter().zip(cluster_iterator) {
+        progress_bar.inc(1);
+
+        // Empty events (eg. from `--write-all`) have no median hit to measure offsets from
+        if cluster.is_empty() || metadata.hits < settings.min_event_hits {
+            continue;
+        }
+
+        // ToA values are counted from the earliest hit of the event (`Run::relative_clusters`)
+        let mut toas: Vec<i64> = cluster.iter().map(|hit| hit.toa as i64).collect();
+        toas.sort();
+
+        let reference = toas[toas.len() / 2];
+
+        for hit in &cluster {
+            if hit.col > 255 || hit.row > 255 {
+                continue;
+            }
+
+            let offset = (hit.toa as i64 - reference) as f64 * TOA_CLOCK_TO_NS;
+
+            if offset.abs() > settings.max_offset {
+                continue;
+            }
+
+            let i = (hit.row / height) as usize * n_cols + (hit.col / width) as usize;
+
+            hits[i] += 1;
+            sum_offset[i] += offset;
+            sum_offset_sq[i] += offset * offset;
+
+            hits_used += 1;
+        }
+
+        events_used += 1;
+
+        if events_used % 100 == 0 {
+            progress_bar.set_message(&format!("| {} Events Used | {}", events_used.separated_string(), run_name));
+        }
+    }
+
+    let mut regions: Vec<TimingOffsetRegion> = (0..n_cols * n_rows)
+        .map(|i| {
+            let (col, row) = ((i % n_cols) as u16 * width, (i / n_cols) as u16 * height);
+
+            let (offset, rms) = if hits[i] > 0 {
+                let mean = sum_offset[i] / hits[i] as f64;
+                (mean, (sum_offset_sq[i] / hits[i] as f64 - mean * mean).max(0.0).sqrt())
+            } else {
+                (0.0, 0.0)
+            };
+
+            TimingOffsetRegion {
+                col_start: col,
+                col_end: col + width,
+                row_start: row,
+                row_end: row + height,
+                hits: hits[i],
+                offset,
+                rms,
+            }
+        })
+        .collect();
+
+    // Offsets are relative to the average region, so the correction doesn't shift the overall time
+    let measured: Vec<f64> = regions.iter().filter(|x| x.hits > 0).map(|x| x.offset).collect();
+
+    if !measured.is_empty() {
+        let mean_offset = measured.iter().sum::<f64>() / measured.len() as f64;
+
+        for region in regions.iter_mut().filter(|x| x.hits > 0) {
+            region.offset -= mean_offset;
+        }
+    }
+
+    let output_csv_file_path = run_dir.join(format!("{}.csv", settings.output_filename));
+    let output_toml_file_path = run_dir.join(format!("{}.toml", settings.output_filename));
+
+    // Write metadata to TOML file
+    write_settings_toml(&output_toml_file_path, settings, &[&input_data_file_path])?;
+
+    let mut csv_writer = csv::Writer::from_path(&output_csv_file_path)?;
+
+    for region in &regions {
+        csv_writer.serialize(region)?;
+    }
+
+    csv_writer.flush()?;
+
+    let summary = TimingOffsetSummary {
+        events: events_used,
+        hits: hits_used,
+        regions_measured: measured.len(),
+        max_abs_offset: regions.iter().map(|x| x.offset.abs()).fold(0.0, f64::max),
+    };
+
+    update_run_summary(run_dir, "timing_offsets", &summary)?;
+
+    progress_bar.finish_with_message(&format!(
+        "| Done | {} Events Used | {} Regions Measured | Max Offset {:.1} ns | {}",
+        events_used.separated_string(),
+        summary.regions_measured,
+        summary.max_abs_offset,
+        run_name
+    ));
+
+    Ok(())
+}
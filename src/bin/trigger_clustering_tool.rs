@@ -18,16 +18,16 @@ use std::fs;
 use std::io;
 use std::io::BufRead;
 use std::path::Path;
+use std::process;
+use std::time::Duration;
 
 use bit_vec::BitVec;
 use clap;
 use colored::Colorize;
 use glob::glob;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use rayon;
+use indicatif::ProgressBar;
 use separator::Separatable as _;
 use serde::Serialize;
-use toml;
 
 use timepix_spidr_data_parser::*;
 
@@ -39,7 +39,12 @@ struct Settings {
     min_cluster_tot: u32,
     max_pixel_gap: u32,
     max_toa_gap: u32,
-    min_hit_tot: u32,
+    #[serde(flatten)]
+    filter: HitFilter,
+    #[serde(flatten)]
+    events: EventSelection,
+    #[serde(skip)]
+    read_buffer: usize, // bytes
 }
 
 trait HasChild {
@@ -117,12 +122,96 @@ fn main() -> io::Result<()> {
                 .long("min-hit-tot")
                 .takes_value(true),
         )
+        .arg(
+            clap::Arg::with_name("event-range")
+                .help("Only processes the events in a range of event numbers, given as 'first:last' (inclusive)")
+                .long("event-range")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("event-list")
+                .help("Only processes the events listed in a CSV file with an 'event' column, and optionally a 'run_id' column restricting each to a run")
+                .long("event-list")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("roi")
+                .help("Only clusters hits within a region of the matrix, given as 'col_min:col_max,row_min:row_max' (inclusive)")
+                .long("roi")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("manifest")
+                .help("Writes a CSV manifest of the processed runs and their outcomes")
+                .long("manifest")
+                .takes_value(true),
+        )
         .arg(
             clap::Arg::with_name("dry-run")
                 .help("Shows output of what runs will be processed without changing any data")
                 .long("dry-run"),
         )
         .arg(clap::Arg::with_name("disable-mt").help("Disables multi-threading").long("disable-mt"))
+        .arg(
+            clap::Arg::with_name("force")
+                .help("Processes runs even if they are locked by another process")
+                .long("force"),
+        )
+        .arg(
+            clap::Arg::with_name("on-complete")
+                .help("Shell command to run after each run completes, with '{run_dir}' and '{summary}' replaced by the run directory and summary file paths")
+                .long("on-complete")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("sync")
+                .help("Flushes the metadata CSV after every event, so it never lags behind the binary file")
+                .long("sync"),
+        )
+        .arg(
+            clap::Arg::with_name("flush-records")
+                .help("Number of events written between flushes of the metadata CSV (default is 10k)")
+                .long("flush-records")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("flush-interval")
+                .help("Longest time between flushes of the metadata CSV (s) (default is 5)")
+                .long("flush-interval")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("read-buffer")
+                .help("Size of the blocks input files are read in, larger blocks help on network filesystems (bytes) (default is 1.6M)")
+                .long("read-buffer")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("min-free-space")
+                .help("Free space to keep on the output filesystem, runs pause and then abort below it (GB) (default is 1)")
+                .long("min-free-space")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("low-space-wait")
+                .help("Time to wait for disk space to be freed before aborting a run (s) (default is 600)")
+                .long("low-space-wait")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("job-index")
+                .help("Index of this task in a batch array job (0 to job-count - 1), only its share of the matched runs are processed")
+                .long("job-index")
+                .takes_value(true)
+                .requires("job-count"),
+        )
+        .arg(
+            clap::Arg::with_name("job-count")
+                .help("Number of tasks in a batch array job the matched runs are shared between")
+                .long("job-count")
+                .takes_value(true)
+                .requires("job-index"),
+        )
         .get_matches();
 
     //
@@ -143,6 +232,20 @@ fn main() -> io::Result<()> {
         let max_pixel_gap = matches.value_of("max-pixel-gap").and_then(parse_human_readable_number).unwrap_or(3);
         let max_toa_gap = (matches.value_of("max-toa-gap").and_then(parse_human_readable_number).unwrap_or(5_000) as f64 / TOA_CLOCK_TO_NS) as u32; // 5µs
         let min_hit_tot = matches.value_of("min-hit-tot").and_then(parse_human_readable_number).unwrap_or(0); // 0 ns
+        let roi = matches.value_of("roi").map(|x| Roi::parse(x).expect("Invalid region of interest"));
+
+        let events = match EventSelection::new(matches.value_of("event-range"), matches.value_of("event-list")) {
+            Ok(events) => events,
+            Err(err) => {
+                println!("{}", err.to_string().red());
+                process::exit(1);
+            }
+        };
+
+        let read_buffer = matches
+            .value_of("read-buffer")
+            .and_then(parse_human_readable_number::<usize>)
+            .map_or(DEFAULT_READ_BUFFER_SIZE, |x| x.max(16));
 
         Settings {
             input_filename,
@@ -151,26 +254,92 @@ fn main() -> io::Result<()> {
             min_cluster_tot,
             max_pixel_gap,
             max_toa_gap,
-            min_hit_tot,
+            filter: HitFilter {
+                min_hit_tot,
+                roi,
+                pixel_mask: None,
+            },
+            events,
+            read_buffer,
         }
     };
 
     let dry_run = matches.is_present("dry-run");
-    let disable_mt = matches.is_present("disable-mt");
+    let manifest = matches.value_of("manifest").map(|x| x.to_owned());
+    let run_options = RunOptions {
+        disable_mt: matches.is_present("disable-mt"),
+        force: matches.is_present("force"),
+        on_complete: matches.value_of("on-complete").map(|x| x.to_owned()),
+    };
+    let disk_space = {
+        let defaults = DiskSpaceLimits::default();
+
+        DiskSpaceLimits {
+            min_free: matches.value_of("min-free-space").and_then(|x| x.parse::<f64>().ok()).map_or(defaults.min_free, |x| (x * 1e9) as u64),
+            max_pause: matches
+                .value_of("low-space-wait")
+                .and_then(|x| x.parse::<f64>().ok())
+                .map_or(defaults.max_pause, Duration::from_secs_f64),
+        }
+    };
+    let flush_policy = if matches.is_present("sync") {
+        FlushPolicy::sync()
+    } else {
+        let defaults = FlushPolicy::default();
+
+        FlushPolicy {
+            records: matches.value_of("flush-records").and_then(parse_human_readable_number::<usize>).map_or(defaults.records, |x| x.max(1)),
+            interval: matches
+                .value_of("flush-interval")
+                .and_then(|x| x.parse::<f64>().ok())
+                .map_or(defaults.interval, Duration::from_secs_f64),
+        }
+    };
+    let job_partition = match JobPartition::from_args(matches.value_of("job-index"), matches.value_of("job-count")) {
+        Ok(job_partition) => job_partition,
+        Err(err) => {
+            println!("{}", err.red());
+            process::exit(1);
+        }
+    };
 
     //
     // Parse input file list
     //
-    let input_dirs: Vec<_> = glob(input_glob_str)
+    if job_partition.count > 1 {
+        println!("Array job task {} of {}", job_partition.index, job_partition.count);
+    }
+
+    let matched_dirs: Vec<_> = glob(input_glob_str)
         .expect("Failed to read input file glob pattern")
         .filter_map(|x| x.ok()) // Check glob worked
+        .enumerate()
+        .filter(|(i, _)| job_partition.contains(*i)) // Check is in this task's share of an array job
+        .map(|(_, x)| x)
         .filter(|x| x.is_dir()) // Check is directory
         .filter(|x| x.has_child(&format!("{}.bin", settings.input_filename)).unwrap())
-        // Check doesnt have existing output files
-        .filter(|x| !x.has_child(&format!("{}.bin", settings.output_filename)).unwrap())
         .collect();
 
-    if input_dirs.is_empty() {
+    // Runs with output made with the same settings are skipped, output made with other settings is
+    // replaced (as is the partial output of an interrupted run)
+    let settings_hash = settings_hash(&settings)?;
+
+    let mut input_dirs = Vec::new();
+    let mut replaced_dirs = Vec::new();
+    let mut existing_dirs = Vec::new();
+
+    for dir in matched_dirs {
+        match existing_output(&dir.join(format!("{}.bin", settings.output_filename)), &settings_hash) {
+            ExistingOutput::None => input_dirs.push(dir),
+            ExistingOutput::DifferentSettings => {
+                replaced_dirs.push(dir.clone());
+                input_dirs.push(dir);
+            }
+            ExistingOutput::SameSettings => existing_dirs.push(dir),
+        }
+    }
+
+    if input_dirs.is_empty() && existing_dirs.is_empty() {
         println!("No input directories matched!");
         return Ok(());
     }
@@ -178,50 +347,92 @@ fn main() -> io::Result<()> {
     println!("Matched {} input directories", input_dirs.len());
 
     if dry_run {
-        println!("");
+        let mut plan = DryRunPlan::new();
 
-        for input_dir in input_dirs {
-            println!("{}", input_dir.to_str().unwrap());
+        for input_dir in &input_dirs {
+            let input_bytes = input_dir.join(format!("{}.bin", settings.input_filename)).metadata()?.len();
+            let hits = Run::open(input_dir)?.cluster_hit_count(&settings.input_filename).ok();
+
+            if replaced_dirs.contains(input_dir) {
+                plan.replacing(input_dir, input_bytes, hits);
+            } else {
+                plan.run(input_dir, input_bytes, hits);
+            }
+        }
+
+        for existing_dir in &existing_dirs {
+            plan.skipped_same_settings(existing_dir);
         }
+
+        plan.print();
     } else {
-        let multi_progress = MultiProgress::new();
-        let sty = ProgressStyle::default_bar()
-            .template(PROGRESS_BAR_TEMPLATE)
-            .progress_chars(PROGRESS_BAR_CHARS);
+        for existing_dir in &existing_dirs {
+            println!("{}", format!("{} | Skipped (output exists with the same settings)", existing_dir.display()).yellow());
+        }
+
+        for replaced_dir in &replaced_dirs {
+            println!("{}", format!("{} | Replacing output made with different settings", replaced_dir.display()).yellow());
+        }
+
+        if input_dirs.is_empty() {
+            return Ok(());
+        }
 
-        let disable_mt = disable_mt || input_dirs.len() == 1;
+        install_interrupt_handler();
+
+        let mut jobs = Vec::new();
+        let mut estimated_size = 0;
 
         for input_dir in input_dirs {
             let file = std::fs::File::open(input_dir.join(format!("{}.csv", settings.input_filename)))?;
 
             let n_events = std::io::BufReader::new(file).lines().count() - 1;
 
-            let progress_bar = ProgressBar::new(n_events as u64);
-            progress_bar.set_style(sty.clone());
+            let input_bytes = input_dir.join(format!("{}.bin", settings.input_filename)).metadata()?.len();
 
-            if disable_mt {
-                process_run(&input_dir, settings.clone(), progress_bar).unwrap();
-            } else {
-                let progress_bar = multi_progress.add(progress_bar);
-                let s = settings.clone();
-                rayon::spawn(move || process_run(&input_dir, s, progress_bar).unwrap());
-            }
+            estimated_size += input_bytes * 2; // Every hit plus at worst a header per hit
+
+            jobs.push(RunJob::new(input_dir, (), input_bytes, n_events as u64));
+        }
+
+        // Assumes all the runs are on the same filesystem
+        if let Err(err) = check_free_space(&jobs[0].run_dir, estimated_size, &disk_space) {
+            println!("{}", err.to_string().red().bold());
+            process::exit(1);
         }
 
-        multi_progress.join().unwrap();
+        let results = process_runs(jobs, run_options, move |run_dir, _, progress_bar| {
+            process_run(run_dir, settings.clone(), disk_space, flush_policy, progress_bar)
+        });
+
+        if let Some(manifest) = &manifest {
+            write_run_manifest(Path::new(manifest), &results)?;
+        }
+
+        if let Some(exit_code) = report_run_results(&results) {
+            process::exit(exit_code);
+        }
     }
 
     Ok(())
 }
 
-fn process_run(run_dir: &Path, settings: Settings, progress_bar: ProgressBar) -> io::Result<()> {
+fn process_run(
+    run_dir: &Path,
+    settings: Settings,
+    disk_space: DiskSpaceLimits,
+    flush_policy: FlushPolicy,
+    progress_bar: ProgressBar,
+) -> io::Result<()> {
     let run_name = run_dir.file_stem().unwrap().to_str().unwrap();
 
-    let event_iterator = ReadClusterIterator::new(&run_dir.join(format!("{}.bin", settings.input_filename)).to_str().unwrap());
+    let input_data_file_path = run_dir.join(format!("{}.bin", settings.input_filename));
+    // Errors for files with signed ToA values, as the hits are clustered on unsigned ToA values
+    let event_iterator = Run::open(run_dir)?.clusters_with_buffer_size(&settings.input_filename, settings.read_buffer)?;
 
     let input_csv_file_path = run_dir.join(format!("{}.csv", settings.input_filename));
     
-    let mut rdr = csv::Reader::from_path(input_csv_file_path)?;
+    let mut rdr = csv::Reader::from_path(&input_csv_file_path)?;
     let input_csv_metadata: Vec<ClusterMetadata> = rdr.deserialize().map(|x| x.unwrap()).collect();
 
     let output_data_file_path = run_dir.join(format!("{}.bin", settings.output_filename));
@@ -231,20 +442,48 @@ fn process_run(run_dir: &Path, settings: Settings, progress_bar: ProgressBar) ->
     let mut output_data_file = fs::File::create(&output_data_file_path)?;
 
     // Setup CSV file
-    let output_csv_file = fs::File::create(&output_csv_file_path)?;
-    let mut csv_writer = csv::Writer::from_writer(output_csv_file);
+    let mut csv_writer = MetadataCsvWriter::create(&output_csv_file_path, flush_policy)?;
 
     // Write metadata to TOML file
-    fs::write(&output_toml_file_path, toml::to_string(&settings).unwrap())?;
+    write_settings_toml(&output_toml_file_path, &settings, &[&input_data_file_path, &input_csv_file_path])?;
 
     let mut clusters_written = 0;
     let mut accumulated_file_size = 0;
+    let mut last_time = None;
+    let mut stopped = None;
+    let mut disk_space_monitor = DiskSpaceMonitor::new(run_dir, disk_space);
+
+    let selected_events = settings.events.events_of(run_name);
 
     progress_bar.set_message(&format!("| 0 Clusters Saved | {}", run_name));
 
     for (i, trigger_window_hits) in event_iterator.enumerate() {
+        if is_interrupted() {
+            stopped = Some(interrupted_error());
+            break;
+        }
+
+        if let Err(err) = disk_space_monitor.check(&progress_bar) {
+            stopped = Some(err);
+            break;
+        }
+
         progress_bar.inc(1);
 
+        let event = input_csv_metadata[i].event;
+
+        if selected_events.last().map_or(false, |last| event > last) {
+            break;
+        }
+
+        if !selected_events.contains(event) {
+            continue;
+        }
+
+        let window_hits = trigger_window_hits.len();
+
+        let trigger_window_hits: Vec<Hit> = settings.filter.apply(trigger_window_hits.into_iter()).collect();
+
         let clusters = find_cluster(&trigger_window_hits, &settings);
 
         if clusters.len() == 1 {
@@ -255,24 +494,61 @@ fn process_run(run_dir: &Path, settings: Settings, progress_bar: ProgressBar) ->
 
             write_cluster_to_file(&mut output_data_file, &cluster, 0)?;
             
-            csv_writer.serialize(ClusterMetadata {
-                event: input_csv_metadata[i].event,
+            let metadata = ClusterMetadata {
+                event,
                 time: start_time as f64 * TOA_CLOCK_TO_NS,
                 duration: (end_time - start_time) as f64 * TOA_CLOCK_TO_NS,
                 hits: cluster.len(),
                 sum_tot: cluster.iter().map(|hit| hit.tot).sum(),
                 offset: accumulated_file_size,
-            })?;
+                flags: 0,
+                time_islands: 0,
+                run_id: run_name.to_owned(),
+                uid: match &input_csv_metadata[i].uid {
+                    uid if uid.is_empty() => make_cluster_uid(run_name, event, false),
+                    uid => uid.to_owned(),
+                },
+                external_id: input_csv_metadata[i].external_id,
+                min_col: None,
+                max_col: None,
+                min_row: None,
+                max_row: None,
+                centroid_col: None,
+                centroid_row: None,
+                trigger_time: input_csv_metadata[i].trigger_time,
+                window_start: input_csv_metadata[i].window_start,
+                window_end: input_csv_metadata[i].window_end,
+                hit_fraction: Some(cluster.len() as f64 / window_hits as f64),
+                energy: None,
+            }
+            .with_position(cluster);
 
-            csv_writer.flush()?;
+            csv_writer.serialize(metadata)?;
 
             clusters_written += 1;
             accumulated_file_size += (cluster.len() + 1) * 16;
+            last_time = Some(start_time as f64 * TOA_CLOCK_TO_NS);
 
             progress_bar.set_message(&format!("| {} Clusters Saved | {}", clusters_written.separated_string(), run_name));
         }
     }
 
+    csv_writer.flush()?;
+
+    if let Some(err) = stopped {
+        let checkpoint = Checkpoint {
+            written: clusters_written,
+            last_time,
+        };
+        write_partial_marker(&output_data_file_path, &checkpoint)?;
+
+        progress_bar.finish_with_message(&format!("| {} | {} Clusters Saved | {}", err, clusters_written.separated_string(), run_name));
+
+        return Err(err);
+    }
+
+    clear_partial_marker(&output_data_file_path)?;
+
     progress_bar.finish_with_message(&format!("| {} Clusters Saved | {}", clusters_written.separated_string(), run_name));
 
     Ok(())
@@ -10,20 +10,20 @@
  * Authors: Jared Vann
  */
 
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::fs;
 use std::io;
 use std::io::prelude::*;
 use std::path::Path;
+use std::process;
+use std::time::Duration;
 
 use clap;
 use colored::Colorize;
 use glob::glob;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use rayon;
+use indicatif::ProgressBar;
 use separator::Separatable as _;
 use serde::Serialize;
-use toml;
 
 use timepix_spidr_data_parser::*;
 
@@ -34,12 +34,84 @@ struct Settings {
     output_filename: String,
     max_hits: Option<usize>,
     max_triggers: Option<usize>,
+    #[serde(flatten)]
+    events: EventSelection,
     min_event_hits: usize,
     window_look_behind: u64,
     window_look_ahead: u64,
     relative_toa: bool,
+    relative_to: ToaReference,
     write_all: bool,
+    compact_empty: bool,
     prevent_overlap: bool,
+    window_file: Option<String>,
+    veto_file: Option<String>,
+    external_events: Option<String>,
+    external_tolerance: f64, // ns
+    uuids: bool,
+    pile_up_gap: u64, // ns
+    pile_up_min_hits: usize,
+    #[serde(skip)]
+    read_buffer: usize, // bytes
+}
+
+/// Reference time that written ToA values are relative to
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ToaReference {
+    Absolute,
+    Window,
+    Trigger,
+}
+
+/// Overlap of a run's trigger windows and the hits they held, for efficiency corrections, written
+/// to the `trigger_windows` section of its `summary.json`
+#[derive(Debug, Default, Serialize)]
+struct WindowStatistics {
+    triggers: usize,    // Triggers reached, up to --max-triggers or the last selected event
+    overlapping: usize, // Triggers kept whose window overlaps the next trigger
+    overlapping_fraction: f64,
+    dropped: usize, // Triggers dropped by --prevent-overlap
+    dropped_fraction: f64,
+    vetoed: usize, // Triggers skipped inside the bad-time intervals of --veto-file
+    dead_time: u64,          // Time covered by the windows of dropped triggers, up to the next window (ns)
+    dead_time_fraction: f64, // Of the time from the first to the last trigger reached
+    mean_hits_per_window: f64,
+    hits_per_window: Vec<usize>, // Windows with 0, 1, 2-3, 4-7, ... hits
+    #[serde(skip)]
+    windows: usize,
+    #[serde(skip)]
+    hits: usize,
+}
+
+impl WindowStatistics {
+    fn add_window(&mut self, hits: usize) {
+        let bin = (64 - (hits as u64).leading_zeros()) as usize;
+
+        if self.hits_per_window.len() <= bin {
+            self.hits_per_window.resize(bin + 1, 0);
+        }
+
+        self.hits_per_window[bin] += 1;
+        self.windows += 1;
+        self.hits += hits;
+    }
+
+    fn finish(&mut self, triggers: &[Trigger]) {
+        let reached = self.triggers;
+        let fraction = |n: usize| if reached > 0 { n as f64 / reached as f64 } else { 0.0 };
+
+        self.overlapping_fraction = fraction(self.overlapping);
+        self.dropped_fraction = fraction(self.dropped);
+
+        let span = match (triggers.first(), triggers.get(self.triggers.wrapping_sub(1))) {
+            (Some(first), Some(last)) => last.time - first.time,
+            _ => 0,
+        };
+
+        self.dead_time_fraction = if span > 0 { (self.dead_time as f64 / span as f64).min(1.0) } else { 0.0 };
+        self.mean_hits_per_window = if self.windows > 0 { self.hits as f64 / self.windows as f64 } else { 0.0 };
+    }
 }
 
 trait HasChild {
@@ -103,6 +175,18 @@ fn main() -> io::Result<()> {
                 .long("max-triggers")
                 .takes_value(true),
         )
+        .arg(
+            clap::Arg::with_name("event-range")
+                .help("Only processes the events in a range of event numbers, given as 'first:last' (inclusive)")
+                .long("event-range")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("event-list")
+                .help("Only processes the events listed in a CSV file with an 'event' column, and optionally a 'run_id' column restricting each to a run")
+                .long("event-list")
+                .takes_value(true),
+        )
         .arg(
             clap::Arg::with_name("min-event-hits")
                 .help("Minimum number of hits in an event to save (default is 0)")
@@ -111,10 +195,9 @@ fn main() -> io::Result<()> {
         )
         .arg(
             clap::Arg::with_name("window-size")
-                .help("Acquisition window in us after each trigger")
+                .help("Acquisition window in us after each trigger, or around it with --post-trigger-percent (instead of --look-behind and --look-ahead)")
                 .long("window-size")
-                .takes_value(true)
-                .required(true),
+                .takes_value(true),
         )
         .arg(
             clap::Arg::with_name("post-trigger-percent")
@@ -122,27 +205,160 @@ fn main() -> io::Result<()> {
                 .long("post-trigger-percent")
                 .takes_value(true),
         )
+        .arg(
+            clap::Arg::with_name("look-behind")
+                .help("Time before each trigger to include, which can be longer than the window after it (us) (default is 0)")
+                .long("look-behind")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("look-ahead")
+                .help("Time after each trigger to include (us) (default is 0)")
+                .long("look-ahead")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("window-file")
+                .help("Sets a CSV file (event,window_size,window_offset in us) of per-trigger windows, relative to each run directory (default is to use any such columns in 'triggers.csv')")
+                .long("window-file")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("veto-file")
+                .help("Sets a CSV file (start,end in ns) of bad-time intervals (e.g. HV trips, calibration pulses) to skip the triggers in, relative to each run directory")
+                .long("veto-file")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("external-events")
+                .help("Sets a CSV file (external_id with event or time in ns) mapping triggers to the event IDs of an external DAQ, relative to each run directory (default is 'external_events.csv' if present)")
+                .long("external-events")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("external-tolerance")
+                .help("Furthest a trigger can be from the time of an external event to be matched to it (ns) (default is 100)")
+                .long("external-tolerance")
+                .takes_value(true),
+        )
         .arg(
             clap::Arg::with_name("relative-toa")
-                .help("Set ToA values relative to the start of acquisition window (default is false)")
-                .long("relative-toa"),
+                .help("Set ToA values relative to the start of acquisition window (same as '--relative-to window')")
+                .long("relative-toa")
+                .conflicts_with("relative-to"),
+        )
+        .arg(
+            clap::Arg::with_name("relative-to")
+                .help("Set ToA values relative to the start of the acquisition window or to the trigger (signed) (default is absolute)")
+                .long("relative-to")
+                .possible_values(&["window", "trigger"])
+                .takes_value(true),
         )
         .arg(
             clap::Arg::with_name("write-all")
                 .help("Write all triggers to file event if the window contains no hits")
                 .long("write-all"),
         )
+        .arg(
+            clap::Arg::with_name("compact-empty")
+                .help("With --write-all, lists events without hits in '<filename>.empty.csv' rather than writing them to the binary and metadata files")
+                .long("compact-empty")
+                .requires("write-all"),
+        )
+        .arg(
+            clap::Arg::with_name("pile-up-gap")
+                .help("Gap between hits that starts a new time island, events with more than one island are flagged as pile-up (ns) (default is 500)")
+                .long("pile-up-gap")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("pile-up-min-hits")
+                .help("Minimum number of hits in a time island for it to count towards pile-up (default is 3)")
+                .long("pile-up-min-hits")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("uuids")
+                .help("Identify events with random UUIDs rather than the run ID and event number")
+                .long("uuids"),
+        )
         .arg(
             clap::Arg::with_name("prevent-overlap")
                 .help("Ignores any triggers that overlap with a previous trigger")
                 .long("prevent-overlap"),
         )
+        .arg(
+            clap::Arg::with_name("manifest")
+                .help("Writes a CSV manifest of the processed runs and their outcomes")
+                .long("manifest")
+                .takes_value(true),
+        )
         .arg(
             clap::Arg::with_name("dry-run")
                 .help("Shows output of what runs will be processed without changing any data")
                 .long("dry-run"),
         )
         .arg(clap::Arg::with_name("disable-mt").help("Disables multi-threading").long("disable-mt"))
+        .arg(
+            clap::Arg::with_name("force")
+                .help("Processes runs even if they are locked by another process")
+                .long("force"),
+        )
+        .arg(
+            clap::Arg::with_name("on-complete")
+                .help("Shell command to run after each run completes, with '{run_dir}' and '{summary}' replaced by the run directory and summary file paths")
+                .long("on-complete")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("sync")
+                .help("Flushes the metadata CSV after every event, so it never lags behind the binary file")
+                .long("sync"),
+        )
+        .arg(
+            clap::Arg::with_name("flush-records")
+                .help("Number of events written between flushes of the metadata CSV (default is 10k)")
+                .long("flush-records")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("flush-interval")
+                .help("Longest time between flushes of the metadata CSV (s) (default is 5)")
+                .long("flush-interval")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("read-buffer")
+                .help("Size of the blocks input files are read in, larger blocks help on network filesystems (bytes) (default is 1.6M)")
+                .long("read-buffer")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("min-free-space")
+                .help("Free space to keep on the output filesystem, runs pause and then abort below it (GB) (default is 1)")
+                .long("min-free-space")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("low-space-wait")
+                .help("Time to wait for disk space to be freed before aborting a run (s) (default is 600)")
+                .long("low-space-wait")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("job-index")
+                .help("Index of this task in a batch array job (0 to job-count - 1), only its share of the matched runs are processed")
+                .long("job-index")
+                .takes_value(true)
+                .requires("job-count"),
+        )
+        .arg(
+            clap::Arg::with_name("job-count")
+                .help("Number of tasks in a batch array job the matched runs are shared between")
+                .long("job-count")
+                .takes_value(true)
+                .requires("job-index"),
+        )
         .get_matches();
 
     //
@@ -157,53 +373,161 @@ fn main() -> io::Result<()> {
         let max_triggers = matches.value_of("max-triggers").and_then(parse_human_readable_number);
         let min_event_hits = matches.value_of("min-event-hits").and_then(parse_human_readable_number).unwrap_or(0);
 
-        let window_size = matches.value_of("window-size").unwrap().parse::<u64>().ok().unwrap() as u64;
-
-        let post_trigger_percent = matches
-            .value_of("post-trigger-percent")
-            .and_then(|x| x.parse::<f64>().ok())
-            .unwrap_or(100.0);
-
-        assert!(post_trigger_percent <= 100.0);
-        assert!(post_trigger_percent > 0.0);
+        let events = match EventSelection::new(matches.value_of("event-range"), matches.value_of("event-list")) {
+            Ok(events) => events,
+            Err(err) => {
+                println!("{}", err.to_string().red());
+                process::exit(1);
+            }
+        };
+
+        let window = parse_trigger_window(
+            matches.value_of("look-behind"),
+            matches.value_of("look-ahead"),
+            matches.value_of("window-size"),
+            matches.value_of("post-trigger-percent"),
+        );
+
+        let (window_look_behind, window_look_ahead) = match window {
+            Ok(Some(window)) => window,
+            Ok(None) => {
+                println!("{}", "A trigger window must be given with --window-size or --look-behind/--look-ahead".red());
+                process::exit(1);
+            }
+            Err(err) => {
+                println!("{}", err.red());
+                process::exit(1);
+            }
+        };
 
-        let window_look_behind = (window_size as f64 * (100.0 - post_trigger_percent) * 10.0) as u64;
-        let window_look_ahead = (window_size as f64 * post_trigger_percent * 10.0) as u64;
+        let relative_to = match matches.value_of("relative-to") {
+            Some("window") => ToaReference::Window,
+            Some("trigger") => ToaReference::Trigger,
+            _ if matches.is_present("relative-toa") => ToaReference::Window,
+            _ => ToaReference::Absolute,
+        };
 
-        let relative_toa = matches.is_present("relative-toa");
+        let relative_toa = relative_to != ToaReference::Absolute;
         let write_all = matches.is_present("write-all");
+        let compact_empty = matches.is_present("compact-empty");
         let prevent_overlap = matches.is_present("prevent-overlap");
+        let uuids = matches.is_present("uuids");
+
+        let pile_up_gap = matches.value_of("pile-up-gap").and_then(|x| x.parse::<u64>().ok()).unwrap_or(500);
+        let pile_up_min_hits = matches.value_of("pile-up-min-hits").and_then(parse_human_readable_number).unwrap_or(3);
+
+        let window_file = matches.value_of("window-file").map(|x| x.to_owned());
+        let veto_file = matches.value_of("veto-file").map(|x| x.to_owned());
+
+        let external_events = matches.value_of("external-events").map(|x| x.to_owned());
+        let external_tolerance = matches.value_of("external-tolerance").and_then(|x| x.parse::<f64>().ok()).unwrap_or(100.0);
+
+        let read_buffer = matches
+            .value_of("read-buffer")
+            .and_then(parse_human_readable_number::<usize>)
+            .map_or(DEFAULT_READ_BUFFER_SIZE, |x| x.max(16));
 
         Settings {
             output_filename,
             max_hits,
             max_triggers,
+            events,
             min_event_hits,
             window_look_behind,
             window_look_ahead,
             relative_toa,
+            relative_to,
             write_all,
+            compact_empty,
             prevent_overlap,
+            window_file,
+            veto_file,
+            external_events,
+            external_tolerance,
+            uuids,
+            pile_up_gap,
+            pile_up_min_hits,
+            read_buffer,
         }
     };
 
     let dry_run = matches.is_present("dry-run");
-    let disable_mt = matches.is_present("disable-mt");
+    let manifest = matches.value_of("manifest").map(|x| x.to_owned());
+    let run_options = RunOptions {
+        disable_mt: matches.is_present("disable-mt"),
+        force: matches.is_present("force"),
+        on_complete: matches.value_of("on-complete").map(|x| x.to_owned()),
+    };
+    let disk_space = {
+        let defaults = DiskSpaceLimits::default();
+
+        DiskSpaceLimits {
+            min_free: matches.value_of("min-free-space").and_then(|x| x.parse::<f64>().ok()).map_or(defaults.min_free, |x| (x * 1e9) as u64),
+            max_pause: matches
+                .value_of("low-space-wait")
+                .and_then(|x| x.parse::<f64>().ok())
+                .map_or(defaults.max_pause, Duration::from_secs_f64),
+        }
+    };
+    let flush_policy = if matches.is_present("sync") {
+        FlushPolicy::sync()
+    } else {
+        let defaults = FlushPolicy::default();
+
+        FlushPolicy {
+            records: matches.value_of("flush-records").and_then(parse_human_readable_number::<usize>).map_or(defaults.records, |x| x.max(1)),
+            interval: matches
+                .value_of("flush-interval")
+                .and_then(|x| x.parse::<f64>().ok())
+                .map_or(defaults.interval, Duration::from_secs_f64),
+        }
+    };
+    let job_partition = match JobPartition::from_args(matches.value_of("job-index"), matches.value_of("job-count")) {
+        Ok(job_partition) => job_partition,
+        Err(err) => {
+            println!("{}", err.red());
+            process::exit(1);
+        }
+    };
 
     //
     // Parse input file list
     //
-    let input_dirs: Vec<_> = glob(input_glob_str)
+    if job_partition.count > 1 {
+        println!("Array job task {} of {}", job_partition.index, job_partition.count);
+    }
+
+    let matched_dirs: Vec<_> = glob(input_glob_str)
         .expect("Failed to read input file glob pattern")
         .filter_map(|x| x.ok()) // Check glob worked
+        .enumerate()
+        .filter(|(i, _)| job_partition.contains(*i)) // Check is in this task's share of an array job
+        .map(|(_, x)| x)
         .filter(|x| x.is_dir()) // Check is directory
         .filter(|x| x.has_child("hits.bin").unwrap())
-        .filter(|x| x.has_child("triggers.csv").unwrap())
-        // Check doesnt have existing output files
-        .filter(|x| !x.has_child(&format!("{}.bin", settings.output_filename)).unwrap())
+        .filter(|x| x.has_child("triggers.bin").unwrap() || x.has_child("triggers.csv").unwrap())
         .collect();
 
-    if input_dirs.is_empty() {
+    // Runs with output made with the same settings are skipped, output made with other settings is
+    // replaced (as is the partial output of an interrupted run)
+    let settings_hash = settings_hash(&settings)?;
+
+    let mut input_dirs = Vec::new();
+    let mut replaced_dirs = Vec::new();
+    let mut existing_dirs = Vec::new();
+
+    for dir in matched_dirs {
+        match existing_output(&dir.join(format!("{}.bin", settings.output_filename)), &settings_hash) {
+            ExistingOutput::None => input_dirs.push(dir),
+            ExistingOutput::DifferentSettings => {
+                replaced_dirs.push(dir.clone());
+                input_dirs.push(dir);
+            }
+            ExistingOutput::SameSettings => existing_dirs.push(dir),
+        }
+    }
+
+    if input_dirs.is_empty() && existing_dirs.is_empty() {
         println!("No input directories matched!");
         return Ok(());
     }
@@ -211,35 +535,72 @@ fn main() -> io::Result<()> {
     println!("Matched {} input directories", input_dirs.len());
 
     if dry_run {
-        println!("");
+        let mut plan = DryRunPlan::new();
 
-        for input_dir in input_dirs {
-            println!("{}", input_dir.to_str().unwrap());
+        for input_dir in &input_dirs {
+            let input_bytes = input_dir.join("hits.bin").metadata()?.len();
+            let hits = Run::open(input_dir)?.hit_count().ok();
+
+            if replaced_dirs.contains(input_dir) {
+                plan.replacing(input_dir, input_bytes, hits);
+            } else {
+                plan.run(input_dir, input_bytes, hits);
+            }
         }
+
+        for existing_dir in &existing_dirs {
+            plan.skipped_same_settings(existing_dir);
+        }
+
+        plan.print();
     } else {
-        let multi_progress = MultiProgress::new();
-        let sty = ProgressStyle::default_bar()
-            .template(PROGRESS_BAR_TEMPLATE)
-            .progress_chars(PROGRESS_BAR_CHARS);
+        for existing_dir in &existing_dirs {
+            println!("{}", format!("{} | Skipped (output exists with the same settings)", existing_dir.display()).yellow());
+        }
+
+        for replaced_dir in &replaced_dirs {
+            println!("{}", format!("{} | Replacing output made with different settings", replaced_dir.display()).yellow());
+        }
 
-        let disable_mt = disable_mt || input_dirs.len() == 1;
+        if input_dirs.is_empty() {
+            return Ok(());
+        }
+
+        install_interrupt_handler();
+
+        let mut jobs = Vec::new();
+        let mut estimated_size = 0;
 
         for input_dir in input_dirs {
-            let n_triggers = get_line_count(input_dir.join("triggers.csv").to_str().unwrap())? - 1;
+            let n_triggers = match input_dir.join("triggers.bin").metadata() {
+                Ok(metadata) => (metadata.len() as usize).saturating_sub(TriggerRecord::HEADER_SIZE) / TriggerRecord::SIZE,
+                Err(_) => get_line_count(input_dir.join("triggers.csv").to_str().unwrap())? - 1,
+            };
 
-            let progress_bar = ProgressBar::new(n_triggers as u64);
-            progress_bar.set_style(sty.clone());
+            let input_bytes = input_dir.join("hits.bin").metadata()?.len();
 
-            if disable_mt {
-                process_run(&input_dir, settings.clone(), progress_bar).unwrap();
-            } else {
-                let progress_bar = multi_progress.add(progress_bar);
-                let s = settings.clone();
-                rayon::spawn(move || process_run(&input_dir, s, progress_bar).unwrap());
-            }
+            estimated_size += input_bytes; // Roughly every hit once
+
+            jobs.push(RunJob::new(input_dir, (), input_bytes, n_triggers as u64));
         }
 
-        multi_progress.join().unwrap();
+        // Assumes all the runs are on the same filesystem
+        if let Err(err) = check_free_space(&jobs[0].run_dir, estimated_size, &disk_space) {
+            println!("{}", err.to_string().red().bold());
+            process::exit(1);
+        }
+
+        let results = process_runs(jobs, run_options, move |run_dir, _, progress_bar| {
+            process_run(run_dir, settings.clone(), disk_space, flush_policy, progress_bar)
+        });
+
+        if let Some(manifest) = &manifest {
+            write_run_manifest(Path::new(manifest), &results)?;
+        }
+
+        if let Some(exit_code) = report_run_results(&results) {
+            process::exit(exit_code);
+        }
     }
 
     Ok(())
@@ -256,13 +617,89 @@ fn vecdeque_insertion_sort<T: Ord>(list: &mut VecDeque<T>) {
     }
 }
 
-fn process_run(run_dir: &Path, settings: Settings, progress_bar: ProgressBar) -> io::Result<()> {
+/// Loads any per-trigger windows for a run, either from the given window file or from extra
+/// columns in the run's `triggers.csv`.
+fn read_run_trigger_windows(run_dir: &Path, settings: &Settings) -> io::Result<HashMap<u32, TriggerWindow>> {
+    let window_file_path = match &settings.window_file {
+        Some(window_file) => run_dir.join(window_file),
+        None => {
+            let triggers_file_path = run_dir.join("triggers.csv");
+
+            if !triggers_file_path.exists() {
+                return Ok(HashMap::new());
+            }
+
+            let has_window_columns = csv::Reader::from_path(&triggers_file_path)?
+                .headers()?
+                .iter()
+                .any(|x| x == "window_size");
+
+            if !has_window_columns {
+                return Ok(HashMap::new());
+            }
+
+            triggers_file_path
+        }
+    };
+
+    Ok(read_trigger_windows(&window_file_path)?.into_iter().map(|x| (x.event, x)).collect())
+}
+
+/// Maps the triggers of a run onto external event IDs, if it has a mapping file, recording how
+/// well they matched in `summary.json` and adding them to `triggers.csv`
+fn read_run_external_ids(run_dir: &Path, settings: &Settings, triggers: &[Trigger]) -> io::Result<HashMap<u32, u64>> {
+    let map_file_path = run_dir.join(settings.external_events.as_deref().unwrap_or("external_events.csv"));
+
+    if settings.external_events.is_none() && !map_file_path.exists() {
+        return Ok(HashMap::new());
+    }
+
+    let (external_ids, matching) = ExternalEventMap::read(&map_file_path, settings.external_tolerance)?.assign(triggers);
+
+    update_run_summary(run_dir, "external_events", &matching)?;
+
+    let triggers_file_path = run_dir.join("triggers.csv");
+
+    if triggers_file_path.exists() {
+        add_external_ids_to_triggers_csv(&triggers_file_path, &external_ids)?;
+    }
+
+    Ok(external_ids)
+}
+
+/// Loads the bad-time intervals of a run from the veto file, merged and in order
+fn read_run_vetoes(run_dir: &Path, settings: &Settings) -> io::Result<Vec<Gate>> {
+    match &settings.veto_file {
+        Some(veto_file) => Ok(merge_intervals(&read_gate_data(&run_dir.join(veto_file))?)),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Whether a time falls inside any of a list of merged intervals (start inclusive, end exclusive)
+fn is_vetoed(vetoes: &[Gate], time: u64) -> bool {
+    match vetoes.binary_search_by_key(&time, |x| x.start) {
+        Ok(_) => true,
+        Err(0) => false,
+        Err(index) => time < vetoes[index - 1].end,
+    }
+}
+
+fn process_run(
+    run_dir: &Path,
+    settings: Settings,
+    disk_space: DiskSpaceLimits,
+    flush_policy: FlushPolicy,
+    progress_bar: ProgressBar,
+) -> io::Result<()> {
     let run_name = run_dir.file_stem().unwrap().to_str().unwrap();
 
     progress_bar.set_message(&format!("| 0 Events Written | 0 Overlapping Triggers Ignored | {}", run_name));
 
-    let mut hit_iterator = ReadHitsIterator::new(&run_dir.join("hits.bin"));
-    let triggers = read_trigger_data(&run_dir.join("triggers.csv"))?;
+    let mut hit_iterator = ReadHitsIterator::with_buffer_size(&run_dir.join("hits.bin"), settings.read_buffer);
+    let triggers = read_run_triggers(run_dir)?;
+    let trigger_windows = read_run_trigger_windows(run_dir, &settings)?;
+    let vetoes = read_run_vetoes(run_dir, &settings)?;
+    let external_ids = read_run_external_ids(run_dir, &settings, &triggers)?;
 
     let output_data_file_path = run_dir.join(format!("{}{}", settings.output_filename, ".bin"));
     let output_csv_file_path = run_dir.join(format!("{}{}", settings.output_filename, ".csv"));
@@ -271,17 +708,46 @@ fn process_run(run_dir: &Path, settings: Settings, progress_bar: ProgressBar) ->
     let mut output_data_file = fs::File::create(&output_data_file_path)?;
 
     // Setup CSV file
-    let output_csv_file = fs::File::create(&output_csv_file_path)?;
-    let mut csv_writer = csv::Writer::from_writer(output_csv_file);
+    let mut csv_writer = MetadataCsvWriter::create(&output_csv_file_path, flush_policy)?;
+
+    let empty_events_file_path = empty_events_path(&output_data_file_path);
+
+    let mut empty_events = if settings.compact_empty {
+        Some(EmptyEventsWriter::create(&empty_events_file_path)?)
+    } else {
+        if empty_events_file_path.exists() {
+            fs::remove_file(&empty_events_file_path)?;
+        }
+
+        None
+    };
 
     // Write metadata to TOML file
-    fs::write(&output_toml_file_path, toml::to_string(&settings).unwrap())?;
+    write_settings_toml(&output_toml_file_path, &settings, &[run_dir.join("hits.bin")])?;
 
     let mut events_written = 0;
     let mut overlapping_triggers_ignored = 0;
     let mut accumulated_file_size = 0;
 
     let mut last_start: usize = 0;
+    let mut last_time = None;
+    let mut stopped = None;
+    let mut disk_space_monitor = DiskSpaceMonitor::new(run_dir, disk_space);
+
+    let selected_events = settings.events.events_of(run_name);
+
+    let window_of = |trigger: &Trigger| match trigger_windows.get(&trigger.event) {
+        Some(window) => (window.look_behind(), window.look_ahead()),
+        None => (settings.window_look_behind, settings.window_look_ahead),
+    };
+
+    // With a look-behind the window of a later trigger can reach back into the window of this one
+    let window_start = |trigger: &Trigger| trigger.time.saturating_sub(window_of(trigger).0);
+
+    let mut statistics = WindowStatistics::default();
+    let mut dead_until = 0;
+
+    let pile_up_gap_clks = (settings.pile_up_gap as f64 / TOA_CLOCK_TO_NS) as u64;
 
     let mut hit_buffer = VecDeque::with_capacity(HIT_BUFFER_SIZE);
 
@@ -295,6 +761,16 @@ fn process_run(run_dir: &Path, settings: Settings, progress_bar: ProgressBar) ->
 
     let mut i = 0;
     while i < triggers.len() {
+        if is_interrupted() {
+            stopped = Some(interrupted_error());
+            break;
+        }
+
+        if let Err(err) = disk_space_monitor.check(&progress_bar) {
+            stopped = Some(err);
+            break;
+        }
+
         let trigger = triggers[i];
         
         progress_bar.inc(1);
@@ -305,8 +781,20 @@ fn process_run(run_dir: &Path, settings: Settings, progress_bar: ProgressBar) ->
             }
         }
 
-        let start_time = trigger.time - settings.window_look_behind;
-        let end_time = trigger.time + settings.window_look_ahead;
+        if selected_events.last().map_or(false, |last| i + 1 > last) {
+            break;
+        }
+
+        if is_vetoed(&vetoes, trigger.time) {
+            statistics.vetoed += 1;
+            i += 1;
+            continue;
+        }
+
+        let (window_look_behind, window_look_ahead) = window_of(&trigger);
+
+        let start_time = trigger.time.saturating_sub(window_look_behind);
+        let end_time = trigger.time + window_look_ahead;
 
         let start_time_clks = (start_time as f64 / TOA_CLOCK_TO_NS) as u64;
         let end_time_clks = (end_time as f64 / TOA_CLOCK_TO_NS) as u64;
@@ -329,10 +817,19 @@ fn process_run(run_dir: &Path, settings: Settings, progress_bar: ProgressBar) ->
         // Check trigger does not overlap with previous and next triggers
         if settings.prevent_overlap {
             let mut skip = 1;
+            let mut vetoed = 0;
             
             for j in i+1..triggers.len() {
-                if triggers[j].time < end_time {
-                    skip += 1;
+                // Vetoed triggers have no window, so they are stepped over and counted as vetoed
+                if is_vetoed(&vetoes, triggers[j].time) {
+                    vetoed += 1;
+                    continue;
+                }
+
+                if window_start(&triggers[j]) < end_time {
+                    skip += 1 + vetoed;
+                    statistics.vetoed += vetoed;
+                    vetoed = 0;
                 }
                 else {
                     break;
@@ -340,9 +837,22 @@ fn process_run(run_dir: &Path, settings: Settings, progress_bar: ProgressBar) ->
             }
             
             if skip > 1 {
+                let dropped: Vec<&Trigger> = triggers[i..i + skip].iter().filter(|x| !is_vetoed(&vetoes, x.time)).collect();
+
+                // No events are recorded until the windows of all the dropped triggers have passed,
+                // or until the window of the next trigger opens, as it can be kept
+                let dead_end = dropped.iter().map(|x| x.time + window_of(x).1).max().unwrap();
+                let dead_end = triggers[i + skip..]
+                    .iter()
+                    .find(|x| !is_vetoed(&vetoes, x.time))
+                    .map_or(dead_end, |x| dead_end.min(window_start(x)));
+
+                statistics.dead_time += dead_end.saturating_sub(start_time.max(dead_until));
+                dead_until = dead_end;
+
                 i += skip;
                 
-                overlapping_triggers_ignored += skip;
+                overlapping_triggers_ignored += dropped.len();
                 
                 progress_bar.set_message(&format!(
                     "| {} Events Written | {} Overlapping Triggers Ignored | {}",
@@ -355,6 +865,10 @@ fn process_run(run_dir: &Path, settings: Settings, progress_bar: ProgressBar) ->
             }
         }
 
+        if triggers[i + 1..].iter().find(|x| !is_vetoed(&vetoes, x.time)).map_or(false, |x| window_start(x) < end_time) {
+            statistics.overlapping += 1;
+        }
+
         let mut start_hit: usize = 0;
         let mut end_hit: usize = 0;
         let mut start_set = false;
@@ -380,15 +894,25 @@ fn process_run(run_dir: &Path, settings: Settings, progress_bar: ProgressBar) ->
             }
         }
 
-        if settings.write_all || (end_set && (end_hit - start_hit) > settings.min_event_hits) {
-            let toa_adjustment = if settings.relative_toa { -(start_time_clks as i64) } else { 0 };
+        statistics.add_window(if end_set { end_hit - start_hit } else { 0 });
 
-            if !end_set {
-                write_cluster_to_file(&mut output_data_file, &[], toa_adjustment)?;
-            } else {
-                write_cluster_to_file(&mut output_data_file, &hit_buffer.as_slices().0[start_hit..end_hit], toa_adjustment)?;
+        if let Some(empty_events) = empty_events.as_mut().filter(|_| !end_set && selected_events.contains(i + 1)) {
+            empty_events.push(i + 1)?;
+            events_written += 1;
+        } else if selected_events.contains(i + 1) && (settings.write_all || (end_set && (end_hit - start_hit) > settings.min_event_hits)) {
+            let event_hits = if end_set { &hit_buffer.as_slices().0[start_hit..end_hit] } else { &[] };
+
+            match settings.relative_to {
+                ToaReference::Absolute => write_cluster_to_file(&mut output_data_file, event_hits, 0)?,
+                ToaReference::Window => write_cluster_to_file(&mut output_data_file, event_hits, -(start_time_clks as i64))?,
+                ToaReference::Trigger => {
+                    let trigger_time_clks = (trigger.time as f64 / TOA_CLOCK_TO_NS) as u64;
+                    write_signed_cluster_to_file(&mut output_data_file, event_hits, trigger_time_clks)?
+                }
             }
 
+            let time_islands = count_time_islands(event_hits, pile_up_gap_clks, settings.pile_up_min_hits);
+
             csv_writer.serialize(ClusterMetadata {
                 event: i + 1 as usize,
                 time: start_time as f64,
@@ -404,11 +928,29 @@ fn process_run(run_dir: &Path, settings: Settings, progress_bar: ProgressBar) ->
                     0
                 },
                 offset: accumulated_file_size,
+                flags: if time_islands > 1 { CLUSTER_FLAG_PILE_UP } else { 0 },
+                time_islands,
+                run_id: run_name.to_owned(),
+                uid: make_cluster_uid(run_name, i + 1, settings.uuids),
+                external_id: external_ids.get(&trigger.event).copied(),
+                min_col: None,
+                max_col: None,
+                min_row: None,
+                max_row: None,
+                centroid_col: None,
+                centroid_row: None,
+                trigger_time: Some(trigger.time as f64),
+                window_start: Some(start_time as f64),
+                window_end: Some(end_time as f64),
+                hit_fraction: None,
+                energy: None,
             })?;
 
             accumulated_file_size += if end_set { (end_hit - start_hit + 1) * 16 } else { 16 };
 
             events_written += 1;
+            last_time = Some(start_time as f64);
+
             progress_bar.set_message(&format!(
                 "| {} Events Written | {} Overlapping Triggers Ignored | {}",
                 events_written.separated_string(),
@@ -424,6 +966,30 @@ fn process_run(run_dir: &Path, settings: Settings, progress_bar: ProgressBar) ->
 
     csv_writer.flush()?;
 
+    if let Some(empty_events) = empty_events {
+        empty_events.finish()?;
+    }
+
+    if let Some(err) = stopped {
+        let checkpoint = Checkpoint {
+            written: events_written,
+            last_time,
+        };
+        write_partial_marker(&output_data_file_path, &checkpoint)?;
+
+        progress_bar.finish_with_message(&format!("| {} | {} Events Written | {}", err, events_written.separated_string(), run_name));
+
+        return Err(err);
+    }
+
+    clear_partial_marker(&output_data_file_path)?;
+
+    statistics.triggers = i.min(triggers.len());
+    statistics.dropped = overlapping_triggers_ignored;
+    statistics.finish(&triggers);
+
+    update_run_summary(run_dir, "trigger_windows", &statistics)?;
+
     progress_bar.finish_with_message(&format!(
         "| Done | {} Events Written | {} Overlapping Triggers Ignored | {}",
         events_written.separated_string(),
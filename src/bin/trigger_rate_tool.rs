@@ -0,0 +1,362 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * -------------------------
+ * Timepix Trigger Rate Tool
+ * -------------------------
+ *
+ * timepix-spidr-data-parser/src/bin/trigger_rate_tool.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+
+use clap;
+use colored::Colorize;
+use glob::glob;
+use serde::Serialize;
+
+use timepix_spidr_data_parser::*;
+
+// Inter-trigger interval histogram spans 100 ns to 100 s in log spaced bins
+const INTERVAL_HIST_MIN_EXP: i32 = 2;
+const INTERVAL_HIST_MAX_EXP: i32 = 11;
+const INTERVAL_HIST_BINS_PER_DECADE: i32 = 10;
+
+#[derive(Clone, Serialize)]
+struct Settings {
+    bin_width: f64,
+    burst_factor: f64,
+    dropout_time: f64,
+    max_bursts: Option<usize>,
+    max_dropouts: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct RateBin {
+    start: u64,
+    end: u64,
+    triggers: usize,
+    rate: f64,
+}
+
+#[derive(Serialize)]
+struct IntervalBin {
+    interval_min: f64,
+    interval_max: f64,
+    count: usize,
+}
+
+#[derive(Serialize)]
+struct Anomaly {
+    kind: &'static str,
+    start: u64,
+    end: u64,
+    value: f64,
+}
+
+#[derive(Serialize)]
+struct TriggerRateSummary {
+    triggers: usize,
+    duration: u64,
+    mean_rate: f64,
+    median_bin_rate: f64,
+    bursts: usize,
+    dropouts: usize,
+}
+
+fn main() -> io::Result<()> {
+    println!(
+        "\n-------------------------\n{}\n-------------------------\n",
+        "Timepix Trigger Rate Tool".bold()
+    );
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(
+            clap::Arg::with_name("INPUT")
+                .help("Sets the input directory pattern")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("bin-width")
+                .help("Width of the trigger rate time bins in s (default is 1)")
+                .long("bin-width")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("burst-factor")
+                .help("Flags time bins with a rate above this multiple of the median rate as bursts (default is 5)")
+                .long("burst-factor")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("dropout-time")
+                .help("Flags gaps between triggers longer than this as dropouts in s (default is 10)")
+                .long("dropout-time")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("max-bursts")
+                .help("Exit with an error status if any run has more bursts than this")
+                .long("max-bursts")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("max-dropouts")
+                .help("Exit with an error status if any run has more dropouts than this")
+                .long("max-dropouts")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("dry-run")
+                .help("Shows output of what runs will be processed without changing any data")
+                .long("dry-run"),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_glob_str = matches.value_of("INPUT").unwrap();
+
+    let settings = {
+        let bin_width = matches.value_of("bin-width").and_then(|x| x.parse::<f64>().ok()).unwrap_or(1.0);
+        let burst_factor = matches.value_of("burst-factor").and_then(|x| x.parse::<f64>().ok()).unwrap_or(5.0);
+        let dropout_time = matches.value_of("dropout-time").and_then(|x| x.parse::<f64>().ok()).unwrap_or(10.0);
+
+        // Bins are whole ns, so narrower widths round down to 0
+        if (bin_width * 1e9) as u64 == 0 {
+            println!("{}", format!("Bin width of {} s is less than 1 ns", bin_width).red());
+            process::exit(1);
+        }
+
+        assert!(burst_factor > 1.0);
+        assert!(dropout_time > 0.0);
+
+        let max_bursts = matches.value_of("max-bursts").and_then(parse_human_readable_number);
+        let max_dropouts = matches.value_of("max-dropouts").and_then(parse_human_readable_number);
+
+        Settings {
+            bin_width,
+            burst_factor,
+            dropout_time,
+            max_bursts,
+            max_dropouts,
+        }
+    };
+
+    let dry_run = matches.is_present("dry-run");
+
+    //
+    // Parse input file list
+    //
+    let input_dirs: Vec<_> = glob(input_glob_str)
+        .expect("Failed to read input file glob pattern")
+        .filter_map(|x| x.ok()) // Check glob worked
+        .filter(|x| x.is_dir()) // Check is directory
+        .filter(|x| x.join("triggers.bin").exists() || x.join("triggers.csv").exists())
+        .collect();
+
+    if input_dirs.is_empty() {
+        println!("No input directories matched!");
+        return Ok(());
+    }
+
+    println!("Matched {} input directories", input_dirs.len());
+
+    if dry_run {
+        let mut plan = DryRunPlan::new();
+
+        for input_dir in &input_dirs {
+            plan.run(input_dir, run_file_bytes(input_dir, &["triggers.bin", "triggers.csv"]), None);
+        }
+
+        plan.print();
+
+        return Ok(());
+    }
+
+    let mut failed_runs = 0;
+
+    for input_dir in input_dirs {
+        let run_name = input_dir.file_stem().unwrap().to_str().unwrap();
+
+        let timer = ProcessingTimer::start(&input_dir);
+
+        let summary = process_run(&input_dir, &settings)?;
+
+        timer.finish(run_file_bytes(&input_dir, &["triggers.bin", "triggers.csv"]))?;
+
+        let summary = match summary {
+            Some(summary) => summary,
+            None => {
+                println!("{}", format!("{} | Skipped (fewer than 2 triggers)", run_name).yellow());
+                continue;
+            }
+        };
+
+        let failed = settings.max_bursts.map_or(false, |max| summary.bursts > max)
+            || settings.max_dropouts.map_or(false, |max| summary.dropouts > max);
+
+        let line = format!(
+            "{} | {:.2} Hz | {} Bursts | {} Dropouts",
+            run_name, summary.mean_rate, summary.bursts, summary.dropouts
+        );
+
+        if failed {
+            failed_runs += 1;
+            println!("{}", line.red());
+        } else {
+            println!("{}", line);
+        }
+    }
+
+    if failed_runs > 0 {
+        println!("{}", format!("\n{} runs failed the trigger rate checks", failed_runs).red().bold());
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+fn process_run(run_dir: &Path, settings: &Settings) -> io::Result<Option<TriggerRateSummary>> {
+    let mut triggers = read_run_triggers(run_dir)?;
+
+    if triggers.len() < 2 {
+        return Ok(None);
+    }
+
+    triggers.sort();
+
+    let first_time = triggers[0].time;
+    let last_time = triggers[triggers.len() - 1].time;
+    let duration = last_time - first_time;
+
+    //
+    // Trigger rate vs time
+    //
+    let bin_width_ns = (settings.bin_width * 1e9) as u64;
+    let n_bins = (duration / bin_width_ns + 1) as usize;
+
+    let mut bin_counts = vec![0; n_bins];
+
+    for trigger in triggers.iter() {
+        bin_counts[((trigger.time - first_time) / bin_width_ns) as usize] += 1;
+    }
+
+    let rate_bins: Vec<_> = bin_counts
+        .iter()
+        .enumerate()
+        .map(|(i, &count)| RateBin {
+            start: first_time + i as u64 * bin_width_ns,
+            end: first_time + (i as u64 + 1) * bin_width_ns,
+            triggers: count,
+            rate: count as f64 / settings.bin_width,
+        })
+        .collect();
+
+    let median_bin_rate = {
+        let mut rates: Vec<f64> = rate_bins.iter().map(|x| x.rate).collect();
+        rates.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        rates[rates.len() / 2]
+    };
+
+    //
+    // Inter-trigger intervals
+    //
+    let intervals: Vec<u64> = triggers.windows(2).map(|x| x[1].time - x[0].time).collect();
+
+    let n_interval_bins = ((INTERVAL_HIST_MAX_EXP - INTERVAL_HIST_MIN_EXP) * INTERVAL_HIST_BINS_PER_DECADE) as usize;
+    let mut interval_counts = vec![0; n_interval_bins];
+
+    for &interval in intervals.iter() {
+        let exponent = (interval.max(1) as f64).log10();
+        let bin = ((exponent - f64::from(INTERVAL_HIST_MIN_EXP)) * f64::from(INTERVAL_HIST_BINS_PER_DECADE)).floor();
+        let bin = (bin.max(0.0) as usize).min(n_interval_bins - 1);
+
+        interval_counts[bin] += 1;
+    }
+
+    let interval_bins: Vec<_> = interval_counts
+        .iter()
+        .enumerate()
+        .map(|(i, &count)| {
+            let bin_exponent = |j: usize| f64::from(INTERVAL_HIST_MIN_EXP) + j as f64 / f64::from(INTERVAL_HIST_BINS_PER_DECADE);
+
+            IntervalBin {
+                interval_min: 10_f64.powf(bin_exponent(i)),
+                interval_max: 10_f64.powf(bin_exponent(i + 1)),
+                count,
+            }
+        })
+        .collect();
+
+    //
+    // Bursts and dropouts
+    //
+    let mut anomalies = Vec::new();
+
+    for bin in rate_bins.iter() {
+        if median_bin_rate > 0.0 && bin.rate > median_bin_rate * settings.burst_factor {
+            anomalies.push(Anomaly {
+                kind: "burst",
+                start: bin.start,
+                end: bin.end,
+                value: bin.rate,
+            });
+        }
+    }
+
+    let dropout_time_ns = (settings.dropout_time * 1e9) as u64;
+
+    for pair in triggers.windows(2) {
+        if pair[1].time - pair[0].time > dropout_time_ns {
+            anomalies.push(Anomaly {
+                kind: "dropout",
+                start: pair[0].time,
+                end: pair[1].time,
+                value: (pair[1].time - pair[0].time) as f64 * 1e-9,
+            });
+        }
+    }
+
+    anomalies.sort_by_key(|x| x.start);
+
+    write_csv(&run_dir.join("trigger_rate.csv"), &rate_bins)?;
+    write_csv(&run_dir.join("trigger_intervals.csv"), &interval_bins)?;
+    write_csv(&run_dir.join("trigger_anomalies.csv"), &anomalies)?;
+
+    let summary = TriggerRateSummary {
+        triggers: triggers.len(),
+        duration,
+        mean_rate: if duration > 0 { (triggers.len() - 1) as f64 / (duration as f64 * 1e-9) } else { 0.0 },
+        median_bin_rate,
+        bursts: anomalies.iter().filter(|x| x.kind == "burst").count(),
+        dropouts: anomalies.iter().filter(|x| x.kind == "dropout").count(),
+    };
+
+    update_run_summary(run_dir, "trigger_rate", &summary)?;
+
+    Ok(Some(summary))
+}
+
+fn write_csv<T: Serialize>(file_path: &Path, records: &[T]) -> io::Result<()> {
+    let mut csv_writer = csv::Writer::from_writer(fs::File::create(file_path)?);
+
+    for record in records {
+        csv_writer.serialize(record)?;
+    }
+
+    csv_writer.flush()?;
+
+    Ok(())
+}
@@ -0,0 +1,299 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * ----------------------
+ * Timepix Hits Validator
+ * ----------------------
+ *
+ * timepix-spidr-data-parser/src/bin/validate_hits.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use clap;
+use colored::Colorize;
+use glob::glob;
+use separator::Separatable as _;
+use serde::Serialize;
+
+use timepix_spidr_data_parser::*;
+
+/// Largest ToT the 10 bit counter can produce (ns)
+const MAX_TOT: u32 = 1023 * TOT_ADU_TO_NS;
+
+/// Number of records failing a check, and the byte offset of the first one
+#[derive(Default, Serialize)]
+struct Check {
+    count: usize,
+    first_offset: Option<u64>,
+}
+
+impl Check {
+    fn fail(&mut self, offset: u64) {
+        self.count += 1;
+
+        if self.first_offset.is_none() {
+            self.first_offset = Some(offset);
+        }
+    }
+}
+
+#[derive(Default, Serialize)]
+struct HitsValidation {
+    acq_mode: AcqMode, // Sets which ToT values are valid
+    hits: usize,
+    trailing_bytes: u64,
+    null_records: Check,
+    non_monotonic_toa: Check,
+    invalid_coordinates: Check,
+    invalid_tot: Check,
+}
+
+/// A run's entry in the `--report` file
+#[derive(Serialize)]
+struct RunValidation {
+    run_dir: PathBuf,
+    passed: bool,
+    validation: HitsValidation,
+}
+
+impl HitsValidation {
+    fn failures(&self) -> usize {
+        self.null_records.count + self.non_monotonic_toa.count + self.invalid_coordinates.count + self.invalid_tot.count
+    }
+
+    fn passed(&self) -> bool {
+        self.failures() == 0 && self.trailing_bytes == 0
+    }
+}
+
+fn main() -> io::Result<()> {
+    println!("\n----------------------\n{}\n----------------------\n", "Timepix Hits Validator".bold());
+
+    //
+    // Generate command line option parser
+    //
+    let matches = clap::App::new("")
+        // General options
+        .arg(
+            clap::Arg::with_name("INPUT")
+                .help("Sets the input directory pattern")
+                .required(true)
+                .index(1),
+        )
+        .arg(
+            clap::Arg::with_name("report")
+                .help("Writes the result of every run to this JSON file, the runs themselves are left untouched")
+                .long("report")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("dry-run")
+                .help("Shows output of what runs will be processed without changing any data")
+                .long("dry-run"),
+        )
+        .get_matches();
+
+    //
+    // Read command line options
+    //
+    let input_glob_str = matches.value_of("INPUT").unwrap();
+
+    let report_path = matches.value_of("report").map(PathBuf::from);
+
+    let dry_run = matches.is_present("dry-run");
+
+    //
+    // Parse input file list
+    //
+    let input_dirs: Vec<_> = glob(input_glob_str)
+        .expect("Failed to read input file glob pattern")
+        .filter_map(|x| x.ok()) // Check glob worked
+        .filter(|x| x.is_dir()) // Check is directory
+        .filter(|x| x.join("hits.bin").exists())
+        .collect();
+
+    if input_dirs.is_empty() {
+        println!("No input directories matched!");
+        return Ok(());
+    }
+
+    println!("Matched {} input directories", input_dirs.len());
+
+    if dry_run {
+        let mut plan = DryRunPlan::new();
+
+        for input_dir in &input_dirs {
+            let input_bytes = input_dir.join("hits.bin").metadata()?.len();
+            let hits = Run::open(input_dir)?.hit_count().ok();
+
+            plan.run(input_dir, input_bytes, hits);
+        }
+
+        plan.print();
+
+        return Ok(());
+    }
+
+    let mut failed_runs = 0;
+    let mut report = Vec::new();
+
+    for input_dir in input_dirs {
+        let run_name = input_dir.file_stem().unwrap().to_str().unwrap();
+
+        let timer = ProcessingTimer::start(&input_dir);
+
+        let acq_mode = Run::open(&input_dir)?.acq_mode()?;
+        let validation = validate_hits(&input_dir.join("hits.bin"), acq_mode)?;
+
+        timer.finish(input_dir.join("hits.bin").metadata()?.len())?;
+
+        if validation.passed() {
+            println!("{}", format!("{} | {} Hits | OK", run_name, validation.hits.separated_string()).green());
+
+            report.push(RunValidation {
+                run_dir: input_dir,
+                passed: true,
+                validation,
+            });
+            continue;
+        }
+
+        failed_runs += 1;
+
+        println!(
+            "{}",
+            format!(
+                "{} | {} Hits | {} Failures",
+                run_name,
+                validation.hits.separated_string(),
+                validation.failures()
+            )
+            .red()
+        );
+
+        let checks = [
+            ("Null records", &validation.null_records),
+            ("Non-monotonic ToA", &validation.non_monotonic_toa),
+            ("Invalid coordinates", &validation.invalid_coordinates),
+            ("Invalid ToT", &validation.invalid_tot),
+        ];
+
+        for (name, check) in checks.iter() {
+            if let Some(offset) = check.first_offset {
+                println!("  - {}: {} (first at byte {})", name, check.count.separated_string(), offset);
+            }
+        }
+
+        if validation.trailing_bytes > 0 {
+            println!("  - Incomplete record: {} trailing bytes", validation.trailing_bytes);
+        }
+
+        report.push(RunValidation {
+            run_dir: input_dir,
+            passed: false,
+            validation,
+        });
+    }
+
+    if let Some(report_path) = report_path {
+        serde_json::to_writer_pretty(io::BufWriter::new(fs::File::create(&report_path)?), &report)?;
+        println!("Wrote report to {:#?}", report_path);
+    }
+
+    if failed_runs > 0 {
+        println!("{}", format!("\n{} runs failed validation", failed_runs).red().bold());
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+/// Checks each record of a hits file, without loading it into memory. The ToT field is checked
+/// against what the acquisition mode fills it with.
+fn validate_hits(data_file: &Path, acq_mode: AcqMode) -> io::Result<HitsValidation> {
+    let file_size = data_file.metadata()?.len();
+
+    let mut validation = HitsValidation {
+        acq_mode,
+        trailing_bytes: file_size % 16,
+        ..HitsValidation::default()
+    };
+
+    let mut prev_toa = 0;
+
+    for_each_hit(data_file, |i, hit| {
+        let offset = i * 16;
+
+        validation.hits += 1;
+
+        // Null records are cluster separators, which have no place in a hits file. In ToA only
+        // mode a hit on pixel (0, 0) at ToA 0 looks the same, so is counted here too.
+        if hit.col == 0 && hit.row == 0 && hit.toa == 0 && hit.tot == 0 {
+            validation.null_records.fail(offset);
+            return;
+        }
+
+        if hit.toa < prev_toa {
+            validation.non_monotonic_toa.fail(offset);
+        }
+
+        if hit.col > 255 || hit.row > 255 {
+            validation.invalid_coordinates.fail(offset);
+        }
+
+        let valid_tot = match acq_mode {
+            AcqMode::ToaTot => hit.tot > 0 && hit.tot <= MAX_TOT,
+            AcqMode::Toa => hit.tot == 0,              // Not filled
+            AcqMode::EventCount => hit.tot >> 24 == 0, // Integral ToT and event count, packed into 24 bits
+        };
+
+        if !valid_tot {
+            validation.invalid_tot.fail(offset);
+        }
+
+        prev_toa = hit.toa;
+    })?;
+
+    Ok(validation)
+}
+
+/// Calls `f` with the index and value of each whole hit in a file, viewing the memory-mapped file
+/// in place.
+#[cfg(all(feature = "zero-copy", target_endian = "little"))]
+fn for_each_hit<F: FnMut(u64, Hit)>(data_file: &Path, mut f: F) -> io::Result<()> {
+    let mapped_hits = MappedHits::open(data_file)?;
+
+    for (i, record) in mapped_hits.records().iter().enumerate() {
+        f(i as u64, Hit::from(*record));
+    }
+
+    Ok(())
+}
+
+/// Calls `f` with the index and value of each whole hit in a file, streaming it.
+#[cfg(not(all(feature = "zero-copy", target_endian = "little")))]
+fn for_each_hit<F: FnMut(u64, Hit)>(data_file: &Path, mut f: F) -> io::Result<()> {
+    use byteorder::{LittleEndian, ReadBytesExt};
+    use std::fs;
+    use std::io::prelude::*;
+
+    let file_size = data_file.metadata()?.len();
+    let mut rdr = io::BufReader::new(fs::File::open(data_file)?).take(file_size - file_size % 16);
+
+    for i in 0..(file_size / 16) {
+        let col = rdr.read_u16::<LittleEndian>()?;
+        let row = rdr.read_u16::<LittleEndian>()?;
+        let toa = rdr.read_u64::<LittleEndian>()?;
+        let (tot, flags) = decode_tot_field(rdr.read_u32::<LittleEndian>()?);
+
+        f(i, Hit { col, row, toa, tot, flags });
+    }
+
+    Ok(())
+}
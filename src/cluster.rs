@@ -0,0 +1,566 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/cluster.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+use std::convert::TryFrom;
+
+use bit_vec::BitVec;
+use indicatif::ProgressBar;
+use separator::Separatable as _;
+use serde::Serialize;
+
+use crate::{Hit, CLUSTER_FLAG_TRUNCATED};
+
+const HITS_BUFFER_SIZE: usize = 1_000_000;
+const REFILL_BLOCK_SIZE: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OversizePolicy {
+    Drop,
+    Truncate,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ClusterSettings {
+    pub min_cluster_hits: usize,
+    pub min_cluster_tot: u32,
+    pub max_pixel_gap: u32,
+    pub max_toa_gap: u32,
+    pub toa_window: u32,
+    pub max_cluster_hits: Option<usize>,
+    pub max_cluster_duration: Option<u32>,
+    pub oversize_policy: OversizePolicy,
+}
+
+/// Push-based clusterer for time-ordered hits arriving over time, e.g. from a live acquisition.
+///
+/// A cluster is seeded from the oldest unprocessed hit and completed as soon as a hit (or a call to
+/// `advance_to`) more than the ToA window past it has been seen, as no later hit can still join it.
+/// Clusters are therefore emitted with a latency bounded by the ToA window rather than only at the
+/// end of the input, and memory is bounded by the hit rate over the window. Hit cuts are not
+/// applied here, the input should already have been through a `HitFilter`.
+pub struct ClusterStream {
+    hits_buffer: VecDeque<Hit>,
+    hits_processed: VecDeque<bool>,
+    latest_toa: u64,
+
+    settings: ClusterSettings,
+
+    n_clusters: usize,
+    total_hits_processed: usize,
+}
+
+impl ClusterStream {
+    pub fn new(settings: &ClusterSettings) -> ClusterStream {
+        ClusterStream {
+            hits_buffer: VecDeque::with_capacity(HITS_BUFFER_SIZE),
+            hits_processed: VecDeque::with_capacity(HITS_BUFFER_SIZE),
+            latest_toa: 0,
+            settings: settings.clone(),
+            n_clusters: 0,
+            total_hits_processed: 0,
+        }
+    }
+
+    /// Adds the next hit, which must not be earlier than the hits already pushed
+    pub fn push(&mut self, hit: Hit) {
+        self.latest_toa = self.latest_toa.max(hit.toa);
+
+        self.hits_buffer.push_back(hit);
+        self.hits_processed.push_back(false);
+    }
+
+    /// Moves the stream time on to `toa` without a hit, so clusters are still completed while the
+    /// detector is quiet (e.g. from the timestamps of the incoming data)
+    pub fn advance_to(&mut self, toa: u64) {
+        self.latest_toa = self.latest_toa.max(toa);
+    }
+
+    /// Returns the next cluster whose ToA window has elapsed, or `None` until more hits arrive
+    pub fn next_completed(&mut self) -> Option<(Vec<Hit>, u8)> {
+        self.next_cluster(false)
+    }
+
+    /// Clusters all remaining hits, at the end of the input
+    pub fn flush(&mut self) -> Vec<(Vec<Hit>, u8)> {
+        let mut clusters = Vec::new();
+
+        while let Some(cluster) = self.next_cluster(true) {
+            clusters.push(cluster);
+        }
+
+        clusters
+    }
+
+    /// Number of hits waiting for their ToA window to elapse
+    pub fn buffered_hits(&self) -> usize {
+        self.hits_processed.iter().filter(|x| !**x).count()
+    }
+
+    pub fn clusters_found(&self) -> usize {
+        self.n_clusters
+    }
+
+    pub fn hits_processed(&self) -> usize {
+        self.total_hits_processed
+    }
+
+    fn next_cluster(&mut self, flush: bool) -> Option<(Vec<Hit>, u8)> {
+        loop {
+            while let Some(true) = self.hits_processed.front() {
+                self.hits_buffer.pop_front();
+                self.hits_processed.pop_front();
+            }
+
+            // Seed the cluster from the oldest unprocessed hit
+            let start_toa = self.hits_buffer.front()?.toa;
+
+            if !flush && self.latest_toa.saturating_sub(start_toa) <= u64::from(self.settings.toa_window) {
+                return None;
+            }
+
+            let mut cluster = Vec::with_capacity(self.settings.min_cluster_hits);
+            let mut flags = 0;
+
+            let mut hits_stack = VecDeque::<usize>::with_capacity(1000);
+            let mut is_in_stack = BitVec::from_elem(self.hits_buffer.len(), false);
+
+            // Add initial hit to the stack
+            hits_stack.push_back(0);
+
+            // Build a stack of neighbouring hits and iterate until all hits in
+            // the stack have been processed.
+            while !hits_stack.is_empty() {
+                let j = hits_stack.pop_front().unwrap();
+                is_in_stack.set(j, false);
+
+                let hit1 = self.hits_buffer[j];
+
+                cluster.push(hit1);
+
+                self.hits_processed[j] = true;
+                self.total_hits_processed += 1;
+
+                // Stop growing the cluster once it reaches the size cap, the
+                // remaining queued hits are consumed so they do not seed a new
+                // (equally oversized) cluster straight away
+                if let Some(max) = self.settings.max_cluster_hits {
+                    if cluster.len() >= max {
+                        flags |= CLUSTER_FLAG_TRUNCATED;
+
+                        while let Some(k) = hits_stack.pop_front() {
+                            self.hits_processed[k] = true;
+                            self.total_hits_processed += 1;
+                        }
+
+                        break;
+                    }
+                }
+
+                // Iterate over all hits (need to start from i and not j to allow
+                // algorithm to look back in time for complex geometries)
+                for k in 0..self.hits_buffer.len() {
+                    if self.hits_processed[k] || is_in_stack[k] {
+                        continue;
+                    }
+
+                    let hit2 = self.hits_buffer[k];
+
+                    // Check hit is within overall time window (ie. 400us drift)
+                    if hit2.toa.saturating_sub(start_toa) > u64::from(self.settings.toa_window) {
+                        break;
+                    }
+
+                    let toa_diff = (i64::try_from(hit2.toa).unwrap() - i64::try_from(hit1.toa).unwrap()).abs();
+                    let col_diff = (i64::from(hit1.col) - i64::from(hit2.col)).abs();
+                    let row_diff = (i64::from(hit1.row) - i64::from(hit2.row)).abs();
+
+                    // Check hit is spatially nearby
+                    if toa_diff >= i64::from(self.settings.max_toa_gap)
+                        || col_diff as u32 > self.settings.max_pixel_gap
+                        || row_diff as u32 > self.settings.max_pixel_gap
+                    {
+                        continue;
+                    }
+
+                    // Check hit is within the maximum cluster duration, only a neighbouring hit
+                    // past it means the cluster was cut short
+                    if let Some(max) = self.settings.max_cluster_duration {
+                        if hit2.toa.saturating_sub(start_toa) > u64::from(max) {
+                            flags |= CLUSTER_FLAG_TRUNCATED;
+                            continue;
+                        }
+                    }
+
+                    hits_stack.push_back(k);
+                    is_in_stack.set(k, true);
+                }
+            }
+
+            if cluster.len() < self.settings.min_cluster_hits {
+                continue;
+            }
+
+            let sum_tot: u32 = cluster.iter().map(|hit| hit.tot).sum();
+
+            if sum_tot < self.settings.min_cluster_tot {
+                continue;
+            }
+
+            if flags & CLUSTER_FLAG_TRUNCATED != 0 && self.settings.oversize_policy == OversizePolicy::Drop {
+                continue;
+            }
+
+            cluster.sort();
+
+            self.n_clusters += 1;
+
+            return Some((cluster, flags));
+        }
+    }
+}
+
+/// Streaming clusterer over a time-ordered hits iterator.
+///
+/// Hits are read in blocks into a `ClusterStream` until the oldest unprocessed hit's ToA window has
+/// elapsed (or the input has run out), so every hit that could join its cluster is available.
+pub struct FindClusterIterator<'a, I: Iterator<Item = Hit>> {
+    run_name: &'a str,
+    hits_iterator: &'a mut I,
+    stream: ClusterStream,
+    input_exhausted: bool,
+
+    progress_bar: &'a ProgressBar,
+}
+
+impl<'a, I: Iterator<Item = Hit>> FindClusterIterator<'a, I> {
+    pub fn new(run_name: &'a str, hits_iterator: &'a mut I, progress_bar: &'a ProgressBar, settings: &ClusterSettings) -> FindClusterIterator<'a, I> {
+        FindClusterIterator {
+            run_name,
+            hits_iterator,
+            stream: ClusterStream::new(settings),
+            input_exhausted: false,
+            progress_bar,
+        }
+    }
+
+    /// Reads a block of hits into the stream
+    fn refill(&mut self) {
+        for _ in 0..REFILL_BLOCK_SIZE {
+            match self.hits_iterator.next() {
+                Some(hit) => self.stream.push(hit),
+                None => {
+                    self.input_exhausted = true;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+impl<'a, I: Iterator<Item = Hit>> Iterator for FindClusterIterator<'a, I> {
+    type Item = (Vec<Hit>, u8);
+
+    fn next(&mut self) -> Option<(Vec<Hit>, u8)> {
+        loop {
+            // Once all input has been read the remaining hits are clustered without waiting
+            if let Some(cluster) = self.stream.next_cluster(self.input_exhausted) {
+                self.progress_bar.set_position(u64::try_from(self.stream.hits_processed()).unwrap());
+                self.progress_bar.set_message(&format!(
+                    "| {} Clusters Found | {}",
+                    self.stream.clusters_found().separated_string(),
+                    self.run_name
+                ));
+
+                return Some(cluster);
+            }
+
+            if self.input_exhausted {
+                return None;
+            }
+
+            self.refill();
+        }
+    }
+}
+
+/// Flattens the hits stored in a cluster/event file back into a single ToA-ordered hit stream.
+///
+/// Trigger windows can overlap and need not be written in order of their start (eg. per-trigger
+/// look-behinds), so each cluster is given the earliest ToA its hits can have (its first hit or
+/// window start). Buffered hits are only released once they are earlier than every cluster still
+/// to be read, which keeps the output in order. Exact duplicates (from overlapping trigger
+/// windows) are only emitted once.
+pub struct FlattenClustersIterator<I: Iterator<Item = Vec<Hit>>> {
+    clusters_iterator: I,
+    later_starts: Vec<u64>, // Earliest start of the clusters from each index on
+    clusters_read: usize,
+    hits_heap: BinaryHeap<Reverse<Hit>>,
+    watermark: u64,
+    exhausted: bool,
+    last_emitted: Vec<Hit>,
+}
+
+impl<I: Iterator<Item = Vec<Hit>>> FlattenClustersIterator<I> {
+    /// `starts` holds the earliest ToA of each cluster in file order. Hits of clusters past the
+    /// end of `starts` are held back until the input has run out.
+    pub fn new(clusters_iterator: I, starts: &[u64]) -> FlattenClustersIterator<I> {
+        let mut later_starts = starts.to_vec();
+
+        for i in (1..later_starts.len()).rev() {
+            later_starts[i - 1] = later_starts[i - 1].min(later_starts[i]);
+        }
+
+        FlattenClustersIterator {
+            clusters_iterator,
+            later_starts,
+            clusters_read: 0,
+            hits_heap: BinaryHeap::new(),
+            watermark: 0,
+            exhausted: false,
+            last_emitted: Vec::new(),
+        }
+    }
+}
+
+impl<I: Iterator<Item = Vec<Hit>>> Iterator for FlattenClustersIterator<I> {
+    type Item = Hit;
+
+    fn next(&mut self) -> Option<Hit> {
+        loop {
+            let can_emit = match self.hits_heap.peek() {
+                Some(Reverse(hit)) => self.exhausted || hit.toa < self.watermark,
+                None if self.exhausted => return None,
+                None => false,
+            };
+
+            if can_emit {
+                let Reverse(hit) = self.hits_heap.pop().unwrap();
+
+                if self.last_emitted.first().map_or(false, |x| x.toa != hit.toa) {
+                    self.last_emitted.clear();
+                }
+
+                if self.last_emitted.iter().any(|x| x.col == hit.col && x.row == hit.row && x.tot == hit.tot) {
+                    continue;
+                }
+
+                self.last_emitted.push(hit);
+
+                return Some(hit);
+            }
+
+            match self.clusters_iterator.next() {
+                Some(cluster) => {
+                    self.clusters_read += 1;
+
+                    // The running minimum never decreases, so neither does the watermark
+                    if let Some(&start) = self.later_starts.get(self.clusters_read) {
+                        self.watermark = start;
+                    }
+
+                    self.hits_heap.extend(cluster.into_iter().map(Reverse));
+                }
+                None => self.exhausted = true,
+            }
+        }
+    }
+}
+
+/// Counts the separate groups of hits in time (time islands) in a time-ordered set of hits, where
+/// a new island starts after a gap of more than `max_toa_gap` clock ticks. Islands with fewer than
+/// `min_island_hits` hits (ie. stray noise hits) are not counted.
+pub fn count_time_islands(hits: &[Hit], max_toa_gap: u64, min_island_hits: usize) -> usize {
+    let min_island_hits = min_island_hits.max(1);
+
+    let mut islands = 0;
+    let mut island_hits = 0;
+    let mut last_toa = None;
+
+    for hit in hits {
+        if let Some(last_toa) = last_toa {
+            if hit.toa.saturating_sub(last_toa) > max_toa_gap {
+                if island_hits >= min_island_hits {
+                    islands += 1;
+                }
+
+                island_hits = 0;
+            }
+        }
+
+        island_hits += 1;
+        last_toa = Some(hit.toa);
+    }
+
+    if island_hits >= min_island_hits {
+        islands += 1;
+    }
+
+    islands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::HitFlags;
+
+    fn hit(toa: u64, col: u16, row: u16) -> Hit {
+        Hit {
+            toa,
+            tot: 100,
+            col,
+            row,
+            flags: HitFlags::default(),
+        }
+    }
+
+    fn settings(oversize_policy: OversizePolicy) -> ClusterSettings {
+        ClusterSettings {
+            min_cluster_hits: 1,
+            min_cluster_tot: 1,
+            max_pixel_gap: 1,
+            max_toa_gap: 10,
+            toa_window: 50,
+            max_cluster_hits: None,
+            max_cluster_duration: None,
+            oversize_policy,
+        }
+    }
+
+    /// Pixels of a cluster's hits (hits compare equal by ToA alone)
+    fn pixels(cluster: &[Hit]) -> Vec<(u16, u16)> {
+        cluster.iter().map(|hit| (hit.col, hit.row)).collect()
+    }
+
+    /// Sizes and flags of the clusters found in a set of hits
+    fn cluster_sizes(hits: &[Hit], settings: &ClusterSettings) -> Vec<(usize, u8)> {
+        let mut stream = ClusterStream::new(settings);
+
+        for hit in hits {
+            stream.push(*hit);
+        }
+
+        stream.flush().iter().map(|(cluster, flags)| (cluster.len(), *flags)).collect()
+    }
+
+    #[test]
+    fn separate_clusters_at_the_same_time() {
+        let hits = vec![hit(100, 10, 10), hit(100, 100, 100), hit(101, 11, 10), hit(101, 101, 100)];
+
+        let mut stream = ClusterStream::new(&settings(OversizePolicy::Truncate));
+
+        for hit in &hits {
+            stream.push(*hit);
+        }
+
+        let clusters = stream.flush();
+
+        assert_eq!(clusters.len(), 2);
+        assert_eq!(pixels(&clusters[0].0), vec![(10, 10), (11, 10)]);
+        assert_eq!(pixels(&clusters[1].0), vec![(100, 100), (101, 100)]);
+    }
+
+    #[test]
+    fn cluster_across_refill_block_boundary() {
+        // Isolated hits, with a four hit cluster whose hits straddle the end of the first block
+        let mut hits: Vec<Hit> = (0..REFILL_BLOCK_SIZE as u64 - 2).map(|i| hit(i * 100, (i % 200) as u16, 0)).collect();
+        let cluster_toa = hits.last().unwrap().toa + 100;
+
+        hits.extend((0..4).map(|i| hit(cluster_toa + i, 50 + i as u16, 50)));
+        hits.extend((1..10).map(|i| hit(cluster_toa + i * 100, 0, 0)));
+
+        let mut hits_iterator = hits.into_iter();
+        let progress_bar = ProgressBar::hidden();
+        let settings = settings(OversizePolicy::Truncate);
+
+        let clusters: Vec<(Vec<Hit>, u8)> = FindClusterIterator::new("test", &mut hits_iterator, &progress_bar, &settings).collect();
+
+        assert_eq!(clusters.len(), REFILL_BLOCK_SIZE - 2 + 1 + 9);
+        assert_eq!(clusters.iter().filter(|(cluster, _)| cluster.len() == 4).count(), 1);
+        assert!(clusters.iter().all(|(cluster, _)| cluster.len() == 1 || cluster.len() == 4));
+    }
+
+    #[test]
+    fn max_cluster_hits_truncates_or_drops() {
+        let hits: Vec<Hit> = (0..5).map(|i| hit(i, i as u16, 0)).collect();
+
+        let mut truncate = settings(OversizePolicy::Truncate);
+        truncate.max_cluster_hits = Some(3);
+
+        let mut drop = settings(OversizePolicy::Drop);
+        drop.max_cluster_hits = Some(3);
+
+        // The hits past the cap seed a cluster of their own
+        assert_eq!(cluster_sizes(&hits, &truncate), vec![(3, CLUSTER_FLAG_TRUNCATED), (2, 0)]);
+        assert_eq!(cluster_sizes(&hits, &drop), vec![(2, 0)]);
+    }
+
+    #[test]
+    fn max_cluster_duration_truncates_or_drops() {
+        let hits: Vec<Hit> = (0..4).map(|i| hit(i * 5, i as u16, 0)).collect();
+
+        let mut truncate = settings(OversizePolicy::Truncate);
+        truncate.max_cluster_duration = Some(8);
+
+        let mut drop = settings(OversizePolicy::Drop);
+        drop.max_cluster_duration = Some(8);
+
+        assert_eq!(cluster_sizes(&hits, &truncate), vec![(2, CLUSTER_FLAG_TRUNCATED), (2, 0)]);
+        assert_eq!(cluster_sizes(&hits, &drop), vec![(2, 0)]);
+    }
+
+    #[test]
+    fn next_completed_waits_for_toa_window() {
+        let mut settings = settings(OversizePolicy::Truncate);
+        settings.toa_window = 100;
+
+        let mut stream = ClusterStream::new(&settings);
+
+        stream.push(hit(0, 10, 10));
+        assert!(stream.next_completed().is_none());
+
+        // A hit exactly the ToA window later could still have joined the cluster
+        stream.push(hit(100, 200, 200));
+        assert!(stream.next_completed().is_none());
+
+        stream.advance_to(101);
+        assert_eq!(stream.next_completed().map(|(cluster, _)| pixels(&cluster)), Some(vec![(10, 10)]));
+        assert!(stream.next_completed().is_none());
+        assert_eq!(stream.buffered_hits(), 1);
+    }
+
+    #[test]
+    fn flatten_orders_overlapping_and_out_of_order_windows() {
+        // Windows (start, hits) in file order: the third starts before the first two and overlaps
+        // them, repeating one of the first window's hits
+        let windows = vec![
+            (100, vec![hit(120, 1, 1), hit(150, 2, 2), hit(200, 3, 3)]),
+            (125, vec![hit(130, 4, 4), hit(160, 5, 5)]),
+            (90, vec![hit(110, 6, 6), hit(150, 2, 2), hit(170, 7, 7)]),
+            (250, vec![hit(260, 8, 8)]),
+        ];
+
+        let starts: Vec<u64> = windows.iter().map(|(start, _)| *start).collect();
+        let flattened: Vec<u64> = FlattenClustersIterator::new(windows.into_iter().map(|(_, hits)| hits), &starts)
+            .map(|hit| hit.toa)
+            .collect();
+
+        assert_eq!(flattened, vec![110, 120, 130, 150, 160, 170, 200, 260]);
+    }
+
+    #[test]
+    fn flatten_holds_hits_of_clusters_without_a_start() {
+        let clusters = vec![vec![hit(50, 1, 1)], vec![hit(10, 2, 2)]];
+
+        let flattened: Vec<u64> = FlattenClustersIterator::new(clusters.into_iter(), &[40]).map(|hit| hit.toa).collect();
+
+        assert_eq!(flattened, vec![10, 50]);
+    }
+}
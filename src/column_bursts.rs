@@ -0,0 +1,145 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/column_bursts.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Hit, TOA_CLOCK_TO_NS};
+
+/// Period of time in which a column had far more hits than the other columns (times in ns)
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct ColumnBurst {
+    pub col: u16,
+    pub start: f64,
+    pub end: f64,
+    pub hits: u32,
+}
+
+/// Finds noisy column bursts by counting the hits of each column in fixed time slices. A column is
+/// bursting in a slice if it has at least `min_hits` and more than `threshold` times the median
+/// column's hits. Hits must be passed in ToA order.
+#[derive(Clone, Debug)]
+pub struct ColumnBurstDetector {
+    slice_width: u64,
+    threshold: f64,
+    min_hits: u32,
+    slice_start: Option<u64>,
+    counts: Vec<u32>,
+    pub slices: usize,
+    pub bursts: Vec<ColumnBurst>,
+}
+
+impl ColumnBurstDetector {
+    /// `slice_width` is in ToA units (1.5625 ns)
+    pub fn new(slice_width: u64, threshold: f64, min_hits: u32) -> ColumnBurstDetector {
+        ColumnBurstDetector {
+            slice_width: slice_width.max(1),
+            threshold,
+            min_hits,
+            slice_start: None,
+            counts: vec![0; 256],
+            slices: 0,
+            bursts: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, hit: &Hit) {
+        let slice_start = hit.toa - hit.toa % self.slice_width;
+
+        match self.slice_start {
+            Some(start) if start == slice_start => {}
+            Some(_) => {
+                self.finish_slice();
+                self.slice_start = Some(slice_start);
+            }
+            None => self.slice_start = Some(slice_start),
+        }
+
+        if hit.col < 256 {
+            self.counts[hit.col as usize] += 1;
+        }
+    }
+
+    /// Checks the last (partial) slice, call once all hits have been added
+    pub fn finish(&mut self) {
+        self.finish_slice();
+        self.slice_start = None;
+    }
+
+    fn finish_slice(&mut self) {
+        let start = match self.slice_start {
+            Some(start) => start,
+            None => return,
+        };
+
+        let mut sorted = self.counts.clone();
+        sorted.sort();
+
+        let median = f64::from(sorted[sorted.len() / 2]).max(1.0);
+
+        for (col, hits) in self.counts.iter().enumerate() {
+            if *hits < self.min_hits || f64::from(*hits) <= self.threshold * median {
+                continue;
+            }
+
+            let start = start as f64 * TOA_CLOCK_TO_NS;
+            let end = start + self.slice_width as f64 * TOA_CLOCK_TO_NS;
+
+            // Merge with a burst in the previous slice of the same column
+            let previous = self.bursts.iter_mut().rev().find(|burst| burst.col == col as u16 && burst.end >= start);
+
+            match previous {
+                Some(burst) => {
+                    burst.end = end;
+                    burst.hits += hits;
+                }
+                None => self.bursts.push(ColumnBurst {
+                    col: col as u16,
+                    start,
+                    end,
+                    hits: *hits,
+                }),
+            }
+        }
+
+        self.slices += 1;
+
+        for count in self.counts.iter_mut() {
+            *count = 0;
+        }
+    }
+}
+
+/// Masks hits from columns during their bursts
+#[derive(Clone, Debug)]
+pub struct ColumnBurstMask {
+    intervals: Vec<Vec<(f64, f64)>>,
+}
+
+impl ColumnBurstMask {
+    pub fn new(bursts: &[ColumnBurst]) -> ColumnBurstMask {
+        let mut intervals = vec![Vec::new(); 256];
+
+        for burst in bursts {
+            if burst.col < 256 {
+                intervals[burst.col as usize].push((burst.start, burst.end));
+            }
+        }
+
+        ColumnBurstMask { intervals }
+    }
+
+    pub fn is_masked(&self, hit: &Hit) -> bool {
+        if hit.col > 255 {
+            return false;
+        }
+
+        let time = hit.toa as f64 * TOA_CLOCK_TO_NS;
+
+        self.intervals[hit.col as usize].iter().any(|(start, end)| time >= *start && time < *end)
+    }
+}
@@ -0,0 +1,63 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/dataframe.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use polars::prelude::*;
+
+use crate::{Hit, HitSoA, RunStartTime, TOA_CLOCK_TO_NS};
+
+/// One row per hit with `col`, `row`, `toa` (clock units), `tot` (ns) and `flags` (`HitFlags`
+/// bits) columns
+pub fn hits_to_dataframe(hits: &[Hit]) -> PolarsResult<DataFrame> {
+    let hits = HitSoA::from(hits);
+
+    DataFrame::new(hit_columns(hits))
+}
+
+/// One row per hit of each cluster/event, numbered from 0 in a leading `cluster` column, followed
+/// by the same columns as `hits_to_dataframe`
+pub fn clusters_to_dataframe(clusters: &[Vec<Hit>]) -> PolarsResult<DataFrame> {
+    let total_hits = clusters.iter().map(|x| x.len()).sum();
+
+    let mut cluster_ids = Vec::with_capacity(total_hits);
+    let mut hits = HitSoA::with_capacity(total_hits);
+
+    for (i, cluster) in clusters.iter().enumerate() {
+        cluster_ids.extend(std::iter::repeat_n(i as u64, cluster.len()));
+        hits.extend(cluster.iter().cloned());
+    }
+
+    let mut columns = vec![Column::new("cluster".into(), cluster_ids)];
+    columns.extend(hit_columns(hits));
+
+    DataFrame::new(columns)
+}
+
+fn hit_columns(hits: HitSoA) -> Vec<Column> {
+    vec![
+        Column::new("col".into(), hits.col),
+        Column::new("row".into(), hits.row),
+        Column::new("toa".into(), hits.toa),
+        Column::new("tot".into(), hits.tot),
+        Column::new("flags".into(), hits.flags.iter().map(|x| x.0).collect::<Vec<_>>()),
+    ]
+}
+
+/// Adds a `utc_ns` column with the UTC time of each hit (ns since the Unix epoch) to a dataframe
+/// with a `toa` column, for joining with other time series
+pub fn add_utc_column(df: &mut DataFrame, start_time: &RunStartTime) -> PolarsResult<()> {
+    let utc_ns: Vec<i64> = df
+        .column("toa")?
+        .u64()?
+        .into_iter()
+        .map(|toa| start_time.utc_ns(toa.unwrap_or(0) as f64 * TOA_CLOCK_TO_NS))
+        .collect();
+
+    df.with_column(Column::new("utc_ns".into(), utc_ns))?;
+
+    Ok(())
+}
@@ -0,0 +1,339 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/dataset.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use csv;
+use serde_json;
+use toml;
+
+use crate::{
+    empty_events_path, has_partial_marker, read_empty_events, read_gate_data, read_hits_time_range, read_run_summary, read_trigger_data,
+    read_trigger_records, AcqMode, ClusterMetadata, Gate, Hit, ReadClusterIterator, ReadHitsIterator, ReadSignedClusterIterator, RunStartTime,
+    Trigger, WithEmptyEvents, DEFAULT_READ_BUFFER_SIZE, RUN_START_TIME_SECTION,
+};
+
+/// An output directory of `raw_data_parser`, holding one directory per run
+pub struct Dataset {
+    root: PathBuf,
+}
+
+impl Dataset {
+    pub fn open(root: &Path) -> io::Result<Dataset> {
+        if !root.is_dir() {
+            return Err(not_found(root));
+        }
+
+        Ok(Dataset { root: root.to_owned() })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Runs of the dataset in name order, which is start time order for the `raw_data_parser`
+    /// directory names. Directories without a `hits.bin` (or a `hits.toml`, for hits files renamed
+    /// with `--hits-filename`) are not runs.
+    pub fn runs(&self) -> io::Result<Vec<Run>> {
+        let mut run_dirs = Vec::new();
+
+        for entry in fs::read_dir(&self.root)? {
+            let path = entry?.path();
+
+            if path.join("hits.bin").is_file() || path.join("hits.toml").is_file() {
+                run_dirs.push(path);
+            }
+        }
+
+        run_dirs.sort();
+
+        run_dirs.iter().map(|run_dir| Run::open(run_dir)).collect()
+    }
+
+    pub fn run(&self, name: &str) -> io::Result<Run> {
+        Run::open(&self.root.join(name))
+    }
+}
+
+/// A run output directory. Nothing is read until it is asked for, and each data product is
+/// looked for under the names and formats the tools have written it in.
+pub struct Run {
+    dir: PathBuf,
+    name: String,
+}
+
+impl Run {
+    pub fn open(dir: &Path) -> io::Result<Run> {
+        if !dir.is_dir() {
+            return Err(not_found(dir));
+        }
+
+        Ok(Run {
+            dir: dir.to_owned(),
+            name: dir.file_name().unwrap().to_string_lossy().into_owned(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Path of the hits file, `hits.bin` unless it was renamed with `--hits-filename`
+    pub fn hits_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.bin", self.product_name("hits_filename", "hits")))
+    }
+
+    /// Number of hits, from the size of the hits file
+    pub fn hit_count(&self) -> io::Result<u64> {
+        Ok(self.existing(self.hits_path())?.metadata()?.len() / 16)
+    }
+
+    /// ToA of the first and last hits (clock units), `None` if there are no hits
+    pub fn hits_time_range(&self) -> io::Result<Option<(u64, u64)>> {
+        read_hits_time_range(&self.existing(self.hits_path())?)
+    }
+
+    pub fn hits(&self) -> io::Result<ReadHitsIterator> {
+        Ok(ReadHitsIterator::new(&self.existing(self.hits_path())?))
+    }
+
+    /// Triggers from `triggers.bin`, or `triggers.csv` for runs parsed before it was written,
+    /// under the name given with `--triggers-filename` if there was one. Empty for runs without
+    /// triggers.
+    pub fn triggers(&self) -> io::Result<Vec<Trigger>> {
+        let bin_file_path = self.triggers_path("bin");
+        let csv_file_path = self.triggers_path("csv");
+
+        if bin_file_path.exists() {
+            Ok(read_trigger_records(&bin_file_path)?.into_iter().map(Trigger::from).collect())
+        } else if csv_file_path.exists() {
+            read_trigger_data(&csv_file_path)
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Path of the triggers file with the given extension (`bin` or `csv`), `triggers.bin`/
+    /// `triggers.csv` unless renamed with `--triggers-filename`
+    pub fn triggers_path(&self, extension: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", self.product_name("triggers_filename", "triggers"), extension))
+    }
+
+    /// Gate intervals from `gates.csv`, empty for runs without gates
+    pub fn gates(&self) -> io::Result<Vec<Gate>> {
+        let gates_file_path = self.dir.join("gates.csv");
+
+        if gates_file_path.exists() {
+            read_gate_data(&gates_file_path)
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Names of the cluster/event files in the run (e.g. `clusters`, `trigger_events`)
+    pub fn cluster_files(&self) -> io::Result<Vec<String>> {
+        let mut names = Vec::new();
+
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+
+            if path.extension().map_or(false, |x| x == "bin") && path.with_extension("csv").exists() {
+                names.push(path.file_stem().unwrap().to_string_lossy().into_owned());
+            }
+        }
+
+        names.sort();
+
+        Ok(names)
+    }
+
+    pub fn clusters(&self, name: &str) -> io::Result<ReadClusterIterator> {
+        self.clusters_with_buffer_size(name, DEFAULT_READ_BUFFER_SIZE)
+    }
+
+    /// Clusters/events of a file read in blocks of `buffer_size` bytes, an error for files with
+    /// signed ToA values
+    pub fn clusters_with_buffer_size(&self, name: &str, buffer_size: usize) -> io::Result<ReadClusterIterator> {
+        let data_file_path = self.existing(self.dir.join(format!("{}.bin", name)))?;
+
+        if self.has_signed_toa(name)? {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} has signed ToA values (from --relative-to trigger), read it with signed_clusters or relative_clusters",
+                    data_file_path.display()
+                ),
+            ));
+        }
+
+        Ok(ReadClusterIterator::with_buffer_size(data_file_path.to_str().unwrap(), buffer_size))
+    }
+
+    /// Clusters/events of a file written with ToA values relative to a reference time, eg. trigger
+    /// events extracted with `--relative-to trigger`
+    pub fn signed_clusters(&self, name: &str) -> io::Result<ReadSignedClusterIterator> {
+        let data_file_path = self.existing(self.dir.join(format!("{}.bin", name)))?;
+
+        Ok(ReadSignedClusterIterator::new(data_file_path.to_str().unwrap()))
+    }
+
+    /// Clusters/events of a file with the ToA of each hit counted from the earliest hit of its
+    /// cluster, along with the ToA of that hit (clock units, negative before the reference time of
+    /// a file with signed ToA values). Reads files with or without signed ToA values, for tools
+    /// that only need the times within a cluster.
+    pub fn relative_clusters(&self, name: &str) -> io::Result<Box<dyn Iterator<Item = (i64, Vec<Hit>)>>> {
+        if self.has_signed_toa(name)? {
+            return Ok(Box::new(self.signed_clusters(name)?.map(|cluster| {
+                let start_toa = cluster.iter().map(|hit| hit.toa).min().unwrap_or(0);
+
+                let hits = cluster
+                    .iter()
+                    .map(|hit| Hit {
+                        toa: (hit.toa - start_toa) as u64,
+                        tot: hit.tot,
+                        col: hit.col,
+                        row: hit.row,
+                        flags: hit.flags,
+                    })
+                    .collect();
+
+                (start_toa, hits)
+            })));
+        }
+
+        Ok(Box::new(self.clusters(name)?.map(|mut cluster| {
+            let start_toa = cluster.iter().map(|hit| hit.toa).min().unwrap_or(0);
+
+            for hit in &mut cluster {
+                hit.toa -= start_toa;
+            }
+
+            (start_toa as i64, cluster)
+        })))
+    }
+
+    /// Whether a cluster/event file was written with signed ToA values, to be read with
+    /// `signed_clusters`
+    pub fn has_signed_toa(&self, name: &str) -> io::Result<bool> {
+        Ok(self
+            .settings(name)?
+            .map_or(false, |x| x.get("relative_to").and_then(|x| x.as_str()) == Some("trigger")))
+    }
+
+    /// Metadata of each cluster/event, with the columns added since older files were written
+    /// left at their defaults
+    pub fn cluster_metadata(&self, name: &str) -> io::Result<Vec<ClusterMetadata>> {
+        let mut rdr = csv::Reader::from_path(self.existing(self.dir.join(format!("{}.csv", name)))?)?;
+        let mut metadata = Vec::new();
+
+        for result in rdr.deserialize() {
+            metadata.push(result?);
+        }
+
+        Ok(metadata)
+    }
+
+    /// Events of a cluster/event file with their event numbers, including the empty events left
+    /// out of the file by `trigger_extraction_tool --compact-empty`
+    pub fn events(&self, name: &str) -> io::Result<impl Iterator<Item = (usize, Vec<Hit>)>> {
+        let event_numbers: Vec<usize> = self.cluster_metadata(name)?.iter().map(|x| x.event).collect();
+
+        let empty_events_file_path = empty_events_path(&self.dir.join(format!("{}.bin", name)));
+
+        let empty_events = if empty_events_file_path.exists() {
+            read_empty_events(&empty_events_file_path)?
+        } else {
+            Vec::new()
+        };
+
+        Ok(WithEmptyEvents::new(event_numbers.into_iter().zip(self.clusters(name)?), empty_events))
+    }
+
+    /// Number of hits in a cluster/event file, from its metadata
+    pub fn cluster_hit_count(&self, name: &str) -> io::Result<u64> {
+        Ok(self.cluster_metadata(name)?.iter().map(|x| x.hits as u64).sum())
+    }
+
+    /// Settings and provenance a data product was made with (`hits` for the output of
+    /// `raw_data_parser`), `None` for files written before settings were recorded
+    pub fn settings(&self, name: &str) -> io::Result<Option<toml::Value>> {
+        let toml_file_path = self.dir.join(format!("{}.toml", name));
+
+        if !toml_file_path.exists() {
+            return Ok(None);
+        }
+
+        let settings = fs::read_to_string(toml_file_path)?
+            .parse::<toml::Value>()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        Ok(Some(settings))
+    }
+
+    /// Version of the tools that made a data product, from the provenance in its settings
+    pub fn tool_version(&self, name: &str) -> io::Result<Option<String>> {
+        Ok(self
+            .settings(name)?
+            .and_then(|x| x.get("provenance")?.get("crate_version")?.as_str().map(|x| x.to_owned())))
+    }
+
+    /// Whether a data product (`hits`, `clusters`, ...) was left unfinished by an interrupted run
+    pub fn is_partial(&self, name: &str) -> bool {
+        has_partial_marker(&self.dir.join(format!("{}.bin", name)))
+    }
+
+    pub fn summary(&self) -> io::Result<serde_json::Value> {
+        read_run_summary(&self.dir)
+    }
+
+    /// Start time of the run, both as in the raw file names and in UTC, for absolute timestamps.
+    /// `None` for runs parsed before it was recorded.
+    pub fn start_time(&self) -> io::Result<Option<RunStartTime>> {
+        match self.summary()?.get(RUN_START_TIME_SECTION) {
+            Some(start_time) => Ok(Some(serde_json::from_value(start_time.clone())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Acquisition mode the hits were recorded in, which sets what their ToT field holds. ToA and
+    /// ToT for runs parsed before it was recorded.
+    pub fn acq_mode(&self) -> io::Result<AcqMode> {
+        match self.summary()?.get("acq_mode") {
+            Some(acq_mode) => Ok(serde_json::from_value(acq_mode.clone())?),
+            None => Ok(AcqMode::ToaTot),
+        }
+    }
+
+    /// File name (without the extension) of a product of `raw_data_parser`, as recorded in
+    /// `hits.toml`, or its default name for runs parsed before it could be changed
+    fn product_name(&self, setting: &str, default: &str) -> String {
+        self.settings("hits")
+            .ok()
+            .flatten()
+            .and_then(|x| x.get(setting)?.as_str().map(|x| x.to_owned()))
+            .unwrap_or_else(|| default.to_owned())
+    }
+
+    fn existing(&self, path: PathBuf) -> io::Result<PathBuf> {
+        if path.exists() {
+            Ok(path)
+        } else {
+            Err(not_found(&path))
+        }
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{} does not exist", path.display()))
+}
@@ -0,0 +1,52 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/dedup.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use serde::Serialize;
+
+use crate::Hit;
+
+/// Removes retransmitted hits: a hit on the same pixel as the previous hit on that pixel, with a
+/// ToA within `tolerance` (1.5625 ns units). Hits must be passed in ToA order.
+#[derive(Clone, Debug, Serialize)]
+pub struct HitDeduplicator {
+    #[serde(skip)]
+    last_toa: Vec<Option<u64>>,
+    pub tolerance: u64,
+    pub duplicates_removed: usize,
+}
+
+impl HitDeduplicator {
+    pub fn new(tolerance: u64) -> HitDeduplicator {
+        HitDeduplicator {
+            last_toa: vec![None; 256 * 256],
+            tolerance,
+            duplicates_removed: 0,
+        }
+    }
+
+    pub fn is_duplicate(&mut self, hit: &Hit) -> bool {
+        if hit.col > 255 || hit.row > 255 {
+            return false;
+        }
+
+        let index = usize::from(hit.col) * 256 + usize::from(hit.row);
+
+        let duplicate = match self.last_toa[index] {
+            Some(last_toa) => hit.toa >= last_toa && hit.toa - last_toa <= self.tolerance,
+            None => false,
+        };
+
+        if duplicate {
+            self.duplicates_removed += 1;
+        } else {
+            self.last_toa[index] = Some(hit.toa);
+        }
+
+        duplicate
+    }
+}
@@ -0,0 +1,157 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/disk_space.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use indicatif::ProgressBar;
+
+use crate::{interrupted_error, is_interrupted};
+
+const CHECK_INTERVAL: usize = 1_000; // Calls to `DiskSpaceMonitor::check` between looking at the clock
+const QUERY_INTERVAL: Duration = Duration::from_secs(5);
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How much space to keep free on the output filesystem and how long to wait for space to be
+/// freed before giving up on a run
+#[derive(Clone, Copy, Debug)]
+pub struct DiskSpaceLimits {
+    pub min_free: u64,       // bytes
+    pub max_pause: Duration, // Time to wait for space to be freed before aborting a run
+}
+
+impl Default for DiskSpaceLimits {
+    fn default() -> Self {
+        DiskSpaceLimits {
+            min_free: 1_000_000_000,
+            max_pause: Duration::from_secs(600),
+        }
+    }
+}
+
+/// Space available to unprivileged users on the filesystem holding `path`, which need not exist
+/// yet (the nearest existing ancestor is used)
+pub fn available_space(path: &Path) -> io::Result<u64> {
+    let existing = path.ancestors().find(|x| x.exists()).unwrap_or_else(|| Path::new("."));
+
+    fs2::available_space(existing)
+}
+
+/// Checks before starting that the output filesystem has room for `estimated_size` bytes of output
+/// while keeping the minimum free space.
+pub fn check_free_space(output_path: &Path, estimated_size: u64, limits: &DiskSpaceLimits) -> io::Result<()> {
+    let available = available_space(output_path)?;
+
+    if available < estimated_size.saturating_add(limits.min_free) {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!(
+                "Not enough disk space: output is estimated at {} but only {} is free on {} (keeping {} free)",
+                format_bytes(estimated_size),
+                format_bytes(available),
+                output_path.display(),
+                format_bytes(limits.min_free)
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Watches the free space on the output filesystem while a run is processed. When it drops below
+/// the minimum, processing is paused until space is freed, and the run aborted if it is not.
+pub struct DiskSpaceMonitor<'a> {
+    path: &'a Path,
+    limits: DiskSpaceLimits,
+    calls: usize,
+    last_query: Instant,
+}
+
+impl<'a> DiskSpaceMonitor<'a> {
+    pub fn new(path: &'a Path, limits: DiskSpaceLimits) -> DiskSpaceMonitor<'a> {
+        DiskSpaceMonitor {
+            path,
+            limits,
+            calls: 0,
+            last_query: Instant::now(),
+        }
+    }
+
+    /// Cheap enough to call per hit/packet, the filesystem is only queried every `QUERY_INTERVAL`.
+    /// Returns an error once the run should stop.
+    pub fn check(&mut self, progress_bar: &ProgressBar) -> io::Result<()> {
+        self.calls += 1;
+
+        if self.calls < CHECK_INTERVAL {
+            return Ok(());
+        }
+
+        self.calls = 0;
+
+        if self.last_query.elapsed() < QUERY_INTERVAL {
+            return Ok(());
+        }
+
+        self.last_query = Instant::now();
+
+        let mut available = available_space(self.path)?;
+
+        if available >= self.limits.min_free {
+            return Ok(());
+        }
+
+        let pause_start = Instant::now();
+
+        while available < self.limits.min_free {
+            if pause_start.elapsed() >= self.limits.max_pause {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!(
+                        "Out of disk space: {} free on {}, below the minimum of {}",
+                        format_bytes(available),
+                        self.path.display(),
+                        format_bytes(self.limits.min_free)
+                    ),
+                ));
+            }
+
+            if is_interrupted() {
+                return Err(interrupted_error());
+            }
+
+            progress_bar.set_message(&format!(
+                "| Paused, low disk space | {} free on {} | waiting for {}",
+                format_bytes(available),
+                self.path.display(),
+                format_bytes(self.limits.min_free)
+            ));
+
+            thread::sleep(PAUSE_POLL_INTERVAL);
+
+            available = available_space(self.path)?;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+
+    while value >= 1000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", value, UNITS[unit])
+}
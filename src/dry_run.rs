@@ -0,0 +1,332 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/dry_run.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use colored::*;
+use separator::Separatable as _;
+use serde::{Deserialize, Serialize};
+
+use crate::{available_space, format_bytes, read_run_summary, update_run_summary};
+
+/// Section of a run's `summary.json` recording, per tool, how much it read and wrote and how long
+/// it took the last time it processed the run
+pub const PROCESSING_SECTION: &str = "processing";
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct ProcessingRecord {
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    pub seconds: f64,
+}
+
+/// Name of the running tool, as recorded in `summary.json`
+pub fn tool_name() -> String {
+    env::args()
+        .next()
+        .and_then(|x| Path::new(&x).file_stem().map(|x| x.to_string_lossy().into_owned()))
+        .unwrap_or_default()
+}
+
+/// Total size of the files under a directory modified since a time (bytes), 0 if it does not exist
+fn bytes_written_since(dir: &Path, since: SystemTime) -> u64 {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+
+    entries
+        .filter_map(|x| x.ok())
+        .map(|entry| match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => bytes_written_since(&entry.path(), since),
+            Ok(metadata) if metadata.modified().map_or(false, |x| x >= since) => metadata.len(),
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Times a tool processing a run and measures what it wrote, from the files in the run directory
+/// written while it ran (so outputs replacing earlier ones count in full), for the estimates of
+/// later dry runs
+pub struct ProcessingTimer {
+    run_dir: PathBuf,
+    start: Instant,
+    start_time: SystemTime,
+}
+
+impl ProcessingTimer {
+    pub fn start(run_dir: &Path) -> ProcessingTimer {
+        ProcessingTimer {
+            run_dir: run_dir.to_owned(),
+            start: Instant::now(),
+            // Allows for filesystems that only keep modification times to the second
+            start_time: SystemTime::now() - Duration::from_secs(1),
+        }
+    }
+
+    /// Records the processing in the `processing` section of the run's `summary.json`
+    pub fn finish(self, input_bytes: u64) -> io::Result<()> {
+        let record = ProcessingRecord {
+            input_bytes,
+            output_bytes: bytes_written_since(&self.run_dir, self.start_time),
+            seconds: self.start.elapsed().as_secs_f64(),
+        };
+
+        let mut records: BTreeMap<String, ProcessingRecord> = read_run_summary(&self.run_dir)?
+            .get(PROCESSING_SECTION)
+            .and_then(|x| serde_json::from_value(x.clone()).ok())
+            .unwrap_or_default();
+
+        records.insert(tool_name(), record);
+
+        update_run_summary(&self.run_dir, PROCESSING_SECTION, &records)
+    }
+}
+
+/// What a tool has made of the runs it processed before, from their `summary.json`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProcessingHistory {
+    pub runs: usize,
+    input_bytes: u64,
+    output_bytes: u64,
+    seconds: f64,
+}
+
+impl ProcessingHistory {
+    /// Collects the records of a tool from the given run directories, any without one are ignored
+    pub fn read<P: AsRef<Path>>(tool: &str, run_dirs: &[P]) -> ProcessingHistory {
+        let mut history = ProcessingHistory::default();
+
+        for run_dir in run_dirs {
+            let record = read_run_summary(run_dir.as_ref())
+                .ok()
+                .and_then(|x| serde_json::from_value::<ProcessingRecord>(x.get(PROCESSING_SECTION)?.get(tool)?.clone()).ok());
+
+            if let Some(record) = record {
+                history.runs += 1;
+                history.input_bytes += record.input_bytes;
+                history.output_bytes += record.output_bytes;
+                history.seconds += record.seconds;
+            }
+        }
+
+        history
+    }
+
+    /// Output bytes per input byte
+    pub fn output_ratio(&self) -> Option<f64> {
+        if self.input_bytes > 0 {
+            Some(self.output_bytes as f64 / self.input_bytes as f64)
+        } else {
+            None
+        }
+    }
+
+    /// Input bytes processed per second
+    pub fn throughput(&self) -> Option<f64> {
+        if self.seconds > 0.0 {
+            Some(self.input_bytes as f64 / self.seconds)
+        } else {
+            None
+        }
+    }
+}
+
+/// A run a dry run would process, or skip as its output already exists
+struct DryRunEntry {
+    path: PathBuf,
+    input_bytes: u64,
+    hits: Option<u64>,            // Expected hit count, if known
+    skipped: Option<&'static str>, // Why the run would be skipped
+    replaces: bool,               // Whether existing output made with other settings would be replaced
+}
+
+/// What a tool would do with the matched runs, with estimates of the output size and processing
+/// time from the history of the runs already processed
+pub struct DryRunPlan {
+    tool: String,
+    output_dir: Option<PathBuf>, // Where the output goes if not into the run directories
+    output_ratio: Option<f64>,   // Output bytes per input byte, if known without any history
+    entries: Vec<DryRunEntry>,
+}
+
+impl DryRunPlan {
+    pub fn new() -> DryRunPlan {
+        DryRunPlan {
+            tool: tool_name(),
+            output_dir: None,
+            output_ratio: None,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Output written elsewhere than the run directories, for the free space check
+    pub fn output_dir(mut self, output_dir: &Path) -> DryRunPlan {
+        self.output_dir = Some(output_dir.to_owned());
+        self
+    }
+
+    /// Output size of a tool that always writes as much as it reads, or some fixed fraction of it
+    pub fn output_ratio(mut self, output_ratio: f64) -> DryRunPlan {
+        self.output_ratio = Some(output_ratio);
+        self
+    }
+
+    /// A run (or single input file) that would be processed
+    pub fn run(&mut self, path: &Path, input_bytes: u64, hits: Option<u64>) {
+        self.entries.push(DryRunEntry {
+            path: path.to_owned(),
+            input_bytes,
+            hits,
+            skipped: None,
+            replaces: false,
+        });
+    }
+
+    /// A run that would be processed again, replacing its output made with different settings
+    pub fn replacing(&mut self, path: &Path, input_bytes: u64, hits: Option<u64>) {
+        self.run(path, input_bytes, hits);
+        self.entries.last_mut().unwrap().replaces = true;
+    }
+
+    /// A run that would be skipped as its output already exists
+    pub fn skipped(&mut self, path: &Path) {
+        self.skip(path, "output exists");
+    }
+
+    /// A run that would be skipped as its output was made with the same settings
+    pub fn skipped_same_settings(&mut self, path: &Path) {
+        self.skip(path, "output exists with the same settings");
+    }
+
+    fn skip(&mut self, path: &Path, reason: &'static str) {
+        self.entries.push(DryRunEntry {
+            path: path.to_owned(),
+            input_bytes: 0,
+            hits: None,
+            skipped: Some(reason),
+            replaces: false,
+        });
+    }
+
+    /// Run directories to learn from, the matched runs and the others alongside them (e.g. the
+    /// runs of an earlier campaign in the same output directory)
+    fn history_dirs(&self) -> Vec<PathBuf> {
+        // Tools working on single files record against the run directory the file is in
+        let run_dirs: BTreeSet<&Path> = self
+            .entries
+            .iter()
+            .filter_map(|x| if x.path.is_file() { x.path.parent() } else { Some(x.path.as_path()) })
+            .collect();
+
+        let parents: BTreeSet<&Path> = run_dirs.iter().filter_map(|x| x.parent()).collect();
+
+        let mut dirs: BTreeSet<PathBuf> = run_dirs.iter().map(|x| x.to_path_buf()).collect();
+
+        for parent in parents {
+            if let Ok(entries) = fs::read_dir(parent) {
+                dirs.extend(entries.filter_map(|x| x.ok()).map(|x| x.path()).filter(|x| x.is_dir()));
+            }
+        }
+
+        dirs.into_iter().collect()
+    }
+
+    pub fn print(&self) {
+        let history = ProcessingHistory::read(&self.tool, &self.history_dirs());
+        let output_ratio = self.output_ratio.or_else(|| history.output_ratio());
+        let throughput = history.throughput();
+
+        println!();
+
+        for entry in &self.entries {
+            if let Some(reason) = entry.skipped {
+                println!("{}", format!("{} | Skipped ({})", entry.path.display(), reason).yellow());
+                continue;
+            }
+
+            println!(
+                "{} | Input: {} | Hits: {} | Output: {} | Time: {}{}",
+                entry.path.display(),
+                format_bytes(entry.input_bytes),
+                format_hits(entry.hits),
+                format_estimate(output_ratio.map(|x| x * entry.input_bytes as f64), |x| format_bytes(x as u64)),
+                format_estimate(throughput.map(|x| entry.input_bytes as f64 / x), format_seconds),
+                if entry.replaces { " | Replaces output with different settings".yellow().to_string() } else { String::new() },
+            );
+        }
+
+        let to_process: Vec<&DryRunEntry> = self.entries.iter().filter(|x| x.skipped.is_none()).collect();
+        let input_bytes: u64 = to_process.iter().map(|x| x.input_bytes).sum();
+        let hits: Option<u64> = to_process.iter().map(|x| x.hits).sum();
+        let output_bytes = output_ratio.map(|x| (x * input_bytes as f64) as u64);
+
+        println!(
+            "\n{} to process, {} skipped | Input: {} | Hits: {} | Output: {} | Time: {} (one at a time)",
+            to_process.len(),
+            self.entries.len() - to_process.len(),
+            format_bytes(input_bytes),
+            format_hits(hits),
+            format_estimate(output_bytes, format_bytes),
+            format_estimate(throughput.map(|x| input_bytes as f64 / x), format_seconds),
+        );
+
+        match history.runs {
+            0 => println!("No runs processed by {} before to estimate from", self.tool),
+            n => println!("Estimated from {} runs processed by {} before", n, self.tool),
+        }
+
+        let output_path = self.output_dir.as_deref().or_else(|| to_process.first().map(|x| x.path.as_path()));
+
+        if let (Some(output_path), Some(output_bytes)) = (output_path, output_bytes) {
+            if let Ok(available) = available_space(output_path) {
+                let line = format!("{} free on the output filesystem", format_bytes(available));
+
+                if available < output_bytes {
+                    println!("{}", line.red());
+                } else {
+                    println!("{}", line);
+                }
+            }
+        }
+    }
+}
+
+fn format_hits(hits: Option<u64>) -> String {
+    hits.map_or_else(|| "?".to_owned(), |x| x.separated_string())
+}
+
+fn format_estimate<T>(value: Option<T>, format: impl Fn(T) -> String) -> String {
+    value.map_or_else(|| "?".to_owned(), |x| format!("~{}", format(x)))
+}
+
+fn format_seconds(seconds: f64) -> String {
+    if seconds < 60.0 {
+        format!("{:.0} s", seconds)
+    } else if seconds < 3600.0 {
+        format!("{:.1} min", seconds / 60.0)
+    } else {
+        format!("{:.1} h", seconds / 3600.0)
+    }
+}
+
+impl Default for DryRunPlan {
+    fn default() -> DryRunPlan {
+        DryRunPlan::new()
+    }
+}
+
+/// Total size of whichever of the named files a run directory has (bytes)
+pub fn run_file_bytes<S: AsRef<Path>>(run_dir: &Path, names: &[S]) -> u64 {
+    names.iter().filter_map(|x| run_dir.join(x).metadata().ok()).map(|x| x.len()).sum()
+}
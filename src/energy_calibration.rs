@@ -0,0 +1,124 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/energy_calibration.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Hit, TOT_ADU_TO_NS};
+
+/// Row of a calibration CSV, the parameters of the surrogate function ToT = a E + b - c / (E - t)
+/// (ToT in ADU, E in keV) of one pixel, or of every pixel not listed if the col and row are empty
+#[derive(Deserialize)]
+struct CalibrationRow {
+    col: Option<u16>,
+    row: Option<u16>,
+    a: f64,
+    b: f64,
+    c: f64,
+    t: f64,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct SurrogateFunction {
+    a: f64,
+    b: f64,
+    c: f64,
+    t: f64,
+}
+
+impl SurrogateFunction {
+    /// Inverts the surrogate function, taking the root above the threshold t
+    fn energy(&self, tot: f64) -> Option<f64> {
+        let SurrogateFunction { a, b, c, t } = *self;
+
+        if a == 0.0 {
+            return None;
+        }
+
+        // a E^2 + (b - a t - ToT) E + (ToT - b) t - c = 0
+        let p = b - a * t - tot;
+        let discriminant = p * p - 4.0 * a * ((tot - b) * t - c);
+
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        Some((-p + discriminant.sqrt()) / (2.0 * a))
+    }
+}
+
+/// Per-pixel ToT to energy (keV) calibration. Pixels without parameters, either their own or a
+/// default for the whole matrix, have no energy.
+#[derive(Clone, Debug, Serialize)]
+pub struct EnergyCalibration {
+    pub file: PathBuf,
+    pub pixels_calibrated: usize,
+    #[serde(skip)]
+    functions: Vec<Option<SurrogateFunction>>,
+}
+
+impl EnergyCalibration {
+    pub fn from_csv(file: &Path) -> io::Result<EnergyCalibration> {
+        let mut rdr = csv::Reader::from_path(file)?;
+
+        let mut default = None;
+        let mut functions = vec![None; 256 * 256];
+
+        for result in rdr.deserialize() {
+            let row: CalibrationRow = result?;
+
+            let function = SurrogateFunction {
+                a: row.a,
+                b: row.b,
+                c: row.c,
+                t: row.t,
+            };
+
+            match (row.col, row.row) {
+                (Some(col), Some(row)) if col <= 255 && row <= 255 => {
+                    functions[usize::from(row) * 256 + usize::from(col)] = Some(function);
+                }
+                (None, None) => default = Some(function),
+                (col, row) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Calibration {:#?} has a pixel outside the matrix ({:?}, {:?})", file, col, row),
+                    ));
+                }
+            }
+        }
+
+        for function in functions.iter_mut().filter(|x| x.is_none()) {
+            *function = default;
+        }
+
+        Ok(EnergyCalibration {
+            file: file.to_path_buf(),
+            pixels_calibrated: functions.iter().filter(|x| x.is_some()).count(),
+            functions,
+        })
+    }
+
+    /// Energy of a hit (keV)
+    pub fn energy(&self, hit: &Hit) -> Option<f64> {
+        if hit.col > 255 || hit.row > 255 {
+            return None;
+        }
+
+        let function = self.functions[usize::from(hit.row) * 256 + usize::from(hit.col)]?;
+
+        function.energy(f64::from(hit.tot) / f64::from(TOT_ADU_TO_NS))
+    }
+
+    /// Summed energy of the hits of a cluster (keV), None if any of the hits has no energy
+    pub fn cluster_energy(&self, cluster: &[Hit]) -> Option<f64> {
+        cluster.iter().map(|hit| self.energy(hit)).sum()
+    }
+}
@@ -0,0 +1,132 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/event_selection.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::collections::BTreeSet;
+use std::io;
+
+use serde::{Deserialize, Serialize, Serializer};
+
+/// Inclusive range of event numbers
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct EventRange {
+    pub first: usize,
+    pub last: usize,
+}
+
+impl EventRange {
+    /// Parses a range given as `first:last`
+    pub fn parse(string: &str) -> Option<EventRange> {
+        let mut bounds = string.split(':').map(|x| x.trim().parse::<usize>().ok());
+
+        match (bounds.next()??, bounds.next()??, bounds.next()) {
+            (first, last, None) if first <= last => Some(EventRange { first, last }),
+            _ => None,
+        }
+    }
+}
+
+/// Written as `first:last`, as given on the command line
+impl Serialize for EventRange {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!("{}:{}", self.first, self.last))
+    }
+}
+
+/// Row of an event list, any other columns (eg. when listing events from a metadata CSV) are ignored
+#[derive(Clone, Debug, Deserialize)]
+struct ListedEvent {
+    event: usize,
+    #[serde(default)]
+    run_id: Option<String>, // Run the event belongs to, the event is selected in every run if empty
+}
+
+/// Events a tool is restricted to, from `--event-range` and `--event-list`, so that specific events
+/// (eg. those flagged by the DQM) can be reprocessed without processing the whole run. Events must
+/// be in both the range and the list if both are given.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct EventSelection {
+    pub event_range: Option<EventRange>,
+    pub event_list: Option<String>, // CSV file the listed events were read from
+    #[serde(skip)]
+    listed_events: Option<Vec<ListedEvent>>,
+}
+
+impl EventSelection {
+    pub fn new(event_range: Option<&str>, event_list: Option<&str>) -> io::Result<EventSelection> {
+        let event_range = match event_range {
+            Some(string) => Some(EventRange::parse(string).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid event range '{}', expected 'first:last'", string),
+                )
+            })?),
+            None => None,
+        };
+
+        let listed_events = match event_list {
+            Some(event_list) => {
+                let mut rdr = csv::Reader::from_path(event_list)?;
+
+                let listed_events = rdr.deserialize().collect::<Result<Vec<ListedEvent>, _>>()?;
+
+                Some(listed_events)
+            }
+            None => None,
+        };
+
+        Ok(EventSelection {
+            event_range,
+            event_list: event_list.map(|x| x.to_owned()),
+            listed_events,
+        })
+    }
+
+    /// The events selected in a run
+    pub fn events_of(&self, run_id: &str) -> SelectedEvents {
+        SelectedEvents {
+            range: self.event_range,
+            events: self.listed_events.as_ref().map(|listed_events| {
+                listed_events
+                    .iter()
+                    .filter(|x| match x.run_id.as_deref() {
+                        None | Some("") => true,
+                        Some(x) => x == run_id,
+                    })
+                    .map(|x| x.event)
+                    .collect()
+            }),
+        }
+    }
+}
+
+/// Events selected in a single run
+#[derive(Clone, Debug)]
+pub struct SelectedEvents {
+    range: Option<EventRange>,
+    events: Option<BTreeSet<usize>>,
+}
+
+impl SelectedEvents {
+    pub fn contains(&self, event: usize) -> bool {
+        let in_range = self.range.iter().all(|x| event >= x.first && event <= x.last);
+        let listed = self.events.iter().all(|x| x.contains(&event));
+
+        in_range && listed
+    }
+
+    /// Last event selected, so that processing can stop once it is past it (None if unbounded)
+    pub fn last(&self) -> Option<usize> {
+        let range_last = self.range.map(|x| x.last);
+        let list_last = self.events.as_ref().map(|x| x.iter().next_back().copied().unwrap_or(0));
+
+        match (range_last, list_last) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
@@ -0,0 +1,171 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/external_events.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Trigger;
+
+/// Row of an external event mapping file, giving either the trigger number or trigger time (ns)
+/// an external event ID belongs to
+#[derive(Deserialize)]
+struct ExternalEventRow {
+    external_id: u64,
+    #[serde(default)]
+    event: Option<u32>,
+    #[serde(default)]
+    time: Option<f64>,
+}
+
+/// Maps the triggers of a run onto the event IDs of another DAQ (e.g. the PMT DAQ), so the
+/// events of both can be merged downstream
+#[derive(Clone, Debug, Default)]
+pub struct ExternalEventMap {
+    file: PathBuf,
+    by_event: HashMap<u32, u64>,
+    by_time: Vec<(f64, u64)>, // In time order
+    tolerance: f64,           // ns
+}
+
+/// How well a mapping file matched a run's triggers, for its `summary.json`
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ExternalEventMatching {
+    pub file: PathBuf,
+    pub entries: usize,
+    pub matched: usize,   // Triggers given an external ID
+    pub unmatched: usize, // Triggers left without one
+}
+
+impl ExternalEventMap {
+    /// Reads a CSV file with an `external_id` column and either an `event` (trigger number) or a
+    /// `time` (trigger time, ns) column on each row. Triggers are matched by time to the nearest
+    /// entry within `tolerance` (ns).
+    pub fn read(file: &Path, tolerance: f64) -> io::Result<ExternalEventMap> {
+        let mut rdr = csv::Reader::from_reader(fs::File::open(file)?);
+        let mut map = ExternalEventMap {
+            file: file.to_owned(),
+            tolerance,
+            ..ExternalEventMap::default()
+        };
+
+        for (line, result) in rdr.deserialize().enumerate() {
+            let row: ExternalEventRow = result?;
+
+            match (row.event, row.time) {
+                (Some(event), _) => {
+                    map.by_event.insert(event, row.external_id);
+                }
+                (None, Some(time)) => map.by_time.push((time, row.external_id)),
+                (None, None) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Line {} of {:#?} has neither an event nor a time", line + 2, file),
+                    ));
+                }
+            }
+        }
+
+        map.by_time.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        Ok(map)
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_event.len() + self.by_time.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// External ID of a trigger, by its number if the mapping has it, otherwise by its time
+    pub fn lookup(&self, trigger: &Trigger) -> Option<u64> {
+        if let Some(&external_id) = self.by_event.get(&trigger.event) {
+            return Some(external_id);
+        }
+
+        let time = trigger.time as f64;
+        let i = self.by_time.partition_point(|&(entry_time, _)| entry_time < time);
+
+        [i.checked_sub(1), Some(i)]
+            .iter()
+            .flatten()
+            .filter_map(|&j| self.by_time.get(j))
+            .filter(|&&(entry_time, _)| (entry_time - time).abs() <= self.tolerance)
+            .min_by(|a, b| (a.0 - time).abs().total_cmp(&(b.0 - time).abs()))
+            .map(|&(_, external_id)| external_id)
+    }
+
+    /// External IDs of a run's triggers, by trigger number
+    pub fn assign(&self, triggers: &[Trigger]) -> (HashMap<u32, u64>, ExternalEventMatching) {
+        let ids: HashMap<u32, u64> = triggers.iter().filter_map(|x| Some((x.event, self.lookup(x)?))).collect();
+
+        let matching = ExternalEventMatching {
+            file: self.file.clone(),
+            entries: self.len(),
+            matched: ids.len(),
+            unmatched: triggers.len() - ids.len(),
+        };
+
+        (ids, matching)
+    }
+}
+
+/// Adds an `external_id` column to a run's `triggers.csv`, replacing any from a previous pass.
+/// Triggers without an external ID are left empty.
+pub fn add_external_ids_to_triggers_csv(file_path: &Path, external_ids: &HashMap<u32, u64>) -> io::Result<()> {
+    let mut rdr = csv::Reader::from_path(file_path)?;
+
+    let headers = rdr.headers()?.clone();
+
+    let event_index = match headers.iter().position(|x| x == "event") {
+        Some(i) => i,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Triggers file {:#?} has no 'event' column", file_path),
+            ));
+        }
+    };
+
+    let kept: Vec<usize> = (0..headers.len()).filter(|&i| &headers[i] != "external_id").collect();
+
+    let mut output_headers: Vec<String> = kept.iter().map(|&i| headers[i].to_owned()).collect();
+    output_headers.push("external_id".to_owned());
+
+    let mut rows = vec![output_headers];
+
+    for result in rdr.records() {
+        let record = result?;
+
+        let external_id = record[event_index].parse::<u32>().ok().and_then(|x| external_ids.get(&x));
+
+        let mut row: Vec<String> = kept.iter().map(|&i| record[i].to_owned()).collect();
+        row.push(external_id.map_or(String::new(), |x| x.to_string()));
+
+        rows.push(row);
+    }
+
+    // Written alongside then moved over the original, so it is never left half written
+    let tmp_file_path = file_path.with_extension("csv.tmp");
+
+    let mut csv_writer = csv::Writer::from_writer(fs::File::create(&tmp_file_path)?);
+
+    for row in rows {
+        csv_writer.write_record(&row)?;
+    }
+
+    csv_writer.flush()?;
+    drop(csv_writer);
+
+    fs::rename(tmp_file_path, file_path)
+}
@@ -0,0 +1,83 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/flat_field.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Hit, HitFlags};
+
+/// Columns of a gain map CSV (as written by `gain_map_tool`) needed for the correction
+#[derive(Deserialize)]
+struct GainMapPixel {
+    col: u16,
+    row: u16,
+    relative_gain: f64,
+    outlier: bool,
+}
+
+/// Per-pixel ToT correction from a gain map. Each hit's ToT is divided by the relative gain of its
+/// pixel; outlier pixels and those without a measured gain are left uncorrected.
+#[derive(Clone, Debug, Serialize)]
+pub struct FlatField {
+    pub file: PathBuf,
+    pub pixels_corrected: usize,
+    #[serde(skip)]
+    factors: Vec<f64>,
+}
+
+impl FlatField {
+    pub fn from_gain_map(file: &Path) -> io::Result<FlatField> {
+        let mut rdr = csv::Reader::from_path(file)?;
+
+        let mut factors = vec![1.0; 256 * 256];
+        let mut pixels_corrected = 0;
+
+        for result in rdr.deserialize() {
+            let pixel: GainMapPixel = result?;
+
+            if pixel.col > 255 || pixel.row > 255 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Gain map {:#?} has a pixel outside the matrix ({}, {})", file, pixel.col, pixel.row),
+                ));
+            }
+
+            if pixel.outlier || pixel.relative_gain <= 0.0 {
+                continue;
+            }
+
+            factors[usize::from(pixel.row) * 256 + usize::from(pixel.col)] = 1.0 / pixel.relative_gain;
+            pixels_corrected += 1;
+        }
+
+        Ok(FlatField {
+            file: file.to_path_buf(),
+            pixels_corrected,
+            factors,
+        })
+    }
+
+    pub fn correct(&self, hit: Hit) -> Hit {
+        if hit.col > 255 || hit.row > 255 {
+            return hit;
+        }
+
+        let factor = self.factors[usize::from(hit.row) * 256 + usize::from(hit.col)];
+
+        let mut flags = hit.flags;
+        flags.insert(HitFlags::CALIBRATED);
+
+        Hit {
+            tot: (f64::from(hit.tot) * factor).round() as u32,
+            flags,
+            ..hit
+        }
+    }
+}
@@ -0,0 +1,205 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/hit_filter.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use serde::Serialize;
+
+use crate::{Hit, PixelMask};
+
+/// Rectangular region of interest on the pixel matrix (bounds inclusive)
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub struct Roi {
+    pub col_min: u16,
+    pub col_max: u16,
+    pub row_min: u16,
+    pub row_max: u16,
+}
+
+impl Roi {
+    /// Parses a region given as `col_min:col_max,row_min:row_max`
+    pub fn parse(string: &str) -> Option<Roi> {
+        let mut ranges = string.split(',').map(|range| {
+            let mut bounds = range.split(':').map(|x| x.trim().parse::<u16>().ok());
+            match (bounds.next()??, bounds.next()??, bounds.next()) {
+                (min, max, None) if min <= max => Some((min, max)),
+                _ => None,
+            }
+        });
+
+        let (col_min, col_max) = ranges.next()??;
+        let (row_min, row_max) = ranges.next()??;
+
+        if ranges.next().is_some() {
+            return None;
+        }
+
+        Some(Roi {
+            col_min,
+            col_max,
+            row_min,
+            row_max,
+        })
+    }
+
+    pub fn contains(&self, hit: &Hit) -> bool {
+        hit.col >= self.col_min && hit.col <= self.col_max && hit.row >= self.row_min && hit.row <= self.row_max
+    }
+}
+
+/// Cuts applied to hits before clustering, so every hit reaching the clusterer has passed the same
+/// selection regardless of how the hits are buffered
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct HitFilter {
+    pub min_hit_tot: u32, // Hits must have a ToT above this (ns)
+    pub roi: Option<Roi>,
+    pub pixel_mask: Option<PixelMask>,
+}
+
+impl HitFilter {
+    pub fn accepts(&self, hit: &Hit) -> bool {
+        if hit.tot <= self.min_hit_tot {
+            return false;
+        }
+
+        if let Some(roi) = &self.roi {
+            if !roi.contains(hit) {
+                return false;
+            }
+        }
+
+        match &self.pixel_mask {
+            Some(pixel_mask) => !pixel_mask.is_masked(hit),
+            None => true,
+        }
+    }
+
+    /// Wraps a hits iterator so only hits passing the filter are returned
+    pub fn apply<I: Iterator<Item = Hit>>(&self, hits: I) -> FilterHitsIterator<'_, I> {
+        FilterHitsIterator {
+            hits,
+            filter: self,
+            hits_rejected: 0,
+        }
+    }
+}
+
+pub struct FilterHitsIterator<'a, I: Iterator<Item = Hit>> {
+    hits: I,
+    filter: &'a HitFilter,
+    pub hits_rejected: usize,
+}
+
+impl<'a, I: Iterator<Item = Hit>> Iterator for FilterHitsIterator<'a, I> {
+    type Item = Hit;
+
+    fn next(&mut self) -> Option<Hit> {
+        for hit in &mut self.hits {
+            if self.filter.accepts(&hit) {
+                return Some(hit);
+            }
+
+            self.hits_rejected += 1;
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+    use std::process;
+
+    use super::*;
+    use crate::{write_hits_to_file, HitFlags, ReadHitsIterator};
+
+    /// Hits spread over the matrix with a range of ToT values, some from pixel (10, 10)
+    fn test_hits() -> Vec<Hit> {
+        (0..1000u64)
+            .map(|i| Hit {
+                toa: i * 3,
+                tot: (i as u32 * 37) % 200,
+                col: if i % 7 == 0 { 10 } else { (i * 13 % 256) as u16 },
+                row: if i % 7 == 0 { 10 } else { (i * 29 % 256) as u16 },
+                flags: HitFlags::default(),
+            })
+            .collect()
+    }
+
+    /// ToT cut, ROI and a mask of pixel (10, 10), which all reject some of the test hits
+    fn test_filter(name: &str) -> HitFilter {
+        let mask_file = env::temp_dir().join(format!("hit_filter_{}_{}.csv", name, process::id()));
+        fs::File::create(&mask_file)
+            .unwrap()
+            .write_all(b"col,row,hits,status\n10,10,1000,hot\n")
+            .unwrap();
+
+        let pixel_mask = PixelMask::from_csv(&mask_file).unwrap();
+        fs::remove_file(&mask_file).unwrap();
+
+        HitFilter {
+            min_hit_tot: 50,
+            roi: Some(Roi::parse("0:199,20:255").unwrap()),
+            pixel_mask: Some(pixel_mask),
+        }
+    }
+
+    /// The hits `test_filter` should accept, worked out independently of it
+    fn expected_hits(hits: &[Hit]) -> Vec<Hit> {
+        hits.iter()
+            .filter(|hit| hit.tot > 50 && hit.col <= 199 && hit.row >= 20 && (hit.col, hit.row) != (10, 10))
+            .copied()
+            .collect()
+    }
+
+    #[test]
+    fn filter_applies_every_cut() {
+        let filter = test_filter("cuts");
+        let hits = test_hits();
+
+        let mut filtered = filter.apply(hits.iter().copied());
+        let accepted: Vec<_> = (&mut filtered).collect();
+
+        assert_eq!(accepted, expected_hits(&hits));
+        assert!(!accepted.is_empty());
+        assert_eq!(filtered.hits_rejected, hits.len() - accepted.len());
+    }
+
+    #[test]
+    fn output_does_not_depend_on_chunk_size() {
+        let filter = test_filter("chunks");
+        let hits = test_hits();
+        let expected = expected_hits(&hits);
+
+        // As when the hits are filtered one buffer refill at a time
+        for chunk_size in [1, 2, 7, 64, 999, 1000, 4096].iter() {
+            let accepted: Vec<_> = hits.chunks(*chunk_size).flat_map(|chunk| filter.apply(chunk.iter().copied())).collect();
+
+            assert_eq!(accepted, expected, "chunk size {}", chunk_size);
+        }
+    }
+
+    #[test]
+    fn output_does_not_depend_on_read_buffer_size() {
+        let filter = test_filter("read_buffer");
+        let hits = test_hits();
+        let expected = expected_hits(&hits);
+
+        let hits_file = env::temp_dir().join(format!("hit_filter_hits_{}.bin", process::id()));
+        write_hits_to_file(&mut fs::File::create(&hits_file).unwrap(), &hits).unwrap();
+
+        for buffer_size in [1, 13, 4096, 1 << 20].iter() {
+            let accepted: Vec<_> = filter.apply(ReadHitsIterator::with_buffer_size(&hits_file, *buffer_size)).collect();
+
+            assert_eq!(accepted, expected, "buffer size {}", buffer_size);
+        }
+
+        fs::remove_file(&hits_file).unwrap();
+    }
+}
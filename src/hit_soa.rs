@@ -0,0 +1,154 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/hit_soa.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::iter::FromIterator;
+
+use crate::{Hit, HitFlags};
+
+/// Hits stored column-wise, one `Vec` per field. Loops over a single field (ToT sums, time ranges,
+/// occupancy maps) only touch the memory they need and vectorise, and each column maps directly
+/// onto an Arrow/numpy array.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HitSoA {
+    pub col: Vec<u16>,
+    pub row: Vec<u16>,
+    pub toa: Vec<u64>,
+    pub tot: Vec<u32>,
+    pub flags: Vec<HitFlags>,
+}
+
+impl HitSoA {
+    pub fn new() -> HitSoA {
+        HitSoA::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> HitSoA {
+        HitSoA {
+            col: Vec::with_capacity(capacity),
+            row: Vec::with_capacity(capacity),
+            toa: Vec::with_capacity(capacity),
+            tot: Vec::with_capacity(capacity),
+            flags: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.toa.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.toa.is_empty()
+    }
+
+    pub fn push(&mut self, hit: Hit) {
+        self.col.push(hit.col);
+        self.row.push(hit.row);
+        self.toa.push(hit.toa);
+        self.tot.push(hit.tot);
+        self.flags.push(hit.flags);
+    }
+
+    pub fn get(&self, i: usize) -> Option<Hit> {
+        if i >= self.len() {
+            return None;
+        }
+
+        Some(Hit {
+            col: self.col[i],
+            row: self.row[i],
+            toa: self.toa[i],
+            tot: self.tot[i],
+            flags: self.flags[i],
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Hit> + '_ {
+        (0..self.len()).map(move |i| Hit {
+            col: self.col[i],
+            row: self.row[i],
+            toa: self.toa[i],
+            tot: self.tot[i],
+            flags: self.flags[i],
+        })
+    }
+
+    pub fn to_hits(&self) -> Vec<Hit> {
+        self.iter().collect()
+    }
+
+    /// Total ToT of all the hits (ns)
+    pub fn sum_tot(&self) -> u64 {
+        self.tot.iter().map(|&tot| u64::from(tot)).sum()
+    }
+
+    /// Earliest and latest ToA of the hits (clock units), without assuming they are in time order.
+    /// Returns `None` if there are no hits.
+    pub fn time_range(&self) -> Option<(u64, u64)> {
+        if self.is_empty() {
+            return None;
+        }
+
+        let first = self.toa.iter().fold(u64::MAX, |a, &b| a.min(b));
+        let last = self.toa.iter().fold(0, |a, &b| a.max(b));
+
+        Some((first, last))
+    }
+
+    /// Adds the hits to a row-major 256x256 pixel map, counting each hit once or weighting it by
+    /// its ToT. Hits outside the matrix are ignored.
+    pub fn fill_occupancy(&self, map: &mut [f64], weight_by_tot: bool) {
+        assert_eq!(map.len(), 256 * 256);
+
+        let pixels = self.col.iter().zip(&self.row).map(|(&col, &row)| (col as usize, row as usize));
+
+        if weight_by_tot {
+            for ((col, row), &tot) in pixels.zip(&self.tot) {
+                if col < 256 && row < 256 {
+                    map[row * 256 + col] += f64::from(tot);
+                }
+            }
+        } else {
+            for (col, row) in pixels {
+                if col < 256 && row < 256 {
+                    map[row * 256 + col] += 1.0;
+                }
+            }
+        }
+    }
+}
+
+impl Extend<Hit> for HitSoA {
+    fn extend<I: IntoIterator<Item = Hit>>(&mut self, iter: I) {
+        for hit in iter {
+            self.push(hit);
+        }
+    }
+}
+
+impl FromIterator<Hit> for HitSoA {
+    fn from_iter<I: IntoIterator<Item = Hit>>(iter: I) -> Self {
+        let iter = iter.into_iter();
+
+        let mut hits = HitSoA::with_capacity(iter.size_hint().0);
+        hits.extend(iter);
+
+        hits
+    }
+}
+
+impl From<Vec<Hit>> for HitSoA {
+    fn from(hits: Vec<Hit>) -> Self {
+        hits.into_iter().collect()
+    }
+}
+
+impl From<&[Hit]> for HitSoA {
+    fn from(hits: &[Hit]) -> Self {
+        hits.iter().cloned().collect()
+    }
+}
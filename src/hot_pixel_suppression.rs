@@ -0,0 +1,115 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/hot_pixel_suppression.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use serde::Serialize;
+
+use crate::{Hit, TOA_CLOCK_TO_NS};
+
+/// A pixel being masked or unmasked by the rolling suppression (time in ns)
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct HotPixelMaskChange {
+    pub col: u16,
+    pub row: u16,
+    pub time: f64,
+    pub masked: bool,
+}
+
+/// Suppresses pixels whose rate goes above a limit, for runs where the static hot pixel list is
+/// stale. Hits are counted per pixel in consecutive windows; a pixel is masked as soon as it
+/// exceeds `max_hits` in a window, and unmasked at the end of the first window in which it no
+/// longer does. Hits should be passed in (roughly) ToA order.
+#[derive(Clone, Debug, Serialize)]
+pub struct RollingHotPixelSuppressor {
+    pub window: u64,
+    pub max_hits: u32,
+    pub pixels_masked: usize,
+    pub hits_suppressed: usize,
+    #[serde(skip)]
+    pub log: Vec<HotPixelMaskChange>,
+    #[serde(skip)]
+    window_start: Option<u64>,
+    #[serde(skip)]
+    counts: Vec<u32>,
+    #[serde(skip)]
+    masked: Vec<bool>,
+}
+
+impl RollingHotPixelSuppressor {
+    /// `window` is in ToA units (1.5625 ns)
+    pub fn new(window: u64, max_hits: u32) -> RollingHotPixelSuppressor {
+        RollingHotPixelSuppressor {
+            window: window.max(1),
+            max_hits,
+            pixels_masked: 0,
+            hits_suppressed: 0,
+            log: Vec::new(),
+            window_start: None,
+            counts: vec![0; 256 * 256],
+            masked: vec![false; 256 * 256],
+        }
+    }
+
+    pub fn is_suppressed(&mut self, hit: &Hit) -> bool {
+        if hit.col > 255 || hit.row > 255 {
+            return false;
+        }
+
+        let window_start = hit.toa - hit.toa % self.window;
+
+        match self.window_start {
+            // Slightly out of order hits are counted in the current window
+            Some(start) if window_start <= start => {}
+            _ => self.start_window(window_start),
+        }
+
+        let i = usize::from(hit.row) * 256 + usize::from(hit.col);
+
+        self.counts[i] += 1;
+
+        if !self.masked[i] && self.counts[i] > self.max_hits {
+            self.masked[i] = true;
+            self.pixels_masked += 1;
+
+            self.log.push(HotPixelMaskChange {
+                col: hit.col,
+                row: hit.row,
+                time: hit.toa as f64 * TOA_CLOCK_TO_NS,
+                masked: true,
+            });
+        }
+
+        if self.masked[i] {
+            self.hits_suppressed += 1;
+        }
+
+        self.masked[i]
+    }
+
+    fn start_window(&mut self, window_start: u64) {
+        if self.window_start.is_some() {
+            for i in 0..256 * 256 {
+                if self.masked[i] && self.counts[i] <= self.max_hits {
+                    self.masked[i] = false;
+
+                    self.log.push(HotPixelMaskChange {
+                        col: (i % 256) as u16,
+                        row: (i / 256) as u16,
+                        time: window_start as f64 * TOA_CLOCK_TO_NS,
+                        masked: false,
+                    });
+                }
+            }
+
+            for count in self.counts.iter_mut() {
+                *count = 0;
+            }
+        }
+
+        self.window_start = Some(window_start);
+    }
+}
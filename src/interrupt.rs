@@ -0,0 +1,78 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/interrupt.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::Serialize;
+
+/// Exit status of a tool stopped by SIGINT/SIGTERM (EX_TEMPFAIL), so a batch wrapper can resubmit it
+pub const INTERRUPTED_EXIT_CODE: i32 = 75;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Progress of an interrupted run, written to the partial marker of its output
+#[derive(Clone, Debug, Serialize)]
+pub struct Checkpoint {
+    pub written: usize,         // Hits/clusters/events written before stopping
+    pub last_time: Option<f64>, // Time of the last one written (ns)
+}
+
+/// Installs a SIGINT/SIGTERM handler that asks processing to stop so outputs can be flushed and
+/// closed. A second signal exits straight away.
+pub fn install_interrupt_handler() {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            process::exit(INTERRUPTED_EXIT_CODE);
+        }
+
+        eprintln!("\nInterrupted, finishing writes (interrupt again to exit immediately)");
+    })
+    .expect("Failed to install interrupt handler");
+}
+
+/// Checked by the processing loops, cheap enough to call per hit/packet
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Error returned by a run that stopped early because of an interrupt
+pub fn interrupted_error() -> io::Error {
+    io::Error::new(io::ErrorKind::Interrupted, "Interrupted")
+}
+
+fn partial_marker_path(output_file: &Path) -> PathBuf {
+    let mut file_name = output_file.file_name().map(|x| x.to_owned()).unwrap_or_default();
+    file_name.push(".partial");
+
+    output_file.with_file_name(file_name)
+}
+
+/// Marks an output file as incomplete, recording how far processing got. Runs with a partial
+/// marker are processed again rather than skipped as done.
+pub fn write_partial_marker(output_file: &Path, checkpoint: &Checkpoint) -> io::Result<()> {
+    fs::write(partial_marker_path(output_file), serde_json::to_string_pretty(checkpoint)?)
+}
+
+pub fn has_partial_marker(output_file: &Path) -> bool {
+    partial_marker_path(output_file).exists()
+}
+
+/// Removes the partial marker of an output file once it has been written completely.
+pub fn clear_partial_marker(output_file: &Path) -> io::Result<()> {
+    let marker = partial_marker_path(output_file);
+
+    if marker.exists() {
+        fs::remove_file(marker)?;
+    }
+
+    Ok(())
+}
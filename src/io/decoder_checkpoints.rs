@@ -0,0 +1,219 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/io/decoder_checkpoints.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::io::input_source::InputSource;
+use crate::{parse_raw_packets_from_slice, DecoderState, Hit, RawCompression, SpidrHeader, Trigger, DEFAULT_CLOCK_PHASES, SPIDR_MAX_HEADER_SIZE};
+
+/// Raw data read and decoded at a time (bytes, 2M packets)
+const READ_BLOCK_SIZE: u64 = 16_000_000;
+
+/// Decoder state at a packet boundary in a raw file
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DecoderCheckpoint {
+    /// Byte in the file decoding carries on from
+    pub offset: u64,
+    pub state: DecoderState,
+}
+
+/// Checkpoints splitting a raw file into chunks that can be decoded independently (eg. on
+/// different machines) with the same result as decoding the whole file in one go. The checkpoint
+/// at the start of each chunk holds the decoder state from decoding everything before it, with a
+/// last checkpoint at the end of the file to carry the timeline on to the next file of the run.
+///
+/// Finding the checkpoints takes one pass decoding the whole file, as the ToA extension depends on
+/// every hit before it, but no sorting or writing of the hits.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DecoderCheckpoints {
+    pub file_len: u64,
+    pub chunk_size: u64, // bytes
+    pub checkpoints: Vec<DecoderCheckpoint>,
+}
+
+impl DecoderCheckpoints {
+    /// Decodes a raw file, taking a checkpoint every `chunk_size` bytes of packet data (rounded
+    /// down to whole packets). A file following on from another in the same run is given the end
+    /// state of the previous file as `previous`.
+    pub fn create(path: &Path, chunk_size: u64, previous: Option<&DecoderState>) -> io::Result<DecoderCheckpoints> {
+        let chunk_size = (chunk_size / 8 * 8).max(8);
+
+        let mut file = open_uncompressed(path)?;
+        let file_len = file.len()?;
+
+        let mut header_bytes = Vec::new();
+        (&mut file).take(u64::from(SPIDR_MAX_HEADER_SIZE)).read_to_end(&mut header_bytes)?;
+
+        let header = SpidrHeader::parse(&header_bytes).ok_or_else(|| invalid_data(format!("{} has no SPIDR header", path.display())))?;
+
+        let mut state = match previous {
+            Some(previous) => previous.clone(),
+            None => {
+                let mut state = DecoderState::new(header.clock_phases().unwrap_or(DEFAULT_CLOCK_PHASES));
+                state.acq_mode = header.acq_mode().unwrap_or_default();
+                state
+            }
+        };
+
+        // As the raw_data_parser does for each file of a run, so the offset applied to it is recorded
+        state.toa_extender.start_file();
+        state.remainder.clear();
+
+        let mut offset = header.data_offset() as u64;
+        let mut checkpoints = Vec::new();
+
+        file.seek(SeekFrom::Start(offset))?;
+
+        while offset < file_len {
+            checkpoints.push(DecoderCheckpoint {
+                offset,
+                state: state.clone(),
+            });
+
+            let chunk_end = (offset + chunk_size).min(file_len);
+
+            // Only the state is kept, the hits are decoded again chunk by chunk
+            decode_range(&mut file, offset, chunk_end, &mut state, |_, _| ())?;
+
+            offset = chunk_end;
+        }
+
+        // End of the file, so the last chunk has an end too
+        checkpoints.push(DecoderCheckpoint { offset, state });
+
+        Ok(DecoderCheckpoints {
+            file_len,
+            chunk_size,
+            checkpoints,
+        })
+    }
+
+    pub fn read(path: &Path) -> io::Result<DecoderCheckpoints> {
+        let checkpoints = serde_json::from_reader(io::BufReader::new(fs::File::open(path)?))?;
+
+        Ok(checkpoints)
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut file = io::BufWriter::new(fs::File::create(path)?);
+
+        serde_json::to_writer(&mut file, self)?;
+        file.flush()?;
+
+        Ok(())
+    }
+
+    pub fn chunks(&self) -> usize {
+        self.checkpoints.len().saturating_sub(1)
+    }
+
+    /// Decoder state at the end of the file, to carry on with the next file of the run
+    pub fn end_state(&self) -> &DecoderState {
+        &self.checkpoints.last().unwrap().state
+    }
+
+    /// Decodes one chunk of the raw file the checkpoints were taken from. The hits are in the
+    /// order they were read out, so only roughly in time order.
+    pub fn decode_chunk(&self, path: &Path, chunk: usize) -> io::Result<(Vec<Hit>, Vec<Trigger>)> {
+        if chunk >= self.chunks() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Chunk {} is out of range, the file has {} chunks", chunk, self.chunks()),
+            ));
+        }
+
+        let mut file = open_uncompressed(path)?;
+
+        if file.len()? != self.file_len {
+            return Err(invalid_data(format!(
+                "{} has changed size since its checkpoints were taken",
+                path.display()
+            )));
+        }
+
+        let start = &self.checkpoints[chunk];
+        let end = &self.checkpoints[chunk + 1];
+
+        let mut state = start.state.clone();
+
+        file.seek(SeekFrom::Start(start.offset))?;
+
+        let mut hits = Vec::new();
+        let mut triggers = Vec::new();
+
+        decode_range(&mut file, start.offset, end.offset, &mut state, |block_hits, block_triggers| {
+            hits.extend(block_hits);
+            triggers.extend(block_triggers);
+        })?;
+
+        // Catches checkpoints taken from a different file of the same size
+        if state.toa_extender.last_toa() != end.state.toa_extender.last_toa() || state.toa_extender.long_time() != end.state.toa_extender.long_time()
+        {
+            return Err(invalid_data(format!(
+                "Chunk {} of {} did not decode to the state at its end checkpoint, the checkpoints are from a different file",
+                chunk,
+                path.display()
+            )));
+        }
+
+        Ok((hits, triggers))
+    }
+}
+
+/// Seeking to a chunk needs the raw file as written, not compressed
+fn open_uncompressed(path: &Path) -> io::Result<InputSource> {
+    let mut file = InputSource::open(path)?;
+
+    let mut magic = [0; 4];
+    let magic_len = file.read(&mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+
+    if RawCompression::detect(&magic[..magic_len]) != RawCompression::None {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is compressed, chunks can only be taken from uncompressed raw files", path.display()),
+        ));
+    }
+
+    Ok(file)
+}
+
+/// Decodes the data from `start` to `end` in blocks, the file having been positioned at `start`
+fn decode_range<F>(file: &mut InputSource, start: u64, end: u64, state: &mut DecoderState, mut on_block: F) -> io::Result<()>
+where
+    F: FnMut(Vec<Hit>, Vec<Trigger>),
+{
+    let mut bytes = Vec::new();
+    let mut position = start;
+
+    while position < end {
+        bytes.clear();
+        (&mut *file).take(READ_BLOCK_SIZE.min(end - position)).read_to_end(&mut bytes)?;
+
+        if bytes.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Raw file ended before the end of the chunk"));
+        }
+
+        let (hits, triggers) = parse_raw_packets_from_slice(&bytes, state);
+        on_block(hits, triggers);
+
+        position += bytes.len() as u64;
+    }
+
+    Ok(())
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,196 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/io/empty_events.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+
+use csv;
+use serde::{Deserialize, Serialize};
+
+use crate::Hit;
+
+/// Consecutive events without any hits (event numbers inclusive), listed by
+/// `trigger_extraction_tool --compact-empty` instead of writing a terminator to the binary file
+/// and a row to the metadata CSV for each
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct EmptyEventRange {
+    pub first: usize,
+    pub last: usize,
+}
+
+/// Path of the empty events list of a cluster/event file (`<name>.empty.csv`)
+pub fn empty_events_path(data_file: &Path) -> PathBuf {
+    data_file.with_extension("empty.csv")
+}
+
+pub fn read_empty_events(data_file: &Path) -> io::Result<Vec<EmptyEventRange>> {
+    let mut rdr = csv::Reader::from_path(data_file)?;
+    let mut ranges = Vec::new();
+
+    for result in rdr.deserialize() {
+        ranges.push(result?);
+    }
+
+    Ok(ranges)
+}
+
+/// Writes the empty events list, merging consecutive events into ranges
+pub struct EmptyEventsWriter {
+    writer: csv::Writer<fs::File>,
+    current: Option<EmptyEventRange>,
+}
+
+impl EmptyEventsWriter {
+    pub fn create(path: &Path) -> io::Result<EmptyEventsWriter> {
+        Ok(EmptyEventsWriter {
+            writer: csv::Writer::from_path(path)?,
+            current: None,
+        })
+    }
+
+    /// Adds an event, events must be added in order
+    pub fn push(&mut self, event: usize) -> io::Result<()> {
+        match &mut self.current {
+            Some(range) if range.last + 1 == event => range.last = event,
+            current => {
+                if let Some(range) = current.replace(EmptyEventRange { first: event, last: event }) {
+                    self.writer.serialize(range)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(range) = self.current.take() {
+            self.writer.serialize(range)?;
+        }
+
+        self.writer.flush()
+    }
+}
+
+/// Events of a cluster/event file in order of event number, with the events left out by
+/// `--compact-empty` put back in as events without hits
+pub struct WithEmptyEvents<I: Iterator<Item = (usize, Vec<Hit>)>> {
+    events: Peekable<I>,
+    empty_events: std::vec::IntoIter<EmptyEventRange>,
+    current: Option<EmptyEventRange>,
+}
+
+impl<I: Iterator<Item = (usize, Vec<Hit>)>> WithEmptyEvents<I> {
+    pub fn new(events: I, empty_events: Vec<EmptyEventRange>) -> WithEmptyEvents<I> {
+        let mut empty_events = empty_events.into_iter();
+
+        WithEmptyEvents {
+            events: events.peekable(),
+            current: empty_events.next(),
+            empty_events,
+        }
+    }
+}
+
+impl<I: Iterator<Item = (usize, Vec<Hit>)>> Iterator for WithEmptyEvents<I> {
+    type Item = (usize, Vec<Hit>);
+
+    fn next(&mut self) -> Option<(usize, Vec<Hit>)> {
+        let next_written = self.events.peek().map(|x| x.0);
+
+        match self.current {
+            Some(range) if next_written.iter().all(|&x| range.first < x) => {
+                self.current = if range.first < range.last {
+                    Some(EmptyEventRange {
+                        first: range.first + 1,
+                        last: range.last,
+                    })
+                } else {
+                    self.empty_events.next()
+                };
+
+                Some((range.first, Vec::new()))
+            }
+            _ => self.events.next(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+    use crate::HitFlags;
+
+    fn event(event: usize) -> (usize, Vec<Hit>) {
+        let hit = Hit {
+            toa: event as u64,
+            tot: 100,
+            col: 0,
+            row: 0,
+            flags: HitFlags::default(),
+        };
+
+        (event, vec![hit])
+    }
+
+    fn range(first: usize, last: usize) -> EmptyEventRange {
+        EmptyEventRange { first, last }
+    }
+
+    /// Event numbers with the number of hits in each
+    fn merged(events: Vec<(usize, Vec<Hit>)>, empty_events: Vec<EmptyEventRange>) -> Vec<(usize, usize)> {
+        WithEmptyEvents::new(events.into_iter(), empty_events)
+            .map(|(event, hits)| (event, hits.len()))
+            .collect()
+    }
+
+    #[test]
+    fn writer_merges_consecutive_events() {
+        let path = env::temp_dir().join(format!("empty_events_{}.csv", process::id()));
+
+        let mut writer = EmptyEventsWriter::create(&path).unwrap();
+
+        for event in &[1, 2, 3, 5, 7, 8] {
+            writer.push(*event).unwrap();
+        }
+
+        writer.finish().unwrap();
+
+        let ranges = read_empty_events(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(ranges, vec![range(1, 3), range(5, 5), range(7, 8)]);
+    }
+
+    #[test]
+    fn empty_events_before_first_written_event() {
+        assert_eq!(merged(vec![event(3), event(4)], vec![range(1, 2)]), vec![(1, 0), (2, 0), (3, 1), (4, 1)]);
+    }
+
+    #[test]
+    fn consecutive_empty_ranges() {
+        assert_eq!(
+            merged(vec![event(1), event(5)], vec![range(2, 3), range(4, 4)]),
+            vec![(1, 1), (2, 0), (3, 0), (4, 0), (5, 1)]
+        );
+    }
+
+    #[test]
+    fn empty_events_after_last_written_event() {
+        assert_eq!(merged(vec![event(1), event(2)], vec![range(3, 4)]), vec![(1, 1), (2, 1), (3, 0), (4, 0)]);
+    }
+
+    #[test]
+    fn no_written_events() {
+        assert_eq!(merged(Vec::new(), vec![range(1, 2), range(4, 4)]), vec![(1, 0), (2, 0), (4, 0)]);
+    }
+}
@@ -0,0 +1,197 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/io/follow_raw_data.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use glob::glob;
+
+use crate::{parse_raw_packets_from_slice, DecoderState, Hit, SpidrHeader, Trigger, DEFAULT_CLOCK_PHASES, SPIDR_MAX_HEADER_SIZE};
+
+/// Data read from the followed file at a time (bytes, 2M packets)
+const READ_CHUNK_SIZE: u64 = 16_000_000;
+
+/// Raw data file being followed as the DAQ writes it
+struct FollowedFile {
+    path: PathBuf,
+    file: fs::File,
+    position: u64,
+    header_bytes: Option<Vec<u8>>, // Start of the file, until it holds the whole header
+}
+
+/// Follows the newest raw data file matching a pattern as the DAQ writes it, decoding the data as
+/// it arrives and moving on to each new file once the current one has been read to its end. The
+/// decoder state is carried across files so the timeline continues from one file to the next.
+pub struct RawDataFollower {
+    pattern: String,
+    from_start: bool,
+    followed: Option<FollowedFile>,
+    state: Option<DecoderState>,
+}
+
+impl RawDataFollower {
+    /// With `from_start` the first file is read from its start, otherwise only the data written
+    /// after it was found is read. Later files are always read from their start.
+    pub fn new(pattern: &str, from_start: bool) -> RawDataFollower {
+        RawDataFollower {
+            pattern: pattern.to_owned(),
+            from_start,
+            followed: None,
+            state: None,
+        }
+    }
+
+    /// File being followed, `None` until a file matching the pattern has been found
+    pub fn path(&self) -> Option<&Path> {
+        self.followed.as_ref().map(|x| x.path.as_path())
+    }
+
+    /// Current time of the data (1.5625 ns units) from the global timestamp, which keeps moving
+    /// while the detector is quiet
+    pub fn timeline_toa(&self) -> Option<u64> {
+        self.state.as_ref().map(|x| x.toa_extender.timeline_toa(x.toa_extender.long_time()))
+    }
+
+    /// Decodes the data written since the last read, `None` once the followed file has been read
+    /// to its end. A file that can no longer be read is given up on, to be found again by
+    /// `follow_newest` if it is still there.
+    pub fn read(&mut self) -> io::Result<Option<(Vec<Hit>, Vec<Trigger>)>> {
+        let followed = match &mut self.followed {
+            Some(followed) => followed,
+            None => return Ok(None),
+        };
+
+        let bytes = match followed.read() {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                let path = self.followed.take().unwrap().path;
+                return Err(io::Error::new(e.kind(), format!("Failed to read {}: {}", path.display(), e)));
+            }
+        };
+
+        if bytes.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(followed.decode(&bytes, &mut self.state)))
+    }
+
+    /// Switches to the newest file matching the pattern if it is not the one being followed,
+    /// returning whether it did
+    pub fn follow_newest(&mut self) -> io::Result<bool> {
+        let newest = match newest_file(&self.pattern) {
+            Some(newest) if self.path() != Some(newest.as_path()) => newest,
+            _ => return Ok(false),
+        };
+
+        let from_start = self.from_start || self.followed.is_some();
+
+        let followed = FollowedFile::open(&newest, from_start, &mut self.state)
+            .map_err(|e| io::Error::new(e.kind(), format!("Failed to open {}: {}", newest.display(), e)))?;
+
+        self.followed = Some(followed);
+
+        Ok(true)
+    }
+}
+
+impl FollowedFile {
+    /// Opens a raw file from the start, or from the last whole packet written so far
+    fn open(path: &Path, from_start: bool, state: &mut Option<DecoderState>) -> io::Result<FollowedFile> {
+        let mut file = fs::File::open(path)?;
+
+        let mut followed = FollowedFile {
+            path: path.to_path_buf(),
+            file: file.try_clone()?,
+            position: 0,
+            header_bytes: Some(Vec::new()),
+        };
+
+        if from_start {
+            return Ok(followed);
+        }
+
+        let mut header_bytes = Vec::new();
+        (&mut file).take(u64::from(SPIDR_MAX_HEADER_SIZE)).read_to_end(&mut header_bytes)?;
+
+        // Read from the start if the header has not been written yet
+        if let Some(header) = SpidrHeader::parse(&header_bytes) {
+            let data_offset = header.data_offset() as u64;
+            let data_bytes = file.metadata()?.len().saturating_sub(data_offset);
+
+            start_decoder(&header, state);
+
+            followed.position = data_offset + data_bytes / 8 * 8;
+            followed.header_bytes = None;
+        }
+
+        Ok(followed)
+    }
+
+    fn read(&mut self) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+
+        self.file.seek(SeekFrom::Start(self.position))?;
+        (&mut self.file).take(READ_CHUNK_SIZE).read_to_end(&mut bytes)?;
+
+        self.position += bytes.len() as u64;
+
+        Ok(bytes)
+    }
+
+    fn decode(&mut self, bytes: &[u8], state: &mut Option<DecoderState>) -> (Vec<Hit>, Vec<Trigger>) {
+        let header_bytes = match &mut self.header_bytes {
+            Some(header_bytes) => header_bytes,
+            None => return parse_raw_packets_from_slice(bytes, state.as_mut().unwrap()),
+        };
+
+        header_bytes.extend_from_slice(bytes);
+
+        let header = match SpidrHeader::parse(header_bytes) {
+            Some(header) => header,
+            None => return (Vec::new(), Vec::new()),
+        };
+
+        start_decoder(&header, state);
+
+        let decoded = parse_raw_packets_from_slice(&header_bytes[header.data_offset()..], state.as_mut().unwrap());
+
+        self.header_bytes = None;
+
+        decoded
+    }
+}
+
+/// Sets up the decoder for a new file, joining its timeline onto the end of the previous file
+fn start_decoder(header: &SpidrHeader, state: &mut Option<DecoderState>) {
+    match state {
+        Some(state) => {
+            state.toa_extender.start_file();
+            state.remainder.clear();
+        }
+        None => {
+            let mut new_state = DecoderState::new(header.clock_phases().unwrap_or(DEFAULT_CLOCK_PHASES));
+            new_state.acq_mode = header.acq_mode().unwrap_or_default();
+
+            *state = Some(new_state);
+        }
+    }
+}
+
+/// Newest file matching the pattern, by modification time
+fn newest_file(pattern: &str) -> Option<PathBuf> {
+    glob(pattern)
+        .ok()?
+        .filter_map(|x| x.ok())
+        .filter(|x| x.is_file())
+        .filter_map(|x| Some((x.metadata().ok()?.modified().ok()?, x)))
+        .max()
+        .map(|(_, x)| x)
+}
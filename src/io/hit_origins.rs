@@ -0,0 +1,55 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/io/hit_origins.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::Path;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+/// Raw packet a hit was decoded from, as recorded in `hit_origins.bin` by
+/// `raw_data_parser --hit-origins`. The Nth record belongs to the Nth hit of `hits.bin`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct HitOrigin {
+    /// Index of the raw file in the run (the order of `input_files` in `hits.toml`)
+    pub file_index: u32,
+    /// Offset of the packet from the start of the raw file, after any decompression (bytes)
+    pub offset: u64,
+}
+
+impl HitOrigin {
+    pub const SIZE: usize = 12;
+}
+
+pub fn write_hit_origins_to_file(file: &mut File, origins: &[HitOrigin]) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(origins.len() * HitOrigin::SIZE);
+
+    for origin in origins {
+        buf.write_u32::<LittleEndian>(origin.file_index)?;
+        buf.write_u64::<LittleEndian>(origin.offset)?;
+    }
+
+    file.write_all(&buf)?;
+
+    Ok(())
+}
+
+/// Looks up where the hit at `hit_index` in `hits.bin` came from, without reading the rest of
+/// the file
+pub fn read_hit_origin(origins_file: &Path, hit_index: u64) -> io::Result<HitOrigin> {
+    let mut file = File::open(origins_file)?;
+
+    file.seek(SeekFrom::Start(hit_index * HitOrigin::SIZE as u64))?;
+
+    Ok(HitOrigin {
+        file_index: file.read_u32::<LittleEndian>()?,
+        offset: file.read_u64::<LittleEndian>()?,
+    })
+}
@@ -0,0 +1,104 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/io/hit_records.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use bytemuck::{Pod, Zeroable};
+use memmap2::Mmap;
+
+use crate::{decode_tot_field, encode_tot_field, Hit};
+
+/// A hit exactly as laid out in `hits.bin` and cluster files: col, row, ToA and ToT field (ToT
+/// and flags) in 16 bytes.
+/// The ToA is split into two halves as it is only 4 byte aligned in the files. Only compiled for
+/// little-endian targets, where the fields can be read in place.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Pod, Zeroable)]
+pub struct HitRecord {
+    pub col: u16,
+    pub row: u16,
+    pub toa_low: u32,
+    pub toa_high: u32,
+    pub tot: u32,
+}
+
+impl HitRecord {
+    pub fn toa(&self) -> u64 {
+        u64::from(self.toa_high) << 32 | u64::from(self.toa_low)
+    }
+    /// Null records separate the clusters in cluster files
+    pub fn is_null(&self) -> bool {
+        *self == HitRecord::default()
+    }
+}
+
+impl From<HitRecord> for Hit {
+    fn from(record: HitRecord) -> Hit {
+        let (tot, flags) = decode_tot_field(record.tot);
+
+        Hit {
+            col: record.col,
+            row: record.row,
+            toa: record.toa(),
+            tot,
+            flags,
+        }
+    }
+}
+
+impl From<Hit> for HitRecord {
+    fn from(hit: Hit) -> HitRecord {
+        HitRecord {
+            col: hit.col,
+            row: hit.row,
+            toa_low: hit.toa as u32,
+            toa_high: (hit.toa >> 32) as u32,
+            tot: encode_tot_field(hit.tot, hit.flags),
+        }
+    }
+}
+
+/// Views hit data as records without copying it. Any partial record at the end is left out.
+/// Fails if the data is not 4 byte aligned.
+pub fn cast_hit_records(bytes: &[u8]) -> io::Result<&[HitRecord]> {
+    let whole_records = bytes.len() - bytes.len() % 16;
+
+    bytemuck::try_cast_slice(&bytes[..whole_records])
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("Cannot view hit data in place: {:?}", err)))
+}
+
+/// A hits or cluster file mapped into memory, for reading its records without deserialising them
+pub struct MappedHits {
+    mmap: Option<Mmap>, // Empty files cannot be mapped
+}
+
+impl MappedHits {
+    pub fn open(data_file: &Path) -> io::Result<MappedHits> {
+        let file = fs::File::open(data_file)?;
+
+        if file.metadata()?.len() == 0 {
+            return Ok(MappedHits { mmap: None });
+        }
+
+        // Safety: the file must not be truncated while it is mapped, which holds for finished
+        // output files as the tools never modify them in place
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        Ok(MappedHits { mmap: Some(mmap) })
+    }
+
+    pub fn records(&self) -> &[HitRecord] {
+        match &self.mmap {
+            // Maps are page aligned so the cast cannot fail
+            Some(mmap) => cast_hit_records(&mmap[..]).unwrap(),
+            None => &[],
+        }
+    }
+}
@@ -0,0 +1,156 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/io/hits_index.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::io::input_source::InputSource;
+use crate::{Hit, ReadHitsIterator};
+
+/// Number of hits between entries in a hits index
+pub const HITS_INDEX_STRIDE: u64 = 100_000;
+
+/// Entry in the sparse time index written alongside a (ToA sorted) hits file
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct HitsIndexEntry {
+    pub toa: u64,
+    pub offset: u64,
+}
+
+/// Path of the index sidecar for a hits file (eg. `hits.idx` for `hits.bin`)
+pub fn hits_index_path(data_file: &Path) -> PathBuf {
+    data_file.with_extension("idx")
+}
+
+/// Collects index entries for every `HITS_INDEX_STRIDE`th hit as hits are written out.
+#[derive(Clone, Debug, Default)]
+pub struct HitsIndexBuilder {
+    hits_seen: u64,
+    pub entries: Vec<HitsIndexEntry>,
+}
+
+impl HitsIndexBuilder {
+    pub fn new() -> HitsIndexBuilder {
+        HitsIndexBuilder::default()
+    }
+
+    /// Adds the next hits written to the file
+    pub fn add(&mut self, hits: &[Hit]) {
+        for hit in hits {
+            if self.hits_seen % HITS_INDEX_STRIDE == 0 {
+                self.entries.push(HitsIndexEntry {
+                    toa: hit.toa,
+                    offset: self.hits_seen * 16,
+                });
+            }
+
+            self.hits_seen += 1;
+        }
+    }
+
+    pub fn write(&self, data_file: &Path) -> io::Result<()> {
+        write_hits_index(data_file, &self.entries)
+    }
+}
+
+pub fn write_hits_index(data_file: &Path, entries: &[HitsIndexEntry]) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(entries.len() * 16);
+
+    for entry in entries {
+        buf.write_u64::<LittleEndian>(entry.toa)?;
+        buf.write_u64::<LittleEndian>(entry.offset)?;
+    }
+
+    fs::write(hits_index_path(data_file), buf)
+}
+
+/// Reads the index sidecar of a hits file, or `None` if it has none
+pub fn read_hits_index(data_file: &Path) -> io::Result<Option<Vec<HitsIndexEntry>>> {
+    let index_file = hits_index_path(data_file);
+
+    if !index_file.exists() {
+        return Ok(None);
+    }
+
+    let buf = fs::read(&index_file)?;
+
+    if buf.len() % 16 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Size of {:#?} is not divisible by 16 (the size of an index entry)", index_file),
+        ));
+    }
+
+    let mut rdr = io::Cursor::new(buf);
+    let mut entries = Vec::new();
+
+    while (rdr.position() as usize) < rdr.get_ref().len() {
+        let toa = rdr.read_u64::<LittleEndian>()?;
+        let offset = rdr.read_u64::<LittleEndian>()?;
+
+        entries.push(HitsIndexEntry { toa, offset });
+    }
+
+    Ok(Some(entries))
+}
+
+/// Builds the index sidecar for an existing hits file
+pub fn build_hits_index(data_file: &Path) -> io::Result<Vec<HitsIndexEntry>> {
+    let mut builder = HitsIndexBuilder::new();
+
+    for hit in ReadHitsIterator::new(&data_file.to_path_buf()) {
+        builder.add(&[hit]);
+    }
+
+    builder.write(data_file)?;
+
+    Ok(builder.entries)
+}
+
+/// Finds the byte offset of the first hit with a ToA at or after `toa`, using the index if there
+/// is one and a binary search of the file otherwise.
+pub fn find_hits_offset(data_file: &Path, toa: u64) -> io::Result<u64> {
+    let mut file = InputSource::open(data_file)?;
+    let n_hits = file.len()? / 16;
+
+    let read_toa = |file: &mut InputSource, i: u64| -> io::Result<u64> {
+        // ToA follows the col and row values in each hit
+        file.seek(io::SeekFrom::Start(i * 16 + 4))?;
+        file.read_u64::<LittleEndian>()
+    };
+
+    // Narrow down the search range with the index
+    let (mut lo, mut hi) = match read_hits_index(data_file)? {
+        Some(entries) => {
+            let i = entries.iter().take_while(|entry| entry.toa < toa).count();
+
+            let lo = if i == 0 { 0 } else { entries[i - 1].offset / 16 };
+            let hi = entries.get(i).map_or(n_hits, |entry| entry.offset / 16);
+
+            (lo, hi)
+        }
+        None => (0, n_hits),
+    };
+
+    // Binary search for the first hit not before `toa`
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+
+        if read_toa(&mut file, mid)? < toa {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+
+    Ok(lo * 16)
+}
@@ -0,0 +1,112 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/io/input_source.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+
+use glob::glob;
+
+#[cfg(feature = "object-store")]
+use crate::io::object_storage::{list_objects, ObjectReader};
+
+/// Whether an input path is the URL of an object (`s3://`, or `http(s)://` for WebDAV) rather
+/// than a local file
+pub fn is_object_url(path: &Path) -> bool {
+    let path = path.to_str().unwrap_or("");
+
+    ["s3://", "s3a://", "http://", "https://"].iter().any(|x| path.starts_with(x))
+}
+
+#[cfg(not(feature = "object-store"))]
+fn object_store_unsupported(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        format!("Cannot read {}, reading from object storage needs the 'object-store' feature", path.display()),
+    )
+}
+
+/// Input files matching a glob pattern, in sorted order. Patterns that are object URLs are listed
+/// from the object store.
+pub fn glob_input_files(pattern: &str) -> io::Result<Vec<PathBuf>> {
+    if is_object_url(Path::new(pattern)) {
+        #[cfg(feature = "object-store")]
+        return list_objects(pattern);
+
+        #[cfg(not(feature = "object-store"))]
+        return Err(object_store_unsupported(Path::new(pattern)));
+    }
+
+    let paths = glob(pattern)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?
+        .filter_map(|x| x.ok()) // Check glob worked
+        .filter(|x| x.is_file()) // Check is file
+        .collect();
+
+    Ok(paths)
+}
+
+/// Size of an input file, local or in object storage (bytes)
+pub fn input_file_len(path: &Path) -> io::Result<u64> {
+    if is_object_url(path) {
+        return InputSource::open(path)?.len();
+    }
+
+    Ok(fs::metadata(path)?.len())
+}
+
+/// An input file opened from the local filesystem or an object store
+pub(crate) enum InputSource {
+    File(fs::File),
+    #[cfg(feature = "object-store")]
+    Object(ObjectReader),
+}
+
+impl InputSource {
+    pub(crate) fn open(path: &Path) -> io::Result<InputSource> {
+        if is_object_url(path) {
+            #[cfg(feature = "object-store")]
+            return Ok(InputSource::Object(ObjectReader::open(path.to_str().unwrap())?));
+
+            #[cfg(not(feature = "object-store"))]
+            return Err(object_store_unsupported(path));
+        }
+
+        Ok(InputSource::File(fs::File::open(path)?))
+    }
+
+    pub(crate) fn len(&self) -> io::Result<u64> {
+        match self {
+            InputSource::File(file) => Ok(file.metadata()?.len()),
+            #[cfg(feature = "object-store")]
+            InputSource::Object(object) => Ok(object.len()),
+        }
+    }
+}
+
+impl Read for InputSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            InputSource::File(file) => file.read(buf),
+            #[cfg(feature = "object-store")]
+            InputSource::Object(object) => object.read(buf),
+        }
+    }
+}
+
+impl Seek for InputSource {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            InputSource::File(file) => file.seek(pos),
+            #[cfg(feature = "object-store")]
+            InputSource::Object(object) => object.seek(pos),
+        }
+    }
+}
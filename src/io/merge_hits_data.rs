@@ -0,0 +1,64 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/io/merge_hits_data.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use indicatif::ProgressBar;
+
+use crate::{write_hits_to_file, HitsIndexBuilder, ReadHitsIterator, BUFFER_SIZE};
+
+/// Merges ToA sorted hits files with a k-way merge, holding only one buffer per input file in memory.
+pub fn merge_hits_files(input_files: &[PathBuf], output_file: &Path, progress_bar: &ProgressBar) -> io::Result<usize> {
+    let mut iterators: Vec<_> = input_files.iter().map(ReadHitsIterator::new).collect();
+
+    // Heads of each input, ordered by ToA then input number so equal times keep their file order
+    let mut heap = BinaryHeap::with_capacity(iterators.len());
+
+    for (i, iterator) in iterators.iter_mut().enumerate() {
+        if let Some(hit) = iterator.next() {
+            heap.push(Reverse((hit.toa, i, hit)));
+        }
+    }
+
+    let mut output_data_file = fs::File::create(output_file)?;
+    let mut hits_index = HitsIndexBuilder::new();
+
+    let mut hits_written = 0;
+    let mut hits = Vec::with_capacity(BUFFER_SIZE);
+
+    while let Some(Reverse((_, i, hit))) = heap.pop() {
+        hits.push(hit);
+
+        if let Some(next_hit) = iterators[i].next() {
+            heap.push(Reverse((next_hit.toa, i, next_hit)));
+        }
+
+        if hits.len() == BUFFER_SIZE {
+            hits_index.add(&hits);
+            write_hits_to_file(&mut output_data_file, &hits)?;
+
+            hits_written += hits.len();
+            hits.clear();
+
+            progress_bar.set_position(hits_written as u64);
+        }
+    }
+
+    hits_index.add(&hits);
+    write_hits_to_file(&mut output_data_file, &hits)?;
+
+    hits_written += hits.len();
+
+    hits_index.write(output_file)?;
+
+    Ok(hits_written)
+}
@@ -6,26 +6,147 @@
  * Authors: Jared Vann
  */
 
+#[cfg(all(feature = "zero-copy", target_endian = "little"))]
+mod hit_records;
+#[cfg(all(feature = "zero-copy", target_endian = "little"))]
+pub use hit_records::{cast_hit_records, HitRecord, MappedHits};
+
+mod decoder_checkpoints;
+pub use decoder_checkpoints::DecoderCheckpoint;
+pub use decoder_checkpoints::DecoderCheckpoints;
+
+mod empty_events;
+pub use empty_events::empty_events_path;
+pub use empty_events::read_empty_events;
+pub use empty_events::EmptyEventRange;
+pub use empty_events::EmptyEventsWriter;
+pub use empty_events::WithEmptyEvents;
+
+mod follow_raw_data;
+pub use follow_raw_data::RawDataFollower;
+
+mod hit_origins;
+pub use hit_origins::read_hit_origin;
+pub use hit_origins::write_hit_origins_to_file;
+pub use hit_origins::HitOrigin;
+
+mod hits_index;
+pub use hits_index::build_hits_index;
+pub use hits_index::find_hits_offset;
+pub use hits_index::hits_index_path;
+pub use hits_index::read_hits_index;
+pub use hits_index::write_hits_index;
+pub use hits_index::HitsIndexBuilder;
+pub use hits_index::HitsIndexEntry;
+pub use hits_index::HITS_INDEX_STRIDE;
+
+mod input_source;
+pub use input_source::glob_input_files;
+pub use input_source::input_file_len;
+pub use input_source::is_object_url;
+
+#[cfg(feature = "object-store")]
+mod object_storage;
+#[cfg(feature = "object-store")]
+pub use object_storage::list_objects;
+#[cfg(feature = "object-store")]
+pub use object_storage::ObjectReader;
+
+mod merge_hits_data;
+pub use merge_hits_data::merge_hits_files;
+
+mod raw_file;
+pub use raw_file::is_raw_data_file;
+pub use raw_file::RawCompression;
+pub use raw_file::RawFile;
+
 mod read_cluster_data;
 pub use read_cluster_data::read_cluster_data;
+pub use read_cluster_data::read_signed_cluster_data;
 pub use read_cluster_data::ReadClusterIterator;
+pub use read_cluster_data::ReadSignedClusterIterator;
+pub use read_cluster_data::SignedHit;
+
+mod read_gate_data;
+pub use read_gate_data::read_gate_data;
 
 mod read_hits_data;
 pub use read_hits_data::read_hits_data;
+pub use read_hits_data::read_hits_time_range;
 pub use read_hits_data::ReadHitsIterator;
 
 mod read_raw_data;
 pub use read_raw_data::read_raw_data;
 pub use read_raw_data::ReadRawDataMode;
 
+mod read_raw_packets;
+pub use read_raw_packets::ReadRawPacketIterator;
+
+mod read_spidr_header;
+pub use read_spidr_header::read_spidr_header;
+
 mod read_trigger_data;
+pub use read_trigger_data::read_run_triggers;
 pub use read_trigger_data::read_trigger_data;
+pub use read_trigger_data::read_trigger_records;
+
+mod read_trigger_windows;
+pub use read_trigger_windows::read_trigger_windows;
+
+mod run_summary;
+pub use run_summary::read_run_summary;
+pub use run_summary::update_run_summary;
+pub use run_summary::RecordCounts;
+pub use run_summary::RECORD_COUNTS_SECTION;
+pub use run_summary::RUN_SUMMARY_FILENAME;
+
+mod t3pa;
+pub use t3pa::read_t3pa;
+pub use t3pa::write_t3pa;
+pub use t3pa::T3paRecord;
 
 mod write_cluster_data;
 pub use write_cluster_data::write_cluster_to_file;
+pub use write_cluster_data::write_signed_cluster_to_file;
+
+mod write_gate_data;
+pub use write_gate_data::write_gates_to_csv;
 
 mod write_hits_data;
 pub use write_hits_data::write_hits_to_file;
 
+mod write_timestamp_data;
+pub use write_timestamp_data::write_timestamps_to_csv;
+
+mod write_metadata_csv;
+pub use write_metadata_csv::FlushPolicy;
+pub use write_metadata_csv::MetadataCsvWriter;
+
+#[cfg(feature = "nexus")]
+mod write_nexus;
+#[cfg(feature = "nexus")]
+pub use write_nexus::write_nexus_event_data;
+
+mod write_npy;
+pub use write_npy::write_npy_f32;
+pub use write_npy::write_npy_i64;
+pub use write_npy::NpyU32Writer;
+
+#[cfg(feature = "parquet")]
+mod write_parquet;
+#[cfg(feature = "parquet")]
+pub use write_parquet::ClusterHitsParquetWriter;
+
+mod write_png;
+pub use write_png::write_greyscale_png;
+
+mod write_tiff;
+pub use write_tiff::write_tiff;
+pub use write_tiff::TiffPixel;
+pub use write_tiff::TiffSampleFormat;
+pub use write_tiff::TiffStackWriter;
+
 mod write_trigger_data;
+pub use write_trigger_data::write_trigger_records_to_file;
 pub use write_trigger_data::write_triggers_to_csv;
+pub use write_trigger_data::write_triggers_to_csv_with_utc;
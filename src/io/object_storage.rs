@@ -0,0 +1,170 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/io/object_storage.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::env;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::PathBuf;
+
+use futures::TryStreamExt;
+use object_store::path::Path as ObjectPath;
+use object_store::ObjectStore;
+use tokio::runtime::Runtime;
+use url::Url;
+
+/// Size of the ranges fetched from the object store per request (bytes)
+const OBJECT_CHUNK_SIZE: u64 = 8 << 20;
+
+/// Connects to the store holding `url`. S3 credentials, region and endpoint are taken from the
+/// usual `AWS_*` environment variables.
+fn open_store(url: &str) -> io::Result<(Box<dyn ObjectStore>, ObjectPath, Runtime)> {
+    let url = Url::parse(url).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid URL '{}': {}", url, err)))?;
+
+    let mut options: Vec<_> = env::vars().map(|(key, value)| (key.to_ascii_lowercase(), value)).collect();
+
+    // Plain HTTP is refused unless asked for, which giving an http:// URL does
+    if url.scheme() == "http" {
+        options.push(("allow_http".to_owned(), "true".to_owned()));
+    }
+
+    let (store, path) = object_store::parse_url_opts(&url, options).map_err(object_store_error)?;
+
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+
+    Ok((store, path, runtime))
+}
+
+fn object_store_error(err: object_store::Error) -> io::Error {
+    match err {
+        object_store::Error::NotFound { .. } => io::Error::new(io::ErrorKind::NotFound, err.to_string()),
+        _ => io::Error::new(io::ErrorKind::Other, err.to_string()),
+    }
+}
+
+/// An object in an S3 or WebDAV/HTTP store, read as a stream of ranged requests so that large
+/// files are never staged to local disk
+pub struct ObjectReader {
+    store: Box<dyn ObjectStore>,
+    path: ObjectPath,
+    runtime: Runtime,
+    size: u64,
+    pos: u64,
+    chunk: Vec<u8>,
+    chunk_start: u64,
+}
+
+impl ObjectReader {
+    pub fn open(url: &str) -> io::Result<ObjectReader> {
+        let (store, path, runtime) = open_store(url)?;
+
+        let size = runtime.block_on(store.head(&path)).map_err(object_store_error)?.size as u64;
+
+        Ok(ObjectReader {
+            store,
+            path,
+            runtime,
+            size,
+            pos: 0,
+            chunk: Vec::new(),
+            chunk_start: 0,
+        })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    fn fetch_chunk(&mut self) -> io::Result<()> {
+        let end = (self.pos + OBJECT_CHUNK_SIZE).min(self.size);
+        let range = self.pos as usize..end as usize;
+
+        let bytes = self.runtime.block_on(self.store.get_range(&self.path, range)).map_err(object_store_error)?;
+
+        self.chunk = bytes.to_vec();
+        self.chunk_start = self.pos;
+
+        Ok(())
+    }
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.size {
+            return Ok(0);
+        }
+
+        if self.pos < self.chunk_start || self.pos >= self.chunk_start + self.chunk.len() as u64 {
+            self.fetch_chunk()?;
+        }
+
+        let offset = (self.pos - self.chunk_start) as usize;
+        let n = buf.len().min(self.chunk.len() - offset);
+
+        buf[..n].copy_from_slice(&self.chunk[offset..offset + n]);
+        self.pos += n as u64;
+
+        Ok(n)
+    }
+}
+
+impl Seek for ObjectReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+        };
+
+        match new_pos {
+            Some(new_pos) => {
+                self.pos = new_pos;
+                Ok(new_pos)
+            }
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "Seek to a negative position")),
+        }
+    }
+}
+
+/// Lists the objects matching a glob pattern URL (e.g. `s3://bucket/raw/run*.dat`), in sorted
+/// order. Only the part of the store under the pattern's fixed prefix is listed.
+pub fn list_objects(pattern: &str) -> io::Result<Vec<PathBuf>> {
+    let glob_pattern = glob::Pattern::new(pattern).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))?;
+
+    let glob_start = pattern.find(['*', '?', '[']).unwrap_or(pattern.len());
+    let prefix_url = &pattern[..pattern[..glob_start].rfind('/').map_or(glob_start, |i| i + 1)];
+
+    let (store, prefix, runtime) = open_store(prefix_url)?;
+
+    // The URL of each object is its location under the root of the store (e.g. `s3://bucket`)
+    let root_url = Url::parse(prefix_url).unwrap()[..url::Position::BeforePath].to_owned();
+
+    let objects = runtime
+        .block_on(store.list(Some(&prefix)).try_collect::<Vec<_>>())
+        .map_err(object_store_error)?;
+
+    let options = glob::MatchOptions {
+        require_literal_separator: true,
+        ..Default::default()
+    };
+
+    let mut urls: Vec<_> = objects
+        .iter()
+        .map(|object| format!("{}/{}", root_url, object.location))
+        .filter(|url| glob_pattern.matches_with(url, options))
+        .map(PathBuf::from)
+        .collect();
+
+    urls.sort();
+
+    Ok(urls)
+}
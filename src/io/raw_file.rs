@@ -0,0 +1,134 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/io/raw_file.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, SeekFrom};
+use std::path::Path;
+
+use flate2::bufread::MultiGzDecoder;
+
+use crate::io::input_source::InputSource;
+
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+/// Size of the buffer compressed data is read into (bytes)
+const COMPRESSED_BUFFER_SIZE: usize = 1 << 20;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RawCompression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl RawCompression {
+    /// Identifies the compression of a file from its first bytes
+    pub fn detect(magic: &[u8]) -> RawCompression {
+        if magic.starts_with(&GZIP_MAGIC) {
+            RawCompression::Gzip
+        } else if magic.starts_with(&ZSTD_MAGIC) {
+            RawCompression::Zstd
+        } else {
+            RawCompression::None
+        }
+    }
+}
+
+/// Whether a path names a raw data file, either as written by the DAQ (`.dat`) or compressed for
+/// archiving (`.dat.gz`, `.dat.zst`)
+pub fn is_raw_data_file(path: &Path) -> bool {
+    let file_name = path.file_name().and_then(|x| x.to_str()).unwrap_or("");
+
+    [".dat", ".dat.gz", ".dat.zst"].iter().any(|x| file_name.ends_with(x))
+}
+
+/// Keeps count of the bytes read from the stored file, for progress through compressed files
+struct CountingFile {
+    source: InputSource,
+    bytes_read: u64,
+}
+
+impl Read for CountingFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.source.read(buf)?;
+        self.bytes_read += bytes_read as u64;
+
+        Ok(bytes_read)
+    }
+}
+
+enum RawFileReader {
+    Plain(CountingFile),
+    Gzip(MultiGzDecoder<BufReader<CountingFile>>),
+    Zstd(zstd::Decoder<'static, BufReader<CountingFile>>),
+}
+
+/// A raw data file, local or in object storage, decompressed on the fly if it is gzip or zstd
+/// compressed (detected from its magic bytes rather than its name), so archived files never need
+/// expanding on disk first
+pub struct RawFile {
+    reader: RawFileReader,
+    compression: RawCompression,
+}
+
+impl RawFile {
+    pub fn open(path: &Path) -> io::Result<RawFile> {
+        let mut source = InputSource::open(path)?;
+
+        let mut magic = [0; 4];
+        let magic_len = source.read(&mut magic)?;
+        source.seek(SeekFrom::Start(0))?;
+
+        let compression = RawCompression::detect(&magic[..magic_len]);
+
+        let file = CountingFile { source, bytes_read: 0 };
+
+        let reader = match compression {
+            RawCompression::None => RawFileReader::Plain(file),
+            RawCompression::Gzip => RawFileReader::Gzip(MultiGzDecoder::new(BufReader::with_capacity(COMPRESSED_BUFFER_SIZE, file))),
+            RawCompression::Zstd => RawFileReader::Zstd(zstd::Decoder::with_buffer(BufReader::with_capacity(COMPRESSED_BUFFER_SIZE, file))?),
+        };
+
+        Ok(RawFile { reader, compression })
+    }
+
+    pub fn compression(&self) -> RawCompression {
+        self.compression
+    }
+
+    /// Size of the stored file (ie. of the compressed data for compressed files)
+    pub fn file_len(&self) -> io::Result<u64> {
+        self.counting_file().source.len()
+    }
+
+    /// Bytes read from the stored file so far, which runs ahead of the data returned by up to a
+    /// buffer
+    pub fn file_bytes_read(&self) -> u64 {
+        self.counting_file().bytes_read
+    }
+
+    fn counting_file(&self) -> &CountingFile {
+        match &self.reader {
+            RawFileReader::Plain(file) => file,
+            RawFileReader::Gzip(decoder) => decoder.get_ref().get_ref(),
+            RawFileReader::Zstd(decoder) => decoder.get_ref().get_ref(),
+        }
+    }
+}
+
+impl Read for RawFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.reader {
+            RawFileReader::Plain(file) => file.read(buf),
+            RawFileReader::Gzip(decoder) => decoder.read(buf),
+            RawFileReader::Zstd(decoder) => decoder.read(buf),
+        }
+    }
+}
@@ -6,47 +6,27 @@
  * Authors: Jared Vann
  */
 
-use std::fs;
 use std::io;
-use std::io::prelude::*;
-use std::io::Cursor;
+use std::io::BufReader;
+use std::path::Path;
 
-use byteorder::{LittleEndian, ReadBytesExt};
-
-use crate::{Hit, BUFFER_SIZE};
+use crate::io::input_source::InputSource;
+use crate::io::read_hits_data::read_hit;
+use crate::io::write_cluster_data::SIGNED_TOA_BIAS;
+use crate::{Hit, HitFlags, DEFAULT_READ_BUFFER_SIZE};
 
 pub fn read_cluster_data(data_file: &str) -> io::Result<Vec<Vec<Hit>>> {
-    let mut file = fs::File::open(&data_file)?;
+    let mut reader = BufReader::with_capacity(DEFAULT_READ_BUFFER_SIZE, InputSource::open(Path::new(data_file))?);
 
     let mut clusters = Vec::new();
     let mut current_cluster = Vec::new();
 
-    let mut buf: [u8; BUFFER_SIZE * 16] = [0; BUFFER_SIZE * 16];
-
-    loop {
-        let bytes_read = file.read(&mut buf)?;
-
-        if bytes_read == 0 {
-            break;
-        }
-
-        // Check that number of bytes is exactly divisible by the size of a hit
-        assert!(bytes_read % 16 == 0);
-
-        let mut cur = Cursor::new(&buf[..]);
-
-        for _ in 0..(bytes_read / 16) {
-            let col = cur.read_u16::<LittleEndian>()?;
-            let row = cur.read_u16::<LittleEndian>()?;
-            let toa = cur.read_u64::<LittleEndian>()?;
-            let tot = cur.read_u32::<LittleEndian>()?;
-
-            if col == 0 && row == 0 && toa == 0 && tot == 0 {
-                clusters.push(current_cluster);
-                current_cluster = Vec::new();
-            } else {
-                current_cluster.push(Hit { col, row, toa, tot });
-            }
+    while let Some(hit) = read_hit(&mut reader)? {
+        if hit.col == 0 && hit.row == 0 && hit.toa == 0 && hit.tot == 0 {
+            clusters.push(current_cluster);
+            current_cluster = Vec::new();
+        } else {
+            current_cluster.push(hit);
         }
     }
 
@@ -54,19 +34,19 @@ pub fn read_cluster_data(data_file: &str) -> io::Result<Vec<Vec<Hit>>> {
 }
 
 pub struct ReadClusterIterator {
-    file: fs::File,
-    buf: [u8; BUFFER_SIZE * 16],
-    bytes_read_from_buf: usize,
-    bytes_left_to_read_from_buf: usize,
+    reader: BufReader<InputSource>,
 }
 
 impl ReadClusterIterator {
     pub fn new(data_file: &str) -> ReadClusterIterator {
+        ReadClusterIterator::with_buffer_size(data_file, DEFAULT_READ_BUFFER_SIZE)
+    }
+
+    /// Reads the file in blocks of `buffer_size` bytes, larger blocks cut down on round trips to
+    /// network filesystems
+    pub fn with_buffer_size(data_file: &str, buffer_size: usize) -> ReadClusterIterator {
         ReadClusterIterator {
-            file: fs::File::open(&data_file).unwrap(),
-            buf: [0; BUFFER_SIZE * 16],
-            bytes_read_from_buf: 0,
-            bytes_left_to_read_from_buf: 0,
+            reader: BufReader::with_capacity(buffer_size, InputSource::open(Path::new(data_file)).unwrap()),
         }
     }
 }
@@ -77,39 +57,85 @@ impl Iterator for ReadClusterIterator {
     fn next(&mut self) -> Option<Vec<Hit>> {
         let mut current_cluster = Vec::new();
 
-        loop {
-            if self.bytes_left_to_read_from_buf == 0 {
-                let bytes_read_into_buf = self.file.read(&mut self.buf).unwrap();
+        // Clusters are terminated by a null hit, an unterminated cluster at the end is dropped
+        while let Some(hit) = read_hit(&mut self.reader).unwrap() {
+            if hit.col == 0 && hit.row == 0 && hit.toa == 0 && hit.tot == 0 {
+                return Some(current_cluster);
+            }
 
-                if bytes_read_into_buf == 0 {
-                    break;
-                }
+            current_cluster.push(hit);
+        }
 
-                // Check that number of bytes is exactly divisible by the size of a hit
-                assert!(bytes_read_into_buf % 16 == 0);
+        None
+    }
+}
 
-                self.bytes_left_to_read_from_buf = bytes_read_into_buf;
-                self.bytes_read_from_buf = 0;
-            }
+/// Hit of a signed cluster file (written by `write_signed_cluster_to_file`, eg. trigger events
+/// extracted with `--relative-to trigger`)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SignedHit {
+    pub toa: i64, // Relative to the reference time the cluster was written against, negative before it
+    pub tot: u32,
+    pub col: u16,
+    pub row: u16,
+    pub flags: HitFlags,
+}
 
-            let mut cur = Cursor::new(&self.buf[..]);
-            cur.set_position(self.bytes_read_from_buf as u64);
+impl SignedHit {
+    fn from_record(hit: Hit) -> SignedHit {
+        SignedHit {
+            toa: (hit.toa ^ SIGNED_TOA_BIAS) as i64,
+            tot: hit.tot,
+            col: hit.col,
+            row: hit.row,
+            flags: hit.flags,
+        }
+    }
+}
+
+pub fn read_signed_cluster_data(data_file: &str) -> io::Result<Vec<Vec<SignedHit>>> {
+    let mut reader = BufReader::with_capacity(DEFAULT_READ_BUFFER_SIZE, InputSource::open(Path::new(data_file))?);
+
+    let mut clusters = Vec::new();
+    let mut current_cluster = Vec::new();
 
-            for _ in 0..(self.bytes_left_to_read_from_buf / 16) {
-                let col = cur.read_u16::<LittleEndian>().unwrap();
-                let row = cur.read_u16::<LittleEndian>().unwrap();
-                let toa = cur.read_u64::<LittleEndian>().unwrap();
-                let tot = cur.read_u32::<LittleEndian>().unwrap();
+    while let Some(hit) = read_hit(&mut reader)? {
+        if hit.col == 0 && hit.row == 0 && hit.toa == 0 && hit.tot == 0 {
+            clusters.push(current_cluster);
+            current_cluster = Vec::new();
+        } else {
+            current_cluster.push(SignedHit::from_record(hit));
+        }
+    }
 
-                self.bytes_left_to_read_from_buf -= 16;
-                self.bytes_read_from_buf += 16;
+    Ok(clusters)
+}
 
-                if col == 0 && row == 0 && toa == 0 && tot == 0 {
-                    return Some(current_cluster);
-                } else {
-                    current_cluster.push(Hit { col, row, toa, tot });
-                }
+pub struct ReadSignedClusterIterator {
+    reader: BufReader<InputSource>,
+}
+
+impl ReadSignedClusterIterator {
+    pub fn new(data_file: &str) -> ReadSignedClusterIterator {
+        ReadSignedClusterIterator {
+            reader: BufReader::with_capacity(DEFAULT_READ_BUFFER_SIZE, InputSource::open(Path::new(data_file)).unwrap()),
+        }
+    }
+}
+
+impl Iterator for ReadSignedClusterIterator {
+    type Item = Vec<SignedHit>;
+
+    fn next(&mut self) -> Option<Vec<SignedHit>> {
+        let mut current_cluster = Vec::new();
+
+        // The offset binary ToA of a hit is never zero, so only the terminator reads as a null hit
+        while let Some(hit) = read_hit(&mut self.reader).unwrap() {
+            if hit.col == 0 && hit.row == 0 && hit.toa == 0 && hit.tot == 0 {
+                return Some(current_cluster);
             }
+
+            current_cluster.push(SignedHit::from_record(hit));
         }
 
         None
@@ -0,0 +1,27 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/io/read_gate_data.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use csv;
+
+use crate::Gate;
+
+pub fn read_gate_data(data_file: &PathBuf) -> io::Result<Vec<Gate>> {
+    let file = fs::File::open(&data_file)?;
+    let mut rdr = csv::Reader::from_reader(file);
+    let mut gates = Vec::new();
+
+    for result in rdr.deserialize() {
+        gates.push(result?);
+    }
+
+    Ok(gates)
+}
@@ -6,55 +6,48 @@
  * Authors: Jared Vann
  */
 
-use std::fs;
 use std::io;
 use std::io::prelude::*;
-use std::io::Cursor;
+use std::io::BufReader;
+use std::io::SeekFrom;
 use std::path::PathBuf;
 
 use byteorder::{LittleEndian, ReadBytesExt};
 use separator::Separatable as _;
 
-use crate::{Hit, BUFFER_SIZE};
+use crate::io::input_source::InputSource;
+use crate::{decode_hit_record, find_hits_offset, Hit, DEFAULT_READ_BUFFER_SIZE, HIT_RECORD_SIZE};
+
+/// Reads the next 16 byte hit, or `None` at the end of the data. A partial hit at the end (e.g.
+/// one still being written) is ignored.
+pub(crate) fn read_hit<R: Read>(reader: &mut R) -> io::Result<Option<Hit>> {
+    let mut bytes = [0; HIT_RECORD_SIZE];
+
+    match reader.read_exact(&mut bytes) {
+        Ok(()) => Ok(Some(decode_hit_record(&bytes))),
+        Err(ref err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(err) => Err(err),
+    }
+}
 
 pub fn read_hits_data(data_file: &PathBuf, max_hits: Option<usize>) -> io::Result<Vec<Hit>> {
-    let mut file = fs::File::open(&data_file)?;
+    let mut reader = BufReader::with_capacity(DEFAULT_READ_BUFFER_SIZE, InputSource::open(data_file)?);
     let mut hits = Vec::new();
-    let mut buf: [u8; BUFFER_SIZE * 16] = [0; BUFFER_SIZE * 16];
 
-    loop {
-        let bytes_read = file.read(&mut buf)?;
-
-        if bytes_read == 0 {
-            break;
+    while let Some(hit) = read_hit(&mut reader)? {
+        if hit.col == 0 && hit.row == 0 && hit.toa == 0 && hit.tot == 0 {
+            panic!("Unexpected null hit")
         }
 
-        if hits.len() % 1000 == 0 {
+        hits.push(hit);
+
+        if hits.len() % 100_000 == 0 {
             print!("\rLoaded {} hits", hits.len().separated_string());
         }
 
-        // Check that number of bytes is exactly divisible by the size of a hit
-        assert!(bytes_read % 16 == 0);
-
-        let mut cur = Cursor::new(&buf[..]);
-
-        for _ in 0..(bytes_read / 16) {
-            let col = cur.read_u16::<LittleEndian>()?;
-            let row = cur.read_u16::<LittleEndian>()?;
-            let toa = cur.read_u64::<LittleEndian>()?;
-            let tot = cur.read_u32::<LittleEndian>()?;
-
-            if col == 0 && row == 0 && toa == 0 && tot == 0 {
-                panic!("Unexpected null hit")
-            } else {
-                hits.push(Hit { col, row, toa, tot });
-
-                if let Some(max) = max_hits {
-                    if hits.len() == max {
-                        print!("\rLoaded {} hits", hits.len().separated_string());
-                        return Ok(hits);
-                    }
-                }
+        if let Some(max) = max_hits {
+            if hits.len() == max {
+                break;
             }
         }
     }
@@ -64,53 +57,60 @@ pub fn read_hits_data(data_file: &PathBuf, max_hits: Option<usize>) -> io::Resul
     Ok(hits)
 }
 
+/// Reads the ToA of the first and last hit in a (ToA sorted) hits file without reading the rest of
+/// the file. Returns `None` for an empty file.
+pub fn read_hits_time_range(data_file: &PathBuf) -> io::Result<Option<(u64, u64)>> {
+    let mut file = InputSource::open(data_file)?;
+    let file_size = file.len()?;
+
+    if file_size < 16 {
+        return Ok(None);
+    }
+
+    // ToA follows the col and row values in each hit
+    file.seek(SeekFrom::Start(4))?;
+    let first_toa = file.read_u64::<LittleEndian>()?;
+
+    file.seek(SeekFrom::Start(file_size - file_size % 16 - 16 + 4))?;
+    let last_toa = file.read_u64::<LittleEndian>()?;
+
+    Ok(Some((first_toa, last_toa)))
+}
+
 pub struct ReadHitsIterator {
-    file: fs::File,
-    buf: [u8; BUFFER_SIZE * 16],
-    bytes_read_from_buf: usize,
-    bytes_left_to_read_from_buf: usize,
+    data_file: PathBuf,
+    reader: BufReader<InputSource>,
 }
 
 impl ReadHitsIterator {
     pub fn new(data_file: &PathBuf) -> ReadHitsIterator {
+        ReadHitsIterator::with_buffer_size(data_file, DEFAULT_READ_BUFFER_SIZE)
+    }
+
+    /// Reads the file in blocks of `buffer_size` bytes, larger blocks cut down on round trips to
+    /// network filesystems
+    pub fn with_buffer_size(data_file: &PathBuf, buffer_size: usize) -> ReadHitsIterator {
         ReadHitsIterator {
-            file: fs::File::open(&data_file).unwrap(),
-            buf: [0; BUFFER_SIZE * 16],
-            bytes_read_from_buf: 0,
-            bytes_left_to_read_from_buf: 0,
+            data_file: data_file.to_owned(),
+            reader: BufReader::with_capacity(buffer_size, InputSource::open(data_file).unwrap()),
         }
     }
+
+    /// Moves the iterator to the first hit with a ToA at or after `toa` (clock units), using the
+    /// index sidecar of the file if there is one.
+    pub fn seek_to_time(&mut self, toa: u64) -> io::Result<()> {
+        let offset = find_hits_offset(&self.data_file, toa)?;
+
+        self.reader.seek(SeekFrom::Start(offset))?;
+
+        Ok(())
+    }
 }
 
 impl Iterator for ReadHitsIterator {
     type Item = Hit;
 
     fn next(&mut self) -> Option<Hit> {
-        if self.bytes_left_to_read_from_buf == 0 {
-            let bytes_read_into_buf = self.file.read(&mut self.buf).unwrap();
-
-            if bytes_read_into_buf == 0 {
-                return None;
-            }
-
-            // Check that number of bytes is exactly divisible by the size of a hit
-            assert!(bytes_read_into_buf % 16 == 0);
-
-            self.bytes_left_to_read_from_buf = bytes_read_into_buf;
-            self.bytes_read_from_buf = 0;
-        }
-
-        let mut cur = Cursor::new(&self.buf[..]);
-        cur.set_position(self.bytes_read_from_buf as u64);
-
-        let col = cur.read_u16::<LittleEndian>().unwrap();
-        let row = cur.read_u16::<LittleEndian>().unwrap();
-        let toa = cur.read_u64::<LittleEndian>().unwrap();
-        let tot = cur.read_u32::<LittleEndian>().unwrap();
-
-        self.bytes_left_to_read_from_buf -= 16;
-        self.bytes_read_from_buf += 16;
-
-        Some(Hit { col, row, toa, tot })
+        read_hit(&mut self.reader).unwrap()
     }
 }
@@ -0,0 +1,169 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/io/read_raw_packets.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::io;
+use std::io::prelude::*;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::{is_plausible_packet, BUFFER_SIZE};
+
+/// Number of consecutive plausible packets needed to accept a resynchronisation point
+const RESYNC_PACKETS: usize = 4;
+
+/// Iterates over the 8 byte packets of a raw data file, starting from the current reader position
+/// (ie. after the SPIDR header).
+///
+/// Partial reads are carried over between buffers, and an incomplete packet at the end of the
+/// file (eg. from a crashed DAQ) is counted in `truncated_bytes` rather than causing a panic.
+/// When `resync` is set, packets with an implausible header are skipped byte by byte until a run
+/// of plausible packets is found again.
+///
+/// A failed read ends the iteration early, with the error kept for `take_error` so the caller can
+/// tell it apart from the end of the file.
+pub struct ReadRawPacketIterator<R: Read> {
+    reader: R,
+    buf: Vec<u8>,
+    pos: usize,
+    len: usize,
+    eof: bool,
+    resync: bool,
+    bytes_consumed: u64,
+    truncated_bytes: usize,
+    skipped_bytes: usize,
+    resyncs: usize,
+    error: Option<io::Error>,
+}
+
+impl<R: Read> ReadRawPacketIterator<R> {
+    pub fn new(reader: R, resync: bool) -> ReadRawPacketIterator<R> {
+        ReadRawPacketIterator {
+            reader,
+            buf: vec![0; BUFFER_SIZE * 8],
+            pos: 0,
+            len: 0,
+            eof: false,
+            resync,
+            bytes_consumed: 0,
+            truncated_bytes: 0,
+            skipped_bytes: 0,
+            resyncs: 0,
+            error: None,
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+
+    /// Bytes of packet data consumed so far (including skipped bytes)
+    pub fn bytes_consumed(&self) -> u64 {
+        self.bytes_consumed
+    }
+
+    /// Bytes at the end of the file that did not form a complete packet
+    pub fn truncated_bytes(&self) -> usize {
+        self.truncated_bytes
+    }
+
+    /// Bytes skipped while resynchronising on corrupted data
+    pub fn skipped_bytes(&self) -> usize {
+        self.skipped_bytes
+    }
+
+    /// Number of times the packet alignment was recovered
+    pub fn resyncs(&self) -> usize {
+        self.resyncs
+    }
+
+    /// Error that stopped the iteration before the end of the file, if reading failed
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    /// Makes sure at least `n` bytes are buffered if the file has them, returning whether they are.
+    fn ensure_buffered(&mut self, n: usize) -> bool {
+        if self.len - self.pos >= n {
+            return true;
+        }
+
+        // Move leftover bytes to the start of the buffer and top it up
+        self.buf.copy_within(self.pos..self.len, 0);
+        self.len -= self.pos;
+        self.pos = 0;
+
+        while !self.eof && self.len < self.buf.len() {
+            match self.reader.read(&mut self.buf[self.len..]) {
+                Ok(0) => self.eof = true,
+                Ok(bytes_read) => self.len += bytes_read,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    self.error = Some(e);
+                    self.eof = true;
+                }
+            }
+        }
+
+        self.len - self.pos >= n
+    }
+
+    fn packet_at(&self, offset: usize) -> u64 {
+        LittleEndian::read_u64(&self.buf[self.pos + offset..self.pos + offset + 8])
+    }
+
+    /// Finds the closest byte offset (1 to 7) after which a run of plausible packets follows
+    fn find_resync_offset(&mut self) -> Option<usize> {
+        if !self.ensure_buffered(8 * RESYNC_PACKETS + 7) {
+            return None;
+        }
+
+        (1..8).find(|&offset| (0..RESYNC_PACKETS).all(|i| is_plausible_packet(self.packet_at(offset + i * 8))))
+    }
+}
+
+impl<R: Read> Iterator for ReadRawPacketIterator<R> {
+    type Item = u64;
+
+    fn next(&mut self) -> Option<u64> {
+        loop {
+            if !self.ensure_buffered(8) {
+                if self.error.is_some() {
+                    return None;
+                }
+
+                self.truncated_bytes += self.len - self.pos;
+                self.bytes_consumed += (self.len - self.pos) as u64;
+                self.pos = self.len;
+
+                return None;
+            }
+
+            let packet = self.packet_at(0);
+
+            if !self.resync || is_plausible_packet(packet) {
+                self.pos += 8;
+                self.bytes_consumed += 8;
+
+                return Some(packet);
+            }
+
+            // Corrupted data, realign on the next run of plausible packets or drop the packet
+            let skip = match self.find_resync_offset() {
+                Some(offset) => {
+                    self.resyncs += 1;
+                    offset
+                }
+                None => 8,
+            };
+
+            self.pos += skip;
+            self.bytes_consumed += skip as u64;
+            self.skipped_bytes += skip;
+        }
+    }
+}
@@ -0,0 +1,29 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/io/read_spidr_header.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::io;
+use std::io::prelude::*;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::{SpidrHeader, SPIDR_MAX_HEADER_SIZE};
+
+/// Reads the header of a SPIDR data file and leaves the reader positioned at the first packet.
+/// The header is read through rather than seeked past, so compressed files can be read as a
+/// stream.
+pub fn read_spidr_header<R: Read>(reader: &mut R) -> io::Result<SpidrHeader> {
+    let mut bytes = vec![0; 8];
+    reader.read_exact(&mut bytes)?;
+
+    let header_size = LittleEndian::read_u32(&bytes[4..8]).min(SPIDR_MAX_HEADER_SIZE);
+
+    bytes.resize(8 + header_size as usize, 0);
+    reader.read_exact(&mut bytes[8..])?;
+
+    Ok(SpidrHeader::parse(&bytes).unwrap())
+}
@@ -8,11 +8,13 @@
 
 use std::fs;
 use std::io;
-use std::path::PathBuf;
+use std::io::Read as _;
+use std::path::{Path, PathBuf};
 
+use byteorder::{LittleEndian, ReadBytesExt};
 use csv;
 
-use crate::Trigger;
+use crate::{Trigger, TriggerRecord};
 
 pub fn read_trigger_data(data_file: &PathBuf) -> io::Result<Vec<Trigger>> {
     let file = fs::File::open(&data_file)?;
@@ -25,3 +27,85 @@ pub fn read_trigger_data(data_file: &PathBuf) -> io::Result<Vec<Trigger>> {
 
     Ok(triggers)
 }
+
+/// Reads the full precision trigger records from a `triggers.bin` file, rejecting files without
+/// the header or of a version this reader does not know.
+pub fn read_trigger_records(data_file: &PathBuf) -> io::Result<Vec<TriggerRecord>> {
+    let file = fs::File::open(&data_file)?;
+    let file_size = file.metadata()?.len() as usize;
+
+    let mut rdr = io::BufReader::new(file);
+
+    let mut magic = [0; 4];
+
+    if file_size < TriggerRecord::HEADER_SIZE || rdr.read_exact(&mut magic).is_err() || magic != TriggerRecord::FILE_MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{:#?} has no triggers.bin header, it was written by an older version and needs parsing again",
+                data_file
+            ),
+        ));
+    }
+
+    let version = rdr.read_u32::<LittleEndian>()?;
+
+    if version != TriggerRecord::FILE_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{:#?} is triggers.bin version {}, only version {} can be read",
+                data_file,
+                version,
+                TriggerRecord::FILE_VERSION
+            ),
+        ));
+    }
+
+    let records_size = file_size - TriggerRecord::HEADER_SIZE;
+
+    if records_size % TriggerRecord::SIZE != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Size of {:#?} is not divisible by {} (the size of a trigger)",
+                data_file,
+                TriggerRecord::SIZE
+            ),
+        ));
+    }
+
+    let mut records = Vec::with_capacity(records_size / TriggerRecord::SIZE);
+
+    for _ in 0..(records_size / TriggerRecord::SIZE) {
+        let event = rdr.read_u32::<LittleEndian>()?;
+        let raw_count = rdr.read_u16::<LittleEndian>()?;
+        let channel = rdr.read_u8()?;
+        let _reserved = rdr.read_u8()?;
+        let coarse = rdr.read_u64::<LittleEndian>()?;
+        let fine = rdr.read_u32::<LittleEndian>()?;
+        let _reserved = rdr.read_u32::<LittleEndian>()?;
+
+        records.push(TriggerRecord {
+            event,
+            raw_count,
+            channel,
+            coarse,
+            fine,
+        });
+    }
+
+    Ok(records)
+}
+
+/// Reads the triggers of a run directory, preferring the full precision `triggers.bin` over
+/// `triggers.csv` when both are present.
+pub fn read_run_triggers(run_dir: &Path) -> io::Result<Vec<Trigger>> {
+    let bin_file_path = run_dir.join("triggers.bin");
+
+    if bin_file_path.exists() {
+        Ok(read_trigger_records(&bin_file_path)?.into_iter().map(Trigger::from).collect())
+    } else {
+        read_trigger_data(&run_dir.join("triggers.csv"))
+    }
+}
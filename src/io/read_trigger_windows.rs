@@ -0,0 +1,38 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/io/read_trigger_windows.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use csv;
+
+use crate::TriggerWindow;
+
+/// Reads per-trigger acquisition windows from a CSV file with `event`, `window_size` and
+/// `window_offset` columns. Any other columns (eg. when reading from `triggers.csv`) are ignored.
+pub fn read_trigger_windows(data_file: &PathBuf) -> io::Result<Vec<TriggerWindow>> {
+    let file = fs::File::open(&data_file)?;
+    let mut rdr = csv::Reader::from_reader(file);
+    let mut windows = Vec::new();
+
+    for result in rdr.deserialize() {
+        let window: TriggerWindow = result?;
+
+        if window.window_size <= 0.0 || window.window_offset < 0.0 || window.window_offset > window.window_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid window for trigger {} in {:#?}", window.event, data_file),
+            ));
+        }
+
+        windows.push(window);
+    }
+
+    Ok(windows)
+}
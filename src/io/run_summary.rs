@@ -0,0 +1,61 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/io/run_summary.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json;
+
+pub const RUN_SUMMARY_FILENAME: &str = "summary.json";
+
+/// Section of `summary.json` with the counts of `RecordCounts`
+pub const RECORD_COUNTS_SECTION: &str = "record_counts";
+
+/// Packets `raw_data_parser` read from the raw files of a run and the records it wrote from them,
+/// which `crosscheck` compares with the files
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct RecordCounts {
+    pub packets: u64,
+    pub hit_packets: u64, // Including hits removed as hot pixels or filtered out
+    pub hits: u64,
+    pub triggers: u64,
+}
+
+/// Reads the `summary.json` of a run directory, returning an empty object if it does not exist yet.
+pub fn read_run_summary(run_dir: &Path) -> io::Result<serde_json::Value> {
+    let summary_file_path = run_dir.join(RUN_SUMMARY_FILENAME);
+
+    if !summary_file_path.exists() {
+        return Ok(serde_json::Value::Object(serde_json::Map::new()));
+    }
+
+    let summary = serde_json::from_str(&fs::read_to_string(summary_file_path)?)?;
+
+    Ok(summary)
+}
+
+/// Sets one section of the `summary.json` of a run directory, leaving the sections written by
+/// other tools untouched.
+pub fn update_run_summary<T: Serialize>(run_dir: &Path, section: &str, value: &T) -> io::Result<()> {
+    let mut summary = read_run_summary(run_dir)?;
+
+    match summary.as_object_mut() {
+        Some(map) => {
+            map.insert(section.to_owned(), serde_json::to_value(value)?);
+        }
+        None => {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Run summary is not a JSON object"));
+        }
+    }
+
+    fs::write(run_dir.join(RUN_SUMMARY_FILENAME), serde_json::to_string_pretty(&summary)?)?;
+
+    Ok(())
+}
@@ -0,0 +1,100 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/io/t3pa.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::io;
+use std::path::Path;
+
+use csv;
+use serde::{Deserialize, Serialize};
+
+use crate::{Hit, HitFlags, TOT_ADU_TO_NS};
+
+/// A line of a `.t3pa` file, the tab separated hit list written by SoPhy/Pixet. The ToA is in
+/// 25 ns ticks, the FToA in 1.5625 ns ticks counted back from the ToA and the ToT in 25 ns ticks.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct T3paRecord {
+    #[serde(rename = "Index")]
+    pub index: u64,
+    #[serde(rename = "Matrix Index")]
+    pub matrix_index: u32, // row * 256 + col
+    #[serde(rename = "ToA")]
+    pub toa: u64,
+    #[serde(rename = "ToT")]
+    pub tot: u32,
+    #[serde(rename = "FToA")]
+    pub ftoa: u8,
+    #[serde(rename = "Overflow")]
+    pub overflow: u8,
+}
+
+impl T3paRecord {
+    pub fn from_hit(index: u64, hit: &Hit) -> T3paRecord {
+        // Round the ToA up to the next 25 ns tick so the FToA is never negative
+        let toa = hit.toa.div_ceil(16);
+
+        T3paRecord {
+            index,
+            matrix_index: u32::from(hit.row) * 256 + u32::from(hit.col),
+            toa,
+            tot: hit.tot / TOT_ADU_TO_NS,
+            ftoa: (toa * 16 - hit.toa) as u8,
+            overflow: 0,
+        }
+    }
+
+    pub fn to_hit(&self) -> io::Result<Hit> {
+        if self.matrix_index >= 256 * 256 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Matrix index {} of t3pa hit {} is outside the 256x256 matrix", self.matrix_index, self.index),
+            ));
+        }
+
+        Ok(Hit {
+            col: (self.matrix_index % 256) as u16,
+            row: (self.matrix_index / 256) as u16,
+            toa: (self.toa * 16).saturating_sub(u64::from(self.ftoa)),
+            tot: self.tot * TOT_ADU_TO_NS,
+            flags: HitFlags::empty(),
+        })
+    }
+}
+
+/// Reads the hits of a `.t3pa` file in file order, which is not necessarily ToA order. The
+/// overflow flag is not kept.
+pub fn read_t3pa(data_file: &Path) -> io::Result<Vec<Hit>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(b'\t')
+        .trim(csv::Trim::All)
+        .from_path(data_file)?;
+
+    let mut hits = Vec::new();
+
+    for result in rdr.deserialize() {
+        let record: T3paRecord = result?;
+        hits.push(record.to_hit()?);
+    }
+
+    Ok(hits)
+}
+
+/// Writes hits as a `.t3pa` file, returning the number of hits written. ToAs are rounded up to
+/// the 25 ns tick with the remainder in the FToA, and ToTs rounded down to whole ticks.
+pub fn write_t3pa<W: io::Write, I: Iterator<Item = Hit>>(writer: W, hits: I) -> io::Result<u64> {
+    let mut csv_writer = csv::WriterBuilder::new().delimiter(b'\t').from_writer(writer);
+    let mut hits_written = 0;
+
+    for hit in hits {
+        csv_writer.serialize(T3paRecord::from_hit(hits_written, &hit))?;
+        hits_written += 1;
+    }
+
+    csv_writer.flush()?;
+
+    Ok(hits_written)
+}
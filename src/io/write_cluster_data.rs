@@ -12,7 +12,7 @@ use std::io::Write as _;
 
 use byteorder::{LittleEndian, WriteBytesExt};
 
-use crate::Hit;
+use crate::{encode_tot_field, Hit};
 
 pub fn write_cluster_to_file(file: &mut File, cluster: &[Hit], toa_adjustment: i64) -> io::Result<()> {
     let mut buf = Vec::with_capacity((cluster.len() + 1) * 16);
@@ -29,7 +29,36 @@ pub fn write_cluster_to_file(file: &mut File, cluster: &[Hit], toa_adjustment: i
         buf.write_u16::<LittleEndian>(hit.col)?;
         buf.write_u16::<LittleEndian>(hit.row)?;
         buf.write_u64::<LittleEndian>(toa as u64)?;
-        buf.write_u32::<LittleEndian>(hit.tot)?;
+        buf.write_u32::<LittleEndian>(encode_tot_field(hit.tot, hit.flags))?;
+    }
+
+    // Terminating zeroed hit
+    buf.write_u128::<LittleEndian>(0_u128)?;
+
+    file.write_all(&buf)?;
+
+    Ok(())
+}
+
+/// Offset added to the signed ToA values of a signed cluster file. Stored offset binary, a hit at
+/// the reference time does not read back as the zeroed terminator, and the values cannot be taken
+/// for absolute ToA values (a read as `u64` puts them over 450 years in), while keeping their
+/// order and the differences between them.
+pub(crate) const SIGNED_TOA_BIAS: u64 = 1 << 63;
+
+/// Writes a cluster with ToA values stored relative to `reference` as signed integers, so hits
+/// before the reference time keep their negative offset. These are read back with
+/// `read_signed_cluster_data`/`ReadSignedClusterIterator`.
+pub fn write_signed_cluster_to_file(file: &mut File, cluster: &[Hit], reference: u64) -> io::Result<()> {
+    let mut buf = Vec::with_capacity((cluster.len() + 1) * 16);
+
+    for hit in cluster {
+        let toa = hit.toa.wrapping_sub(reference) ^ SIGNED_TOA_BIAS;
+
+        buf.write_u16::<LittleEndian>(hit.col)?;
+        buf.write_u16::<LittleEndian>(hit.row)?;
+        buf.write_u64::<LittleEndian>(toa)?;
+        buf.write_u32::<LittleEndian>(encode_tot_field(hit.tot, hit.flags))?;
     }
 
     // Terminating zeroed hit
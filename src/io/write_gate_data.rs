@@ -0,0 +1,26 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/io/write_gate_data.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs::File;
+use std::io;
+
+use csv;
+
+use crate::Gate;
+
+pub fn write_gates_to_csv(file: &mut File, gates: &[Gate]) -> io::Result<()> {
+    let mut csv_writer = csv::Writer::from_writer(file);
+
+    for gate in gates {
+        csv_writer.serialize(gate)?;
+    }
+
+    csv_writer.flush()?;
+
+    Ok(())
+}
@@ -10,18 +10,13 @@ use std::fs::File;
 use std::io;
 use std::io::Write as _;
 
-use byteorder::{LittleEndian, WriteBytesExt};
-
-use crate::Hit;
+use crate::{encode_hit_record, Hit, HIT_RECORD_SIZE};
 
 pub fn write_hits_to_file(file: &mut File, hits: &[Hit]) -> io::Result<()> {
-    let mut buf = Vec::with_capacity(hits.len() * 16);
+    let mut buf = Vec::with_capacity(hits.len() * HIT_RECORD_SIZE);
 
     for hit in hits {
-        buf.write_u16::<LittleEndian>(hit.col)?;
-        buf.write_u16::<LittleEndian>(hit.row)?;
-        buf.write_u64::<LittleEndian>(hit.toa)?;
-        buf.write_u32::<LittleEndian>(hit.tot)?;
+        buf.extend_from_slice(&encode_hit_record(hit));
     }
 
     file.write_all(&buf)?;
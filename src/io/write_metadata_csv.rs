@@ -0,0 +1,88 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/io/write_metadata_csv.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use csv;
+use serde::Serialize;
+
+/// How often a `MetadataCsvWriter` pushes its buffered records out to the file
+#[derive(Clone, Copy, Debug)]
+pub struct FlushPolicy {
+    pub records: usize,     // Records written between flushes
+    pub interval: Duration, // Longest time records are held before being flushed
+}
+
+impl FlushPolicy {
+    /// Flushes after every record, so the CSV never falls behind the binary file it describes
+    pub fn sync() -> FlushPolicy {
+        FlushPolicy {
+            records: 1,
+            interval: Duration::from_secs(0),
+        }
+    }
+}
+
+impl Default for FlushPolicy {
+    fn default() -> Self {
+        FlushPolicy {
+            records: 10_000,
+            interval: Duration::from_secs(5),
+        }
+    }
+}
+
+/// CSV writer for per-event metadata that flushes every so many records or seconds rather than
+/// after every record. Anything still buffered is flushed when it is dropped.
+pub struct MetadataCsvWriter {
+    csv_writer: csv::Writer<File>,
+    policy: FlushPolicy,
+    pending: usize,
+    last_flush: Instant,
+}
+
+impl MetadataCsvWriter {
+    pub fn create(path: &Path, policy: FlushPolicy) -> io::Result<MetadataCsvWriter> {
+        Ok(MetadataCsvWriter {
+            csv_writer: csv::Writer::from_writer(File::create(path)?),
+            policy,
+            pending: 0,
+            last_flush: Instant::now(),
+        })
+    }
+
+    pub fn serialize<T: Serialize>(&mut self, record: T) -> io::Result<()> {
+        self.csv_writer.serialize(record)?;
+        self.pending += 1;
+
+        if self.pending >= self.policy.records || self.last_flush.elapsed() >= self.policy.interval {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.csv_writer.flush()?;
+        self.pending = 0;
+        self.last_flush = Instant::now();
+
+        Ok(())
+    }
+}
+
+impl Drop for MetadataCsvWriter {
+    fn drop(&mut self) {
+        if self.pending > 0 {
+            let _ = self.csv_writer.flush();
+        }
+    }
+}
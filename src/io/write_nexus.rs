@@ -0,0 +1,138 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/io/write_nexus.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::io;
+use std::path::Path;
+
+use hdf5::types::VarLenUnicode;
+
+use crate::{Hit, Trigger, TOA_CLOCK_TO_NS};
+
+/// Hits buffered before being appended to the event datasets
+const NEXUS_CHUNK_SIZE: usize = 1 << 20;
+
+fn hdf5_error(err: hdf5::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("HDF5 error: {}", err))
+}
+
+fn write_str_attr(location: &hdf5::Location, name: &str, value: &str) -> hdf5::Result<()> {
+    let value: VarLenUnicode = value.parse().unwrap();
+
+    location.new_attr::<VarLenUnicode>().create(name)?.write_scalar(&value)
+}
+
+/// A 1D dataset that grows as the hits are streamed in, so a run never has to fit in memory
+fn create_extendable<T: hdf5::H5Type>(group: &hdf5::Group, name: &str, units: Option<&str>) -> hdf5::Result<hdf5::Dataset> {
+    let dataset = group.new_dataset::<T>().chunk(NEXUS_CHUNK_SIZE).shape(0..).create(name)?;
+
+    if let Some(units) = units {
+        write_str_attr(&dataset, "units", units)?;
+    }
+
+    Ok(dataset)
+}
+
+fn append<T: hdf5::H5Type>(dataset: &hdf5::Dataset, data: &[T]) -> hdf5::Result<()> {
+    let start = dataset.size();
+    let end = start + data.len();
+
+    dataset.resize(end)?;
+    dataset.write_slice(data, start..end)
+}
+
+/// Writes the hits of a run to a NeXus file as an `NXevent_data` group (`/entry/events`), with
+/// the triggers as pulses:
+///
+/// - `event_id`: pixel of each hit (`row * 256 + col`)
+/// - `event_time_offset`: time of each hit after its pulse (ns)
+/// - `event_time_zero`: time of each pulse from the start of the run (ns)
+/// - `event_index`: index of the first hit of each pulse
+/// - `event_tot`: time over threshold of each hit (ns), not part of the NeXus base class
+///
+/// Hits before the first trigger, or all hits of a run without triggers, belong to an extra pulse
+/// at the start of the run. The hits must be in time order. Returns the number of hits written.
+pub fn write_nexus_event_data<I>(path: &Path, hits: I, triggers: &[Trigger], start_time: &str) -> io::Result<u64>
+where
+    I: IntoIterator<Item = Hit>,
+{
+    let mut hits = hits.into_iter().peekable();
+
+    let mut pulse_times: Vec<u64> = triggers.iter().map(|trigger| trigger.time).collect();
+    pulse_times.sort_unstable();
+
+    let first_hit_time = hits.peek().map(|hit| (hit.toa as f64 * TOA_CLOCK_TO_NS) as u64);
+
+    if pulse_times.is_empty() || first_hit_time.map_or(false, |time| time < pulse_times[0]) {
+        pulse_times.insert(0, 0);
+    }
+
+    let file = hdf5::File::create(path).map_err(hdf5_error)?;
+
+    let entry = file.create_group("entry").map_err(hdf5_error)?;
+    write_str_attr(&entry, "NX_class", "NXentry").map_err(hdf5_error)?;
+
+    let events = entry.create_group("events").map_err(hdf5_error)?;
+    write_str_attr(&events, "NX_class", "NXevent_data").map_err(hdf5_error)?;
+
+    let event_id = create_extendable::<u32>(&events, "event_id", None).map_err(hdf5_error)?;
+    let event_time_offset = create_extendable::<f64>(&events, "event_time_offset", Some("ns")).map_err(hdf5_error)?;
+    let event_tot = create_extendable::<u32>(&events, "event_tot", Some("ns")).map_err(hdf5_error)?;
+
+    let mut ids = Vec::with_capacity(NEXUS_CHUNK_SIZE);
+    let mut offsets = Vec::with_capacity(NEXUS_CHUNK_SIZE);
+    let mut tots = Vec::with_capacity(NEXUS_CHUNK_SIZE);
+
+    let mut event_index = vec![0u64; pulse_times.len()];
+    let mut pulse = 0;
+    let mut written = 0;
+
+    loop {
+        let hit = hits.next();
+
+        if hit.is_none() || ids.len() == NEXUS_CHUNK_SIZE {
+            append(&event_id, &ids).map_err(hdf5_error)?;
+            append(&event_time_offset, &offsets).map_err(hdf5_error)?;
+            append(&event_tot, &tots).map_err(hdf5_error)?;
+
+            ids.clear();
+            offsets.clear();
+            tots.clear();
+        }
+
+        let hit = match hit {
+            Some(hit) => hit,
+            None => break,
+        };
+
+        let time = hit.toa as f64 * TOA_CLOCK_TO_NS;
+
+        // Pulses with no hits start where the next pulse does
+        while pulse + 1 < pulse_times.len() && pulse_times[pulse + 1] as f64 <= time {
+            pulse += 1;
+            event_index[pulse] = written;
+        }
+
+        ids.push(hit.row as u32 * 256 + hit.col as u32);
+        offsets.push(time - pulse_times[pulse] as f64);
+        tots.push(hit.tot);
+
+        written += 1;
+    }
+
+    for index in &mut event_index[pulse + 1..] {
+        *index = written;
+    }
+
+    let event_time_zero = events.new_dataset_builder().with_data(&pulse_times[..]).create("event_time_zero").map_err(hdf5_error)?;
+    write_str_attr(&event_time_zero, "units", "ns").map_err(hdf5_error)?;
+    write_str_attr(&event_time_zero, "offset", start_time).map_err(hdf5_error)?;
+
+    events.new_dataset_builder().with_data(&event_index[..]).create("event_index").map_err(hdf5_error)?;
+
+    Ok(written)
+}
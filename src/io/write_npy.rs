@@ -0,0 +1,109 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/io/write_npy.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write as _};
+use std::path::Path;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+/// Writes the header of a version 1.0 `.npy` file for a C ordered array
+fn write_npy_header(buf: &mut Vec<u8>, descr: &str, shape: &[usize]) -> io::Result<()> {
+    let shape_str = match shape {
+        [n] => format!("({},)", n),
+        _ => format!("({})", shape.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(", ")),
+    };
+
+    let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}", descr, shape_str);
+
+    // Magic string, version and header length take 10 bytes, and the data must start 64 byte aligned
+    while (10 + header.len() + 1) % 64 != 0 {
+        header.push(' ');
+    }
+    header.push('\n');
+
+    buf.extend_from_slice(b"\x93NUMPY\x01\x00");
+    buf.write_u16::<LittleEndian>(header.len() as u16)?;
+    buf.extend_from_slice(header.as_bytes());
+
+    Ok(())
+}
+
+pub fn write_npy_f32(file: &mut File, shape: &[usize], data: &[f32]) -> io::Result<()> {
+    assert_eq!(shape.iter().product::<usize>(), data.len());
+
+    let mut buf = Vec::with_capacity(128 + data.len() * 4);
+
+    write_npy_header(&mut buf, "<f4", shape)?;
+
+    for x in data {
+        buf.write_f32::<LittleEndian>(*x)?;
+    }
+
+    file.write_all(&buf)?;
+
+    Ok(())
+}
+
+pub fn write_npy_i64(file: &mut File, shape: &[usize], data: &[i64]) -> io::Result<()> {
+    assert_eq!(shape.iter().product::<usize>(), data.len());
+
+    let mut buf = Vec::with_capacity(128 + data.len() * 8);
+
+    write_npy_header(&mut buf, "<i8", shape)?;
+
+    for x in data {
+        buf.write_i64::<LittleEndian>(*x)?;
+    }
+
+    file.write_all(&buf)?;
+
+    Ok(())
+}
+
+/// Writes a `.npy` file of u32 values a block at a time, for arrays too large to hold in memory
+/// (e.g. a long stack of frames)
+pub struct NpyU32Writer {
+    writer: BufWriter<File>,
+    remaining: usize, // Values still to be written to fill the shape
+}
+
+impl NpyU32Writer {
+    pub fn create(path: &Path, shape: &[usize]) -> io::Result<NpyU32Writer> {
+        let mut header = Vec::with_capacity(128);
+
+        write_npy_header(&mut header, "<u4", shape)?;
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(&header)?;
+
+        Ok(NpyU32Writer {
+            writer,
+            remaining: shape.iter().product(),
+        })
+    }
+
+    pub fn write(&mut self, data: &[u32]) -> io::Result<()> {
+        assert!(data.len() <= self.remaining, "More values written than the shape holds");
+
+        for x in data {
+            self.writer.write_u32::<LittleEndian>(*x)?;
+        }
+
+        self.remaining -= data.len();
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        assert_eq!(self.remaining, 0, "Fewer values written than the shape holds");
+
+        self.writer.flush()
+    }
+}
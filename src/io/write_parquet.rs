@@ -0,0 +1,143 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/io/write_parquet.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::data_type::{ByteArray, ByteArrayType, Int32Type, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+
+use crate::Hit;
+
+/// Hits buffered before being written out as a row group
+const PARQUET_ROW_GROUP_SIZE: usize = 1 << 20;
+
+const CLUSTER_HITS_SCHEMA: &str = "
+    message cluster_hits {
+        required binary run_id (STRING);
+        required int64 cluster_id (INTEGER(64, false));
+        required int32 col (INTEGER(16, false));
+        required int32 row (INTEGER(16, false));
+        required int64 toa (INTEGER(64, false));
+        required int32 tot (INTEGER(32, false));
+        required int32 flags (INTEGER(8, false));
+    }
+";
+
+fn parquet_error(err: parquet::errors::ParquetError) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("Parquet error: {}", err))
+}
+
+/// Writes the hits of clusters/events to a Parquet file as one flat table, a row per hit with the
+/// run and cluster it belongs to, zstd compressed. The unsigned columns are stored in the signed
+/// physical types with unsigned logical types, as readers expect.
+pub struct ClusterHitsParquetWriter {
+    writer: SerializedFileWriter<File>,
+    run_ids: Vec<ByteArray>,
+    cluster_ids: Vec<i64>,
+    cols: Vec<i32>,
+    rows: Vec<i32>,
+    toas: Vec<i64>,
+    tots: Vec<i32>,
+    flags: Vec<i32>,
+}
+
+impl ClusterHitsParquetWriter {
+    pub fn create(path: &Path) -> io::Result<ClusterHitsParquetWriter> {
+        let schema = Arc::new(parse_message_type(CLUSTER_HITS_SCHEMA).map_err(parquet_error)?);
+        let properties = Arc::new(
+            WriterProperties::builder()
+                .set_compression(Compression::ZSTD(ZstdLevel::default()))
+                .build(),
+        );
+
+        let writer = SerializedFileWriter::new(File::create(path)?, schema, properties).map_err(parquet_error)?;
+
+        Ok(ClusterHitsParquetWriter {
+            writer,
+            run_ids: Vec::with_capacity(PARQUET_ROW_GROUP_SIZE),
+            cluster_ids: Vec::with_capacity(PARQUET_ROW_GROUP_SIZE),
+            cols: Vec::with_capacity(PARQUET_ROW_GROUP_SIZE),
+            rows: Vec::with_capacity(PARQUET_ROW_GROUP_SIZE),
+            toas: Vec::with_capacity(PARQUET_ROW_GROUP_SIZE),
+            tots: Vec::with_capacity(PARQUET_ROW_GROUP_SIZE),
+            flags: Vec::with_capacity(PARQUET_ROW_GROUP_SIZE),
+        })
+    }
+
+    pub fn write_cluster(&mut self, run_id: &str, cluster_id: u64, cluster: &[Hit]) -> io::Result<()> {
+        let run_id = ByteArray::from(run_id);
+
+        for hit in cluster {
+            self.run_ids.push(run_id.clone());
+            self.cluster_ids.push(cluster_id as i64);
+            self.cols.push(i32::from(hit.col));
+            self.rows.push(i32::from(hit.row));
+            self.toas.push(hit.toa as i64);
+            self.tots.push(hit.tot as i32);
+            self.flags.push(i32::from(hit.flags.0));
+        }
+
+        if self.cluster_ids.len() >= PARQUET_ROW_GROUP_SIZE {
+            self.write_row_group()?;
+        }
+
+        Ok(())
+    }
+
+    fn write_row_group(&mut self) -> io::Result<()> {
+        let mut row_group = self.writer.next_row_group().map_err(parquet_error)?;
+
+        // Columns are written in the order of the schema
+        let mut column_index = 0;
+
+        while let Some(mut column) = row_group.next_column().map_err(parquet_error)? {
+            let written = match column_index {
+                0 => column.typed::<ByteArrayType>().write_batch(&self.run_ids, None, None),
+                1 => column.typed::<Int64Type>().write_batch(&self.cluster_ids, None, None),
+                2 => column.typed::<Int32Type>().write_batch(&self.cols, None, None),
+                3 => column.typed::<Int32Type>().write_batch(&self.rows, None, None),
+                4 => column.typed::<Int64Type>().write_batch(&self.toas, None, None),
+                5 => column.typed::<Int32Type>().write_batch(&self.tots, None, None),
+                _ => column.typed::<Int32Type>().write_batch(&self.flags, None, None),
+            };
+
+            written.map_err(parquet_error)?;
+            column.close().map_err(parquet_error)?;
+
+            column_index += 1;
+        }
+
+        row_group.close().map_err(parquet_error)?;
+
+        self.run_ids.clear();
+        self.cluster_ids.clear();
+        self.cols.clear();
+        self.rows.clear();
+        self.toas.clear();
+        self.tots.clear();
+        self.flags.clear();
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        if !self.cluster_ids.is_empty() {
+            self.write_row_group()?;
+        }
+
+        self.writer.close().map_err(parquet_error)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/io/write_png.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF_u32;
+
+    for byte in data {
+        crc ^= u32::from(*byte);
+
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1_u32, 0_u32);
+
+    for byte in data {
+        a = (a + u32::from(*byte)) % 65521;
+        b = (b + a) % 65521;
+    }
+
+    (b << 16) | a
+}
+
+fn write_chunk(buf: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    buf.write_u32::<BigEndian>(data.len() as u32)?;
+
+    let start = buf.len();
+    buf.extend_from_slice(chunk_type);
+    buf.extend_from_slice(data);
+
+    let crc = crc32(&buf[start..]);
+    buf.write_u32::<BigEndian>(crc)?;
+
+    Ok(())
+}
+
+/// Writes an 8 bit greyscale PNG. The image data is stored uncompressed (the images written are
+/// small pixel maps), so no compression library is needed.
+pub fn write_greyscale_png(path: &Path, width: usize, height: usize, pixels: &[u8]) -> io::Result<()> {
+    assert_eq!(width * height, pixels.len());
+
+    // Each scanline starts with its filter type (0 for none)
+    let mut raw = Vec::with_capacity((width + 1) * height);
+
+    for row in pixels.chunks(width) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    // Zlib stream of stored (uncompressed) deflate blocks
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(0xFFFF).peekable();
+
+    while let Some(block) = blocks.next() {
+        zlib.push(if blocks.peek().is_none() { 1 } else { 0 });
+        zlib.write_u16::<LittleEndian>(block.len() as u16)?;
+        zlib.write_u16::<LittleEndian>(!(block.len() as u16))?;
+        zlib.extend_from_slice(block);
+    }
+
+    zlib.write_u32::<BigEndian>(adler32(&raw))?;
+
+    let mut header = Vec::with_capacity(13);
+    header.write_u32::<BigEndian>(width as u32)?;
+    header.write_u32::<BigEndian>(height as u32)?;
+    header.extend_from_slice(&[8, 0, 0, 0, 0]); // Bit depth, greyscale, compression, filter, interlace
+
+    let mut buf = b"\x89PNG\r\n\x1a\n".to_vec();
+
+    write_chunk(&mut buf, b"IHDR", &header)?;
+    write_chunk(&mut buf, b"IDAT", &zlib)?;
+    write_chunk(&mut buf, b"IEND", &[])?;
+
+    fs::write(path, buf)
+}
@@ -0,0 +1,190 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/io/write_tiff.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write as _};
+use std::path::Path;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use serde::Serialize;
+
+const IFD_ENTRIES: u16 = 10;
+const IFD_SIZE: u64 = 2 + IFD_ENTRIES as u64 * 12 + 4;
+
+// Field types
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+
+/// Type of the pixels in a TIFF file. 16-bit images open in the most software (eg. ImageJ), but
+/// values above 65535 are clipped.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TiffSampleFormat {
+    U16,
+    U32,
+    F32,
+}
+
+impl TiffSampleFormat {
+    fn bytes(self) -> u64 {
+        match self {
+            TiffSampleFormat::U16 => 2,
+            TiffSampleFormat::U32 | TiffSampleFormat::F32 => 4,
+        }
+    }
+
+    /// Value of the SampleFormat tag
+    fn tag_value(self) -> u32 {
+        match self {
+            TiffSampleFormat::U16 | TiffSampleFormat::U32 => 1, // Unsigned integer
+            TiffSampleFormat::F32 => 3,                         // IEEE floating point
+        }
+    }
+}
+
+/// Pixel values that can be written to a TIFF file in any of the sample formats, converting to
+/// integers rounds and clips to the range of the format
+pub trait TiffPixel: Copy {
+    fn to_f64(self) -> f64;
+}
+
+impl TiffPixel for u32 {
+    fn to_f64(self) -> f64 {
+        f64::from(self)
+    }
+}
+
+impl TiffPixel for f64 {
+    fn to_f64(self) -> f64 {
+        self
+    }
+}
+
+/// Writes a stack of greyscale images as a multi-page baseline TIFF a page at a time. Every page is the same size, so each page's image data is followed by its directory and
+/// all the offsets are known up front. Baseline TIFF offsets are 32-bit, so the file is limited to
+/// 4 GB.
+pub struct TiffStackWriter {
+    writer: BufWriter<File>,
+    width: u32,
+    height: u32,
+    sample_format: TiffSampleFormat,
+    pages: u64,
+    written: u64, // Pages written so far
+}
+
+impl TiffStackWriter {
+    pub fn create(path: &Path, width: u32, height: u32, sample_format: TiffSampleFormat, pages: u64) -> io::Result<TiffStackWriter> {
+        if pages == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "A TIFF stack needs at least one page"));
+        }
+
+        let page_size = u64::from(width) * u64::from(height) * sample_format.bytes() + IFD_SIZE;
+
+        if 8 + pages * page_size > u64::from(u32::MAX) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} pages of {}x{} are too large for a TIFF file (4 GB)", pages, width, height),
+            ));
+        }
+
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        // Byte order, magic number and the offset of the first directory, after the first page
+        writer.write_all(b"II")?;
+        writer.write_u16::<LittleEndian>(42)?;
+        writer.write_u32::<LittleEndian>((8 + page_size - IFD_SIZE) as u32)?;
+
+        Ok(TiffStackWriter {
+            writer,
+            width,
+            height,
+            sample_format,
+            pages,
+            written: 0,
+        })
+    }
+
+    pub fn write_page<T: TiffPixel>(&mut self, data: &[T]) -> io::Result<()> {
+        assert_eq!(data.len() as u64, u64::from(self.width) * u64::from(self.height));
+        assert!(self.written < self.pages, "More pages written than the stack holds");
+
+        let data_size = data.len() as u64 * self.sample_format.bytes();
+        let page_size = data_size + IFD_SIZE;
+        let data_offset = 8 + self.written * page_size;
+
+        for x in data {
+            let x = x.to_f64();
+
+            // Float to integer casts saturate, with NaN written as 0
+            match self.sample_format {
+                TiffSampleFormat::U16 => self.writer.write_u16::<LittleEndian>(x.round() as u16)?,
+                TiffSampleFormat::U32 => self.writer.write_u32::<LittleEndian>(x.round() as u32)?,
+                TiffSampleFormat::F32 => self.writer.write_f32::<LittleEndian>(x as f32)?,
+            }
+        }
+
+        let bits_per_sample = self.sample_format.bytes() as u32 * 8;
+
+        let entries: [(u16, u16, u32); IFD_ENTRIES as usize] = [
+            (256, LONG, self.width),                      // ImageWidth
+            (257, LONG, self.height),                     // ImageLength
+            (258, SHORT, bits_per_sample),                // BitsPerSample
+            (259, SHORT, 1),                              // Compression (none)
+            (262, SHORT, 1),                              // PhotometricInterpretation (black is zero)
+            (273, LONG, data_offset as u32),              // StripOffsets
+            (277, SHORT, 1),                              // SamplesPerPixel
+            (278, LONG, self.height),                     // RowsPerStrip
+            (279, LONG, data_size as u32),                // StripByteCounts
+            (339, SHORT, self.sample_format.tag_value()), // SampleFormat
+        ];
+
+        self.writer.write_u16::<LittleEndian>(IFD_ENTRIES)?;
+
+        for (tag, field_type, value) in entries.iter() {
+            self.writer.write_u16::<LittleEndian>(*tag)?;
+            self.writer.write_u16::<LittleEndian>(*field_type)?;
+            self.writer.write_u32::<LittleEndian>(1)?;
+
+            // Values shorter than 4 bytes are left justified in the value field
+            if *field_type == SHORT {
+                self.writer.write_u16::<LittleEndian>(*value as u16)?;
+                self.writer.write_u16::<LittleEndian>(0)?;
+            } else {
+                self.writer.write_u32::<LittleEndian>(*value)?;
+            }
+        }
+
+        self.written += 1;
+
+        // Offset of the next page's directory, 0 after the last page
+        let next_ifd_offset = if self.written < self.pages {
+            data_offset + 2 * page_size - IFD_SIZE
+        } else {
+            0
+        };
+
+        self.writer.write_u32::<LittleEndian>(next_ifd_offset as u32)?;
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        assert_eq!(self.written, self.pages, "Fewer pages written than the stack holds");
+
+        self.writer.flush()
+    }
+}
+
+/// Writes a single image as a TIFF file
+pub fn write_tiff<T: TiffPixel>(path: &Path, width: u32, height: u32, sample_format: TiffSampleFormat, data: &[T]) -> io::Result<()> {
+    let mut writer = TiffStackWriter::create(path, width, height, sample_format, 1)?;
+
+    writer.write_page(data)?;
+    writer.finish()
+}
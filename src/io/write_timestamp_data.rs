@@ -0,0 +1,26 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/io/write_timestamp_data.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs::File;
+use std::io;
+
+use csv;
+
+use crate::AbsoluteTimestamp;
+
+pub fn write_timestamps_to_csv(file: &mut File, timestamps: &[AbsoluteTimestamp]) -> io::Result<()> {
+    let mut csv_writer = csv::Writer::from_writer(file);
+
+    for timestamp in timestamps {
+        csv_writer.serialize(timestamp)?;
+    }
+
+    csv_writer.flush()?;
+
+    Ok(())
+}
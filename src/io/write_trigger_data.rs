@@ -8,10 +8,21 @@
 
 use std::fs::File;
 use std::io;
+use std::io::Write as _;
 
+use byteorder::{LittleEndian, WriteBytesExt};
 use csv;
+use serde::Serialize;
 
-use crate::Trigger;
+use crate::{RunStartTime, Trigger, TriggerRecord};
+
+/// Row of `triggers.csv` with an absolute time column
+#[derive(Serialize)]
+struct AbsoluteTimeTrigger {
+    event: u32,
+    time: u64,   // ns
+    utc_ns: i64, // ns since the Unix epoch
+}
 
 pub fn write_triggers_to_csv(file: &mut File, triggers: &[Trigger]) -> io::Result<()> {
     let mut csv_writer = csv::Writer::from_writer(file);
@@ -24,3 +35,43 @@ pub fn write_triggers_to_csv(file: &mut File, triggers: &[Trigger]) -> io::Resul
 
     Ok(())
 }
+
+/// Writes triggers to CSV with a `utc_ns` column of their UTC times, from the start time of the run
+pub fn write_triggers_to_csv_with_utc(file: &mut File, triggers: &[Trigger], start_time: &RunStartTime) -> io::Result<()> {
+    let mut csv_writer = csv::Writer::from_writer(file);
+
+    for trigger in triggers {
+        csv_writer.serialize(AbsoluteTimeTrigger {
+            event: trigger.event,
+            time: trigger.time,
+            utc_ns: start_time.utc_ns(trigger.time as f64),
+        })?;
+    }
+
+    csv_writer.flush()?;
+
+    Ok(())
+}
+
+/// Writes full precision trigger records in the `triggers.bin` format (fine time stored in ps),
+/// after a header of the magic number and format version.
+pub fn write_trigger_records_to_file(file: &mut File, records: &[TriggerRecord]) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(TriggerRecord::HEADER_SIZE + records.len() * TriggerRecord::SIZE);
+
+    buf.write_all(&TriggerRecord::FILE_MAGIC)?;
+    buf.write_u32::<LittleEndian>(TriggerRecord::FILE_VERSION)?;
+
+    for record in records {
+        buf.write_u32::<LittleEndian>(record.event)?;
+        buf.write_u16::<LittleEndian>(record.raw_count)?;
+        buf.write_u8(record.channel)?;
+        buf.write_u8(0)?;
+        buf.write_u64::<LittleEndian>(record.coarse)?;
+        buf.write_u32::<LittleEndian>(record.fine)?;
+        buf.write_u32::<LittleEndian>(0)?;
+    }
+
+    file.write_all(&buf)?;
+
+    Ok(())
+}
@@ -6,18 +6,110 @@
  * Authors: Jared Vann
  */
 
-use std::cmp::Ordering;
-
 use num_traits::Num;
 use serde::{Deserialize, Serialize};
 
+// Decoding core, kept in its own crate so that it also builds for wasm32
+pub use timepix_decode::{
+    column_phase_correction, decode_event_count_field, decode_hit, decode_hit_record, decode_hit_records, decode_hit_with_mode, decode_tdc_fine,
+    decode_tot_field, encode_event_count_field, encode_hit_record, encode_tot_field, is_control_packet, is_plausible_packet, packet_type,
+    parse_raw_packets_from_slice, split_packets, AbsoluteTimestamp, AcqMode, ControlPacketCounts, DecoderState, GlobalTimeJump, Hit, HitFlags, SpidrHeader,
+    TdcTime, TdcTimeDecoder, TdcTimeJump, TimeExtensionCounters, TimestampDecoder, TimestampSource, ToaExtender, Trigger, DEFAULT_CLOCK_PHASES,
+    HITS_FORMAT_VERSION, HIT_RECORD_SIZE, MAX_RECORD_TOT, SPIDR_MAX_HEADER_SIZE, TDC_COARSE_TICK_PS, TIMESTAMP_LATCH_SUBHEADER, TIMESTAMP_SECONDS_SUBHEADER,
+    TOA_CLOCK_TO_NS, TOA_ROLLOVER_PERIOD, TOT_ADU_TO_NS,
+};
+
+mod cluster;
+pub use cluster::{count_time_islands, ClusterSettings, ClusterStream, FindClusterIterator, FlattenClustersIterator, OversizePolicy};
+
+mod column_bursts;
+pub use column_bursts::{ColumnBurst, ColumnBurstDetector, ColumnBurstMask};
+
+mod dataset;
+pub use dataset::{Dataset, Run};
+
+#[cfg(feature = "polars")]
+mod dataframe;
+#[cfg(feature = "polars")]
+pub use dataframe::{add_utc_column, clusters_to_dataframe, hits_to_dataframe};
+
+mod dedup;
+pub use dedup::HitDeduplicator;
+
+mod disk_space;
+pub use disk_space::{available_space, check_free_space, format_bytes, DiskSpaceLimits, DiskSpaceMonitor};
+
+mod dry_run;
+pub use dry_run::{run_file_bytes, tool_name, DryRunPlan, ProcessingHistory, ProcessingRecord, ProcessingTimer, PROCESSING_SECTION};
+
+mod energy_calibration;
+pub use energy_calibration::EnergyCalibration;
+
+mod event_selection;
+pub use event_selection::{EventRange, EventSelection, SelectedEvents};
+
+mod external_events;
+pub use external_events::{add_external_ids_to_triggers_csv, ExternalEventMap, ExternalEventMatching};
+
+mod flat_field;
+pub use flat_field::FlatField;
+
+mod hit_filter;
+pub use hit_filter::{FilterHitsIterator, HitFilter, Roi};
+
+mod hit_soa;
+pub use hit_soa::HitSoA;
+
+mod hot_pixel_suppression;
+pub use hot_pixel_suppression::{HotPixelMaskChange, RollingHotPixelSuppressor};
+
+mod interrupt;
+pub use interrupt::{
+    clear_partial_marker, has_partial_marker, install_interrupt_handler, interrupted_error, is_interrupted, write_partial_marker, Checkpoint,
+    INTERRUPTED_EXIT_CODE,
+};
+
 mod io;
 pub use io::*;
 
-pub const TOA_CLOCK_TO_NS: f64 = 1.5625;
-pub const TOT_ADU_TO_NS: u32 = 25;
+mod live_time;
+pub use live_time::{calculate_live_time, merge_intervals, LiveTime};
+
+mod output_template;
+pub use output_template::{OutputTemplate, DEFAULT_RUN_DIR_TEMPLATE};
+
+mod path_safety;
+pub use path_safety::{check_output_outside_input, count_files, resolved_path, DEFAULT_MAX_OVERWRITE_FILES};
+
+mod pixel_mask;
+pub use pixel_mask::{PixelMask, PixelStatus, PixelStatusRecord};
+
+mod progress;
+pub use progress::{total_file_bytes, FileByteProgress, BYTES_PROGRESS_BAR_TEMPLATE};
+
+mod provenance;
+pub use provenance::{existing_output, settings_hash, write_settings_toml, ExistingOutput, InputFile, Provenance};
+
+mod run_index;
+pub use run_index::{RunEntry, RunIndex, RunQuery};
+
+mod run_lock;
+pub use run_lock::{RunLock, RunLockInfo};
+
+mod run_processing;
+pub use run_processing::{process_runs, report_run_results, write_run_manifest, JobPartition, RunJob, RunOptions, RunResult, RunStatus};
+
+mod run_start_time;
+pub use run_start_time::{parse_timezone, ClockFit, RunStartTime, RUN_START_TIME_SECTION};
+
+mod slow_control;
+pub use slow_control::{parse_slow_control_time, read_slow_control_csv, SlowControlChannel, SlowControlSummary};
+
+mod timing_offsets;
+pub use timing_offsets::{TimingOffsetRegion, TimingOffsets};
 
 pub const BUFFER_SIZE: usize = 100_000; // 800KB (this seems fairly optimal - bigger causes stack to fill)
+pub const DEFAULT_READ_BUFFER_SIZE: usize = 1_600_000; // bytes, 100k hits
 
 pub const HOT_PIXELS: [(u16, u16); 30] = [
     // (16, 163),
@@ -66,57 +158,152 @@ pub static PROGRESS_BAR_CHARS: &'static str = "##-";
 // const XY_CONVERSION_FACTOR: f64 = 0.703_125; // pixels -> mm
 // const Z_CONVERSION_FACTOR: f64 = 0.1 * (25.0 / 4096.0); // ns -> mm
 
-#[derive(Clone, Copy, Debug, Eq)]
-pub struct Hit {
-    pub toa: u64,
-    pub tot: u32,
-    pub col: u16,
-    pub row: u16,
+/// Full precision trigger as stored in `triggers.bin`.
+/// `coarse` is in 25 ns ticks and `fine` is the offset within that tick (ps).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct TriggerRecord {
+    pub event: u32,
+    pub raw_count: u16,
+    pub channel: u8,
+    pub coarse: u64,
+    pub fine: u32,
 }
 
-impl Ord for Hit {
-    fn cmp(&self, other: &Hit) -> Ordering {
-        self.toa.cmp(&other.toa)
+impl TriggerRecord {
+    /// Size of a record in `triggers.bin` (bytes)
+    pub const SIZE: usize = 24;
+
+    /// Start of the header of `triggers.bin`, followed by the format version (u32)
+    pub const FILE_MAGIC: [u8; 4] = *b"TPXT";
+
+    /// Version of the `triggers.bin` format, bumped whenever the layout or meaning of a field
+    /// changes. Version 1 has `fine` in ps.
+    pub const FILE_VERSION: u32 = 1;
+
+    /// Size of the header of `triggers.bin` (bytes)
+    pub const HEADER_SIZE: usize = 8;
+
+    /// Trigger time (ps)
+    pub fn time_ps(&self) -> u64 {
+        TdcTime {
+            coarse: self.coarse,
+            fine: self.fine,
+        }
+        .as_ps()
+    }
+
+    /// Trigger time (ns)
+    pub fn time(&self) -> u64 {
+        self.time_ps() / 1000
     }
 }
 
-impl PartialOrd for Hit {
-    fn partial_cmp(&self, other: &Hit) -> Option<Ordering> {
-        Some(self.cmp(other))
+impl From<TriggerRecord> for Trigger {
+    fn from(record: TriggerRecord) -> Trigger {
+        Trigger {
+            event: record.event,
+            time: record.time(),
+        }
     }
 }
 
-impl PartialEq for Hit {
-    fn eq(&self, other: &Hit) -> bool {
-        self.toa == other.toa
+/// Exposure interval between a gate start and stop edge (ns)
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct Gate {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl Gate {
+    pub fn duration(&self) -> u64 {
+        self.end - self.start
+    }
+
+    pub fn contains(&self, time: u64) -> bool {
+        time >= self.start && time < self.end
     }
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, Serialize, Eq)]
-pub struct Trigger {
+/// Acquisition window for a single trigger, overriding the global window in the extraction tools.
+/// `window_size` and `window_offset` (the part of the window before the trigger) are in µs.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct TriggerWindow {
     pub event: u32,
-    pub time: u64,
+    pub window_size: f64,
+    pub window_offset: f64,
 }
 
-impl Ord for Trigger {
-    fn cmp(&self, other: &Trigger) -> Ordering {
-        self.time.cmp(&other.time)
+impl TriggerWindow {
+    /// Time before the trigger to include (ns)
+    pub fn look_behind(&self) -> u64 {
+        (self.window_offset * 1000.0) as u64
     }
-}
 
-impl PartialOrd for Trigger {
-    fn partial_cmp(&self, other: &Trigger) -> Option<Ordering> {
-        Some(self.cmp(other))
+    /// Time after the trigger to include (ns)
+    pub fn look_ahead(&self) -> u64 {
+        ((self.window_size - self.window_offset) * 1000.0) as u64
     }
 }
 
-impl PartialEq for Trigger {
-    fn eq(&self, other: &Trigger) -> bool {
-        self.time == other.time
+/// Parses the global trigger window of the extraction tools, either from `--look-behind` and
+/// `--look-ahead` (µs before and after the trigger, so the look-behind can be several windows
+/// long) or from `--window-size` (µs) with `--post-trigger-percent` of it after the trigger.
+/// Returns the look-behind and look-ahead (ns), `None` if no window was given.
+pub fn parse_trigger_window(
+    look_behind: Option<&str>,
+    look_ahead: Option<&str>,
+    window_size: Option<&str>,
+    post_trigger_percent: Option<&str>,
+) -> Result<Option<(u64, u64)>, String> {
+    let parse = |name: &str, value: &str| {
+        value
+            .parse::<f64>()
+            .ok()
+            .filter(|x| *x >= 0.0)
+            .ok_or_else(|| format!("Invalid {} '{}'", name, value))
+    };
+
+    if look_behind.is_some() || look_ahead.is_some() {
+        if window_size.is_some() || post_trigger_percent.is_some() {
+            return Err("--look-behind and --look-ahead cannot be combined with --window-size or --post-trigger-percent".to_owned());
+        }
+
+        let look_behind = look_behind.map(|x| parse("look-behind", x)).transpose()?.unwrap_or(0.0);
+        let look_ahead = look_ahead.map(|x| parse("look-ahead", x)).transpose()?.unwrap_or(0.0);
+
+        if look_behind + look_ahead <= 0.0 {
+            return Err("The trigger window is empty".to_owned());
+        }
+
+        return Ok(Some(((look_behind * 1000.0) as u64, (look_ahead * 1000.0) as u64)));
+    }
+
+    let window_size = match window_size {
+        Some(window_size) => parse("window size", window_size)?,
+        None if post_trigger_percent.is_some() => return Err("--post-trigger-percent needs --window-size".to_owned()),
+        None => return Ok(None),
+    };
+
+    let post_trigger_percent = post_trigger_percent
+        .map(|x| parse("post-trigger percent", x))
+        .transpose()?
+        .unwrap_or(100.0);
+
+    if post_trigger_percent <= 0.0 || post_trigger_percent > 100.0 {
+        return Err(format!("Post-trigger percent {} is outside (0, 100]", post_trigger_percent));
     }
+
+    Ok(Some((
+        (window_size * (100.0 - post_trigger_percent) * 10.0) as u64,
+        (window_size * post_trigger_percent * 10.0) as u64,
+    )))
 }
 
-#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+// Bits used in the `flags` column of the cluster metadata
+pub const CLUSTER_FLAG_TRUNCATED: u8 = 0x1; // Cluster hit the size/duration cap and was cut short
+pub const CLUSTER_FLAG_PILE_UP: u8 = 0x2; // Event has more than one time island, ie. a second interaction
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ClusterMetadata {
     pub event: usize,
     pub time: f64,
@@ -124,6 +311,72 @@ pub struct ClusterMetadata {
     pub hits: usize,
     pub sum_tot: u32,
     pub offset: usize,
+    #[serde(default)]
+    pub flags: u8,
+    #[serde(default)]
+    pub time_islands: usize, // Separate groups of hits in time, 0 if not counted (only events are)
+    #[serde(default)]
+    pub run_id: String, // Name of the run directory the cluster/event came from
+    #[serde(default)]
+    pub uid: String, // Globally unique ID, assigned once and carried through every processing step
+    #[serde(default)]
+    pub external_id: Option<u64>, // Event ID of the trigger in an external DAQ, if mapped
+    #[serde(default)]
+    pub min_col: Option<u16>, // Pixel bounding box, empty if not recorded
+    #[serde(default)]
+    pub max_col: Option<u16>,
+    #[serde(default)]
+    pub min_row: Option<u16>,
+    #[serde(default)]
+    pub max_row: Option<u16>,
+    #[serde(default)]
+    pub centroid_col: Option<f64>, // ToT weighted (unweighted for hits without ToT), empty if not recorded
+    #[serde(default)]
+    pub centroid_row: Option<f64>,
+    #[serde(default)]
+    pub trigger_time: Option<f64>, // Time of the trigger an event was extracted around (ns), empty if not recorded
+    #[serde(default)]
+    pub window_start: Option<f64>, // Trigger window (ns)
+    #[serde(default)]
+    pub window_end: Option<f64>,
+    #[serde(default)]
+    pub hit_fraction: Option<f64>, // Fraction of the trigger window's hits in the cluster
+    #[serde(default)]
+    pub energy: Option<f64>, // Summed hit energies (keV) with --energy-calibration, empty if not calibrated
+}
+
+impl ClusterMetadata {
+    /// Fills in the bounding box and centroid columns from the hits of the cluster/event, so
+    /// clusters can be selected by position without reading the binary file
+    pub fn with_position(self, hits: &[Hit]) -> ClusterMetadata {
+        if hits.is_empty() {
+            return self;
+        }
+
+        let sum_tot: f64 = hits.iter().map(|hit| f64::from(hit.tot)).sum();
+        let weight = |hit: &Hit| if sum_tot > 0.0 { f64::from(hit.tot) } else { 1.0 };
+        let sum_weights: f64 = hits.iter().map(weight).sum();
+
+        ClusterMetadata {
+            min_col: hits.iter().map(|hit| hit.col).min(),
+            max_col: hits.iter().map(|hit| hit.col).max(),
+            min_row: hits.iter().map(|hit| hit.row).min(),
+            max_row: hits.iter().map(|hit| hit.row).max(),
+            centroid_col: Some(hits.iter().map(|hit| f64::from(hit.col) * weight(hit)).sum::<f64>() / sum_weights),
+            centroid_row: Some(hits.iter().map(|hit| f64::from(hit.row) * weight(hit)).sum::<f64>() / sum_weights),
+            ..self
+        }
+    }
+}
+
+/// Makes the globally unique ID of a cluster/event, either from the run ID and event number or a
+/// random UUID (v4)
+pub fn make_cluster_uid(run_id: &str, event: usize, use_uuid: bool) -> String {
+    if use_uuid {
+        uuid::Uuid::new_v4().to_string()
+    } else {
+        format!("{}-{:09}", run_id, event)
+    }
 }
 
 pub fn parse_human_readable_number<T: Num + std::str::FromStr + std::convert::TryFrom<u64>>(string: &str) -> Option<T> {
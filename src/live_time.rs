@@ -0,0 +1,64 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/live_time.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use serde::{Deserialize, Serialize};
+
+use crate::Gate;
+
+/// Exposure accounting for a run, all times in ns
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LiveTime {
+    pub source: String,
+    pub intervals: usize,
+    pub run_start: u64,
+    pub run_end: u64,
+    pub run_duration: u64,
+    pub exposure_time: u64,
+    pub dead_time: u64,
+    pub duty_cycle: f64,
+}
+
+/// Merges overlapping or touching intervals into a sorted list of disjoint intervals.
+pub fn merge_intervals(intervals: &[Gate]) -> Vec<Gate> {
+    let mut sorted = intervals.to_vec();
+    sorted.sort_by_key(|gate| gate.start);
+
+    let mut merged: Vec<Gate> = Vec::with_capacity(sorted.len());
+
+    for gate in sorted {
+        match merged.last_mut() {
+            Some(last) if gate.start <= last.end => last.end = last.end.max(gate.end),
+            _ => merged.push(gate),
+        }
+    }
+
+    merged
+}
+
+/// Computes the exposure of a run from its live intervals. The run is taken to span from
+/// `run_start` to `run_end`, extended to cover all of the intervals.
+pub fn calculate_live_time(source: &str, intervals: &[Gate], run_start: u64, run_end: u64) -> LiveTime {
+    let merged = merge_intervals(intervals);
+
+    let run_start = merged.first().map_or(run_start, |gate| gate.start.min(run_start));
+    let run_end = merged.last().map_or(run_end, |gate| gate.end.max(run_end));
+
+    let run_duration = run_end - run_start;
+    let exposure_time: u64 = merged.iter().map(|gate| gate.duration()).sum();
+
+    LiveTime {
+        source: source.to_owned(),
+        intervals: intervals.len(),
+        run_start,
+        run_end,
+        run_duration,
+        exposure_time,
+        dead_time: run_duration - exposure_time,
+        duty_cycle: if run_duration > 0 { exposure_time as f64 / run_duration as f64 } else { 0.0 },
+    }
+}
@@ -0,0 +1,130 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/output_template.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::path::{Component, Path, PathBuf};
+
+use serde::{Serialize, Serializer};
+
+/// Directory of each run under the output directory, as `raw_data_parser` has always named them
+pub const DEFAULT_RUN_DIR_TEMPLATE: &str = "{datetime}_{run_name}";
+
+#[derive(Clone, Debug)]
+enum Segment {
+    Literal(String),
+    Field(String),
+}
+
+/// A template for output paths with `{field}` placeholders filled in for each run (e.g.
+/// `{device}/{date}/{run_name}`). Templates are checked when parsed, so a mistake is reported
+/// before any run is processed rather than part way through.
+#[derive(Clone, Debug)]
+pub struct OutputTemplate {
+    template: String,
+    segments: Vec<Segment>,
+}
+
+impl OutputTemplate {
+    /// Parses a template, which may only use the given fields and must stay within the directory
+    /// it is relative to
+    pub fn parse(template: &str, fields: &[&str]) -> Result<OutputTemplate, String> {
+        let mut segments = Vec::new();
+        let mut rest = template;
+
+        while let Some(start) = rest.find(['{', '}']) {
+            if rest[start..].starts_with('}') {
+                return Err(format!("Unmatched '}}' in output template '{}'", template));
+            }
+
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                None => return Err(format!("Unmatched '{{' in output template '{}'", template)),
+            };
+
+            let field = &rest[start + 1..end];
+
+            if !fields.contains(&field) {
+                return Err(format!(
+                    "Unknown field '{{{}}}' in output template '{}' (expected one of {})",
+                    field,
+                    template,
+                    fields.iter().map(|x| format!("{{{}}}", x)).collect::<Vec<_>>().join(", ")
+                ));
+            }
+
+            if start > 0 {
+                segments.push(Segment::Literal(rest[..start].to_owned()));
+            }
+
+            segments.push(Segment::Field(field.to_owned()));
+
+            rest = &rest[end + 1..];
+        }
+
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.to_owned()));
+        }
+
+        let path = Path::new(template);
+
+        if segments.is_empty() || !path.components().all(|x| matches!(x, Component::Normal(_))) {
+            return Err(format!("Output template '{}' must be a relative path without '.' or '..'", template));
+        }
+
+        Ok(OutputTemplate {
+            template: template.to_owned(),
+            segments,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.template
+    }
+
+    /// Fills in the fields of the template. Path separators in the values are replaced, so each
+    /// value stays within its path component, and separators left dangling by an empty value
+    /// (e.g. `{run_name}` for an unnamed run) are trimmed, as are components left empty.
+    pub fn render<V: AsRef<str>>(&self, values: &[(&str, V)]) -> PathBuf {
+        let mut rendered = String::new();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(literal) => rendered.push_str(literal),
+                Segment::Field(field) => {
+                    let value = values.iter().find(|(name, _)| name == field).map_or("", |(_, value)| value.as_ref());
+
+                    if value.is_empty() {
+                        rendered.push('\0'); // Marks where an empty value was, for trimming
+                    } else {
+                        rendered.push_str(&value.replace('/', "_"));
+                    }
+                }
+            }
+        }
+
+        rendered
+            .split('/')
+            .map(|component| {
+                if component.contains('\0') {
+                    component
+                        .replace("\0", "")
+                        .trim_matches(|c| c == '_' || c == '-' || c == ' ')
+                        .to_owned()
+                } else {
+                    component.to_owned()
+                }
+            })
+            .filter(|component| !component.is_empty())
+            .collect()
+    }
+}
+
+impl Serialize for OutputTemplate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.template)
+    }
+}
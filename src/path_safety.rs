@@ -0,0 +1,121 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/path_safety.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use glob::Pattern;
+
+use crate::is_object_url;
+
+/// Files `--overwrite` may remove before `--yes` is needed to confirm it
+pub const DEFAULT_MAX_OVERWRITE_FILES: usize = 1000;
+
+/// Absolute path with symlinks resolved, for paths that need not exist yet (the nearest existing
+/// ancestor is resolved and the rest appended)
+pub fn resolved_path(path: &Path) -> io::Result<PathBuf> {
+    let path = if path.is_absolute() { path.to_owned() } else { std::env::current_dir()?.join(path) };
+
+    for ancestor in path.ancestors() {
+        if let Ok(resolved) = fs::canonicalize(ancestor) {
+            let rest = path.strip_prefix(ancestor).unwrap();
+
+            return Ok(if rest.as_os_str().is_empty() { resolved } else { resolved.join(rest) });
+        }
+    }
+
+    Ok(path)
+}
+
+/// Splits a glob pattern into the directory it searches under (the components before the first
+/// with a wildcard) and the remaining components
+fn split_glob(pattern: &str) -> (PathBuf, Vec<String>) {
+    let mut base = PathBuf::new();
+    let mut rest = Vec::new();
+
+    for component in Path::new(pattern).components() {
+        let name = component.as_os_str().to_string_lossy().into_owned();
+
+        if rest.is_empty() && !name.contains(['*', '?', '[']) {
+            base.push(component);
+        } else {
+            rest.push(name);
+        }
+    }
+
+    (base, rest)
+}
+
+/// Checks the output directory is outside everything the input pattern reads, so the outputs can
+/// never be read back as inputs and removing the output directory can never remove any inputs
+pub fn check_output_outside_input(input_glob: &str, input_files: &[PathBuf], output_dir: &Path) -> io::Result<()> {
+    if is_object_url(Path::new(input_glob)) {
+        return Ok(());
+    }
+
+    let error = |message: String| Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+
+    let output_dir = resolved_path(output_dir)?;
+
+    for input_file in input_files {
+        if resolved_path(input_file)?.starts_with(&output_dir) {
+            return error(format!(
+                "Input file '{}' is inside the output directory '{}'",
+                input_file.display(),
+                output_dir.display()
+            ));
+        }
+    }
+
+    let (base, rest) = split_glob(input_glob);
+
+    // Whether the pattern reaches down into the output directory, where it would match the
+    // outputs of this or a later run
+    if let Ok(relative) = output_dir.strip_prefix(resolved_path(&base)?) {
+        let relative: Vec<String> = relative.components().map(|x| x.as_os_str().to_string_lossy().into_owned()).collect();
+
+        let reaches = rest.iter().any(|x| x == "**")
+            || (rest.len() > relative.len()
+                && relative
+                    .iter()
+                    .zip(&rest)
+                    .all(|(name, pattern)| Pattern::new(pattern).map_or(false, |x| x.matches(name))));
+
+        if reaches {
+            return error(format!(
+                "Output directory '{}' is inside the directories matched by the input pattern '{}'",
+                output_dir.display(),
+                input_glob
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Number of files under a directory, 0 if it does not exist
+pub fn count_files(dir: &Path) -> io::Result<usize> {
+    if !dir.is_dir() {
+        return Ok(0);
+    }
+
+    let mut count = 0;
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+
+        if entry.file_type()?.is_dir() {
+            count += count_files(&entry.path())?;
+        } else {
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
@@ -0,0 +1,91 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/pixel_mask.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Hit;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PixelStatus {
+    Ok,
+    Hot,
+    Dead,
+    NoisyColumn,
+}
+
+/// Row of a pixel status CSV (as written by `hot_pixel_search`)
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PixelStatusRecord {
+    pub col: u16,
+    pub row: u16,
+    pub hits: u64,
+    pub status: PixelStatus,
+}
+
+/// Status of every pixel in the matrix, used to mask out hits from pixels that are not `Ok`
+#[derive(Clone, Debug, Serialize)]
+pub struct PixelMask {
+    pub file: PathBuf,
+    pub masked_pixels: usize,
+    #[serde(skip)]
+    status: Vec<PixelStatus>,
+}
+
+impl PixelMask {
+    pub fn from_csv(file: &Path) -> io::Result<PixelMask> {
+        let mut rdr = csv::Reader::from_path(file)?;
+
+        let mut status = vec![PixelStatus::Ok; 256 * 256];
+
+        for result in rdr.deserialize() {
+            let record: PixelStatusRecord = result?;
+
+            if record.col > 255 || record.row > 255 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Pixel status map {:#?} has a pixel outside the matrix ({}, {})",
+                        file, record.col, record.row
+                    ),
+                ));
+            }
+
+            status[usize::from(record.row) * 256 + usize::from(record.col)] = record.status;
+        }
+
+        Ok(PixelMask {
+            file: file.to_path_buf(),
+            masked_pixels: status.iter().filter(|x| **x != PixelStatus::Ok).count(),
+            status,
+        })
+    }
+
+    pub fn status(&self, col: u16, row: u16) -> PixelStatus {
+        if col > 255 || row > 255 {
+            return PixelStatus::Ok;
+        }
+
+        self.status[usize::from(row) * 256 + usize::from(col)]
+    }
+
+    pub fn is_masked(&self, hit: &Hit) -> bool {
+        self.status(hit.col, hit.row) != PixelStatus::Ok
+    }
+
+    /// Coordinates of the masked pixels, in the form of `HOT_PIXELS`
+    pub fn masked_pixel_list(&self) -> Vec<(u16, u16)> {
+        (0..256 * 256)
+            .filter(|i| self.status[*i] != PixelStatus::Ok)
+            .map(|i| ((i % 256) as u16, (i / 256) as u16))
+            .collect()
+    }
+}
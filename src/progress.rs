@@ -0,0 +1,53 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/progress.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::io;
+use std::path::Path;
+
+use crate::input_file_len;
+
+pub static BYTES_PROGRESS_BAR_TEMPLATE: &str = "[{elapsed_precise}] {bar:40.cyan/blue} {bytes:>9}/{total_bytes:9} {msg}";
+
+/// Total size of a set of input files from their metadata, the length of a progress bar over them
+pub fn total_file_bytes<P: AsRef<Path>>(paths: &[P]) -> io::Result<u64> {
+    let mut total = 0;
+
+    for path in paths {
+        total += input_file_len(path.as_ref())?;
+    }
+
+    Ok(total)
+}
+
+/// Progress through a sequence of files in bytes consumed. Each file counts for its actual size
+/// once finished, so headers of any size and truncated files still end with the bar at 100%.
+#[derive(Debug, Default)]
+pub struct FileByteProgress {
+    finished_bytes: u64, // Total size of the files finished so far
+    file_len: u64,       // Size of the current file
+}
+
+impl FileByteProgress {
+    pub fn new() -> FileByteProgress {
+        FileByteProgress::default()
+    }
+
+    pub fn start_file(&mut self, file_len: u64) {
+        self.file_len = file_len;
+    }
+
+    /// Position for the progress bar given the bytes consumed from the current file
+    pub fn position(&self, file_bytes_consumed: u64) -> u64 {
+        self.finished_bytes + file_bytes_consumed.min(self.file_len)
+    }
+
+    pub fn finish_file(&mut self) {
+        self.finished_bytes += self.file_len;
+        self.file_len = 0;
+    }
+}
@@ -0,0 +1,158 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/provenance.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::prelude::*;
+use serde::Serialize;
+
+use crate::{has_partial_marker, input_file_len};
+
+/// Input file of a data product, as it was when the product was made
+#[derive(Clone, Debug, Serialize)]
+pub struct InputFile {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: Option<String>,
+}
+
+/// How a data product was made, written alongside the settings of each tool's output
+#[derive(Clone, Debug, Serialize)]
+pub struct Provenance {
+    pub crate_version: String,
+    pub git_commit: Option<String>,
+    pub command_line: String,
+    pub hostname: String,
+    pub created: String,
+    /// Hash of the settings the product was made with, see `settings_hash`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub settings_hash: Option<String>,
+    pub input_files: Vec<InputFile>,
+}
+
+impl Provenance {
+    pub fn new<P: AsRef<Path>>(input_files: &[P]) -> Provenance {
+        let input_files = input_files
+            .iter()
+            .map(|path| {
+                let path = path.as_ref();
+                let metadata = fs::metadata(path).ok();
+
+                InputFile {
+                    path: path.to_owned(),
+                    size: metadata.as_ref().map_or_else(|| input_file_len(path).unwrap_or(0), |x| x.len()),
+                    modified: metadata.and_then(|x| x.modified().ok()).map(|x| DateTime::<Utc>::from(x).to_rfc3339()),
+                }
+            })
+            .collect();
+
+        Provenance {
+            crate_version: env!("CARGO_PKG_VERSION").to_owned(),
+            git_commit: option_env!("GIT_COMMIT_HASH").map(|x| x.to_owned()),
+            command_line: env::args().collect::<Vec<_>>().join(" "),
+            hostname: hostname(),
+            created: Utc::now().to_rfc3339(),
+            settings_hash: None,
+            input_files,
+        }
+    }
+}
+
+pub(crate) fn hostname() -> String {
+    env::var("HOSTNAME")
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|x| x.trim().to_owned())
+        .filter(|x| !x.is_empty())
+        .unwrap_or_else(|| "unknown".to_owned())
+}
+
+#[derive(Serialize)]
+struct SettingsWithProvenance<'a, T: Serialize> {
+    #[serde(flatten)]
+    settings: &'a T,
+    provenance: Provenance,
+}
+
+/// Writes the settings TOML of a data product, with a `[provenance]` table recording how it was
+/// made from `input_files`.
+pub fn write_settings_toml<T: Serialize, P: AsRef<Path>>(path: &Path, settings: &T, input_files: &[P]) -> io::Result<()> {
+    let contents = SettingsWithProvenance {
+        settings,
+        provenance: Provenance {
+            settings_hash: Some(settings_hash(settings)?),
+            ..Provenance::new(input_files)
+        },
+    };
+
+    let toml_str = toml::to_string(&contents).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+    fs::write(path, toml_str)
+}
+
+/// Hash of the settings TOML (as FNV-1a over the TOML with its keys sorted), so outputs made with
+/// the same settings can be recognised whatever order the fields were written in
+pub fn settings_hash<T: Serialize>(settings: &T) -> io::Result<String> {
+    let value = toml::Value::try_from(settings).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+    hash_settings_value(&value)
+}
+
+fn hash_settings_value(value: &toml::Value) -> io::Result<String> {
+    let toml_str = toml::to_string(value).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+    let hash = toml_str
+        .bytes()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| (hash ^ u64::from(byte)).wrapping_mul(0x100_0000_01b3));
+
+    Ok(format!("{:016x}", hash))
+}
+
+/// Settings hash of an existing data product, from its settings TOML (hashing the settings in it
+/// for products written before the hash was recorded)
+fn stored_settings_hash(toml_path: &Path) -> io::Result<String> {
+    let mut value: toml::Value =
+        toml::from_str(&fs::read_to_string(toml_path)?).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+    let table = value.as_table_mut().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Settings TOML is not a table"))?;
+
+    match table.remove("provenance") {
+        Some(provenance) => match provenance.get("settings_hash").and_then(|x| x.as_str()) {
+            Some(hash) => Ok(hash.to_owned()),
+            None => hash_settings_value(&value),
+        },
+        None => hash_settings_value(&value),
+    }
+}
+
+/// How the output a tool would write to a run compares with what is already there
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExistingOutput {
+    /// No output, or only the partial output of an interrupted run
+    None,
+    /// Output made with the same settings, so there is nothing to do
+    SameSettings,
+    /// Output made with different settings (or whose settings can not be read), to be replaced
+    DifferentSettings,
+}
+
+/// Compares the output at `output_data_file_path` (whose settings are in the `.toml` alongside it)
+/// with output made with the settings hashing to `settings_hash`
+pub fn existing_output(output_data_file_path: &Path, settings_hash: &str) -> ExistingOutput {
+    if !output_data_file_path.exists() || has_partial_marker(output_data_file_path) {
+        return ExistingOutput::None;
+    }
+
+    match stored_settings_hash(&output_data_file_path.with_extension("toml")) {
+        Ok(hash) if hash == settings_hash => ExistingOutput::SameSettings,
+        _ => ExistingOutput::DifferentSettings,
+    }
+}
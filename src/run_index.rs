@@ -0,0 +1,172 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/run_index.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDateTime;
+use regex::Regex;
+
+use crate::Run;
+
+/// A run directory found by `RunIndex::scan`, with what it contains
+#[derive(Clone, Debug)]
+pub struct RunEntry {
+    pub dir: PathBuf,
+    pub name: Option<String>,               // Run name given in the raw file names, if any
+    pub start_time: Option<NaiveDateTime>,  // From the directory name written by `raw_data_parser`
+    pub device: Option<String>,             // Chip ID (e.g. W0005_E09) from the raw files in `hits.toml`
+    pub hits: u64,
+    pub triggers: bool,
+    pub gates: bool,
+    pub cluster_files: Vec<String>, // Cluster/event files (e.g. `clusters`, `trigger_events`)
+    pub partial: bool,              // `hits.bin` was left unfinished by an interrupted run
+}
+
+impl RunEntry {
+    pub fn dir_name(&self) -> &str {
+        self.dir.file_name().and_then(|x| x.to_str()).unwrap_or("")
+    }
+
+    pub fn has_cluster_file(&self, name: &str) -> bool {
+        self.cluster_files.iter().any(|x| x == name)
+    }
+
+    pub fn open(&self) -> io::Result<Run> {
+        Run::open(&self.dir)
+    }
+}
+
+/// Criteria for selecting runs from a `RunIndex`, unset fields match every run
+#[derive(Clone, Debug, Default)]
+pub struct RunQuery {
+    pub after: Option<NaiveDateTime>,
+    pub before: Option<NaiveDateTime>,
+    pub device: Option<String>,
+    pub name: Option<String>,         // Part of the run directory name
+    pub cluster_file: Option<String>, // Only runs that have this cluster/event file
+}
+
+impl RunQuery {
+    pub fn matches(&self, run: &RunEntry) -> bool {
+        if self.after.is_some() || self.before.is_some() {
+            match run.start_time {
+                Some(start_time) => {
+                    if self.after.map_or(false, |after| start_time < after) || self.before.map_or(false, |before| start_time >= before) {
+                        return false;
+                    }
+                }
+                None => return false,
+            }
+        }
+
+        if let Some(device) = &self.device {
+            if run.device.as_ref() != Some(device) {
+                return false;
+            }
+        }
+
+        if let Some(name) = &self.name {
+            if !run.dir_name().contains(name.as_str()) {
+                return false;
+            }
+        }
+
+        if let Some(cluster_file) = &self.cluster_file {
+            if !run.has_cluster_file(cluster_file) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Every run directory under an output tree, in start time order
+#[derive(Clone, Debug, Default)]
+pub struct RunIndex {
+    pub runs: Vec<RunEntry>,
+}
+
+impl RunIndex {
+    /// Walks the tree under `root` for run directories (those with a `hits.bin`). The
+    /// subdirectories of a run are not searched, and symlinks are not followed.
+    pub fn scan(root: &Path) -> io::Result<RunIndex> {
+        let device_regex = Regex::new(r"(\w\d{4}_\w\d{2})-\d{6}-\d{6}-\d+\.dat$").unwrap();
+
+        let mut runs = Vec::new();
+        let mut dirs = vec![root.to_owned()];
+
+        while let Some(dir) = dirs.pop() {
+            if dir.join("hits.bin").is_file() {
+                runs.push(index_run(&dir, &device_regex)?);
+                continue;
+            }
+
+            for entry in fs::read_dir(&dir)? {
+                let entry = entry?;
+
+                if entry.file_type()?.is_dir() {
+                    dirs.push(entry.path());
+                }
+            }
+        }
+
+        // Runs without a start time go last
+        runs.sort_by(|a, b| (a.start_time.is_none(), a.start_time, &a.dir).cmp(&(b.start_time.is_none(), b.start_time, &b.dir)));
+
+        Ok(RunIndex { runs })
+    }
+
+    pub fn len(&self) -> usize {
+        self.runs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.runs.is_empty()
+    }
+
+    pub fn query(&self, query: &RunQuery) -> Vec<&RunEntry> {
+        self.runs.iter().filter(|run| query.matches(run)).collect()
+    }
+}
+
+fn index_run(dir: &Path, device_regex: &Regex) -> io::Result<RunEntry> {
+    let run = Run::open(dir)?;
+
+    // Directories are named `<%Y-%m-%d_%H-%M-%S>[_<run name>]` by `raw_data_parser`
+    let dir_name = run.name();
+
+    let start_time = dir_name.get(..19).and_then(|x| NaiveDateTime::parse_from_str(x, "%Y-%m-%d_%H-%M-%S").ok());
+
+    let name = match start_time {
+        Some(_) => dir_name.get(20..).filter(|x| !x.is_empty()).map(|x| x.to_owned()),
+        None => Some(dir_name.to_owned()),
+    };
+
+    // Runs parsed before provenance was recorded have no device
+    let device = run
+        .settings("hits")
+        .ok()
+        .flatten()
+        .and_then(|settings| settings.get("provenance")?.get("input_files")?.as_array()?.first()?.get("path")?.as_str().map(|x| x.to_owned()))
+        .and_then(|path| device_regex.captures(&path).map(|caps| caps[1].to_owned()));
+
+    Ok(RunEntry {
+        dir: dir.to_owned(),
+        name,
+        start_time,
+        device,
+        hits: run.hit_count()?,
+        triggers: dir.join("triggers.bin").exists() || dir.join("triggers.csv").exists(),
+        gates: dir.join("gates.csv").exists(),
+        cluster_files: run.cluster_files()?,
+        partial: run.is_partial("hits"),
+    })
+}
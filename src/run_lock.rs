@@ -0,0 +1,112 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/run_lock.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::fs;
+use std::io::{self, Write as _};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::provenance::hostname;
+
+/// Contents of a run lock file, identifying the process holding it
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RunLockInfo {
+    pub pid: u32,
+    pub hostname: String,
+    pub created: String,
+}
+
+impl RunLockInfo {
+    fn current() -> RunLockInfo {
+        RunLockInfo {
+            pid: process::id(),
+            hostname: hostname(),
+            created: Utc::now().to_rfc3339(),
+        }
+    }
+
+    /// Whether the holder is known to have exited without releasing the lock. Only decidable for
+    /// locks taken on this host.
+    fn is_stale(&self) -> bool {
+        let proc_dir = Path::new("/proc");
+
+        self.hostname == hostname() && proc_dir.is_dir() && !proc_dir.join(self.pid.to_string()).exists()
+    }
+}
+
+/// Advisory lock on a run, held while it is being processed and released when dropped. The lock
+/// file sits next to the run directory (`<run_dir>.lock`) so it survives the directory being
+/// created or removed.
+#[derive(Debug)]
+pub struct RunLock {
+    path: PathBuf,
+}
+
+impl RunLock {
+    /// Takes the lock on a run, failing if another process holds it. Locks left by processes on
+    /// this host that no longer exist are taken over, `force` takes over any existing lock.
+    pub fn acquire(run_dir: &Path, force: bool) -> io::Result<RunLock> {
+        let path = lock_path(run_dir);
+        let info = RunLockInfo::current();
+        let contents = serde_json::to_string(&info)?;
+
+        match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(contents.as_bytes())?;
+                return Ok(RunLock { path });
+            }
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(err) => return Err(err),
+        }
+
+        let holder = read_lock(&path);
+        let stale = holder.as_ref().filter(|x| x.is_stale()).is_some();
+
+        if !force && !stale {
+            let holder = match holder {
+                Some(x) => format!("process {} on {} since {}", x.pid, x.hostname, x.created),
+                None => "another process".to_owned(),
+            };
+
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Run is locked by {} ({}), use --force to override", holder, path.display()),
+            ));
+        }
+
+        fs::write(&path, contents)?;
+
+        Ok(RunLock { path })
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        // Leave a lock that has since been taken over by another process with --force
+        if read_lock(&self.path)
+            .filter(|x| x.pid == process::id() && x.hostname == hostname())
+            .is_some()
+        {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+fn lock_path(run_dir: &Path) -> PathBuf {
+    let mut file_name = run_dir.file_name().map(|x| x.to_owned()).unwrap_or_default();
+    file_name.push(".lock");
+
+    run_dir.with_file_name(file_name)
+}
+
+fn read_lock(path: &Path) -> Option<RunLockInfo> {
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
@@ -0,0 +1,412 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/run_processing.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::any::Any;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use colored::*;
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use serde::Serialize;
+
+use crate::{
+    format_bytes, is_interrupted, ProcessingTimer, RunLock, BYTES_PROGRESS_BAR_TEMPLATE, INTERRUPTED_EXIT_CODE, PROGRESS_BAR_CHARS,
+    PROGRESS_BAR_TEMPLATE, RUN_SUMMARY_FILENAME,
+};
+
+const OVERALL_PROGRESS_BAR_TEMPLATE: &str = "[{elapsed_precise}] {bar:40.green/white} {bytes:>9}/{total_bytes:9} {bytes_per_sec} ETA {eta} {msg}";
+const OVERALL_PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    Ok,
+    Failed,
+    Interrupted,
+}
+
+/// The share of the matched runs processed by one task of a batch array job (e.g. Slurm or
+/// HTCondor), set with `--job-index` and `--job-count`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct JobPartition {
+    pub index: usize,
+    pub count: usize,
+}
+
+impl Default for JobPartition {
+    fn default() -> Self {
+        JobPartition { index: 0, count: 1 }
+    }
+}
+
+impl JobPartition {
+    /// Parses the values of `--job-index` and `--job-count`, which must be given together
+    pub fn from_args(index: Option<&str>, count: Option<&str>) -> Result<JobPartition, String> {
+        let (index, count) = match (index, count) {
+            (None, None) => return Ok(JobPartition::default()),
+            (Some(index), Some(count)) => (index, count),
+            _ => return Err("--job-index and --job-count must be given together".to_owned()),
+        };
+
+        let index = index.parse::<usize>().map_err(|_| format!("Invalid job index '{}'", index))?;
+        let count = count.parse::<usize>().map_err(|_| format!("Invalid job count '{}'", count))?;
+
+        if count == 0 || index >= count {
+            return Err(format!("Job index {} is out of range for a job count of {}", index, count));
+        }
+
+        Ok(JobPartition { index, count })
+    }
+
+    /// Whether the `i`th matched run belongs to this task. Runs are dealt out to the tasks in
+    /// turn, so as long as every task matches the same runs in the same order each run is
+    /// processed by exactly one task.
+    pub fn contains(&self, i: usize) -> bool {
+        i % self.count == self.index
+    }
+}
+
+/// Options common to the multi-run tools for how `process_runs` handles the runs
+#[derive(Clone, Debug, Default)]
+pub struct RunOptions {
+    pub disable_mt: bool,
+    pub force: bool,                 // Process runs even if they are locked by another process
+    pub on_complete: Option<String>, // Shell command run after each run completes successfully
+}
+
+/// A run for `process_runs` to process, along with the input passed to the processing function
+pub struct RunJob<T> {
+    pub run_dir: PathBuf,
+    pub input: T,
+    pub input_bytes: u64, // Size of the run's input files, weights the run in the overall progress
+    progress_total: u64,
+    progress_bar: ProgressBar,
+}
+
+impl<T> RunJob<T> {
+    /// `progress_total` is the length of the run's progress bar, in whatever units the processing
+    /// function advances it by.
+    pub fn new(run_dir: PathBuf, input: T, input_bytes: u64, progress_total: u64) -> RunJob<T> {
+        RunJob::with_template(run_dir, input, input_bytes, progress_total, PROGRESS_BAR_TEMPLATE)
+    }
+
+    /// For runs whose progress bar is advanced by the bytes of input consumed (see
+    /// `FileByteProgress`)
+    pub fn with_byte_progress(run_dir: PathBuf, input: T, input_bytes: u64) -> RunJob<T> {
+        RunJob::with_template(run_dir, input, input_bytes, input_bytes, BYTES_PROGRESS_BAR_TEMPLATE)
+    }
+
+    fn with_template(run_dir: PathBuf, input: T, input_bytes: u64, progress_total: u64, template: &str) -> RunJob<T> {
+        let progress_bar = ProgressBar::new(progress_total);
+        progress_bar.set_style(ProgressStyle::default_bar().template(template).progress_chars(PROGRESS_BAR_CHARS));
+
+        RunJob {
+            run_dir,
+            input,
+            input_bytes,
+            progress_total,
+            progress_bar,
+        }
+    }
+
+    /// Input bytes of the run processed so far, estimated from its progress bar
+    fn bytes_done(&self) -> u64 {
+        if self.progress_total == 0 {
+            return 0;
+        }
+
+        let fraction = self.progress_bar.position().min(self.progress_total) as f64 / self.progress_total as f64;
+
+        (self.input_bytes as f64 * fraction) as u64
+    }
+}
+
+/// Outcome of processing a single run, one row of the processing manifest
+#[derive(Clone, Debug, Serialize)]
+pub struct RunResult {
+    pub run: PathBuf,
+    pub status: RunStatus,
+    pub seconds: f64,
+    pub input_bytes: u64,
+    pub mb_per_second: f64,
+    pub error: Option<String>,
+    pub note: Option<String>, // Anything the tool decided about the run before processing it (e.g. how it was named)
+}
+
+impl RunResult {
+    fn new(run: PathBuf, status: RunStatus, seconds: f64, input_bytes: u64, error: Option<String>) -> RunResult {
+        let mb_per_second = if seconds > 0.0 { input_bytes as f64 / 1e6 / seconds } else { 0.0 };
+
+        RunResult {
+            run,
+            status,
+            seconds,
+            input_bytes,
+            mb_per_second,
+            error,
+            note: None,
+        }
+    }
+}
+
+/// Processes each run with `process`, concurrently unless `disable_mt` is set. Each run is locked
+/// while it is processed (see `RunLock`), runs locked by another process fail unless `force` is
+/// set, and the `on_complete` command is run after each run that succeeds. Errors and panics are
+/// caught per run and recorded as failures. Results are returned in the same order as `jobs`
+/// regardless of the order the runs complete in.
+pub fn process_runs<T, F>(jobs: Vec<RunJob<T>>, options: RunOptions, process: F) -> Vec<RunResult>
+where
+    T: Send + 'static,
+    F: Fn(&Path, T, ProgressBar) -> io::Result<()> + Send + Sync + 'static,
+{
+    let start = Instant::now();
+    let n_jobs = jobs.len();
+    let total_bytes: u64 = jobs.iter().map(|x| x.input_bytes).sum();
+    let process = Arc::new(process);
+    let options = Arc::new(options);
+
+    let results: Vec<RunResult> = if options.disable_mt || n_jobs == 1 {
+        jobs.into_iter().map(|job| run_job(&*process, job, &options)).collect()
+    } else {
+        let multi_progress = MultiProgress::new();
+
+        // Added first so it sits above the bars of the individual runs
+        let overall_progress_bar = multi_progress.add(ProgressBar::new(total_bytes));
+        overall_progress_bar.set_style(
+            ProgressStyle::default_bar()
+                .template(OVERALL_PROGRESS_BAR_TEMPLATE)
+                .progress_chars(PROGRESS_BAR_CHARS),
+        );
+
+        let (sender, receiver) = mpsc::channel();
+        let mut run_progress = Vec::with_capacity(n_jobs);
+        let done: Arc<Vec<AtomicBool>> = Arc::new((0..n_jobs).map(|_| AtomicBool::new(false)).collect());
+
+        for (i, mut job) in jobs.into_iter().enumerate() {
+            job.progress_bar = multi_progress.add(job.progress_bar);
+
+            run_progress.push(RunJob {
+                run_dir: job.run_dir.clone(),
+                input: (),
+                input_bytes: job.input_bytes,
+                progress_total: job.progress_total,
+                progress_bar: job.progress_bar.clone(),
+            });
+
+            let process = Arc::clone(&process);
+            let options = Arc::clone(&options);
+            let done = Arc::clone(&done);
+            let sender = sender.clone();
+
+            rayon::spawn(move || {
+                let result = run_job(&*process, job, &options);
+                done[i].store(true, Ordering::SeqCst);
+                sender.send((i, result)).unwrap();
+            });
+        }
+
+        drop(sender);
+
+        let overall_progress = thread::spawn(move || loop {
+            let n_done = done.iter().filter(|x| x.load(Ordering::SeqCst)).count();
+
+            // Runs that stopped early (failed or interrupted) count as fully processed
+            let bytes_done: u64 = run_progress
+                .iter()
+                .zip(done.iter())
+                .map(|(run, done)| {
+                    if done.load(Ordering::SeqCst) {
+                        run.input_bytes
+                    } else {
+                        run.bytes_done()
+                    }
+                })
+                .sum();
+
+            overall_progress_bar.set_position(bytes_done);
+            overall_progress_bar.set_message(&format!("| {} of {} Runs Done", n_done, n_jobs));
+
+            if n_done == n_jobs {
+                overall_progress_bar.finish();
+                break;
+            }
+
+            thread::sleep(OVERALL_PROGRESS_INTERVAL);
+        });
+
+        multi_progress.join().unwrap();
+        overall_progress.join().unwrap();
+
+        let mut results: Vec<Option<RunResult>> = (0..n_jobs).map(|_| None).collect();
+
+        for (i, result) in receiver {
+            results[i] = Some(result);
+        }
+
+        results.into_iter().map(|x| x.expect("Run did not report a result")).collect()
+    };
+
+    let seconds = start.elapsed().as_secs_f64();
+
+    println!(
+        "\nProcessed {} of input from {} runs in {:.1} s ({:.1} MB/s)",
+        format_bytes(total_bytes),
+        n_jobs,
+        seconds,
+        total_bytes as f64 / 1e6 / seconds
+    );
+
+    results
+}
+
+fn run_job<T, F>(process: &F, job: RunJob<T>, options: &RunOptions) -> RunResult
+where
+    F: Fn(&Path, T, ProgressBar) -> io::Result<()>,
+{
+    let start = Instant::now();
+    let RunJob {
+        run_dir,
+        input,
+        input_bytes,
+        progress_bar,
+        ..
+    } = job;
+
+    // Runs not started before an interrupt are left for the resubmitted job
+    if is_interrupted() {
+        return RunResult::new(run_dir, RunStatus::Interrupted, 0.0, input_bytes, None);
+    }
+
+    // Held until the run has finished, including if it panics
+    let _lock = match RunLock::acquire(&run_dir, options.force) {
+        Ok(lock) => lock,
+        Err(err) => {
+            progress_bar.finish_with_message(&format!("| Locked | {}", run_dir.display()));
+
+            return RunResult::new(run_dir, RunStatus::Failed, 0.0, input_bytes, Some(err.to_string()));
+        }
+    };
+
+    let timer = ProcessingTimer::start(&run_dir);
+
+    // A panic (e.g. from corrupted data) only fails this run, the remaining runs carry on
+    let (status, error) = match panic::catch_unwind(AssertUnwindSafe(|| process(&run_dir, input, progress_bar))) {
+        Ok(Ok(())) => (RunStatus::Ok, None),
+        Ok(Err(err)) if err.kind() == io::ErrorKind::Interrupted => (RunStatus::Interrupted, None),
+        Ok(Err(err)) => (RunStatus::Failed, Some(err.to_string())),
+        Err(payload) => (RunStatus::Failed, Some(format!("panicked: {}", panic_message(&*payload)))),
+    };
+
+    // Kept for the estimates of later dry runs, a run that can't be recorded has still succeeded
+    if status == RunStatus::Ok {
+        let _ = timer.finish(input_bytes);
+    }
+
+    // A failed hook fails the run, as whatever was chained after it has not happened
+    let (status, error) = match (&options.on_complete, status) {
+        (Some(command), RunStatus::Ok) => match run_on_complete(command, &run_dir) {
+            Ok(()) => (status, error),
+            Err(err) => (RunStatus::Failed, Some(format!("on-complete command failed: {}", err))),
+        },
+        _ => (status, error),
+    };
+
+    RunResult::new(run_dir, status, start.elapsed().as_secs_f64(), input_bytes, error)
+}
+
+/// Runs the `--on-complete` command for a run through the shell, with `{run_dir}` and `{summary}`
+/// replaced by the (quoted) paths of the run directory and its summary file
+fn run_on_complete(command: &str, run_dir: &Path) -> io::Result<()> {
+    let command = command
+        .replace("{run_dir}", &shell_quote(&run_dir.to_string_lossy()))
+        .replace("{summary}", &shell_quote(&run_dir.join(RUN_SUMMARY_FILENAME).to_string_lossy()));
+
+    // Output is captured so it doesn't break up the progress bars
+    let output = Command::new("sh").arg("-c").arg(&command).output()?;
+
+    if !output.status.success() {
+        let mut message = format!("'{}' exited with {}", command, output.status);
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !stderr.trim().is_empty() {
+            message.push_str(&format!(": {}", stderr.trim()));
+        }
+
+        return Err(io::Error::new(io::ErrorKind::Other, message));
+    }
+
+    Ok(())
+}
+
+fn shell_quote(string: &str) -> String {
+    format!("'{}'", string.replace('\'', "'\\''"))
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+/// Writes the results of a set of runs as a CSV manifest, in the order they were given.
+pub fn write_run_manifest(path: &Path, results: &[RunResult]) -> io::Result<()> {
+    let mut csv_writer = csv::Writer::from_path(path)?;
+
+    for result in results {
+        csv_writer.serialize(result)?;
+    }
+
+    csv_writer.flush()
+}
+
+/// Prints the runs that failed or were interrupted, returning the exit status the tool should
+/// finish with if there were any.
+pub fn report_run_results(results: &[RunResult]) -> Option<i32> {
+    let failed: Vec<_> = results.iter().filter(|x| x.status == RunStatus::Failed).collect();
+    let interrupted: Vec<_> = results.iter().filter(|x| x.status == RunStatus::Interrupted).collect();
+
+    if !failed.is_empty() {
+        println!("{}", format!("\n{} of {} runs failed:", failed.len(), results.len()).red().bold());
+
+        for result in failed.iter() {
+            println!("  - {}: {}", result.run.display(), result.error.as_deref().unwrap_or("unknown error"));
+        }
+    }
+
+    if !interrupted.is_empty() {
+        println!(
+            "{}",
+            format!("\n{} of {} runs were interrupted:", interrupted.len(), results.len())
+                .yellow()
+                .bold()
+        );
+
+        for result in interrupted.iter() {
+            println!("  - {}", result.run.display());
+        }
+
+        return Some(INTERRUPTED_EXIT_CODE);
+    }
+
+    if failed.is_empty() {
+        None
+    } else {
+        Some(1)
+    }
+}
@@ -0,0 +1,112 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/run_start_time.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use chrono::{DateTime, LocalResult, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+
+/// Section of a run's `summary.json` holding its start time
+pub const RUN_START_TIME_SECTION: &str = "start_time";
+
+/// Start time of a run, from the timestamp in the name of its first raw file
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct RunStartTime {
+    pub file_time: NaiveDateTime, // As written in the file name, in the local time of the DAQ
+    pub timezone: String,         // Time zone the file name time was taken to be in
+    pub utc: DateTime<Utc>,
+    /// Fit to the absolute timestamp packets in the data, if there were any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock_fit: Option<ClockFit>,
+}
+
+/// Linear relation between the ToA timeline and UTC fitted to absolute (GPS/White Rabbit)
+/// timestamps: `utc = offset + time * (1 + drift)`
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ClockFit {
+    pub timestamps: usize,
+    pub offset: i64,       // UTC ns at the start of the ToA timeline
+    pub drift: f64,        // Fractional rate difference of the SPIDR clock from UTC
+    pub rms_residual: f64, // ns
+    pub max_residual: f64, // ns
+}
+
+impl ClockFit {
+    /// Least squares fit to pairs of ToA timeline time (ns) and UTC time (ns since the Unix
+    /// epoch). A single timestamp only fixes the offset, so the drift is then taken to be 0.
+    pub fn fit(points: &[(f64, i64)]) -> Option<ClockFit> {
+        let (_, reference) = *points.first()?;
+
+        // Relative to the first timestamp, as ns since the epoch do not fit exactly in an f64
+        let xy: Vec<(f64, f64)> = points.iter().map(|&(time, utc)| (time, (utc - reference) as f64)).collect();
+
+        let n = xy.len() as f64;
+        let mean_x = xy.iter().map(|&(x, _)| x).sum::<f64>() / n;
+        let mean_y = xy.iter().map(|&(_, y)| y).sum::<f64>() / n;
+
+        let sxx: f64 = xy.iter().map(|&(x, _)| (x - mean_x).powi(2)).sum();
+        let sxy: f64 = xy.iter().map(|&(x, y)| (x - mean_x) * (y - mean_y)).sum();
+
+        let slope = if sxx > 0.0 { sxy / sxx } else { 1.0 };
+        let intercept = mean_y - slope * mean_x;
+
+        let residuals: Vec<f64> = xy.iter().map(|&(x, y)| y - (intercept + slope * x)).collect();
+
+        Some(ClockFit {
+            timestamps: points.len(),
+            offset: reference + intercept.round() as i64,
+            drift: slope - 1.0,
+            rms_residual: (residuals.iter().map(|x| x * x).sum::<f64>() / n).sqrt(),
+            max_residual: residuals.iter().fold(0.0, |max: f64, x| max.max(x.abs())),
+        })
+    }
+
+    /// UTC time (ns since the Unix epoch) of a time on the ToA timeline (ns)
+    pub fn utc_ns(&self, time: f64) -> i64 {
+        self.offset + (time * (1.0 + self.drift)).round() as i64
+    }
+}
+
+impl RunStartTime {
+    /// Resolves a file name time in a time zone. Of the two times a wall clock time repeated when
+    /// the clocks go back could be, the earlier is taken. Times skipped when the clocks go forward
+    /// cannot be resolved.
+    pub fn resolve(file_time: NaiveDateTime, timezone: Tz) -> Result<RunStartTime, String> {
+        let utc = match timezone.from_local_datetime(&file_time) {
+            LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => time.with_timezone(&Utc),
+            LocalResult::None => return Err(format!("{} does not exist in {}", file_time, timezone)),
+        };
+
+        Ok(RunStartTime {
+            file_time,
+            timezone: timezone.name().to_owned(),
+            utc,
+            clock_fit: None,
+        })
+    }
+
+    /// UTC time (ns since the Unix epoch) of a time on the run's ToA timeline (ns). From the
+    /// clock fit if the data had absolute timestamps, otherwise from the start of the run, which
+    /// is only as accurate as the time in the file names (1 s).
+    pub fn utc_ns(&self, time: f64) -> i64 {
+        match &self.clock_fit {
+            Some(clock_fit) => clock_fit.utc_ns(time),
+            None => self.utc.timestamp() * 1_000_000_000 + time.round() as i64,
+        }
+    }
+
+    /// UTC time of the start of the run's ToA timeline
+    pub fn timeline_start(&self) -> DateTime<Utc> {
+        Utc.timestamp_nanos(self.utc_ns(0.0))
+    }
+}
+
+/// Parses a time zone from its IANA name (e.g. `UTC`, `Europe/London`)
+pub fn parse_timezone(name: &str) -> Result<Tz, String> {
+    name.parse::<Tz>()
+        .map_err(|_| format!("Unknown time zone '{}', expected an IANA name such as 'UTC' or 'Europe/London'", name))
+}
@@ -0,0 +1,180 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/slow_control.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::io;
+use std::path::Path;
+
+use chrono::{DateTime, LocalResult, NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
+use serde::Serialize;
+
+/// One slow control quantity (e.g. pressure, HV, temperature) sampled over time
+#[derive(Clone, Debug)]
+pub struct SlowControlChannel {
+    pub name: String,
+    samples: Vec<(i64, f64)>, // UTC time (ns since the Unix epoch) and value, in time order
+}
+
+/// Summary of a slow control channel over a time range, for a run's `summary.json`
+#[derive(Clone, Debug, Serialize)]
+pub struct SlowControlSummary {
+    pub samples: usize, // Samples within the range
+    pub start: Option<f64>,
+    pub end: Option<f64>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub mean: Option<f64>, // Time weighted, from the interpolated values
+}
+
+impl SlowControlChannel {
+    pub fn new(name: &str, mut samples: Vec<(i64, f64)>) -> SlowControlChannel {
+        samples.sort_by_key(|&(time, _)| time);
+
+        SlowControlChannel {
+            name: name.to_owned(),
+            samples,
+        }
+    }
+
+    /// Value at a time (UTC ns), linearly interpolated between the samples either side. `None`
+    /// outside the time covered by the samples.
+    pub fn interpolate(&self, time: i64) -> Option<f64> {
+        let i = self.samples.partition_point(|&(sample_time, _)| sample_time < time);
+
+        match (i.checked_sub(1).map(|j| self.samples[j]), self.samples.get(i)) {
+            (_, Some(&(t1, v1))) if t1 == time => Some(v1),
+            (Some((t0, v0)), Some(&(t1, v1))) => Some(v0 + (v1 - v0) * ((time - t0) as f64 / (t1 - t0) as f64)),
+            _ => None,
+        }
+    }
+
+    /// Mean of the interpolated values over a time range (UTC ns), `None` if the samples do not
+    /// cover any of it
+    pub fn mean(&self, start: i64, end: i64) -> Option<f64> {
+        let first = self.samples.first()?.0.max(start);
+        let last = self.samples.last()?.0.min(end);
+
+        if first > last {
+            return None;
+        }
+
+        if first == last {
+            return self.interpolate(first);
+        }
+
+        // Trapezoids between the range ends and the samples within it
+        let mut points = vec![(first, self.interpolate(first)?)];
+        points.extend(self.samples.iter().copied().filter(|&(time, _)| time > first && time < last));
+        points.push((last, self.interpolate(last)?));
+
+        let integral: f64 = points.windows(2).map(|x| (x[1].0 - x[0].0) as f64 * (x[0].1 + x[1].1) / 2.0).sum();
+
+        Some(integral / (last - first) as f64)
+    }
+
+    pub fn summarise(&self, start: i64, end: i64) -> SlowControlSummary {
+        let within: Vec<f64> = self
+            .samples
+            .iter()
+            .filter(|&&(time, _)| time >= start && time <= end)
+            .map(|&(_, value)| value)
+            .collect();
+
+        let bounds = [self.interpolate(start), self.interpolate(end)];
+        let values = within.iter().copied().chain(bounds.iter().flatten().copied());
+
+        SlowControlSummary {
+            samples: within.len(),
+            start: bounds[0],
+            end: bounds[1],
+            min: values.clone().fold(None, |min: Option<f64>, x| Some(min.map_or(x, |min| min.min(x)))),
+            max: values.fold(None, |max: Option<f64>, x| Some(max.map_or(x, |max| max.max(x)))),
+            mean: self.mean(start, end),
+        }
+    }
+}
+
+/// Parses a slow control timestamp to UTC ns: RFC 3339 (with an offset), a date and time without
+/// an offset (taken to be in `timezone`) or a Unix time in s
+pub fn parse_slow_control_time(time: &str, timezone: Tz) -> Option<i64> {
+    let time = time.trim();
+
+    if let Ok(seconds) = time.parse::<f64>() {
+        return Some((seconds * 1e9).round() as i64);
+    }
+
+    if let Ok(time) = DateTime::parse_from_rfc3339(time) {
+        return Some(time.timestamp() * 1_000_000_000 + i64::from(time.timestamp_subsec_nanos()));
+    }
+
+    let naive_time = ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f", "%d/%m/%Y %H:%M:%S%.f"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(time, format).ok())?;
+
+    match timezone.from_local_datetime(&naive_time) {
+        LocalResult::Single(time) | LocalResult::Ambiguous(time, _) => {
+            Some(time.timestamp() * 1_000_000_000 + i64::from(time.timestamp_subsec_nanos()))
+        }
+        LocalResult::None => None,
+    }
+}
+
+/// Reads a slow control CSV with a timestamp column and one or more value columns, giving a
+/// channel per value column. Empty or non-numeric values (e.g. `nan`, a sensor dropout) are
+/// skipped.
+pub fn read_slow_control_csv(file: &Path, time_column: &str, timezone: Tz) -> io::Result<Vec<SlowControlChannel>> {
+    let mut rdr = csv::Reader::from_path(file)?;
+
+    let headers = rdr.headers()?.clone();
+
+    let time_index = match headers.iter().position(|x| x.trim() == time_column) {
+        Some(i) => i,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Slow control file {:#?} has no '{}' column", file, time_column),
+            ));
+        }
+    };
+
+    let mut samples = vec![Vec::new(); headers.len()];
+
+    for (line, result) in rdr.records().enumerate() {
+        let record = result?;
+
+        let time = match record.get(time_index).and_then(|x| parse_slow_control_time(x, timezone)) {
+            Some(time) => time,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid timestamp on line {} of {:#?}", line + 2, file),
+                ));
+            }
+        };
+
+        for (i, value) in record.iter().enumerate() {
+            if i == time_index {
+                continue;
+            }
+
+            if let Ok(value) = value.trim().parse::<f64>() {
+                if value.is_finite() {
+                    samples[i].push((time, value));
+                }
+            }
+        }
+    }
+
+    Ok(headers
+        .iter()
+        .zip(samples)
+        .enumerate()
+        .filter(|&(i, _)| i != time_index)
+        .map(|(_, (name, samples))| SlowControlChannel::new(name.trim(), samples))
+        .collect())
+}
@@ -0,0 +1,93 @@
+/*
+ * ARIADNE Experiment, Department of Physics, University of Liverpool
+ *
+ * timepix-spidr-data-parser/src/timing_offsets.rs
+ *
+ * Authors: Jared Vann
+ */
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Hit, HitFlags, TOA_CLOCK_TO_NS};
+
+/// Row of a timing offsets CSV (as written by `timing_offset_tool`), giving the mean ToA offset
+/// (ns) of a rectangular region of pixels. The end of each range is exclusive.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TimingOffsetRegion {
+    pub col_start: u16,
+    pub col_end: u16,
+    pub row_start: u16,
+    pub row_end: u16,
+    pub hits: u64,
+    pub offset: f64,
+    pub rms: f64,
+}
+
+/// Per-pixel ToA correction from a table of timing offsets. Each hit's ToA has the offset of its
+/// region subtracted.
+#[derive(Clone, Debug, Serialize)]
+pub struct TimingOffsets {
+    pub file: PathBuf,
+    pub regions: usize,
+    #[serde(skip)]
+    offsets: Vec<i64>,
+}
+
+impl TimingOffsets {
+    pub fn from_csv(file: &Path) -> io::Result<TimingOffsets> {
+        let mut rdr = csv::Reader::from_path(file)?;
+
+        let mut offsets = vec![0; 256 * 256];
+        let mut regions = 0;
+
+        for result in rdr.deserialize() {
+            let region: TimingOffsetRegion = result?;
+
+            if region.col_end > 256 || region.row_end > 256 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Timing offsets {:#?} have a region outside the matrix", file),
+                ));
+            }
+
+            // Offsets are applied in ToA clock units
+            let offset = (region.offset / TOA_CLOCK_TO_NS).round() as i64;
+
+            for col in region.col_start..region.col_end {
+                for row in region.row_start..region.row_end {
+                    offsets[usize::from(row) * 256 + usize::from(col)] = offset;
+                }
+            }
+
+            regions += 1;
+        }
+
+        Ok(TimingOffsets {
+            file: file.to_path_buf(),
+            regions,
+            offsets,
+        })
+    }
+
+    pub fn correct(&self, hit: Hit) -> Hit {
+        if hit.col > 255 || hit.row > 255 {
+            return hit;
+        }
+
+        let offset = self.offsets[usize::from(hit.row) * 256 + usize::from(hit.col)];
+
+        let toa = if offset >= 0 {
+            hit.toa.saturating_sub(offset as u64)
+        } else {
+            hit.toa + (-offset) as u64
+        };
+
+        let mut flags = hit.flags;
+        flags.insert(HitFlags::TIME_CORRECTED);
+
+        Hit { toa, flags, ..hit }
+    }
+}